@@ -0,0 +1,94 @@
+# Deutsche Übersetzung der Konsolenausgabe, siehe locales/en.ftl
+
+## Start des Laufs
+config-not-found = Konfigurationsdatei nicht gefunden
+config-unreadable = Konfiguration konnte nicht gelesen werden: { $error }
+config-without-output = Die Konfiguration hat weder outputDirectory noch outputs
+reencode-loops = Synchronisierung abgelehnt, die Ausgaben würden bei jedem Lauf erneut kodiert:
+syncing-output = Synchronisiere Ausgabe { $name }
+waiting-for-device = Warte, bis das Ausgabegerät angeschlossen ist
+device-not-found = Ausgabegerät nicht gefunden
+program-not-found = { $program } nicht gefunden
+no-failed-files = Keine fehlgeschlagenen Dateien zu wiederholen
+name-collision = Namenskollision mit den aktuellen Einstellungen gefunden, Abbruch
+collision-sources = { $output } ist der resultierende Dateiname für:
+test-encode-failed = Testkodierung mit den ffmpeg-Parametern "{ $params }" fehlgeschlagen, Abbruch
+
+## Zusammenfassung vor der Bestätigung
+plan-summary = { $process } Titel zu kodieren/kopieren, { $rename } umzubenennen und { $delete } zu löschen
+remuxable-outputs = { $count ->
+    [one] { $count } Ausgabe mit geänderter Endung könnte umverpackt statt neu kodiert werden, setze remuxOnExtensionChange
+   *[other] { $count } Ausgaben mit geänderter Endung könnten umverpackt statt neu kodiert werden, setze remuxOnExtensionChange
+}
+delete-limit-exceeded = { $delete } von { $output } Ausgabedateien sollen gelöscht werden, { $reason }
+delete-limit-forced = { $message }, fahre fort, da --force-delete gesetzt ist
+delete-limit-dry-run = { $message }, ohne --dry-run würde der Lauf abgebrochen
+delete-limit-aborting = { $message }, Abbruch
+delete-limit-hint = Prüfe das Eingabeverzeichnis oder starte mit --force-delete, wenn die Löschungen gewollt sind
+action-process = Kodieren/Kopieren
+action-rename = Umbenennen
+action-delete = Löschen
+estimated-cost = Geschätzte Kosten: bis zu { $cost }
+
+## Abfragen
+prompt-continue = Möchtest du fortfahren?
+prompt-category = { $count ->
+    [one] { $action }: { $count } Datei?
+   *[other] { $action }: { $count } Dateien?
+}
+skipping-category = { $count ->
+    [one] Übersprungen: { $action } { $count } Datei
+   *[other] Übersprungen: { $action } { $count } Dateien
+}
+aborting = Abgebrochen
+
+## Zusammenfassung nach dem Lauf
+done-processing = Dateien in { $duration } verarbeitet
+transferred = { $downloaded } heruntergeladen, { $uploaded } hochgeladen, { $written } lokal geschrieben
+cost = Kosten: { $cost }
+sync-interrupted = Synchronisierung unterbrochen: { $error }
+out-of-time-actions = { $count ->
+    [one] --max-duration erreicht, { $count } Aktion bleibt für den nächsten Lauf
+   *[other] --max-duration erreicht, { $count } Aktionen bleiben für den nächsten Lauf
+}
+out-of-time-outputs = --max-duration erreicht, die übrigen Ausgaben werden beim nächsten Lauf synchronisiert
+failed-encodes = { $count ->
+    [one] { $count } Datei konnte nicht kodiert werden, siehe die Warnungen oben
+   *[other] { $count } Dateien konnten nicht kodiert werden, siehe die Warnungen oben
+}
+warnings-heading = { $count ->
+    [one] { $count } Warnung
+   *[other] { $count } Warnungen
+}
+watching-input = Beobachte die Eingabe auf Änderungen
+
+## Warum Dateien verarbeitet, umbenannt oder gelöscht werden
+reason-new = neue Quelle
+reason-output-changed = Ausgabe fehlt
+reason-source-changed = Quelle geändert
+reason-params-changed = ffmpeg-Parameter geändert
+reason-extension-changed = Zielendung geändert
+reason-failed = im letzten Lauf fehlgeschlagen
+reason-name-changed = Benennung geändert
+reason-no-source = Quelle entfernt
+reason-pruned = durch Aufbewahrungsregel entfernt
+
+## Warnungen, am Ende des Laufs nach Art gruppiert
+warning-missing-extension = Dateien ohne Endung, übersprungen
+warning-incomplete-file = Dateien, die noch geschrieben werden, übersprungen
+warning-unreadable-directory = Ordner, die nicht gelesen werden konnten
+warning-suspicious-name = Verdächtige Ausgabedateinamen
+warning-missing-track-number = Dateien ohne Titelnummer in den Tags
+warning-unknown-bitrate = Quellen mit unbekannter Bitrate, kodiert statt kopiert
+warning-params-corrected = ffmpeg-Parameter für die Zielendung korrigiert, korrigiere sie in der Konfiguration
+warning-encode-failed = Dateien, die nicht kodiert werden konnten, wiederhole sie mit --retry-failed
+warning-slow-file = Dateien, deren Kodierung länger dauerte als slowFileBudget erlaubt, prüfe die Festplatten und Remotes
+warning-remux-failed = Ausgaben, die nicht in die neue Endung umverpackt werden konnten, der nächste Lauf versucht es erneut
+warning-cover-copy-failed = Dateien, in die das Cover nicht kopiert werden konnte
+warning-tag-copy-failed = Dateien, in die die Tags nicht kopiert werden konnten
+warning-mirror-failed = Dateien, die nicht auf einen Spiegel übertragen werden konnten, der nächste Lauf versucht es erneut
+warning-content-store-failed = Ausgaben, die nicht in den inhaltsadressierten Speicher übernommen werden konnten, der nächste Lauf versucht es erneut
+warning-output-busy = Ausgaben, die gerade von einem anderen Programm geschrieben werden, bleiben für den nächsten Lauf
+warning-status-publish-failed = Status konnte nicht veröffentlicht werden an
+warning-album-gain-failed = Alben und Titel, die nicht mit ihrem Album-ReplayGain getaggt werden konnten
+warning-folder-cover-failed = Alben, deren Cover nicht exportiert werden konnte
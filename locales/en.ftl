@@ -0,0 +1,96 @@
+# Messages of the console output in the Fluent syntax, see https://projectfluent.org/fluent/guide/
+# Placeholders like { $count } are filled in by ffmusicsync and have to be kept in translations, counts are numbers
+# which can choose the plural form of the message
+
+## Starting the run
+config-not-found = Config file not found
+config-unreadable = Failed to read config: { $error }
+config-without-output = Config has neither outputDirectory nor outputs
+reencode-loops = Refusing to sync, the outputs would be encoded again on every run:
+syncing-output = Syncing output { $name }
+waiting-for-device = Waiting for the output device to be connected
+device-not-found = Output device not found
+program-not-found = { $program } not found
+no-failed-files = No failed files to retry
+name-collision = Found a name collision with the current settings, aborting
+collision-sources = { $output } is the resulting file name for:
+test-encode-failed = Test encode with ffmpeg params "{ $params }" failed, aborting
+
+## Summary before confirming
+plan-summary = { $process } songs to encode/copy, { $rename } to rename and { $delete } to delete
+remuxable-outputs = { $count ->
+    [one] { $count } output with a changed extension could be remuxed instead of encoded again, set remuxOnExtensionChange
+   *[other] { $count } outputs with a changed extension could be remuxed instead of encoded again, set remuxOnExtensionChange
+}
+delete-limit-exceeded = Planned to delete { $delete } of { $output } output files, { $reason }
+delete-limit-forced = { $message }, continuing as --force-delete is set
+delete-limit-dry-run = { $message }, the run would be aborted without --dry-run
+delete-limit-aborting = { $message }, aborting
+delete-limit-hint = Check the input directory, or run with --force-delete if the deletions are intended
+action-process = Encode/copy
+action-rename = Rename
+action-delete = Delete
+estimated-cost = Estimated cost: up to { $cost }
+
+## Prompts
+prompt-continue = Do you want to continue?
+prompt-category = { $count ->
+    [one] { $action } { $count } file?
+   *[other] { $action } { $count } files?
+}
+skipping-category = { $count ->
+    [one] Skipping: { $action } { $count } file
+   *[other] Skipping: { $action } { $count } files
+}
+aborting = Aborting
+
+## Summary after the run
+done-processing = Done processing files in { $duration }
+transferred = Downloaded { $downloaded }, uploaded { $uploaded }, written { $written } locally
+cost = Cost: { $cost }
+sync-interrupted = Sync interrupted: { $error }
+out-of-time-actions = { $count ->
+    [one] Reached --max-duration, { $count } action is left for the next run
+   *[other] Reached --max-duration, { $count } actions are left for the next run
+}
+out-of-time-outputs = Reached --max-duration, the remaining outputs are synced by the next run
+failed-encodes = { $count ->
+    [one] { $count } file failed to encode, see the warnings above
+   *[other] { $count } files failed to encode, see the warnings above
+}
+warnings-heading = { $count ->
+    [one] { $count } warning
+   *[other] { $count } warnings
+}
+watching-input = Watching the input for changes
+
+## Why files are processed, renamed or deleted
+reason-new = new source
+reason-output-changed = output missing
+reason-source-changed = source changed
+reason-params-changed = ffmpeg params changed
+reason-extension-changed = encoded extension changed
+reason-failed = failed in the last run
+reason-name-changed = naming settings changed
+reason-no-source = source removed
+reason-pruned = pruned by retention
+
+## Warnings, grouped by their kind at the end of the run
+warning-missing-extension = Files without an extension, skipped
+warning-incomplete-file = Files still being written, skipped
+warning-unreadable-directory = Directories that could not be read
+warning-suspicious-name = Suspicious output file names
+warning-missing-track-number = Files without a track number in their tags
+warning-unknown-bitrate = Sources whose bitrate could not be read, encoded instead of copied
+warning-params-corrected = ffmpeg params corrected to write the encoded extension, fix them in the config
+warning-encode-failed = Files which failed to encode, retry them with --retry-failed
+warning-slow-file = Files which took longer to encode than slowFileBudget allows, check the disks and remotes
+warning-remux-failed = Outputs which could not be remuxed to the new extension, retried by the next run
+warning-cover-copy-failed = Files the cover could not be copied to
+warning-tag-copy-failed = Files the tags could not be copied to
+warning-mirror-failed = Files which could not be pushed to a mirror, retried by the next run
+warning-content-store-failed = Outputs which could not be added to the content store, retried by the next run
+warning-output-busy = Outputs being written by another program, left for the next run
+warning-status-publish-failed = Status could not be published to
+warning-album-gain-failed = Albums and tracks which could not be tagged with their album ReplayGain
+warning-folder-cover-failed = Albums whose cover could not be exported
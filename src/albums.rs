@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lofty::{ItemKey, ItemValue, MimeType, PictureType, Probe, TagExt, TagItem, TagType};
+
+use crate::config::Config;
+use crate::output_info::OutputInfo;
+use crate::programs;
+use crate::redact;
+use crate::sync::content_hash;
+use crate::tags::primary_tag_or_new;
+use crate::warnings::{WarningKind, Warnings};
+use crate::{ExecutedActions, SyncPlan};
+
+/// Names exportFolderCovers writes the cover of an album to, by the type of the picture
+pub const FOLDER_COVER_NAMES: [&str; 2] = ["cover.jpg", "cover.png"];
+
+/// Loudness ReplayGain 2.0 brings albums to, in LUFS
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Loudness of an album, measured over all of its tracks played one after another
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlbumLoudness {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBFS
+    pub true_peak: f64,
+}
+
+impl AlbumLoudness {
+    /// Album gain in dB
+    pub fn gain(&self) -> f64 {
+        REFERENCE_LOUDNESS - self.integrated
+    }
+    /// Album peak as a linear sample value, 1.0 being full scale
+    pub fn peak(&self) -> f64 {
+        10f64.powf(self.true_peak / 20.0)
+    }
+}
+
+/// Measures the loudness of the tracks as one stream with ffmpeg's ebur128 filter
+pub fn measure_album(config: &Config, tracks: &[PathBuf]) -> Result<AlbumLoudness, String> {
+    let mut command = programs::ffmpeg(config);
+    command.args(["-hide_banner", "-nostats"]);
+    for track in tracks {
+        command.arg("-i").arg(track);
+    }
+    let inputs = (0..tracks.len()).map(|index| format!("[{}:a]", index)).collect::<String>();
+    command
+        .arg("-filter_complex")
+        .arg(format!("{}concat=n={}:v=0:a=1,ebur128=peak=true", inputs, tracks.len()))
+        .args(["-f", "null", "-"]);
+    let output = command.output().map_err(|error| error.to_string())?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(stderr.lines().last().unwrap_or("ffmpeg failed").to_string());
+    }
+    parse_ebur128_summary(&stderr).ok_or_else(|| String::from("ffmpeg printed no loudness summary"))
+}
+
+/// Reads the integrated loudness and true peak from the summary the ebur128 filter prints at the end
+pub fn parse_ebur128_summary(output: &str) -> Option<AlbumLoudness> {
+    let summary = &output[output.rfind("Summary:")?..];
+    let value = |label: &str| {
+        summary
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(label))?
+            .split_whitespace()
+            .next()?
+            .parse::<f64>()
+            .ok()
+    };
+    Some(
+        AlbumLoudness {
+            integrated: value("I:")?,
+            true_peak: value("Peak:")?,
+        }
+    )
+}
+
+/// Writes REPLAYGAIN_ALBUM_GAIN and REPLAYGAIN_ALBUM_PEAK to the primary tag of the output
+///
+/// Returns false if its tag format can't store them, ID3v2 only has them in TXXX frames which can't be written yet
+pub fn write_album_gain(output: &Path, loudness: &AlbumLoudness) -> lofty::error::Result<bool> {
+    let tagged_file = Probe::open(output)?.guess_file_type()?.read(false)?;
+    let mut tag = primary_tag_or_new(&tagged_file);
+    let key = |name: &str| match tag.tag_type() {
+        TagType::VorbisComments | TagType::Ape => Some(ItemKey::Unknown(name.to_string())),
+        TagType::Mp4Ilst => Some(ItemKey::Unknown(format!("----:com.apple.iTunes:{}", name))),
+        _ => None,
+    };
+    let (Some(gain_key), Some(peak_key)) = (key("REPLAYGAIN_ALBUM_GAIN"), key("REPLAYGAIN_ALBUM_PEAK")) else {
+        return Ok(false);
+    };
+    tag.insert_item_unchecked(TagItem::new(gain_key, ItemValue::Text(format!("{:.2} dB", loudness.gain()))));
+    tag.insert_item_unchecked(TagItem::new(peak_key, ItemValue::Text(format!("{:.6}", loudness.peak()))));
+    tag.save_to_path(output)?;
+    Ok(true)
+}
+
+/// Writes the front cover, or the first picture, of the first source having one to the output folder of the album
+///
+/// Returns the name of the written cover, None if the folder already has an image or no source has a picture
+pub fn export_folder_cover(sources: &[PathBuf], output_directory: &Path) -> lofty::error::Result<Option<&'static str>> {
+    let has_image = fs::read_dir(output_directory)?.flatten().any(|entry| {
+        Path::new(&entry.file_name())
+            .extension()
+            .is_some_and(|extension| ["jpg", "jpeg", "png"].contains(&extension.to_string_lossy().to_lowercase().as_str()))
+    });
+    if has_image {
+        return Ok(None);
+    }
+    for source in sources {
+        let tagged_file = Probe::open(source)?.guess_file_type()?.read(false)?;
+        let Some(tag) = tagged_file.primary_tag() else {
+            continue;
+        };
+        let Some(picture) = tag.get_picture_type(PictureType::CoverFront).or(tag.pictures().first()) else {
+            continue;
+        };
+        let name = match picture.mime_type() {
+            MimeType::Png => FOLDER_COVER_NAMES[1],
+            _ => FOLDER_COVER_NAMES[0],
+        };
+        fs::write(output_directory.join(name), picture.data())?;
+        return Ok(Some(name));
+    }
+    Ok(None)
+}
+
+/// Tags the albums completed by the run with their album ReplayGain and exports their covers, see
+/// [SyncPlan::completed_albums]
+///
+/// Both read and write the output directly, so it has to be local. Retagged tracks are recorded as refreshed, so the
+/// state, mirrors and the content store get their new contents
+pub fn update_albums(plan: &SyncPlan, executed: &mut ExecutedActions, warnings: &mut Warnings) {
+    let config = plan.config();
+    let replay_gain = config.album_replay_gain == Some(true);
+    // Covers are read from the sources
+    let folder_covers = config.export_folder_covers == Some(true) && !config.input_directory.is_remote();
+    if (!replay_gain && !folder_covers) || config.output_directory.is_remote() {
+        return;
+    }
+    let input_directory = PathBuf::from(config.input_directory.clone().path_string());
+    let output_directory = PathBuf::from(config.output_directory.clone().path_string());
+    for (directory, tracks) in plan.completed_albums(executed) {
+        if replay_gain {
+            println!("Measuring the loudness of album {}", redact::path(&directory));
+            let outputs = tracks
+                .values()
+                .map(|output_file_name| output_directory.join(output_file_name))
+                .collect::<Vec<PathBuf>>();
+            match measure_album(config, &outputs) {
+                Ok(loudness) => {
+                    for output_file_name in tracks.values() {
+                        let output_file_path = output_directory.join(output_file_name);
+                        match write_album_gain(&output_file_path, &loudness) {
+                            Ok(true) => {
+                                executed.refreshed.insert(output_file_name.clone());
+                                if let Some(output_info) = OutputInfo::read(&output_file_path) {
+                                    executed.output_info.insert(output_file_name.clone(), output_info);
+                                }
+                                if let Some(content_hash) = content_hash(config, &output_file_path) {
+                                    executed.content_hashes.insert(output_file_name.clone(), content_hash);
+                                }
+                            }
+                            Ok(false) => warnings.push(
+                                WarningKind::AlbumGainFailed,
+                                output_file_name.clone(),
+                                Some(String::from("its tag format can't store the album gain")),
+                            ),
+                            Err(error) => warnings.push(
+                                WarningKind::AlbumGainFailed,
+                                output_file_name.clone(),
+                                Some(error.to_string()),
+                            ),
+                        }
+                    }
+                }
+                Err(error) => warnings.push(WarningKind::AlbumGainFailed, directory.clone(), Some(error)),
+            }
+        }
+        if folder_covers {
+            let sources = tracks
+                .keys()
+                .map(|input_file_name| input_directory.join(input_file_name))
+                .collect::<Vec<PathBuf>>();
+            match export_folder_cover(&sources, &output_directory.join(&directory)) {
+                Ok(Some(name)) => println!("Exported the cover of album {} to {}", redact::path(&directory), name),
+                Ok(None) => {}
+                Err(error) => warnings.push(WarningKind::FolderCoverFailed, directory.clone(), Some(error.to_string())),
+            }
+        }
+    }
+}
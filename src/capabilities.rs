@@ -0,0 +1,261 @@
+use std::path::Path;
+
+/// What a container format can store, used to catch misconfigurations before ffmpeg runs
+#[derive(Debug, PartialEq, Eq)]
+pub struct Container {
+    /// File extensions of the container
+    pub extensions: &'static [&'static str],
+    /// ffmpeg audio encoders whose output fits the container
+    pub codecs: &'static [&'static str],
+    /// ffmpeg muxers writing the container, as chosen with -f
+    pub formats: &'static [&'static str],
+    /// Embedded cover art
+    pub pictures: bool,
+    /// Chapter marks
+    pub chapters: bool,
+    /// Encoder delay and padding, so albums play without gaps between tracks
+    pub gapless: bool,
+}
+
+/// Containers ffmusicsync knows about, files with other extensions aren't checked
+pub const CONTAINERS: [Container; 7] = [
+    Container {
+        extensions: &["ogg", "oga"],
+        codecs: &["libopus", "opus", "libvorbis", "vorbis", "flac"],
+        formats: &["ogg", "oga"],
+        pictures: true,
+        chapters: true,
+        gapless: true,
+    },
+    Container {
+        extensions: &["opus"],
+        codecs: &["libopus", "opus"],
+        formats: &["opus", "ogg"],
+        pictures: true,
+        chapters: true,
+        gapless: true,
+    },
+    Container {
+        extensions: &["mp3"],
+        codecs: &["libmp3lame", "libshine", "mp3"],
+        formats: &["mp3"],
+        pictures: true,
+        chapters: true,
+        gapless: true,
+    },
+    Container {
+        extensions: &["m4a", "m4b", "mp4"],
+        codecs: &["aac", "libfdk_aac", "aac_at", "alac", "alac_at"],
+        formats: &["ipod", "mp4", "mov"],
+        pictures: true,
+        chapters: true,
+        gapless: true,
+    },
+    Container {
+        extensions: &["aac"],
+        codecs: &["aac", "libfdk_aac", "aac_at"],
+        formats: &["adts"],
+        pictures: false,
+        chapters: false,
+        gapless: false,
+    },
+    Container {
+        extensions: &["flac"],
+        codecs: &["flac"],
+        formats: &["flac"],
+        pictures: true,
+        chapters: false,
+        gapless: true,
+    },
+    Container {
+        extensions: &["wav"],
+        codecs: &["pcm_s16le", "pcm_s24le", "pcm_s32le", "pcm_f32le", "pcm_u8"],
+        formats: &["wav"],
+        pictures: false,
+        chapters: false,
+        gapless: true,
+    },
+];
+
+/// The container stored in files with the extension, ignoring its case
+pub fn container(extension: &str) -> Option<&'static Container> {
+    let extension = extension.to_lowercase();
+    CONTAINERS
+        .iter()
+        .find(|container| container.extensions.contains(&extension.as_str()))
+}
+
+/// The container of a file by its extension
+pub fn container_of<P: AsRef<Path>>(file: P) -> Option<&'static Container> {
+    container(&file.as_ref().extension()?.to_string_lossy())
+}
+
+/// The output format forced by the ffmpeg params with -f, if any
+pub fn output_format(ffmpeg_params: &str) -> Option<&str> {
+    let params = ffmpeg_params.split(' ').collect::<Vec<&str>>();
+    params
+        .windows(2)
+        .rev()
+        .find(|pair| pair[0] == "-f")
+        .map(|pair| pair[1])
+}
+
+/// Makes the ffmpeg params write files of the encoded extension, returns the corrected params and what was changed
+///
+/// A leading dot of the extension and a -f format not writing the container are removed, so ffmpeg picks the
+/// format from the extension. An encoder whose output can't be stored in the container is an error,
+/// as there's no way to tell which one was meant
+pub fn reconcile_params(extension: &str, ffmpeg_params: &str) -> Result<(String, String, Vec<String>), String> {
+    let mut corrections = Vec::new();
+    let trimmed_extension = extension.trim_start_matches('.');
+    if trimmed_extension != extension {
+        corrections.push(format!("removed the leading dot of the extension {}, names would end with ..", extension));
+    }
+    let Some(container) = container(trimmed_extension) else {
+        return Ok((trimmed_extension.to_string(), ffmpeg_params.to_string(), corrections));
+    };
+    if let Some(codec) = audio_codec(ffmpeg_params).filter(|codec| !container.fits(codec)) {
+        return Err(format!("encodes with {}, which can't be stored in .{} files", codec, trimmed_extension));
+    }
+    let split_params = ffmpeg_params.split(' ').collect::<Vec<&str>>();
+    let mut kept = Vec::new();
+    let mut index = 0;
+    while index < split_params.len() {
+        match (split_params[index], split_params.get(index + 1)) {
+            ("-f", Some(format)) if !container.formats.contains(format) => {
+                corrections.push(format!("removed -f {}, which doesn't write .{} files", format, trimmed_extension));
+                index += 2;
+            }
+            (param, _) => {
+                kept.push(param);
+                index += 1;
+            }
+        }
+    }
+    let params = kept.join(" ");
+    Ok((trimmed_extension.to_string(), params, corrections))
+}
+
+/// The audio encoder chosen by the ffmpeg params with -c:a, -codec:a or -acodec, if any
+///
+/// `copy` isn't an encoder and is ignored, the codec of the source isn't known
+pub fn audio_codec(ffmpeg_params: &str) -> Option<&str> {
+    let params = ffmpeg_params.split(' ').collect::<Vec<&str>>();
+    params
+        .windows(2)
+        .rev()
+        .find(|pair| ["-c:a", "-codec:a", "-acodec"].contains(&pair[0]))
+        .map(|pair| pair[1])
+        .filter(|codec| *codec != "copy")
+}
+
+/// The codec an ffmpeg encoder produces, encoders of the same codec can be told apart only by their quality
+fn codec_family(encoder: &str) -> &str {
+    match encoder {
+        "libopus" | "opus" => "opus",
+        "libvorbis" | "vorbis" => "vorbis",
+        "libmp3lame" | "libshine" | "mp3" => "mp3",
+        "aac" | "libfdk_aac" | "aac_at" => "aac",
+        "alac" | "alac_at" => "alac",
+        encoder => encoder,
+    }
+}
+
+/// ffmpeg params changing the audio itself, sources can't be remuxed when they're used
+const AUDIO_CHANGING_PARAMS: [&str; 7] = ["-af", "-filter:a", "-filter_complex", "-ac", "-ar", "-sample_fmt", "-t"];
+
+/// Params copying the audio of a source which already holds the codec the params encode to, None if it has to be
+/// encoded
+///
+/// `source_codec` is the codec name given by ffprobe, like opus. The bitrate of the source is kept
+pub fn remux_params(source_codec: &str, extension: &str, ffmpeg_params: &str) -> Option<String> {
+    let codec = audio_codec(ffmpeg_params)?;
+    if codec_family(codec) != codec_family(source_codec) {
+        return None;
+    }
+    if container(extension).is_some_and(|container| !container.codecs.contains(&source_codec)) {
+        return None;
+    }
+    if ffmpeg_params.split(' ').any(|param| AUDIO_CHANGING_PARAMS.contains(&param)) {
+        return None;
+    }
+    let mut params = String::from("-vn -c:a copy");
+    if let Some(format) = output_format(ffmpeg_params) {
+        params.push_str(&format!(" -f {}", format));
+    }
+    Some(params)
+}
+
+/// Whether an output can get another extension without being encoded again, by renaming or remuxing it
+///
+/// The codec of the output is taken from the params it was encoded with, or from its container if the container only
+/// holds one codec
+pub fn can_change_extension(output_file_name: &str, extension: &str, old_params: Option<&str>, params: &str) -> bool {
+    let (Some(old_container), Some(new_container)) = (container_of(output_file_name), container(extension)) else {
+        return false;
+    };
+    // Different extensions of the same container
+    if old_container == new_container {
+        return true;
+    }
+    let Some(codec) = audio_codec(params) else {
+        return false;
+    };
+    let old_codec = match old_params {
+        Some(old_params) => audio_codec(old_params).map(codec_family),
+        None => {
+            let mut families = old_container.codecs.iter().map(|codec| codec_family(codec));
+            let family = families.next();
+            family.filter(|family| families.all(|other| other == *family))
+        }
+    };
+    old_codec == Some(codec_family(codec)) && new_container.codecs.contains(&codec)
+}
+
+/// Extensions of sources which are always lossy, encoding them to a lossy format again only loses quality
+///
+/// m4a isn't one of them, it holds ALAC as well
+const LOSSY_EXTENSIONS: [&str; 5] = ["mp3", "aac", "ogg", "oga", "opus"];
+
+/// ffmpeg encoders keeping the audio as it is, PCM encoders are recognized by their prefix
+const LOSSLESS_ENCODERS: [&str; 6] = ["flac", "alac", "alac_at", "wavpack", "tta", "libwavpack"];
+
+/// Extensions of audio files ffmpeg reads besides the [CONTAINERS], encode rules given as globs only apply to audio
+const AUDIO_EXTENSIONS: [&str; 12] = ["aif", "aiff", "ape", "wv", "tta", "wma", "dsf", "dff", "mka", "spx", "mpc", "ac3"];
+
+/// Whether the file is audio by its extension, ignoring its case
+pub fn is_audio_file<P: AsRef<Path>>(file: P) -> bool {
+    container_of(&file).is_some()
+        || file
+            .as_ref()
+            .extension()
+            .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Whether the file is a lossy source by its extension, ignoring its case
+pub fn is_lossy_source<P: AsRef<Path>>(file: P) -> bool {
+    file.as_ref()
+        .extension()
+        .is_some_and(|extension| LOSSY_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Whether encoding with the params loses quality, by the encoder or without -c:a by the default encoder of the
+/// extension
+pub fn encodes_lossy(extension: &str, ffmpeg_params: &str) -> bool {
+    match audio_codec(ffmpeg_params) {
+        Some(codec) => !LOSSLESS_ENCODERS.contains(&codec) && !codec.starts_with("pcm_"),
+        None => !["flac", "wav", "aiff", "wv", "tta"].contains(&extension.to_lowercase().as_str()),
+    }
+}
+
+impl Container {
+    /// Whether files of the container can be encoded with the codec, unknown encoders are assumed to fit
+    pub fn fits(&self, codec: &str) -> bool {
+        if self.codecs.contains(&codec) {
+            return true;
+        }
+        !CONTAINERS
+            .iter()
+            .any(|container| container.codecs.contains(&codec))
+    }
+}
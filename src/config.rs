@@ -1,28 +1,650 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
 use std::str::FromStr;
+use std::time::Duration;
+use std::{fs, io};
 
+use regex::Regex;
 use serde::Deserialize;
 use serde_with::{FromInto, serde_as};
 
+use crate::capabilities::{is_audio_file, reconcile_params};
 use crate::fs_wrapper::RclonePath;
+use crate::overrides::OVERRIDE_FILE_NAME;
+use crate::rclone_filter::RcloneFilter;
+use crate::secret::Secret;
 
 #[serde_as]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
     // Allow input and output directory to be either a string or specify the remote and directory as strings
     #[serde_as(as = "FromInto<RclonePathWrapper>")]
     pub input_directory: RclonePath,
+    /// Filesystem snapshot of the local input taken before a sync, which is read instead of the input
+    pub input_snapshot: Option<SnapshotKind>,
+    /// inputDirectory of the config while the input directory points to its snapshot
+    #[serde(skip)]
+    pub snapshot_of: Option<RclonePath>,
+    /// Can be left out when outputs are used
     #[serde_as(as = "FromInto<RclonePathWrapper>")]
+    #[serde(default = "empty_rclone_path")]
     pub output_directory: RclonePath,
+    /// Removable device the output directory is on, the output directory is relative to its mount point
+    pub output_device: Option<OutputDevice>,
+    /// Directories every change of the output directory is pushed to after it's done, local or remote
+    #[serde_as(as = "Option<Vec<FromInto<RclonePathWrapper>>>")]
+    #[serde(default)]
+    pub mirrors: Option<Vec<RclonePath>>,
+    /// Content-addressed copy of the output updated after every sync, local or remote, see [crate::content_store]
+    #[serde_as(as = "Option<FromInto<RclonePathWrapper>>")]
+    #[serde(default)]
+    pub content_store: Option<RclonePath>,
     pub temp_directory: Option<String>,
+    /// ffmpeg to run instead of the one in PATH, like a static build, ffprobe is taken from its directory
+    pub ffmpeg_path: Option<String>,
+    /// rclone to run instead of the one in PATH
+    pub rclone_path: Option<String>,
+    /// Arguments added to every rclone command, like `["--bwlimit", "5M"]`
+    pub rclone_flags: Option<Vec<String>>,
+    /// Lists, copies, moves and deletes on remotes through one `rclone rcd` instead of an rclone run each, see
+    /// [crate::rclone_rc]
+    pub rclone_rc: Option<bool>,
+    /// Deleted outputs are moved here instead of being removed, into a directory for every run
+    #[serde_as(as = "Option<FromInto<RclonePathWrapper>>")]
+    #[serde(default)]
+    pub trash_directory: Option<RclonePath>,
+    /// Runs moved to the trash directory longer ago than this are removed by the clean command
+    pub trash_days: Option<u64>,
+    /// Delete outputs after encoding, copying and renaming instead of before, defaults to true
+    pub defer_deletes: Option<bool>,
+    /// Only sync input files matching any of these globs
+    pub include: Option<Vec<Glob>>,
+    /// Don't sync input files matching any of these globs, their outputs are deleted
+    pub exclude: Option<Vec<Glob>>,
+    /// rclone filter file deciding which input files are synced, passed to rclone as --filter-from for remote inputs
+    /// and applied the same way to local ones, see [crate::rclone_filter]
+    pub rclone_filter_file: Option<String>,
+    /// Limits how many of the matching files are kept in the output, like episodes of podcasts
+    pub retention: Option<Vec<RetentionRule>>,
     pub extensions_to_encode: Vec<String>,
     pub encoded_extension: String,
     pub copy_covers: Option<bool>,
+    /// Extensions of files belonging to the track with the same name, like lyrics, which are named after its output
+    /// Files with them but without a track belong to their folder instead, see [crate::naming::OutputNamer]
+    pub sidecar_extensions: Option<Vec<String>>,
+    /// Point the entries of copied m3u and m3u8 playlists to the output names of their files
+    pub rewrite_playlists: Option<bool>,
+    /// Write a playlist of all synced audio files to this path in the output directory after every run
+    pub export_playlist: Option<String>,
+    pub verify_copies: Option<bool>,
+    /// Decode every output after encoding it and compare its duration to the source, failing the encode if either check
+    /// fails
+    pub verify_after_encode: Option<bool>,
+    pub copy_retries: Option<u32>,
+    /// rclone copies running at once for copied files which get another name, the ones keeping it are copied by a
+    /// single rclone run
+    pub copy_workers: Option<usize>,
+    /// Process sources again when their contents change, detected by their size and hash
+    pub detect_changes: Option<bool>,
+    /// Skip input files whose size changes within this many seconds, as they're still being written
+    pub settle_seconds: Option<u64>,
+    /// Skip whole directories while any of their files is still being written
+    pub settle_directories: Option<bool>,
+    /// Tag the encoded tracks of albums completed by a run with the ReplayGain of the whole album
+    pub album_replay_gain: Option<bool>,
+    /// Write the embedded cover of albums completed by a run to their output folder, if it has no image yet
+    pub export_folder_covers: Option<bool>,
+    /// Save the state every this many processed files, so a crash doesn't lose the progress of the run
+    pub state_save_interval: Option<usize>,
+    /// Abort instead of deleting more output files than this, unless --force-delete is given
+    pub max_delete_count: Option<usize>,
+    /// Abort instead of deleting more than this percentage of the output files, unless --force-delete is given
+    pub max_delete_percent: Option<f64>,
+    /// Interleave new sources with the ones processed again, processing this many new ones (newest first) for each
+    /// of the others, so new albums don't wait for a long backlog of encodes
+    pub new_sources_per_cycle: Option<usize>,
+    /// Lock local outputs while changing them and leave the ones another program is writing alone
+    pub lock_outputs: Option<bool>,
+    pub flush_output: Option<bool>,
+    pub eject_output: Option<bool>,
     pub ffmpeg_params: String,
+    /// ffmpeg params placed before -i when encoding, like -hwaccel auto to decode with the GPU
+    pub ffmpeg_pre: Option<String>,
+    /// Niceness ffmpeg runs with when encoding, up to 19 for the lowest priority
+    pub nice: Option<i32>,
+    /// Encoding settings for parts of the input, the first matching rule wins
+    pub encode_rules: Option<Vec<EncodeRule>>,
+    /// Treat matching files as audiobooks, encoded and named with their own defaults
+    pub audiobooks: Option<Audiobooks>,
+    /// Remove long silence from the start and the end of matching files when encoding them, like live recordings
+    pub trim_silence: Option<Vec<Glob>>,
+    /// Encode sources again when the ffmpeg params they were encoded with change
+    pub reencode_on_param_change: Option<bool>,
+    /// With reencodeOnParamChange, encode sources again whose params weren't recorded, like the ones of older versions
+    pub reencode_unknown_params: Option<bool>,
+    /// Rename or remux outputs encoded to another extension instead of encoding them again, if the codec matches
+    pub remux_on_extension_change: Option<bool>,
+    /// Copy lossy sources instead of encoding them to a lossy format again
+    pub skip_lossy_transcode: Option<bool>,
+    /// Only copy lossy sources up to this bitrate in kbps with skipLossyTranscode, the others are still encoded
+    pub skip_lossy_transcode_max_bitrate: Option<u32>,
+    /// Copy sources instead of encoding them when their bitrate in kbps is below this, encoding wouldn't make them smaller
+    pub copy_if_bitrate_below: Option<u32>,
+    /// Copy the audio of sources already holding the codec of the ffmpeg params into the output instead of encoding it
+    pub remux_when_possible: Option<bool>,
+    pub overwrite_output: Option<bool>,
     pub remove_round_brackets: Option<bool>,
     pub remove_square_brackets: Option<bool>,
     pub remove_curly_brackets: Option<bool>,
     pub remove_angle_brackets: Option<bool>,
+    pub merge_disc_folders: Option<bool>,
+    pub track_numbers: Option<TrackNumbers>,
+    /// Prices of the used rclone remotes, by remote name
+    pub pricing: Option<HashMap<String, RemotePricing>>,
+    pub mqtt: Option<MqttConfig>,
+    /// Count used options, warnings and errors of every run in the --telemetry file, which is never sent anywhere
+    pub telemetry: Option<bool>,
+    /// Pause encoding while the CPU is too hot or the machine runs on battery
+    pub throttle: Option<Throttle>,
+    /// Time an encode may take depending on the size and length of its source, slower ones are reported
+    pub slow_file_budget: Option<SlowFileBudget>,
+    /// Sync the input to several outputs in one run, replacing outputDirectory and the encoding settings
+    pub outputs: Option<Vec<OutputProfile>>,
+}
+
+/// One of several outputs the input is synced to, all missing fields are taken from [Config]
+#[serde_as]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputProfile {
+    /// Used in the output and to name the state files of the profile
+    pub name: String,
+    #[serde_as(as = "FromInto<RclonePathWrapper>")]
+    pub output_directory: RclonePath,
+    pub output_device: Option<OutputDevice>,
+    pub extensions_to_encode: Option<Vec<String>>,
+    pub encoded_extension: Option<String>,
+    pub ffmpeg_params: Option<String>,
+    pub encode_rules: Option<Vec<EncodeRule>>,
+    /// File storing which songs are already encoded, defaults to the --encoded file with the name inserted,
+    /// like encoded.phone.json
+    pub encoded: Option<String>,
+}
+
+/// Encodes the files matching any of the extensions or globs with its own settings,
+/// replacing extensionsToEncode, encodedExtension and ffmpegParams for them
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeRule {
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub globs: Vec<Glob>,
+    pub encoded_extension: String,
+    pub ffmpeg_params: String,
+    /// Playback speed the audio is encoded at, like 1.25 for spoken word
+    pub speed: Option<f64>,
+}
+
+impl EncodeRule {
+    pub fn matches(&self, file: &str) -> bool {
+        matches_rule(&self.extensions, &self.globs, file)
+    }
+}
+
+/// Whether a rule with these extensions and globs applies to the file, extensions are compared ignoring their case
+///
+/// Globs only match audio files, so a rule for a whole folder doesn't encode its covers and booklets
+fn matches_rule(extensions: &[String], globs: &[Glob], file: &str) -> bool {
+    let file_extension = Path::new(file)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string());
+    file_extension.is_some_and(|file_extension| {
+        extensions.iter().any(|extension| extension.eq_ignore_ascii_case(&file_extension))
+    }) || (is_audio_file(file) && globs.iter().any(|glob| glob.matches(file)))
+}
+
+/// Keeps only the newest files matching the globs in the output, counted per directory
+///
+/// Files are ordered by their modification time, the sources are never touched
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionRule {
+    pub globs: Vec<Glob>,
+    /// Number of files kept in each directory
+    pub keep_last: Option<usize>,
+    /// Files older than this many days are removed
+    pub keep_days: Option<u64>,
+}
+
+/// When encodes are paused, checked before every encode on Linux, where the CPU temperature and the power supply
+/// can be read from /sys
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Throttle {
+    /// CPU temperature in °C from which encodes are paused
+    pub max_temperature: Option<f64>,
+    /// CPU temperature in °C encodes are resumed at, defaults to 10 °C below maxTemperature
+    pub resume_temperature: Option<f64>,
+    /// Pause encodes while the machine isn't plugged in
+    #[serde(default)]
+    pub pause_on_battery: bool,
+    /// How often the temperature and the power supply are checked again while paused
+    #[serde(default = "default_throttle_check_seconds")]
+    pub check_seconds: u64,
+}
+
+impl Throttle {
+    pub fn resume_temperature(&self) -> Option<f64> {
+        self.resume_temperature
+            .or(self.max_temperature.map(|max_temperature| max_temperature - 10.0))
+    }
+}
+
+/// Time an encode is expected to take at most, from waiting for its source until its output is complete
+///
+/// Encodes taking longer still finish, but are listed at the end of the run, as a short track taking minutes points
+/// to a thrashing disk or a stuck remote
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SlowFileBudget {
+    /// Seconds every encode may take, whatever the size and length of the source
+    #[serde(default)]
+    pub base_seconds: f64,
+    /// Seconds added for every MB of the source
+    #[serde(default)]
+    pub seconds_per_mb: f64,
+    /// Seconds added for every minute of audio, nothing is added for sources whose length can't be read
+    #[serde(default)]
+    pub seconds_per_minute: f64,
+}
+
+impl SlowFileBudget {
+    /// The time a source of this size in bytes and length may take
+    pub fn budget(&self, size: u64, duration: Option<Duration>) -> Duration {
+        let minutes = duration.map(|duration| duration.as_secs_f64() / 60.0).unwrap_or_default();
+        let seconds = self.base_seconds + self.seconds_per_mb * size as f64 / 1_000_000.0 + self.seconds_per_minute * minutes;
+        Duration::try_from_secs_f64(seconds).unwrap_or_default()
+    }
+}
+
+fn default_throttle_check_seconds() -> u64 {
+    30
+}
+
+/// Files which are audiobooks rather than music
+///
+/// They're encoded to low-bitrate mono keeping their chapters, and bracket removal doesn't apply to them.
+/// Encode rules matching an audiobook still replace the encoding settings
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Audiobooks {
+    #[serde(default = "default_audiobook_extensions")]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub globs: Vec<Glob>,
+    #[serde(default = "default_audiobook_encoded_extension")]
+    pub encoded_extension: String,
+    #[serde(default = "default_audiobook_ffmpeg_params")]
+    pub ffmpeg_params: String,
+    /// Playback speed the audio is encoded at, like 1.25
+    pub speed: Option<f64>,
+}
+
+impl Audiobooks {
+    pub fn matches(&self, file: &str) -> bool {
+        matches_rule(&self.extensions, &self.globs, file)
+    }
+}
+
+fn default_audiobook_extensions() -> Vec<String> {
+    vec![String::from("m4b")]
+}
+
+fn default_audiobook_encoded_extension() -> String {
+    String::from("ogg")
+}
+
+fn default_audiobook_ffmpeg_params() -> String {
+    String::from("-map_chapters 0 -c:a libopus -b:a 32K -ac 1 -vn")
+}
+
+/// Adds atempo filters changing the speed of the audio to the ffmpeg params, after an -af filter already present
+///
+/// Chapter marks aren't moved by atempo, so they're left out instead of pointing to the wrong places
+pub fn with_speed(ffmpeg_params: &str, speed: f64) -> String {
+    // Reported by lint-config, the audio is left as it is
+    if !speed.is_finite() || speed <= 0.0 {
+        return ffmpeg_params.to_string();
+    }
+    // atempo only accepts factors from 0.5 to 2 in older ffmpeg versions, larger changes are chained
+    let mut factors = Vec::new();
+    let mut remaining = speed;
+    while remaining > 2.0 {
+        factors.push(2.0);
+        remaining /= 2.0;
+    }
+    while remaining < 0.5 {
+        factors.push(0.5);
+        remaining /= 0.5;
+    }
+    factors.push(remaining);
+    let atempo = factors
+        .iter()
+        .map(|factor| format!("atempo={}", factor))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let mut params = split_params(&with_audio_filter(ffmpeg_params, &atempo));
+    match params.iter().position(|param| param == "-map_chapters") {
+        Some(index) if index + 1 < params.len() => params[index + 1] = String::from("-1"),
+        _ => params.extend([String::from("-map_chapters"), String::from("-1")]),
+    }
+    params.join(" ")
+}
+
+/// Filter removing silence longer than a second from the start and the end
+///
+/// The end is trimmed as the start of the reversed audio, stop periods would shorten pauses within the file too
+const TRIM_SILENCE_FILTER: &str = "silenceremove=start_periods=1:start_threshold=-60dB:start_silence=1,areverse,\
+    silenceremove=start_periods=1:start_threshold=-60dB:start_silence=1,areverse";
+
+/// Adds an audio filter to the ffmpeg params, after an -af filter already present
+pub fn with_audio_filter(ffmpeg_params: &str, filter: &str) -> String {
+    let mut params = split_params(ffmpeg_params);
+    match params.iter().position(|param| param == "-af" || param == "-filter:a") {
+        Some(index) if index + 1 < params.len() => params[index + 1] = format!("{},{}", params[index + 1], filter),
+        _ => params.extend([String::from("-af"), filter.to_string()]),
+    }
+    params.join(" ")
+}
+
+fn split_params(ffmpeg_params: &str) -> Vec<String> {
+    ffmpeg_params
+        .split(' ')
+        .filter(|param| !param.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Pattern matched against paths relative to the input directory
+///
+/// `*` and `?` match within a single folder, `**` matches any number of folders
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+    pub fn matches(&self, file: &str) -> bool {
+        self.regex.is_match(file)
+    }
+}
+
+impl TryFrom<String> for Glob {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(char) = chars.next() {
+            match char {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                _ => regex.push_str(&regex::escape(&char.to_string())),
+            }
+        }
+        regex.push('$');
+        Ok(Self {
+            regex: Regex::new(&regex)?,
+            pattern,
+        })
+    }
+}
+
+impl FromStr for Glob {
+    type Err = regex::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Self::try_from(pattern.to_string())
+    }
+}
+
+/// What to do with track numbers at the start of file names
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TrackNumbers {
+    /// Leave the names as they are
+    Keep,
+    /// Remove leading track numbers ("01 - Song.flac" becomes "Song.ogg")
+    Strip,
+    /// Prefix names with the zero-padded track number from the tags ("Song.flac" becomes "01 - Song.ogg")
+    Add,
+}
+
+/// How the input is snapshotted, see [crate::snapshot]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SnapshotKind {
+    /// Read-only snapshot of the btrfs subvolume the input is in
+    Btrfs,
+    /// Snapshot of the ZFS dataset the input is in
+    Zfs,
+    /// Windows Volume Shadow Copy of the drive the input is on
+    Vss,
+}
+
+/// Prices charged by a cloud storage provider, all in dollars
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RemotePricing {
+    pub egress_per_gb: Option<f64>,
+    pub ingress_per_gb: Option<f64>,
+    pub per_thousand_operations: Option<f64>,
+}
+
+/// MQTT broker to publish the sync state to
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttConfig {
+    /// host:port of the broker
+    pub broker: String,
+    pub client_id: Option<String>,
+    pub username: Option<Secret>,
+    pub password: Option<Secret>,
+    /// Base topic, defaults to ffmusicsync
+    pub topic: Option<String>,
+    /// Publish Home Assistant discovery payloads, defaults to true
+    pub discovery: Option<bool>,
+    pub discovery_prefix: Option<String>,
+}
+
+/// Identifies a removable device independently of where it's mounted, uuid takes precedence
+#[derive(Deserialize, Debug, Clone)]
+pub struct OutputDevice {
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+}
+
+/// Contents of a per-directory config file, all present fields replace the ones in [Config]
+/// for every file in that directory and its subdirectories
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigOverride {
+    pub extensions_to_encode: Option<Vec<String>>,
+    pub encoded_extension: Option<String>,
+    pub copy_covers: Option<bool>,
+    pub ffmpeg_params: Option<String>,
+    pub encode_rules: Option<Vec<EncodeRule>>,
+    pub overwrite_output: Option<bool>,
+    pub remove_round_brackets: Option<bool>,
+    pub remove_square_brackets: Option<bool>,
+    pub remove_curly_brackets: Option<bool>,
+    pub remove_angle_brackets: Option<bool>,
+    pub merge_disc_folders: Option<bool>,
+    pub track_numbers: Option<TrackNumbers>,
+    /// Don't sync the directory at all
+    pub skip: Option<bool>,
+}
+
+impl Config {
+    /// The input directory of the config, also while the input is read from a snapshot of it
+    pub fn source_directory(&self) -> &RclonePath {
+        self.snapshot_of.as_ref().unwrap_or(&self.input_directory)
+    }
+    /// Corrects ffmpeg params which would write another container than the encoded extension, see [reconcile_params]
+    ///
+    /// Returns the setting and the description of every correction, or an error if the params can't be corrected
+    pub fn reconcile_params(&mut self) -> Result<Vec<(&'static str, String)>, String> {
+        let mut corrections = Vec::new();
+        let mut encodings = vec![("ffmpegParams", &mut self.encoded_extension, &mut self.ffmpeg_params)];
+        encodings.extend(self.encode_rules.iter_mut().flatten().map(|encode_rule| {
+            ("encodeRules", &mut encode_rule.encoded_extension, &mut encode_rule.ffmpeg_params)
+        }));
+        encodings.extend(self.audiobooks.iter_mut().map(|audiobooks| {
+            ("audiobooks", &mut audiobooks.encoded_extension, &mut audiobooks.ffmpeg_params)
+        }));
+        for (setting, encoded_extension, ffmpeg_params) in encodings {
+            let (extension, params, setting_corrections) = reconcile_params(encoded_extension, ffmpeg_params)
+                .map_err(|error| format!("{} {}", setting, error))?;
+            *encoded_extension = extension;
+            *ffmpeg_params = params;
+            corrections.extend(setting_corrections.into_iter().map(|correction| (setting, correction)));
+        }
+        Ok(corrections)
+    }
+    /// Returns a copy of the config with all fields present in the override replaced
+    pub fn with_override(&self, config_override: &ConfigOverride) -> Self {
+        let mut config = self.clone();
+        if let Some(extensions_to_encode) = &config_override.extensions_to_encode {
+            config.extensions_to_encode = extensions_to_encode.clone();
+        }
+        if let Some(encoded_extension) = &config_override.encoded_extension {
+            config.encoded_extension = encoded_extension.clone();
+        }
+        if let Some(ffmpeg_params) = &config_override.ffmpeg_params {
+            config.ffmpeg_params = ffmpeg_params.clone();
+        }
+        if let Some(encode_rules) = &config_override.encode_rules {
+            config.encode_rules = Some(encode_rules.clone());
+        }
+        config.copy_covers = config_override.copy_covers.or(config.copy_covers);
+        config.overwrite_output = config_override.overwrite_output.or(config.overwrite_output);
+        config.remove_round_brackets = config_override.remove_round_brackets.or(config.remove_round_brackets);
+        config.remove_square_brackets = config_override.remove_square_brackets.or(config.remove_square_brackets);
+        config.remove_curly_brackets = config_override.remove_curly_brackets.or(config.remove_curly_brackets);
+        config.remove_angle_brackets = config_override.remove_angle_brackets.or(config.remove_angle_brackets);
+        config.merge_disc_folders = config_override.merge_disc_folders.or(config.merge_disc_folders);
+        config.track_numbers = config_override.track_numbers.or(config.track_numbers);
+        config
+    }
+    /// Returns the config for syncing to one of the outputs
+    pub fn for_output(&self, output: &OutputProfile) -> Self {
+        let mut config = self.clone();
+        config.outputs = None;
+        config.output_directory = output.output_directory.clone();
+        config.output_device = output.output_device.clone();
+        if let Some(extensions_to_encode) = &output.extensions_to_encode {
+            config.extensions_to_encode = extensions_to_encode.clone();
+        }
+        if let Some(encoded_extension) = &output.encoded_extension {
+            config.encoded_extension = encoded_extension.clone();
+        }
+        if let Some(ffmpeg_params) = &output.ffmpeg_params {
+            config.ffmpeg_params = ffmpeg_params.clone();
+        }
+        if let Some(encode_rules) = &output.encode_rules {
+            config.encode_rules = Some(encode_rules.clone());
+        }
+        config
+    }
+    /// Whether the input file is synced according to include and exclude
+    pub fn is_included(&self, file: &str) -> bool {
+        let included = match &self.include {
+            Some(include) => include.iter().any(|glob| glob.matches(file)),
+            None => true,
+        };
+        included && !self.exclude.iter().flatten().any(|glob| glob.matches(file))
+    }
+    /// Rules of rcloneFilterFile, None if the config has none
+    ///
+    /// The .ffmusicsync.json files are always included, they aren't synced but configure the directories they're in
+    pub fn rclone_filter(&self) -> io::Result<Option<RcloneFilter>> {
+        let Some(filter) = self.rclone_filter_file.as_deref().map(RcloneFilter::load).transpose()? else {
+            return Ok(None);
+        };
+        filter.including(OVERRIDE_FILE_NAME).map(Some).map_err(io::Error::other)
+    }
+    /// Whether silence is removed from the file according to trimSilence
+    pub fn trims_silence(&self, file: &str) -> bool {
+        self.trim_silence.iter().flatten().any(|glob| glob.matches(file))
+    }
+    /// Whether the file is an audiobook according to audiobooks
+    pub fn is_audiobook(&self, file: &str) -> bool {
+        self.audiobooks.as_ref().map(|audiobooks| audiobooks.matches(file)).unwrap_or(false)
+    }
+    /// Returns a copy of the config with the audiobook settings if the file is one,
+    /// then with the settings of the first encode rule matching the file and the silence trimming
+    pub fn with_encode_rule(&self, file: &str) -> Self {
+        let mut config = self.clone();
+        if let Some(audiobooks) = self.audiobooks.as_ref().filter(|audiobooks| audiobooks.matches(file)) {
+            if let Some(extension) = Path::new(file).extension() {
+                let extension = extension.to_string_lossy().to_string();
+                if !config.extensions_to_encode.contains(&extension) {
+                    config.extensions_to_encode.push(extension);
+                }
+            }
+            config.encoded_extension = audiobooks.encoded_extension.clone();
+            config.ffmpeg_params = match audiobooks.speed {
+                Some(speed) => with_speed(&audiobooks.ffmpeg_params, speed),
+                None => audiobooks.ffmpeg_params.clone(),
+            };
+            // Titles like "Book (Unabridged)" or "[Part 1]" are part of the name
+            config.remove_round_brackets = Some(false);
+            config.remove_square_brackets = Some(false);
+            config.remove_curly_brackets = Some(false);
+            config.remove_angle_brackets = Some(false);
+        }
+        let encode_rule = self
+            .encode_rules
+            .iter()
+            .flatten()
+            .find(|encode_rule| encode_rule.matches(file));
+        if let Some(encode_rule) = encode_rule {
+            if let Some(extension) = Path::new(file).extension() {
+                let extension = extension.to_string_lossy().to_string();
+                if !config.extensions_to_encode.contains(&extension) {
+                    config.extensions_to_encode.push(extension);
+                }
+            }
+            config.encoded_extension = encode_rule.encoded_extension.clone();
+            config.ffmpeg_params = match encode_rule.speed {
+                Some(speed) => with_speed(&encode_rule.ffmpeg_params, speed),
+                None => encode_rule.ffmpeg_params.clone(),
+            };
+        }
+        if self.trims_silence(file) {
+            config.ffmpeg_params = with_audio_filter(&config.ffmpeg_params, TRIM_SILENCE_FILTER);
+        }
+        config
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -38,6 +660,40 @@ struct NamedRclonePath {
     path: Option<String>,
 }
 
+fn empty_rclone_path() -> RclonePath {
+    RclonePath::Local(String::new())
+}
+
+/// Whether the config should be fetched over HTTP(S) instead of read from a file
+pub fn is_config_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Reads the config from stdin if the source is "-", fetches it with curl if it's a URL
+/// or reads it from a file otherwise
+pub fn read_config_source(source: &str) -> io::Result<String> {
+    if source == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        Ok(contents)
+    } else if is_config_url(source) {
+        let output = Command::new("curl")
+            .arg("--fail")
+            .arg("--silent")
+            .arg("--show-error")
+            .arg("--location")
+            .arg(source)
+            .output()
+            .map_err(|error| io::Error::new(error.kind(), format!("Failed to run curl: {}", error)))?;
+        if !output.status.success() {
+            return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        fs::read_to_string(source)
+    }
+}
+
 impl From<RclonePathWrapper> for RclonePath {
     fn from(rclone_path_wrapper: RclonePathWrapper) -> Self {
         match rclone_path_wrapper {
@@ -46,7 +702,7 @@ impl From<RclonePathWrapper> for RclonePath {
             }
             RclonePathWrapper::NamedRclonePath(named_rclone_path) => {
                 if let Some(remote) = named_rclone_path.remote {
-                    if remote == "" {
+                    if remote.is_empty() {
                         RclonePath::Local(named_rclone_path.path.unwrap_or(String::from("")))
                     } else {
                         RclonePath::Remote(remote, named_rclone_path.path.unwrap_or(String::from("")))
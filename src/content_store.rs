@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fs_wrapper;
+use crate::fs_wrapper::RclonePath;
+use crate::hashing::{hash_local_file, FileHash};
+use crate::sync::ExecutedActions;
+
+/// Index of the store, mapping the files of the output to the objects holding them
+pub const INDEX_FILE: &str = "index.json";
+/// Playlist of all objects, titled with the names of the files in the output
+pub const PLAYLIST_FILE: &str = "index.m3u8";
+
+/// Content-addressed copy of an output, every distinct file is stored once as `objects/ab/abcdef....ogg`
+///
+/// Files only get a new object when their contents change, so renames in the output don't transfer anything
+/// and identical encodes in several directories share one object
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreIndex {
+    /// Output file name -> object
+    pub files: BTreeMap<String, StoredFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredFile {
+    /// Path of the object relative to the store
+    pub object: String,
+    pub size: u64,
+}
+
+/// What updating the store did
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct StoreUpdate {
+    pub uploaded: usize,
+    pub removed: usize,
+    /// Output files which could not be hashed and are left out of the index
+    pub unreadable: Vec<String>,
+}
+
+impl StoreIndex {
+    /// Reads the index of the store, empty if the store is new
+    pub fn load(store: &RclonePath) -> io::Result<Self> {
+        match fs_wrapper::read_to_string(&in_store(store, INDEX_FILE)) {
+            // rclone prints nothing for missing files
+            Ok(contents) if contents.trim().is_empty() => Ok(Self::default()),
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+    /// Output files whose object has to be found again, as they are new or changed since the last index
+    ///
+    /// Renamed files keep the object of their old name
+    pub fn files_to_hash(&mut self, output_files: &BTreeSet<String>, executed: &ExecutedActions) -> Vec<String> {
+        let previous = std::mem::take(&mut self.files);
+        for (old_file_name, new_file_name) in &executed.renamed {
+            if let Some(stored_file) = previous.get(old_file_name) {
+                self.files.insert(new_file_name.clone(), stored_file.clone());
+            }
+        }
+        for output_file_name in output_files {
+            if let Some(stored_file) = previous.get(output_file_name) {
+                self.files.entry(output_file_name.clone()).or_insert_with(|| stored_file.clone());
+            }
+        }
+        // Processed, remuxed and refreshed files got new contents, even if their name stayed the same
+        for output_file_name in executed.processed.values().chain(executed.content_hashes.keys()) {
+            self.files.remove(output_file_name);
+        }
+        self.files.retain(|output_file_name, _| output_files.contains(output_file_name));
+        output_files
+            .iter()
+            .filter(|output_file_name| !self.files.contains_key(*output_file_name))
+            .cloned()
+            .collect()
+    }
+    pub fn objects(&self) -> BTreeSet<String> {
+        self.files.values().map(|stored_file| stored_file.object.clone()).collect()
+    }
+    /// M3U playlist of every file, so the store can be played without the index being read by a program
+    pub fn playlist(&self) -> String {
+        let mut playlist = String::from("#EXTM3U\n");
+        for (output_file_name, stored_file) in &self.files {
+            playlist.push_str(&format!("#EXTINF:-1,{}\n{}\n", output_file_name, stored_file.object));
+        }
+        playlist
+    }
+}
+
+/// Object name of a file with this hash, like `objects/ab/abcdef.ogg` for `blake3:abcdef`
+pub fn object_name(hash: &str, output_file_name: &str) -> String {
+    let hash = hash.rsplit(':').next().unwrap_or(hash).to_lowercase();
+    let prefix = hash.get(..2).unwrap_or(&hash);
+    match Path::new(output_file_name).extension() {
+        Some(extension) => format!("objects/{}/{}.{}", prefix, hash, extension.to_string_lossy()),
+        None => format!("objects/{}/{}", prefix, hash),
+    }
+}
+
+/// Brings the store up to date with the output after a sync
+///
+/// New objects are uploaded before the index is replaced and objects nothing refers to anymore are removed last,
+/// so an interrupted update leaves a store whose index is still complete
+pub fn update_store(
+    store: &RclonePath,
+    output_directory: &RclonePath,
+    output_files: &BTreeSet<String>,
+    executed: &ExecutedActions,
+) -> io::Result<StoreUpdate> {
+    let mut update = StoreUpdate::default();
+    let mut index = StoreIndex::load(store)?;
+    // Objects are always named by the BLAKE3 hash of their contents, the hashes of remotes can collide (CRC32) or be
+    // missing (crypt). Outputs written this run were hashed before they were uploaded, others are read again
+    for output_file_name in index.files_to_hash(output_files, executed) {
+        let file_hash = match executed.content_hashes.get(&output_file_name) {
+            Some(file_hash) => Ok(file_hash.clone()),
+            None => hash_output(output_directory, &output_file_name),
+        };
+        match file_hash {
+            Ok(file_hash) => {
+                let object = object_name(&file_hash.hash, &output_file_name);
+                index.files.insert(output_file_name, StoredFile { object, size: file_hash.size });
+            }
+            Err(_) => update.unreadable.push(output_file_name),
+        }
+    }
+
+    let stored_objects = object_sizes(store)?;
+    // One file of every object which isn't stored yet, or was damaged since it was
+    let mut uploads = HashMap::new();
+    for (output_file_name, stored_file) in &index.files {
+        if stored_objects.get(&stored_file.object) != Some(&stored_file.size) {
+            uploads.entry(stored_file.object.clone()).or_insert(output_file_name);
+        }
+    }
+    for (object, output_file_name) in uploads {
+        let object = in_store(store, &object);
+        if !object.is_remote() {
+            if let Some(parent) = Path::new(&object.clone().path_string()).parent() {
+                fs_wrapper::create_dir_all(&RclonePath::Local(parent.to_string_lossy().to_string()))?;
+            }
+        }
+        fs_wrapper::copy(&in_store(output_directory, output_file_name), &object)?;
+        update.uploaded += 1;
+    }
+
+    write_to_store(store, INDEX_FILE, &serde_json::to_string_pretty(&index)?)?;
+    write_to_store(store, PLAYLIST_FILE, &index.playlist())?;
+
+    let referenced = index.objects();
+    let unreferenced = stored_objects
+        .into_keys()
+        .filter(|object| !referenced.contains(object))
+        .collect::<Vec<String>>();
+    fs_wrapper::remove_files(store, &unreferenced)?;
+    update.removed = unreferenced.len();
+    Ok(update)
+}
+
+/// Objects of the index which are missing from the store or have another size, with what's wrong with them
+///
+/// With a local output directory the outputs are hashed as well, to find the ones whose object is stale because they
+/// were changed since the store was updated
+pub fn verify_store(store: &RclonePath, output_directory: Option<&RclonePath>) -> io::Result<Vec<(String, String)>> {
+    let index = StoreIndex::load(store)?;
+    let sizes = object_sizes(store)?;
+    let output_directory = output_directory.filter(|output_directory| !output_directory.is_remote());
+    Ok(index
+        .files
+        .into_iter()
+        .filter_map(|(output_file_name, stored_file)| match sizes.get(&stored_file.object) {
+            None => Some((output_file_name, format!("{} is missing", stored_file.object))),
+            Some(size) if *size != stored_file.size => Some((
+                output_file_name,
+                format!("{} has {} bytes instead of {}", stored_file.object, size, stored_file.size),
+            )),
+            Some(_) => {
+                let output_directory = output_directory?;
+                let file_hash = hash_output(output_directory, &output_file_name).ok()?;
+                (object_name(&file_hash.hash, &output_file_name) != stored_file.object)
+                    .then(|| (output_file_name, format!("{} is older than the output", stored_file.object)))
+            }
+        })
+        .collect())
+}
+
+/// BLAKE3 hash of an output, remote outputs are downloaded to a temporary file to hash them
+fn hash_output(output_directory: &RclonePath, output_file_name: &str) -> io::Result<FileHash> {
+    let output_file = output_directory.with_path(format!("{}/{}", output_directory.clone().path_string(), output_file_name));
+    if !output_file.is_remote() {
+        return hash_local_file(output_file.path_string());
+    }
+    let extension = Path::new(output_file_name).extension().unwrap_or_default().to_string_lossy().to_string();
+    let temp_file = std::env::temp_dir().join(format!("ffmusicsync-{}-object.{}", std::process::id(), extension));
+    let downloaded = fs_wrapper::copy(&output_file, &RclonePath::Local(temp_file.to_string_lossy().to_string()))
+        .and_then(|_| hash_local_file(&temp_file));
+    let _ = std::fs::remove_file(&temp_file);
+    downloaded
+}
+
+/// Sizes of the objects in the store, by their path relative to it
+fn object_sizes(store: &RclonePath) -> io::Result<HashMap<String, u64>> {
+    Ok(
+        fs_wrapper::list_file_sizes(&in_store(store, "objects"))?
+            .into_iter()
+            .map(|(object, size)| (format!("objects/{}", object.replace('\\', "/")), size))
+            .collect()
+    )
+}
+
+fn in_store(store: &RclonePath, file_name: &str) -> RclonePath {
+    store.with_path(format!("{}/{}", store.clone().path_string(), file_name))
+}
+
+fn write_to_store(store: &RclonePath, file_name: &str, contents: &str) -> io::Result<()> {
+    let destination = in_store(store, file_name);
+    if destination.is_remote() {
+        let temp_file = std::env::temp_dir().join(format!("ffmusicsync-{}-{}", std::process::id(), file_name));
+        std::fs::write(&temp_file, contents)?;
+        let copied = fs_wrapper::copy(&RclonePath::Local(temp_file.to_string_lossy().to_string()), &destination);
+        std::fs::remove_file(&temp_file)?;
+        copied
+    } else {
+        fs_wrapper::create_dir_all(store)?;
+        std::fs::write(destination.path_string(), contents)
+    }
+}
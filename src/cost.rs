@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::config::RemotePricing;
+
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+/// Traffic and operations against each remote, by remote name
+#[derive(Default, Debug)]
+pub struct CostEstimate {
+    pub egress: HashMap<String, u64>,
+    pub ingress: HashMap<String, u64>,
+    pub operations: HashMap<String, u64>,
+}
+
+impl CostEstimate {
+    pub fn add_egress(&mut self, remote: &str, bytes: u64) {
+        *self.egress.entry(remote.to_string()).or_default() += bytes;
+    }
+    pub fn add_ingress(&mut self, remote: &str, bytes: u64) {
+        *self.ingress.entry(remote.to_string()).or_default() += bytes;
+    }
+    pub fn add_operations(&mut self, remote: &str, operations: u64) {
+        *self.operations.entry(remote.to_string()).or_default() += operations;
+    }
+    /// Returns the total cost and the cost of every remote with pricing, sorted by remote name
+    pub fn cost(&self, pricing: &HashMap<String, RemotePricing>) -> (f64, Vec<(String, f64)>) {
+        let mut remotes = pricing
+            .iter()
+            .map(|(remote, remote_pricing)| {
+                let egress = *self.egress.get(remote).unwrap_or(&0) as f64 / BYTES_PER_GB;
+                let ingress = *self.ingress.get(remote).unwrap_or(&0) as f64 / BYTES_PER_GB;
+                let operations = *self.operations.get(remote).unwrap_or(&0) as f64 / 1000.0;
+                (
+                    remote.clone(),
+                    egress * remote_pricing.egress_per_gb.unwrap_or(0.0)
+                        + ingress * remote_pricing.ingress_per_gb.unwrap_or(0.0)
+                        + operations * remote_pricing.per_thousand_operations.unwrap_or(0.0),
+                )
+            })
+            .collect::<Vec<(String, f64)>>();
+        remotes.sort_by(|a, b| a.0.cmp(&b.0));
+        (remotes.iter().map(|(_, cost)| cost).sum(), remotes)
+    }
+}
+
+pub fn format_cost(total: f64, remotes: &[(String, f64)]) -> String {
+    let remotes = remotes
+        .iter()
+        .map(|(remote, cost)| format!("{}: ${:.2}", remote, cost))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!("${:.2} ({})", total, remotes)
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::OutputDevice;
+
+/// Finds where the device is mounted, returns None if it's not connected
+pub fn find_mount_point(device: &OutputDevice) -> io::Result<Option<String>> {
+    let mount_point = if cfg!(windows) {
+        // Passed in an environment variable, so quotes in the label aren't read as PowerShell
+        let (filter, value) = if let Some(uuid) = &device.uuid {
+            ("$_.DeviceID.Contains($env:FFMUSICSYNC_DEVICE)", uuid.clone())
+        } else {
+            ("$_.Label -eq $env:FFMUSICSYNC_DEVICE", device.label.clone().unwrap_or_default())
+        };
+        let output = Command::new("powershell")
+            .arg("-NoProfile")
+            .arg("-Command")
+            .arg(format!("(Get-CimInstance Win32_Volume | Where-Object {{ {} }}).DriveLetter", filter))
+            .env("FFMUSICSYNC_DEVICE", value)
+            .output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .map(|drive| format!("{}\\", drive.trim()))
+    } else if cfg!(target_os = "macos") {
+        let identifier = device.uuid.clone().or_else(|| device.label.clone()).unwrap_or_default();
+        let output = Command::new("diskutil").arg("info").arg(identifier).output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Mount Point:").map(|mount_point| mount_point.trim().to_string()))
+    } else {
+        let source = if let Some(uuid) = &device.uuid {
+            format!("UUID={}", uuid)
+        } else {
+            format!("LABEL={}", device.label.clone().unwrap_or_default())
+        };
+        let output = Command::new("findmnt")
+            .arg("-n")
+            .arg("-o")
+            .arg("TARGET")
+            .arg("--source")
+            .arg(source)
+            .output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .map(|mount_point| mount_point.to_string())
+    };
+    Ok(mount_point.filter(|mount_point| !mount_point.trim().is_empty() && mount_point.trim() != "\\"))
+}
+
+/// Flushes the filesystem buffers of the filesystem containing the path
+pub fn flush<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if cfg!(windows) {
+        // Passed in an environment variable like the label, nothing from the path becomes PowerShell
+        run(
+            Command::new("powershell")
+                .arg("-NoProfile")
+                .arg("-Command")
+                .arg("Write-VolumeCache -DriveLetter $env:FFMUSICSYNC_DRIVE")
+                .env("FFMUSICSYNC_DRIVE", drive_letter(path)?.to_string())
+        )
+    } else if cfg!(target_os = "linux") {
+        run(Command::new("sync").arg("-f").arg(path))
+    } else {
+        run(&mut Command::new("sync"))
+    }
+}
+
+/// Unmounts and ejects the removable device containing the path
+pub fn eject<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if cfg!(windows) {
+        run(
+            Command::new("powershell")
+                .arg("-NoProfile")
+                .arg("-Command")
+                .arg(
+                    "(New-Object -ComObject Shell.Application).Namespace(17)\
+                    .ParseName($env:FFMUSICSYNC_DRIVE).InvokeVerb('Eject')"
+                )
+                .env("FFMUSICSYNC_DRIVE", format!("{}:", drive_letter(path)?))
+        )
+    } else if cfg!(target_os = "macos") {
+        run(Command::new("diskutil").arg("eject").arg(mount_point(path)?))
+    } else {
+        let output = Command::new("findmnt")
+            .arg("-n")
+            .arg("-o")
+            .arg("SOURCE")
+            .arg("--target")
+            .arg(path)
+            .output()?;
+        let device = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if device.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Could not find the device of the output directory"));
+        }
+        run(Command::new("udisksctl").arg("unmount").arg("-b").arg(&device))?;
+        run(Command::new("udisksctl").arg("power-off").arg("-b").arg(&device))
+    }
+}
+
+fn run(command: &mut Command) -> io::Result<()> {
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{:?} failed with {}", command, status)))
+    }
+}
+
+fn drive_letter(path: &Path) -> io::Result<char> {
+    path.canonicalize()?
+        .to_string_lossy()
+        .trim_start_matches(r"\\?\")
+        .chars()
+        .next()
+        .filter(|letter| letter.is_ascii_alphabetic())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The output directory is not on a drive"))
+}
+
+fn mount_point(path: &Path) -> io::Result<String> {
+    let output = Command::new("df").arg("-P").arg(path).output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .map(|line| line.split_whitespace().skip(5).collect::<Vec<&str>>().join(" "))
+        .filter(|mount_point| !mount_point.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find the mount point of the output directory"))
+}
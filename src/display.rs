@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::fs_wrapper::RclonePath;
+use crate::redact;
+
+static ABSOLUTE: AtomicBool = AtomicBool::new(false);
+
+/// Makes the console lines show files with their input or output directory, like `Phone:Music/Song.ogg` instead of
+/// `Song.ogg`
+pub fn enable_absolute() {
+    ABSOLUTE.store(true, Ordering::SeqCst);
+}
+
+pub fn is_absolute() -> bool {
+    ABSOLUTE.load(Ordering::SeqCst)
+}
+
+/// A file relative to the directory, or in it with --absolute-paths, redacted if --redact is given
+pub fn file(directory: &RclonePath, file_name: &str) -> String {
+    if is_absolute() {
+        redact::path(&qualified(directory, file_name))
+    } else {
+        redact::path(file_name)
+    }
+}
+
+/// A file going from one directory to another, like a source encoded or copied to the output
+///
+/// Shown as `Song.flac -> Song.ogg (Phone:Music)` with the relative names and the target directory, with
+/// --absolute-paths both files are shown with their directories instead
+pub fn transfer(from_directory: &RclonePath, from: &str, to_directory: &RclonePath, to: &str) -> String {
+    if is_absolute() {
+        format!("{} -> {}", file(from_directory, from), file(to_directory, to))
+    } else {
+        format!("{} -> {} ({})", file(from_directory, from), file(to_directory, to), redact::path(&to_directory.to_string()))
+    }
+}
+
+/// The file name joined to the directory, keeping the `remote:` of remote directories
+pub fn qualified(directory: &RclonePath, file_name: &str) -> String {
+    let directory = directory.to_string();
+    match directory.trim_end_matches('/') {
+        "" if directory.starts_with('/') => format!("/{}", file_name),
+        "" => file_name.to_string(),
+        trimmed if trimmed.ends_with(':') => format!("{}{}", trimmed, file_name),
+        trimmed => format!("{}/{}", trimmed, file_name),
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::BTreeSet;
+use std::process::Command;
+
+use crate::capabilities::audio_codec;
+use crate::config::{Config, Throttle};
+use crate::fs_wrapper::RclonePath;
+use crate::health::Severity;
+use crate::lint::lint_config;
+use crate::programs;
+use crate::theme;
+use crate::throttle::{on_battery, read_cpu_temperature};
+
+/// Result of one check of the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoctorCheck {
+    pub name: String,
+    /// What was found, like the version of a program
+    pub detail: String,
+    /// None if everything is fine
+    pub problem: Option<DoctorProblem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoctorProblem {
+    pub severity: Severity,
+    /// What to do to fix it
+    pub fix: String,
+}
+
+impl DoctorCheck {
+    fn ok(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            detail: detail.into(),
+            problem: None,
+        }
+    }
+    fn problem(name: impl Into<String>, detail: impl Into<String>, severity: Severity, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            detail: detail.into(),
+            problem: Some(DoctorProblem { severity, fix: fix.into() }),
+        }
+    }
+}
+
+/// Checks ffmpeg, rclone and the config, `config` is the parsed config or why it couldn't be read
+pub fn run_checks(config: Result<&Config, String>) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    let config = match config {
+        Ok(config) => {
+            checks.push(DoctorCheck::ok("Config", "parsed"));
+            config
+        }
+        Err(error) => {
+            checks.push(DoctorCheck::problem(
+                "Config",
+                error,
+                Severity::High,
+                "Fix the config file or create one with ffmusicsync init",
+            ));
+            // ffmpeg from PATH and rclone are still checked, just not for what the config needs
+            checks.extend(check_ffmpeg("ffmpeg", &BTreeSet::new(), true));
+            checks.extend(check_rclone(&BTreeSet::new(), false));
+            return checks;
+        }
+    };
+    for issue in lint_config(config) {
+        checks.push(DoctorCheck::problem("Config", issue.message, issue.severity, issue.explanation));
+    }
+    match config.clone().reconcile_params() {
+        Ok(corrections) => {
+            for (setting, correction) in corrections {
+                checks.push(DoctorCheck::problem(
+                    "Config",
+                    format!("{} {}", setting, correction),
+                    Severity::Low,
+                    "Every sync corrects this, fix it in the config to silence the warning",
+                ));
+            }
+        }
+        Err(error) => checks.push(DoctorCheck::problem(
+            "Config",
+            error,
+            Severity::High,
+            "Change the encoder or the encoded extension, syncs refuse to start like this",
+        )),
+    }
+    if let Some(throttle) = &config.throttle {
+        checks.extend(check_throttle(throttle));
+    }
+    let encoders = required_encoders(config);
+    checks.extend(check_ffmpeg(&programs::ffmpeg_path(config), &encoders, encodes_anything(config)));
+    if all_outputs(config).iter().any(|config| config.remux_when_possible == Some(true)) {
+        checks.push(check_ffprobe(&programs::ffprobe_path(config)));
+    }
+    let remotes = required_remotes(config);
+    checks.extend(check_rclone(&remotes, !remotes.is_empty()));
+    checks
+}
+
+/// Prints all checks, returns whether any of them has a high severity problem
+pub fn print_checks(checks: &[DoctorCheck]) -> bool {
+    for check in checks {
+        match &check.problem {
+            None => println!("{} {}", theme::success().apply_to(format!("{}:", check.name)), check.detail),
+            Some(problem) => {
+                println!(
+                    "{} {} ({:?})",
+                    theme::severity(problem.severity).apply_to(format!("{}:", check.name)),
+                    check.detail,
+                    problem.severity
+                );
+                println!("  {}", problem.fix);
+            }
+        }
+    }
+    checks
+        .iter()
+        .any(|check| check.problem.as_ref().is_some_and(|problem| problem.severity == Severity::High))
+}
+
+fn encodes_anything(config: &Config) -> bool {
+    let configs = all_outputs(config);
+    configs.iter().any(|config| {
+        !config.extensions_to_encode.is_empty()
+            || config.encode_rules.as_ref().is_some_and(|encode_rules| !encode_rules.is_empty())
+            || config.audiobooks.is_some()
+    })
+}
+
+/// The config of every output, or just the config if outputs aren't used
+fn all_outputs(config: &Config) -> Vec<Config> {
+    match &config.outputs {
+        Some(outputs) => outputs.iter().map(|output| config.for_output(output)).collect(),
+        None => vec![config.clone()],
+    }
+}
+
+/// ffmpeg encoders set with -c:a in any ffmpeg params of the config
+pub fn required_encoders(config: &Config) -> BTreeSet<String> {
+    let mut encoders = BTreeSet::new();
+    for config in all_outputs(config) {
+        let mut params = vec![config.ffmpeg_params.clone()];
+        params.extend(config.encode_rules.iter().flatten().map(|encode_rule| encode_rule.ffmpeg_params.clone()));
+        params.extend(config.audiobooks.iter().map(|audiobooks| audiobooks.ffmpeg_params.clone()));
+        encoders.extend(params.iter().filter_map(|params| audio_codec(params)).map(String::from));
+    }
+    encoders
+}
+
+/// rclone remotes used by any directory of the config
+pub fn required_remotes(config: &Config) -> BTreeSet<String> {
+    let mut directories = vec![config.input_directory.clone()];
+    directories.extend(config.trash_directory.clone());
+    directories.extend(config.mirrors.iter().flatten().cloned());
+    for config in all_outputs(config) {
+        directories.push(config.output_directory);
+    }
+    directories
+        .iter()
+        .filter_map(RclonePath::remote)
+        .map(String::from)
+        .collect()
+}
+
+fn check_ffmpeg(ffmpeg_path: &str, encoders: &BTreeSet<String>, required: bool) -> Vec<DoctorCheck> {
+    let severity = if required { Severity::High } else { Severity::Low };
+    let version = match Command::new(ffmpeg_path).arg("-version").output() {
+        Ok(output) if output.status.success() => parse_version(&String::from_utf8_lossy(&output.stdout)),
+        Ok(output) => {
+            return vec![DoctorCheck::problem(
+                "ffmpeg",
+                format!("ffmpeg -version failed with {}", output.status),
+                severity,
+                "Reinstall ffmpeg, the installed one doesn't run",
+            )];
+        }
+        Err(error) => {
+            return vec![DoctorCheck::problem(
+                "ffmpeg",
+                format!("not found ({})", error),
+                severity,
+                "Install ffmpeg and make sure it's in PATH or set ffmpegPath, it's needed to encode files",
+            )];
+        }
+    };
+    let mut checks = vec![DoctorCheck::ok("ffmpeg", format!("version {}", version))];
+    if encoders.is_empty() {
+        return checks;
+    }
+    let available = match Command::new(ffmpeg_path).args(["-hide_banner", "-encoders"]).output() {
+        Ok(output) => parse_encoders(&String::from_utf8_lossy(&output.stdout)),
+        Err(error) => {
+            checks.push(DoctorCheck::problem(
+                "ffmpeg encoders",
+                format!("couldn't be listed ({})", error),
+                Severity::Medium,
+                "Run ffmpeg -encoders to check the encoders by hand",
+            ));
+            return checks;
+        }
+    };
+    for encoder in encoders {
+        if available.contains(encoder) {
+            checks.push(DoctorCheck::ok(format!("Encoder {}", encoder), "available"));
+        } else {
+            checks.push(DoctorCheck::problem(
+                format!("Encoder {}", encoder),
+                "not in ffmpeg -encoders",
+                Severity::High,
+                "Install an ffmpeg build with this encoder, or change -c:a in ffmpegParams to one listed by ffmpeg -encoders",
+            ));
+        }
+    }
+    checks
+}
+
+fn check_ffprobe(ffprobe_path: &str) -> DoctorCheck {
+    match Command::new(ffprobe_path).arg("-version").output() {
+        Ok(output) if output.status.success() => {
+            DoctorCheck::ok("ffprobe", format!("version {}", parse_version(&String::from_utf8_lossy(&output.stdout))))
+        }
+        Ok(output) => DoctorCheck::problem(
+            "ffprobe",
+            format!("ffprobe -version failed with {}", output.status),
+            Severity::Medium,
+            "Reinstall ffmpeg, remuxWhenPossible encodes every source without a working ffprobe",
+        ),
+        Err(error) => DoctorCheck::problem(
+            "ffprobe",
+            format!("not found ({})", error),
+            Severity::Medium,
+            "Install ffprobe (it comes with ffmpeg), remuxWhenPossible encodes every source without it",
+        ),
+    }
+}
+
+fn check_throttle(throttle: &Throttle) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    if throttle.max_temperature.is_some() {
+        checks.push(match read_cpu_temperature() {
+            Some(temperature) => DoctorCheck::ok("CPU temperature", format!("{:.0} °C", temperature)),
+            None => DoctorCheck::problem(
+                "CPU temperature",
+                "couldn't be read from /sys/class/thermal",
+                Severity::Medium,
+                "Encodes are never paused for the temperature here, remove throttle.maxTemperature",
+            ),
+        });
+    }
+    if throttle.pause_on_battery {
+        checks.push(match on_battery() {
+            Some(true) => DoctorCheck::ok("Power supply", "on battery"),
+            Some(false) => DoctorCheck::ok("Power supply", "plugged in"),
+            None => DoctorCheck::problem(
+                "Power supply",
+                "no mains power supply in /sys/class/power_supply",
+                Severity::Low,
+                "Encodes are never paused for the battery here, remove throttle.pauseOnBattery",
+            ),
+        });
+    }
+    checks
+}
+
+fn check_rclone(remotes: &BTreeSet<String>, required: bool) -> Vec<DoctorCheck> {
+    let version = match programs::rclone().arg("version").output() {
+        Ok(output) if output.status.success() => parse_version(&String::from_utf8_lossy(&output.stdout)),
+        Ok(output) => {
+            return vec![DoctorCheck::problem(
+                "rclone",
+                format!("rclone version failed with {}", output.status),
+                if required { Severity::High } else { Severity::Low },
+                "Reinstall rclone, the installed one doesn't run",
+            )];
+        }
+        Err(_) if !required => return vec![DoctorCheck::ok("rclone", "not found, only needed for remotes")],
+        Err(error) => {
+            return vec![DoctorCheck::problem(
+                "rclone",
+                format!("not found ({})", error),
+                Severity::High,
+                "Install rclone and make sure it's in PATH or set rclonePath, it's needed for the remotes in the config",
+            )];
+        }
+    };
+    let mut checks = vec![DoctorCheck::ok("rclone", format!("version {}", version))];
+    if remotes.is_empty() {
+        return checks;
+    }
+    let configured = match programs::rclone().arg("listremotes").output() {
+        Ok(output) if output.status.success() => parse_remotes(&String::from_utf8_lossy(&output.stdout)),
+        Ok(output) => {
+            checks.push(DoctorCheck::problem(
+                "rclone remotes",
+                format!("rclone listremotes failed: {}", String::from_utf8_lossy(&output.stderr).trim()),
+                Severity::High,
+                "Check the rclone config with rclone config file",
+            ));
+            return checks;
+        }
+        Err(error) => {
+            checks.push(DoctorCheck::problem(
+                "rclone remotes",
+                format!("couldn't be listed ({})", error),
+                Severity::High,
+                "Check the rclone config with rclone config file",
+            ));
+            return checks;
+        }
+    };
+    for remote in remotes {
+        if configured.contains(remote) {
+            checks.push(DoctorCheck::ok(format!("Remote {}", remote), "configured"));
+        } else {
+            checks.push(DoctorCheck::problem(
+                format!("Remote {}", remote),
+                "not in rclone listremotes",
+                Severity::High,
+                format!("Create it with rclone config, or fix the spelling of {}: in the config", remote),
+            ));
+        }
+    }
+    checks
+}
+
+/// Version from the first line of ffmpeg -version or rclone version, like "ffmpeg version 6.1.1 Copyright..."
+/// or "rclone v1.65.0"
+pub fn parse_version(output: &str) -> String {
+    let words = output.lines().next().unwrap_or_default().split_whitespace().collect::<Vec<&str>>();
+    let index = match words.iter().position(|word| *word == "version") {
+        Some(index) => index + 1,
+        None => 1,
+    };
+    words.get(index).unwrap_or(&"unknown").to_string()
+}
+
+/// Encoder names from ffmpeg -encoders, listed after the legend ending with a dashed line
+pub fn parse_encoders(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(String::from)
+        .collect()
+}
+
+/// Remote names from rclone listremotes, which prints them like "Phone:"
+pub fn parse_remotes(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .map(|line| line.trim().trim_end_matches(':'))
+        .filter(|remote| !remote.is_empty())
+        .map(String::from)
+        .collect()
+}
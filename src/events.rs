@@ -0,0 +1,171 @@
+use std::io;
+#[cfg(unix)]
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::config::MqttConfig;
+use crate::interrupt::INTERRUPTED_EXIT_CODE;
+use crate::mqtt::MqttPublisher;
+
+/// Events sent to subscribers of the events socket, one JSON object per line
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Event<'a> {
+    #[serde(rename_all = "camelCase")]
+    Plan {
+        to_process: usize,
+        to_rename: usize,
+        to_delete: usize,
+    },
+    #[serde(rename_all = "camelCase")]
+    Progress {
+        action: &'a str,
+        file: &'a str,
+        current: usize,
+        total: usize,
+    },
+    #[serde(rename_all = "camelCase")]
+    Completed {
+        warnings: usize,
+        failed: usize,
+        exit_code: Option<i32>,
+        error: Option<&'a str>,
+    },
+}
+
+/// Broadcasts events to every client connected to a Unix domain socket
+/// and publishes the sync state to MQTT
+#[derive(Default)]
+pub struct EventSink {
+    #[cfg(unix)]
+    clients: Option<Arc<Mutex<Vec<UnixStream>>>>,
+    path: Option<PathBuf>,
+    mqtt: Mutex<Option<MqttPublisher>>,
+    mqtt_topic: String,
+}
+
+impl EventSink {
+    /// An event sink that drops all events until a socket is bound or MQTT is connected
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Listens on a Unix domain socket, accepting clients in the background
+    #[cfg(unix)]
+    pub fn bind_socket(&mut self, path: PathBuf) -> io::Result<()> {
+        // A socket left over from a crashed run would make bind fail
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted_clients = clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accepted_clients.lock().unwrap().push(stream);
+            }
+        });
+        self.clients = Some(clients);
+        self.path = Some(path);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    pub fn bind_socket(&mut self, _path: PathBuf) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "The events socket is only supported on Unix"))
+    }
+    /// Connects to the MQTT broker and publishes Home Assistant discovery payloads if enabled
+    pub fn connect_mqtt(&mut self, config: &MqttConfig) -> io::Result<()> {
+        let topic = config.topic.clone().unwrap_or(String::from("ffmusicsync"));
+        let mut publisher = MqttPublisher::connect(config, &format!("{}/state", topic))?;
+        if config.discovery != Some(false) {
+            let discovery_prefix = config.discovery_prefix.as_deref().unwrap_or("homeassistant");
+            for (sensor, name, unit) in [
+                ("state", "ffmusicsync state", None),
+                ("progress", "ffmusicsync progress", Some("%")),
+                ("last_result", "ffmusicsync last result", None),
+            ] {
+                let mut payload = serde_json::json!({
+                    "name": name,
+                    "unique_id": format!("{}_{}", topic.replace('/', "_"), sensor),
+                    "state_topic": format!("{}/{}", topic, sensor),
+                });
+                if let Some(unit) = unit {
+                    payload["unit_of_measurement"] = serde_json::json!(unit);
+                }
+                publisher.publish(
+                    &format!("{}/sensor/{}/{}/config", discovery_prefix, topic.replace('/', "_"), sensor),
+                    &payload.to_string(),
+                    true,
+                )?;
+            }
+        }
+        self.mqtt = Mutex::new(Some(publisher));
+        self.mqtt_topic = topic;
+        Ok(())
+    }
+    pub fn emit(&self, event: Event) {
+        #[cfg(unix)]
+        if let Some(clients) = &self.clients {
+            let mut line = serde_json::to_string(&event).unwrap();
+            line.push('\n');
+            // Clients that disconnected are dropped
+            clients.lock().unwrap().retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+        }
+        let mut mqtt = self.mqtt.lock().unwrap();
+        if let Some(publisher) = mqtt.as_mut() {
+            let messages = match &event {
+                Event::Plan { .. } => vec![("state", String::from("running")), ("progress", String::from("0"))],
+                Event::Progress { current, total, .. } => {
+                    vec![("progress", format!("{}", current * 100 / (*total).max(1)))]
+                }
+                Event::Completed {
+                    warnings,
+                    failed,
+                    exit_code,
+                    error,
+                } => {
+                    let last_result = match (error, exit_code) {
+                        (Some(error), _) => format!("failed: {}", error),
+                        (None, Some(INTERRUPTED_EXIT_CODE)) => String::from("interrupted"),
+                        (None, Some(exit_code)) => format!("failed with exit code {}", exit_code),
+                        (None, None) if *failed > 0 => format!("failed for {} files", failed),
+                        (None, None) => format!("ok with {} warnings", warnings),
+                    };
+                    vec![
+                        ("state", String::from("idle")),
+                        ("progress", String::from("100")),
+                        ("last_result", last_result),
+                    ]
+                }
+            };
+            // The sync goes on if the broker goes away, without waiting for it on every event
+            let published = messages.into_iter().try_for_each(|(sensor, payload)| {
+                publisher.publish(&format!("{}/{}", self.mqtt_topic, sensor), &payload, true)
+            });
+            if let Err(error) = published {
+                eprintln!("Stopped publishing the sync state to the MQTT broker: {}", error);
+                *mqtt = None;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = event;
+    }
+}
+
+impl Drop for EventSink {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+        // The broker only publishes the last will if the connection is lost, not when it's closed
+        if let Some(publisher) = self.mqtt.get_mut().unwrap() {
+            let _ = publisher.publish(&format!("{}/state", self.mqtt_topic), "offline", true);
+        }
+    }
+}
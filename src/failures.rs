@@ -0,0 +1,32 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::save_json;
+
+/// Files which failed to process in the last run, so they can be retried without scanning the library
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedFiles {
+    /// Per-directory config files present in the input directory during the run
+    pub override_files: BTreeSet<String>,
+    /// Input file name -> output file name
+    pub files: BTreeMap<String, String>,
+}
+
+impl FailedFiles {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save_json(path, self, true)
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Formats a size in bytes with the largest binary unit it reaches, like 1.50 GiB
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+/// Formats a count with thousands separators, like 12,345
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// Parses a duration like 2h, 90m, 1h30m or 45s, a number without a unit is in seconds
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {}, use a number with h, m or s like 2h or 1h30m", duration);
+    let duration = duration.trim();
+    if let Ok(seconds) = duration.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let mut seconds = 0;
+    let mut number = String::new();
+    for character in duration.chars() {
+        match character {
+            '0'..='9' => number.push(character),
+            'h' | 'm' | 's' if !number.is_empty() => {
+                let multiplier = match character {
+                    'h' => 3600,
+                    'm' => 60,
+                    _ => 1,
+                };
+                seconds += number.parse::<u64>().map_err(|_| invalid())? * multiplier;
+                number.clear();
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    if !number.is_empty() || duration.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Formats a duration as h:mm:ss, like 1:02:03
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
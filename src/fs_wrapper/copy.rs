@@ -1,23 +1,95 @@
 use std::{fs, io};
-use std::process::Command;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Stdio;
 
-use super::RclonePath;
+use crate::interrupt::PartialFile;
+use crate::{programs, rclone_rc};
+
+use super::files_match::local_files_match;
+use super::io_stats::{record_downloaded, record_uploaded, record_written};
+use super::partial_path::partial_path;
+use super::server_side::{is_server_side_unsupported, server_side_across_configs};
+use super::{check_output, RclonePath};
 
 pub fn copy(from: &RclonePath, to: &RclonePath) -> io::Result<()> {
     let use_rclone = from.is_remote() || to.is_remote();
-
-    let from = from.clone().to_string();
-    let to = to.clone().to_string();
+    let is_download = from.is_remote() && !to.is_remote();
+    let is_upload = !from.is_remote() && to.is_remote();
 
     if use_rclone {
-        Command::new("rclone")
-            .arg("copyto")
-            .arg(from)
-            .arg(to)
-            .status()?;
+        let across_configs = server_side_across_configs(from, to);
+        match copy_with_rclone(from, to, across_configs) {
+            // Providers don't allow copies between every two accounts, rclone fails then instead of transferring
+            // the file by itself. Other failures are real ones, copying again would only hide them
+            Err(error) if across_configs && is_server_side_unsupported(&error) => copy_with_rclone(from, to, false)?,
+            copied => copied?,
+        }
+        if is_download {
+            record_downloaded(to.to_string());
+        } else if is_upload {
+            record_uploaded(from.to_string());
+        }
     } else {
-        fs::copy(from, to)?;
+        let to = to.to_string();
+        copy_local(&from.to_string(), &to)?;
+        record_written(&to);
     }
     Ok(())
 }
 
+/// Copies to a partial name which is renamed once the copy is complete, so a crash never leaves a truncated file
+/// behind under the real name. rclone does the same for its downloads
+fn copy_local(from: &str, to: &str) -> io::Result<()> {
+    let partial = partial_path(Path::new(to));
+    let _partial_file = PartialFile::track(&partial);
+    if let Err(error) = fs::copy(from, &partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(error);
+    }
+    fs::rename(&partial, to)
+}
+
+fn copy_with_rclone(from: &RclonePath, to: &RclonePath, across_configs: bool) -> io::Result<()> {
+    if rclone_rc::is_running() {
+        let parameters = rclone_rc::transfer_parameters(from, to);
+        let parameters = if across_configs { rclone_rc::across_configs(parameters) } else { parameters };
+        return rclone_rc::call("operations/copyfile", &parameters).map(|_| ());
+    }
+    let mut command = programs::rclone();
+    command.arg("copyto");
+    if across_configs {
+        command.arg("--server-side-across-configs");
+    }
+    // The error of rclone tells whether the provider refused the server-side copy
+    check_output(&command.arg(from.to_string()).arg(to.to_string()).stderr(Stdio::piped()).output()?)
+}
+
+/// Same as [copy], but local copies are read back and compared to the source afterwards,
+/// retrying up to `retries` times if they don't match
+///
+/// rclone verifies transfers by itself, so copies involving remotes are not checked again
+pub fn copy_verified(from: &RclonePath, to: &RclonePath, retries: u32) -> io::Result<()> {
+    if from.is_remote() || to.is_remote() {
+        return copy(from, to);
+    }
+
+    let from = from.clone().to_string();
+    let to = to.clone().to_string();
+
+    for _ in 0..=retries {
+        copy_local(&from, &to)?;
+        record_written(&to);
+        File::open(&to)?.sync_all()?;
+        if local_files_match(&from, &to)? {
+            return Ok(());
+        }
+    }
+    Err(
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} does not match {} after {} attempts", to, from, retries + 1),
+        )
+    )
+}
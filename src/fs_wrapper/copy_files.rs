@@ -0,0 +1,73 @@
+use std::{fs, io};
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::{programs, rclone_rc};
+
+use super::io_stats::{record_downloaded, record_uploaded, record_written};
+use super::server_side::{is_server_side_unsupported, server_side_across_configs};
+use super::{check_output, transfer_parameters, write_files_from, RclonePath};
+
+/// Copies files between two directories keeping their relative paths, with a single rclone run
+/// if any of the directories is remote
+pub fn copy_files(from: &RclonePath, to: &RclonePath, files: &[String]) -> io::Result<()> {
+    let use_rclone = from.is_remote() || to.is_remote();
+
+    if use_rclone {
+        if !from.is_remote() {
+            for file in files {
+                record_uploaded(Path::new(&from.clone().path_string()).join(file));
+            }
+        }
+        let across_configs = server_side_across_configs(from, to);
+        match copy_with_rclone(from, to, files, across_configs) {
+            // Files copied before the provider refused are skipped by the second run, other failures are real ones
+            Err(error) if across_configs && is_server_side_unsupported(&error) => {
+                copy_with_rclone(from, to, files, false)?
+            }
+            copied => copied?,
+        }
+        if !to.is_remote() {
+            for file in files {
+                record_downloaded(Path::new(&to.clone().path_string()).join(file));
+            }
+        }
+    } else {
+        for file in files {
+            let destination = Path::new(&to.clone().path_string()).join(file);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(Path::new(&from.clone().path_string()).join(file), &destination)?;
+            record_written(&destination);
+        }
+    }
+    Ok(())
+}
+
+fn copy_with_rclone(from: &RclonePath, to: &RclonePath, files: &[String], across_configs: bool) -> io::Result<()> {
+    if rclone_rc::is_running() {
+        for file in files {
+            let parameters = transfer_parameters(from, to, file);
+            let parameters = if across_configs { rclone_rc::across_configs(parameters) } else { parameters };
+            rclone_rc::call("operations/copyfile", &parameters)?;
+        }
+        return Ok(());
+    }
+    let files_from = write_files_from("copy", files)?;
+    let mut command = programs::rclone();
+    command.arg("copy");
+    if across_configs {
+        command.arg("--server-side-across-configs");
+    }
+    // The error of rclone tells whether the provider refused the server-side copy
+    let output = command
+        .arg("--files-from-raw")
+        .arg(&files_from)
+        .arg(from.to_string())
+        .arg(to.to_string())
+        .stderr(Stdio::piped())
+        .output();
+    fs::remove_file(&files_from)?;
+    check_output(&output?)
+}
@@ -1,15 +1,20 @@
 use std::{fs, io};
-use std::process::Command;
+
+use crate::{programs, rclone_rc};
 
 use super::RclonePath;
 
 pub fn create_dir_all(path: &RclonePath) -> io::Result<()> {
     let use_rclone = path.is_remote();
 
+    if use_rclone && rclone_rc::is_running() {
+        return rclone_rc::on_path("operations/mkdir", path).map(|_| ());
+    }
+
     let path = path.clone().to_string();
 
     if use_rclone {
-        Command::new("rclone")
+        programs::rclone()
             .arg("mkdir")
             .arg(path)
             .status()?;
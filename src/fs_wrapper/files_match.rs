@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{programs, rclone_rc};
+
+use super::RclonePath;
+
+/// Checks whether two files have the same contents
+///
+/// Local files are compared byte by byte, for remotes rclone is asked for the size and hashes. Files whose backends
+/// share no hash type count as different
+pub fn files_match(a: &RclonePath, b: &RclonePath) -> io::Result<bool> {
+    if a.is_remote() || b.is_remote() {
+        let a = rclone_file_info(a)?;
+        let b = rclone_file_info(b)?;
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                if a.size != b.size {
+                    return Ok(false);
+                }
+                // Only hashes supported by both backends can be compared, without one in common the size alone
+                // doesn't prove anything
+                let matches = a.hashes
+                    .iter()
+                    .filter_map(|(hash_type, hash)| b.hashes.get(hash_type).map(|other_hash| hash == other_hash))
+                    .collect::<Vec<bool>>();
+                Ok(!matches.is_empty() && matches.into_iter().all(|hash_matches| hash_matches))
+            }
+            _ => Ok(false),
+        }
+    } else {
+        local_files_match(a.clone().path_string(), b.clone().path_string())
+    }
+}
+
+pub(super) fn local_files_match<P: AsRef<Path>>(a: P, b: P) -> io::Result<bool> {
+    if !a.as_ref().exists() || !b.as_ref().exists() {
+        return Ok(false);
+    }
+    if a.as_ref().metadata()?.len() != b.as_ref().metadata()?.len() {
+        return Ok(false);
+    }
+    let mut a = BufReader::new(File::open(a)?);
+    let mut b = BufReader::new(File::open(b)?);
+    let mut a_buffer = [0u8; 64 * 1024];
+    let mut b_buffer = [0u8; 64 * 1024];
+    loop {
+        let read = a.read(&mut a_buffer)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut b_buffer[..read])?;
+        if a_buffer[..read] != b_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct RcloneFileInfo {
+    size: i64,
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+}
+
+fn rclone_file_info(path: &RclonePath) -> io::Result<Option<RcloneFileInfo>> {
+    if rclone_rc::is_running() {
+        let (fs, remote) = rclone_rc::file_parameters(path);
+        return Ok(rclone_rc::call("operations/stat", &json!({ "fs": fs, "remote": remote, "opt": { "showHash": true } }))
+            .ok()
+            .and_then(|mut stat| serde_json::from_value(stat["item"].take()).ok()));
+    }
+    let output = programs::rclone()
+        .arg("lsjson")
+        .arg("--hash")
+        .arg("--files-only")
+        .arg(path.to_string())
+        .output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let files: Vec<RcloneFileInfo> = serde_json::from_slice(&output.stdout)?;
+    Ok(files.into_iter().next())
+}
@@ -0,0 +1,54 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+static UPLOADED: AtomicU64 = AtomicU64::new(0);
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes transferred during the run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// Downloaded from remotes
+    pub downloaded: u64,
+    /// Uploaded to remotes
+    pub uploaded: u64,
+    /// Written to local disks, including downloads and ffmpeg output
+    pub written: u64,
+}
+
+impl IoStats {
+    /// Bytes transferred since the earlier stats were taken
+    pub fn since(&self, earlier: IoStats) -> IoStats {
+        IoStats {
+            downloaded: self.downloaded - earlier.downloaded,
+            uploaded: self.uploaded - earlier.uploaded,
+            written: self.written - earlier.written,
+        }
+    }
+}
+
+pub fn io_stats() -> IoStats {
+    IoStats {
+        downloaded: DOWNLOADED.load(Ordering::Relaxed),
+        uploaded: UPLOADED.load(Ordering::Relaxed),
+        written: WRITTEN.load(Ordering::Relaxed),
+    }
+}
+
+pub fn record_downloaded<P: AsRef<Path>>(local_path: P) {
+    let size = file_size(local_path);
+    DOWNLOADED.fetch_add(size, Ordering::Relaxed);
+    WRITTEN.fetch_add(size, Ordering::Relaxed);
+}
+
+pub fn record_uploaded<P: AsRef<Path>>(local_path: P) {
+    UPLOADED.fetch_add(file_size(local_path), Ordering::Relaxed);
+}
+
+pub fn record_written<P: AsRef<Path>>(local_path: P) {
+    WRITTEN.fetch_add(file_size(local_path), Ordering::Relaxed);
+}
+
+fn file_size<P: AsRef<Path>>(path: P) -> u64 {
+    path.as_ref().metadata().map(|metadata| metadata.len()).unwrap_or(0)
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::{fs, io};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::rclone_filter::RcloneFilter;
+use crate::{programs, rclone_rc};
+
+use super::{check_output, RclonePath, DIRECTORY_NOT_FOUND};
+
+/// Hash types rclone can report, in the order they are preferred
+const REMOTE_HASH_TYPES: [&str; 4] = ["md5", "sha1", "sha256", "crc32"];
+
+/// A file found in a directory, with what the listing tells about it without reading it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteEntry {
+    /// Path relative to the listed directory
+    pub path: String,
+    /// None if the remote doesn't know it, like for Google Docs
+    pub size: Option<u64>,
+    /// Modification time as a Unix timestamp
+    pub mtime: Option<u64>,
+    /// Hash stored by the remote as `type:value`, like `md5:...`, only listed with `hashes`
+    pub hash: Option<String>,
+}
+
+/// Entry of `rclone lsjson`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsJsonEntry {
+    path: String,
+    size: i64,
+    mod_time: String,
+    #[serde(default)]
+    hashes: BTreeMap<String, String>,
+}
+
+/// Returns all files in the directory and its subdirectories with their size and modification time
+///
+/// Remote directories are listed with one `rclone lsjson -R`, `hashes` adds the hashes the remote stores, which
+/// is slow for remotes having to calculate them like sftp. Local files never have a hash. Fails if rclone does, so a
+/// remote which can't be listed isn't mistaken for an empty one
+pub fn list_entries(path: &RclonePath, hashes: bool) -> io::Result<Vec<RemoteEntry>> {
+    list_filtered_entries(path, hashes, None)
+}
+
+/// Like [list_entries], leaving out the files the filter excludes
+///
+/// rclone reads the filter file itself for remote directories, so it doesn't descend into excluded ones
+pub fn list_filtered_entries(
+    path: &RclonePath,
+    hashes: bool,
+    filter: Option<&RcloneFilter>,
+) -> io::Result<Vec<RemoteEntry>> {
+    if path.is_remote() && rclone_rc::is_running() {
+        let mut parameters = json!({
+            "fs": path.to_string(),
+            "remote": "",
+            "opt": { "recurse": true, "filesOnly": true, "showHash": hashes },
+        });
+        if let Some(filter) = filter {
+            // rclone applies FilterRule before FilterFrom
+            parameters["_filter"] = json!({ "FilterRule": filter.extra_rules(), "FilterFrom": [filter.path()] });
+        }
+        // A directory which doesn't exist yet is empty, like a local one
+        let mut listing = match rclone_rc::call("operations/list", &parameters) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            listing => listing?,
+        };
+        // Items of operations/list are the entries of lsjson
+        let entries = serde_json::from_value(listing["list"].take()).map_err(invalid_listing)?;
+        Ok(to_remote_entries(entries))
+    } else if path.is_remote() {
+        let mut command = programs::rclone();
+        command.arg("lsjson").arg("-R").arg("--files-only");
+        if hashes {
+            command.arg("--hash");
+        }
+        if let Some(filter) = filter {
+            for rule in filter.extra_rules() {
+                command.arg("--filter").arg(rule);
+            }
+            command.arg("--filter-from").arg(filter.path());
+        }
+        let output = command.arg(path.to_string()).output()?;
+        if output.status.code() == Some(DIRECTORY_NOT_FOUND) {
+            return Ok(Vec::new());
+        }
+        check_output(&output)?;
+        parse_lsjson(&output.stdout)
+    } else {
+        let mut entries = Vec::new();
+        traverse_local_directory(Path::new(&path.clone().path_string()), Path::new(""), &mut entries);
+        if let Some(filter) = filter {
+            entries.retain(|entry| filter.includes(&entry.path));
+        }
+        Ok(entries)
+    }
+}
+
+/// Parses the output of `rclone lsjson`
+pub fn parse_lsjson(output: &[u8]) -> io::Result<Vec<RemoteEntry>> {
+    let entries = serde_json::from_slice(output).map_err(invalid_listing)?;
+    Ok(to_remote_entries(entries))
+}
+
+fn invalid_listing(error: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid listing from rclone: {}", error))
+}
+
+fn to_remote_entries(entries: Vec<LsJsonEntry>) -> Vec<RemoteEntry> {
+    entries
+        .into_iter()
+        .map(|entry| {
+            let hash = REMOTE_HASH_TYPES
+                .iter()
+                .find_map(|kind| entry.hashes.get_key_value(*kind))
+                .or_else(|| entry.hashes.iter().next())
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(kind, value)| format!("{}:{}", kind, value));
+            RemoteEntry {
+                path: entry.path,
+                size: u64::try_from(entry.size).ok(),
+                mtime: parse_rfc3339(&entry.mod_time),
+                hash,
+            }
+        })
+        .collect()
+}
+
+/// Parses times like "2022-04-01T12:30:00.123456789+02:00" as printed by rclone lsjson into a Unix timestamp
+pub fn parse_rfc3339(time: &str) -> Option<u64> {
+    let (date, time) = time.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(index) => time.split_at(index),
+        None => (time, ""),
+    };
+    let mut time = time.splitn(3, ':');
+    let hours = time.next()?.parse::<i64>().ok()?;
+    let minutes = time.next()?.parse::<i64>().ok()?;
+    // Fractions of seconds are dropped
+    let seconds = time.next()?.split('.').next()?.parse::<i64>().ok()?;
+    let offset_seconds = match offset.split_at_checked(1) {
+        Some((sign @ ("+" | "-"), offset)) => {
+            let (offset_hours, offset_minutes) = offset.split_once(':')?;
+            let offset = offset_hours.parse::<i64>().ok()? * 3600 + offset_minutes.parse::<i64>().ok()? * 60;
+            if sign == "+" { offset } else { -offset }
+        }
+        _ => 0,
+    };
+    // Days since 1970-01-01 of the proleptic Gregorian calendar
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days * 86400 + hours * 3600 + minutes * 60 + seconds - offset_seconds).ok()
+}
+
+fn traverse_local_directory(root: &Path, relative: &Path, entries: &mut Vec<RemoteEntry>) {
+    if let Ok(directory) = fs::read_dir(root.join(relative)) {
+        for entry in directory.flatten() {
+            if let Ok(metadata) = entry.metadata() {
+                let relative = relative.join(entry.file_name());
+                if metadata.is_dir() {
+                    traverse_local_directory(root, &relative, entries);
+                } else {
+                    entries.push(RemoteEntry {
+                        path: relative.to_string_lossy().to_string(),
+                        size: Some(metadata.len()),
+                        mtime: metadata
+                            .modified()
+                            .ok()
+                            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                            .map(|time| time.as_secs()),
+                        hash: None,
+                    });
+                }
+            }
+        }
+    }
+}
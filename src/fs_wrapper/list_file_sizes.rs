@@ -0,0 +1,14 @@
+use std::collections::HashMap;
+use std::io;
+
+use super::{list_entries, RclonePath};
+
+/// Returns the sizes of all files in the directory, by their path relative to it
+pub fn list_file_sizes(path: &RclonePath) -> io::Result<HashMap<String, u64>> {
+    Ok(
+        list_entries(path, false)?
+            .into_iter()
+            .filter_map(|entry| Some((entry.path, entry.size?)))
+            .collect()
+    )
+}
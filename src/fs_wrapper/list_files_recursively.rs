@@ -1,28 +1,25 @@
-use std::fs;
+use std::{fs, io};
+use std::io::ErrorKind;
 use std::path::Path;
-use std::process::Command;
 
-use super::RclonePath;
+use crate::warnings::{WarningKind, Warnings};
 
-pub fn list_files_recursively(path: &RclonePath) -> Vec<RclonePath> {
+use super::{list_entries, RclonePath};
+
+/// Lists all files in the directory and its subdirectories
+///
+/// Fails if a remote directory can't be listed, local subdirectories which can't be read are skipped with a warning
+pub fn list_files_recursively(path: &RclonePath, warnings: &mut Warnings) -> io::Result<Vec<RclonePath>> {
     if path.is_remote() {
-        let stdout = Command::new("rclone")
-            .arg("lsf")
-            .arg("-R")
-            .arg("--files-only")
-            .arg(path.clone().to_string())
-            .output()
-            .expect("Failed to run rclone")
-            .stdout;
-        String::from_utf8_lossy(&*stdout).to_string().lines().map(
-            |line| {
+        Ok(list_entries(path, false)?.into_iter().map(
+            |entry| {
                 match path {
                     RclonePath::Local(path) => {
                         RclonePath::Local(
                             format!(
                                 "{}/{}",
                                 path,
-                                line.to_string()
+                                entry.path
                             )
                         )
                     }
@@ -32,30 +29,30 @@ pub fn list_files_recursively(path: &RclonePath) -> Vec<RclonePath> {
                             format!(
                                 "{}/{}",
                                 path,
-                                line.to_string()
+                                entry.path
                             )
                         )
                     }
                 }
             }
-        ).collect()
+        ).collect())
     } else {
-        traverse_local_directory(path.clone().to_string()).into_iter().map(
+        Ok(traverse_local_directory(path.clone().to_string(), warnings).into_iter().map(
             |file| {
                 RclonePath::Local(file)
             }
-        ).collect()
+        ).collect())
     }
 }
 
-fn traverse_local_directory<P: AsRef<Path>>(path: P) -> Vec<String> {
+fn traverse_local_directory<P: AsRef<Path>>(path: P, warnings: &mut Warnings) -> Vec<String> {
     let mut result = Vec::new();
-    if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
+    match fs::read_dir(&path) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
                 if let Ok(file_type) = entry.file_type() {
                     if file_type.is_dir() {
-                        let mut contents = traverse_local_directory(entry.path());
+                        let mut contents = traverse_local_directory(entry.path(), warnings);
                         result.append(&mut contents);
                     } else {
                         result.push(entry.path().to_string_lossy().to_string())
@@ -63,6 +60,16 @@ fn traverse_local_directory<P: AsRef<Path>>(path: P) -> Vec<String> {
                 }
             }
         }
+        Err(error) => {
+            // A missing output directory is expected on the first run
+            if error.kind() != ErrorKind::NotFound {
+                warnings.push(
+                    WarningKind::UnreadableDirectory,
+                    path.as_ref().to_string_lossy().to_string(),
+                    Some(error.to_string()),
+                );
+            }
+        }
     }
     result
 }
\ No newline at end of file
@@ -1,15 +1,90 @@
-pub use copy::copy;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
+
+pub use copy::{copy, copy_verified};
+pub use copy_files::copy_files;
 pub use create_dir_all::create_dir_all;
+pub use files_match::files_match;
+pub use io_stats::{io_stats, record_written};
+pub use list_entries::{list_entries, list_filtered_entries, parse_lsjson, parse_rfc3339, RemoteEntry};
+pub use list_file_sizes::list_file_sizes;
+pub use list_modification_times::list_modification_times;
 pub use list_files_recursively::list_files_recursively;
+pub use move_files::move_files;
+pub use partial_path::{partial_path, PARTIAL_SUFFIX};
 pub use rclone_path::RclonePath;
+pub use read::read;
+pub use read_to_string::read_to_string;
+pub use remove_dir_all::{check_purge_status, remove_dir_all};
 pub use remove_empty_dirs::remove_empty_dirs;
 pub use remove_file::remove_file;
+pub use remove_files::remove_files;
 pub use rename::rename;
+pub use server_side::{is_server_side_unsupported, parse_remote_types, server_side_across_configs};
 
 mod copy;
+mod copy_files;
 mod create_dir_all;
+mod files_match;
+mod io_stats;
+mod list_entries;
+mod list_file_sizes;
+mod list_modification_times;
 mod list_files_recursively;
+mod move_files;
+mod partial_path;
 mod rename;
+mod remove_dir_all;
 mod remove_empty_dirs;
 mod remove_file;
-mod rclone_path;
\ No newline at end of file
+mod remove_files;
+mod rclone_path;
+mod read;
+mod read_to_string;
+mod server_side;
+/// Writes relative paths to a temporary file for `rclone --files-from-raw`, removed by the caller after the run
+fn write_files_from(operation: &str, files: &[String]) -> io::Result<PathBuf> {
+    let files_from = std::env::temp_dir().join(format!("ffmusicsync-{}-{}.txt", std::process::id(), operation));
+    let mut writer = BufWriter::new(File::create(&files_from)?);
+    for file in files {
+        writeln!(writer, "{}", file)?;
+    }
+    writer.flush()?;
+    Ok(files_from)
+}
+
+/// Parameters of `operations/copyfile` and `operations/movefile` for a file keeping its path relative to the directories
+fn transfer_parameters(from: &RclonePath, to: &RclonePath, file: &str) -> serde_json::Value {
+    serde_json::json!({
+        "srcFs": from.to_string(),
+        "srcRemote": file,
+        "dstFs": to.to_string(),
+        "dstRemote": file,
+    })
+}
+
+/// Exit code of rclone when the directory to work on doesn't exist
+const DIRECTORY_NOT_FOUND: i32 = 3;
+
+/// Turns a failed rclone run into an error, so the file isn't assumed to be transferred
+fn check_status(status: ExitStatus) -> io::Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("rclone {}", status)))
+    }
+}
+
+/// Like [check_status] for a run whose stderr was captured, with the last line rclone printed in the error
+fn check_output(output: &Output) -> io::Result<()> {
+    check_status(output.status).map_err(|error| {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => io::Error::other(format!("{}: {}", error, line.trim())),
+            None => error,
+        }
+    })
+}
@@ -0,0 +1,56 @@
+use std::{fs, io};
+use std::path::Path;
+
+use crate::{programs, rclone_rc};
+
+use super::io_stats::{record_downloaded, record_uploaded};
+use super::{check_status, rename, transfer_parameters, write_files_from, RclonePath};
+
+/// Moves files between two directories keeping their relative paths, with a single rclone run
+/// if any of the directories is remote
+pub fn move_files(from: &RclonePath, to: &RclonePath, files: &[String]) -> io::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    if from.is_remote() || to.is_remote() {
+        if !from.is_remote() {
+            // The files are gone afterwards, so they have to be counted beforehand
+            for file in files {
+                record_uploaded(Path::new(&from.clone().path_string()).join(file));
+            }
+        }
+        if rclone_rc::is_running() {
+            for file in files {
+                rclone_rc::call("operations/movefile", &transfer_parameters(from, to, file))?;
+            }
+        } else {
+            let files_from = write_files_from("move", files)?;
+            let status = programs::rclone()
+                .arg("move")
+                .arg("--files-from-raw")
+                .arg(&files_from)
+                .arg(from.to_string())
+                .arg(to.to_string())
+                .status();
+            fs::remove_file(&files_from)?;
+            check_status(status?)?;
+        }
+        if !to.is_remote() {
+            for file in files {
+                record_downloaded(Path::new(&to.clone().path_string()).join(file));
+            }
+        }
+    } else {
+        for file in files {
+            let destination = Path::new(&to.clone().path_string()).join(file);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            rename(
+                &RclonePath::Local(Path::new(&from.clone().path_string()).join(file).to_string_lossy().to_string()),
+                &RclonePath::Local(destination.to_string_lossy().to_string()),
+            )?;
+        }
+    }
+    Ok(())
+}
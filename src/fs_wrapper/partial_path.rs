@@ -0,0 +1,13 @@
+use std::path::{Path, PathBuf};
+
+/// Suffix of the partial names, no source is named like it so no output can be
+pub const PARTIAL_SUFFIX: &str = "ffmusicsync-part";
+
+/// Path a file is written to before it's renamed into place, like `Song.ffmusicsync-part.ogg` for `Song.ogg`,
+/// keeping the extension ffmpeg picks the container by
+pub fn partial_path(path: &Path) -> PathBuf {
+    match path.extension() {
+        Some(extension) => path.with_extension(format!("{}.{}", PARTIAL_SUFFIX, extension.to_string_lossy())),
+        None => path.with_extension(PARTIAL_SUFFIX),
+    }
+}
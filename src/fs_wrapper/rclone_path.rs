@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Clone, Debug)]
@@ -7,16 +8,6 @@ pub enum RclonePath {
 }
 
 impl RclonePath {
-    pub fn to_string(self) -> String {
-        match self {
-            Self::Local(path) => {
-                path
-            }
-            Self::Remote(remote, path) => {
-                format!("{}:{}", remote, path)
-            }
-        }
-    }
     pub fn path_string(self) -> String {
         match self {
             Self::Local(path) => {
@@ -27,6 +18,12 @@ impl RclonePath {
             }
         }
     }
+    pub fn remote(&self) -> Option<&str> {
+        match self {
+            Self::Local(_) => None,
+            Self::Remote(remote, _) => Some(remote)
+        }
+    }
     pub fn is_remote(&self) -> bool {
         match self {
             Self::Local(_) => false,
@@ -48,6 +45,19 @@ impl RclonePath {
     }
 }
 
+impl fmt::Display for RclonePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local(path) => {
+                write!(f, "{}", path)
+            }
+            Self::Remote(remote, path) => {
+                write!(f, "{}:{}", remote, path)
+            }
+        }
+    }
+}
+
 impl FromStr for RclonePath {
     type Err = ();
 
@@ -55,7 +65,7 @@ impl FromStr for RclonePath {
         Ok(if s.contains(":") {
             let remote_and_directory = s.split(":").map(|x| x.to_string()).collect::<Vec<String>>();
             Self::Remote(
-                remote_and_directory.get(0).unwrap().to_string(),
+                remote_and_directory.first().unwrap().to_string(),
                 remote_and_directory.get(1).unwrap_or(&String::from("")).to_string(),
             )
         } else {
@@ -0,0 +1,23 @@
+use std::{fs, io};
+
+use crate::programs;
+
+use super::RclonePath;
+
+pub fn read(path: &RclonePath) -> io::Result<Vec<u8>> {
+    let use_rclone = path.is_remote();
+
+    let path = path.clone().to_string();
+
+    if use_rclone {
+        Ok(
+            programs::rclone()
+                .arg("cat")
+                .arg(path)
+                .output()?
+                .stdout
+        )
+    } else {
+        fs::read(path)
+    }
+}
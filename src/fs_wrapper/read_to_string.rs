@@ -0,0 +1,22 @@
+use std::{fs, io};
+
+use crate::programs;
+
+use super::RclonePath;
+
+pub fn read_to_string(path: &RclonePath) -> io::Result<String> {
+    let use_rclone = path.is_remote();
+
+    let path = path.clone().to_string();
+
+    if use_rclone {
+        let stdout = programs::rclone()
+            .arg("cat")
+            .arg(path)
+            .output()?
+            .stdout;
+        Ok(String::from_utf8_lossy(&stdout).to_string())
+    } else {
+        fs::read_to_string(path)
+    }
+}
@@ -0,0 +1,42 @@
+use std::{fs, io};
+use std::process::ExitStatus;
+
+use crate::{programs, rclone_rc};
+
+use super::{check_status, RclonePath, DIRECTORY_NOT_FOUND};
+
+pub fn remove_dir_all(path: &RclonePath) -> io::Result<()> {
+    let use_rclone = path.is_remote();
+
+    if use_rclone && rclone_rc::is_running() {
+        // Like rclone purge, a directory which is already gone is no error
+        return match rclone_rc::on_path("operations/purge", path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        };
+    }
+
+    let path = path.clone().to_string();
+
+    if use_rclone {
+        check_purge_status(
+            programs::rclone()
+                .arg("purge")
+                .arg(path)
+                .status()?
+        )?;
+    } else {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+/// Turns a failed rclone purge into an error, so the directory isn't assumed to be removed
+///
+/// Like with the API, a directory which is already gone is no error
+pub fn check_purge_status(status: ExitStatus) -> io::Result<()> {
+    if status.code() == Some(DIRECTORY_NOT_FOUND) {
+        return Ok(());
+    }
+    check_status(status)
+}
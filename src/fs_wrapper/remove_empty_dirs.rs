@@ -1,23 +1,30 @@
 use std::{fs, io};
 use std::path::Path;
-use std::process::Command;
+
+use crate::{programs, rclone_rc};
 
 use super::RclonePath;
 
 pub fn remove_empty_dirs(path: &RclonePath) -> io::Result<()> {
     let use_rclone = path.is_remote();
 
+    if use_rclone && rclone_rc::is_running() {
+        // Like rclone rmdirs, a directory which is already gone is no error
+        return match rclone_rc::on_path("operations/rmdirs", path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        };
+    }
+
     let path = path.clone().to_string();
 
     if use_rclone {
-        Command::new("rclone")
+        programs::rclone()
             .arg("rmdirs")
             .arg(path)
             .status()?;
-    } else {
-        if traverse_local_directory(&path)? {
-            fs::remove_dir(path)?;
-        }
+    } else if traverse_local_directory(&path)? {
+        fs::remove_dir(path)?;
     }
     Ok(())
 }
@@ -25,16 +32,12 @@ pub fn remove_empty_dirs(path: &RclonePath) -> io::Result<()> {
 fn traverse_local_directory<P: AsRef<Path>>(path: P) -> io::Result<bool> {
     let mut count = 0;
     if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                count += 1;
-                if let Ok(file_type) = entry.file_type() {
-                    if file_type.is_dir() {
-                        if traverse_local_directory(entry.path())? {
-                            fs::remove_dir(entry.path())?;
-                            count -= 1;
-                        }
-                    }
+        for entry in entries.flatten() {
+            count += 1;
+            if let Ok(file_type) = entry.file_type() {
+                if file_type.is_dir() && traverse_local_directory(entry.path())? {
+                    fs::remove_dir(entry.path())?;
+                    count -= 1;
                 }
             }
         }
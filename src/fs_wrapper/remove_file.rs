@@ -1,15 +1,20 @@
 use std::{fs, io};
-use std::process::Command;
+
+use crate::{programs, rclone_rc};
 
 use super::RclonePath;
 
 pub fn remove_file(path: &RclonePath) -> io::Result<()> {
     let use_rclone = path.is_remote();
 
+    if use_rclone && rclone_rc::is_running() {
+        return rclone_rc::on_path("operations/deletefile", path).map(|_| ());
+    }
+
     let path = path.clone().to_string();
 
     if use_rclone {
-        Command::new("rclone")
+        programs::rclone()
             .arg("delete")
             .arg(path)
             .status()?;
@@ -0,0 +1,35 @@
+use std::{fs, io};
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::{programs, rclone_rc};
+
+use super::{check_status, write_files_from, RclonePath};
+
+/// Deletes files of a directory by their relative paths, with a single rclone run if it's remote
+pub fn remove_files(directory: &RclonePath, files: &[String]) -> io::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    if directory.is_remote() && rclone_rc::is_running() {
+        for file in files {
+            rclone_rc::call("operations/deletefile", &json!({ "fs": directory.to_string(), "remote": file }))?;
+        }
+    } else if directory.is_remote() {
+        let files_from = write_files_from("delete", files)?;
+        let status = programs::rclone()
+            .arg("delete")
+            .arg("--files-from-raw")
+            .arg(&files_from)
+            .arg(directory.to_string())
+            .status();
+        fs::remove_file(&files_from)?;
+        check_status(status?)?;
+    } else {
+        for file in files {
+            fs::remove_file(Path::new(&directory.clone().path_string()).join(file))?;
+        }
+    }
+    Ok(())
+}
@@ -1,22 +1,46 @@
 use std::{fs, io};
-use std::process::Command;
 
+use crate::{programs, rclone_rc};
+
+use super::io_stats::{record_downloaded, record_uploaded};
 use super::RclonePath;
 
 pub fn rename(from: &RclonePath, to: &RclonePath) -> io::Result<()> {
     let use_rclone = from.is_remote() || to.is_remote();
+    let is_download = from.is_remote() && !to.is_remote();
+    let is_upload = !from.is_remote() && to.is_remote();
+
+    if is_upload {
+        // The file is gone afterwards, so it has to be counted beforehand
+        record_uploaded(from.to_string());
+    }
+    if use_rclone && rclone_rc::is_running() {
+        rclone_rc::transfer_file("operations/movefile", from, to)?;
+        if is_download {
+            record_downloaded(to.to_string());
+        }
+        return Ok(());
+    }
 
     let from = from.clone().to_string();
     let to = to.clone().to_string();
 
     if use_rclone {
-        Command::new("rclone")
+        programs::rclone()
             .arg("moveto")
             .arg(from)
-            .arg(to)
+            .arg(&to)
             .status()?;
-    } else {
-        fs::rename(from, to)?;
+        if is_download {
+            record_downloaded(&to);
+        }
+    } else if let Err(error) = fs::rename(&from, &to) {
+        // Moving to another filesystem, like a trash directory on another disk
+        if error.kind() != io::ErrorKind::CrossesDevices {
+            return Err(error);
+        }
+        fs::copy(&from, &to)?;
+        fs::remove_file(&from)?;
     }
     Ok(())
 }
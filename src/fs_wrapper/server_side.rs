@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::OnceLock;
+
+use crate::programs;
+
+use super::RclonePath;
+
+static REMOTE_TYPES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Whether a copy between the paths can be done by the provider if rclone is allowed to across remotes
+///
+/// rclone copies within one remote on the provider's side by itself, but not between two remotes of the same backend,
+/// like two Google Drive accounts, without --server-side-across-configs. The files are downloaded and uploaded again
+/// by this computer otherwise
+pub fn server_side_across_configs(from: &RclonePath, to: &RclonePath) -> bool {
+    let (Some(from), Some(to)) = (from.remote(), to.remote()) else {
+        return false;
+    };
+    if from == to {
+        return false;
+    }
+    let remote_types = REMOTE_TYPES.get_or_init(|| {
+        programs::rclone()
+            .arg("listremotes")
+            .arg("--long")
+            .output()
+            .map(|output| parse_remote_types(&String::from_utf8_lossy(&output.stdout)))
+            .unwrap_or_default()
+    });
+    matches!((remote_types.get(from), remote_types.get(to)), (Some(from), Some(to)) if from == to)
+}
+
+/// Backends of the remotes from rclone listremotes --long, which prints them like "Phone:   sftp"
+pub fn parse_remote_types(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(remote, backend)| (remote.trim().to_string(), backend.trim().to_string()))
+        .filter(|(remote, backend)| !remote.is_empty() && !backend.is_empty())
+        .collect()
+}
+
+/// Whether a copy with --server-side-across-configs failed as the provider doesn't copy between the remotes, so
+/// the file has to be transferred by this computer instead
+///
+/// rclone reports it like "server side copy not supported", "optional feature not implemented" or "can't copy object -
+/// incompatible remotes"
+pub fn is_server_side_unsupported(error: &io::Error) -> bool {
+    let message = error.to_string().to_lowercase();
+    ["not supported", "not implemented", "incompatible remotes"]
+        .iter()
+        .any(|reason| message.contains(reason))
+}
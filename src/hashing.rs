@@ -0,0 +1,134 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fs_wrapper;
+use crate::fs_wrapper::RclonePath;
+use crate::redact;
+
+/// Size and content hash of a file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileHash {
+    pub size: u64,
+    /// "blake3:<hex>" for local files, for remotes one of the hashes the backend supports, like "md5:<hex>"
+    pub hash: String,
+}
+
+/// Hashes the files in the directory, local files are hashed in parallel with BLAKE3
+/// and remotes are asked for their own hashes with a single rclone run
+///
+/// Local files which can't be read are left out, a remote which can't be listed is an error
+pub fn hash_files(directory: &RclonePath, files: &[String]) -> io::Result<HashMap<String, FileHash>> {
+    if files.is_empty() {
+        return Ok(HashMap::new());
+    }
+    if directory.is_remote() {
+        let wanted = files.iter().collect::<HashSet<&String>>();
+        return Ok(
+            remote_hashes(directory)?
+                .into_iter()
+                .filter(|(file, _)| wanted.contains(file))
+                .collect()
+        );
+    }
+
+    let root = directory.clone().path_string();
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let hashes = Mutex::new(HashMap::new());
+    let threads = std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(4)
+        .min(files.len());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(index) else {
+                    break;
+                };
+                if let Ok(file_hash) = hash_local_file(Path::new(&root).join(file)) {
+                    hashes.lock().unwrap().insert(file.clone(), file_hash);
+                }
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                if done.is_multiple_of(100) || done == files.len() {
+                    eprint!("\rHashing files: {}/{}", done, files.len());
+                }
+            });
+        }
+    });
+    eprintln!();
+    Ok(hashes.into_inner().unwrap())
+}
+
+/// Finds files whose contents changed since the previous hashes were taken
+///
+/// Files with a different size are changed without hashing them, only files with the same size
+/// (or without a previous hash) are hashed. Returns the changed files and the current hashes of all others
+pub fn find_changed_files(
+    directory: &RclonePath,
+    files: &HashSet<String>,
+    previous: &HashMap<String, FileHash>,
+) -> io::Result<(HashSet<String>, HashMap<String, FileHash>)> {
+    let sizes = fs_wrapper::list_file_sizes(directory)?;
+    let mut changed = HashSet::new();
+    let mut files_to_hash = Vec::new();
+    for file in files {
+        match (previous.get(file), sizes.get(file)) {
+            (Some(previous_hash), Some(size)) if previous_hash.size != *size => {
+                changed.insert(file.clone());
+            }
+            _ => files_to_hash.push(file.clone()),
+        }
+    }
+    files_to_hash.sort();
+    let hashes = hash_files(directory, &files_to_hash)?;
+    for (file, file_hash) in &hashes {
+        if previous.get(file).map(|previous_hash| previous_hash != file_hash).unwrap_or(false) {
+            changed.insert(file.clone());
+        }
+    }
+    let hashes = hashes
+        .into_iter()
+        .filter(|(file, _)| !changed.contains(file))
+        .collect();
+    Ok((changed, hashes))
+}
+
+/// BLAKE3 hash of a local file, like "blake3:<hex>"
+pub fn hash_local_file<P: AsRef<Path>>(path: P) -> io::Result<FileHash> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok(
+        FileHash {
+            size,
+            hash: format!("blake3:{}", hasher.finalize().to_hex()),
+        }
+    )
+}
+
+fn remote_hashes(directory: &RclonePath) -> io::Result<HashMap<String, FileHash>> {
+    eprintln!("Reading hashes of {}", redact::path(&directory.to_string()));
+    Ok(
+        fs_wrapper::list_entries(directory, true)?
+            .into_iter()
+            .filter_map(|entry| Some((entry.path, FileHash { size: entry.size?, hash: entry.hash? })))
+            .collect()
+    )
+}
@@ -0,0 +1,93 @@
+use crate::format::format_count;
+use crate::redact;
+use crate::theme;
+
+/// How much a category of problems affects the library
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    fn weight(&self) -> f64 {
+        match self {
+            Self::Low => 0.25,
+            Self::Medium => 1.0,
+            Self::High => 4.0,
+        }
+    }
+}
+
+pub struct HealthCategory {
+    pub name: &'static str,
+    pub severity: Severity,
+    pub files: Vec<String>,
+    /// Suggested config or CLI change fixing the problem
+    pub hint: &'static str,
+}
+
+/// Results of all health checks of a library
+#[derive(Default)]
+pub struct HealthReport {
+    pub categories: Vec<HealthCategory>,
+    /// Outputs found broken by verify, which verify --requeue makes the next sync process again
+    pub broken: Vec<String>,
+}
+
+impl HealthReport {
+    pub fn add(&mut self, name: &'static str, severity: Severity, mut files: Vec<String>, hint: &'static str) {
+        if files.is_empty() {
+            return;
+        }
+        files.sort();
+        self.categories.push(
+            HealthCategory {
+                name,
+                severity,
+                files,
+                hint,
+            }
+        );
+    }
+    /// Score from 0 to 100, every affected file lowers it by the weight of its category
+    pub fn score(&self, total_files: usize) -> u32 {
+        let penalty = self.categories
+            .iter()
+            .map(|category| category.files.len() as f64 * category.severity.weight())
+            .sum::<f64>();
+        let score = 100.0 * (1.0 - penalty / total_files.max(1) as f64);
+        score.clamp(0.0, 100.0).round() as u32
+    }
+    /// Prints the score and all problems, the most severe first
+    pub fn print(&mut self, total_files: usize) {
+        println!("{}", theme::heading().apply_to(format!("Library health: {}/100", self.score(total_files))));
+        self.print_problems();
+    }
+    /// Prints all problems, the most severe first
+    pub fn print_problems(&mut self) {
+        self.categories.sort_by(|a, b| b.severity.cmp(&a.severity).then(b.files.len().cmp(&a.files.len())));
+        for category in &self.categories {
+            let style = theme::severity(category.severity);
+            println!();
+            println!(
+                "{} ({:?}, {} files)",
+                style.apply_to(category.name),
+                category.severity,
+                format_count(category.files.len())
+            );
+            for file in category.files.iter().take(5) {
+                println!(" - {}", redact::path(file));
+            }
+            if category.files.len() > 5 {
+                println!(" - and {} more", format_count(category.files.len() - 5));
+            }
+            println!("Hint: {}", category.hint);
+        }
+    }
+    /// Whether any problem has a high severity
+    pub fn has_severe(&self) -> bool {
+        self.categories.iter().any(|category| category.severity == Severity::High)
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentResource, FluentValue};
+use intl_memoizer::concurrent::IntlLangMemoizer;
+use lazy_static::lazy_static;
+use unic_langid::LanguageIdentifier;
+
+use crate::format::format_count;
+
+pub use fluent_bundle::FluentArgs;
+
+/// Translations of the console output by language code, English is used for missing messages
+///
+/// The files are Fluent resources, messages can pick their wording by the numbers passed to them, like
+/// `{ $count -> [one] ... *[other] ... }` with the plural categories of the language
+pub const LOCALES: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+];
+
+lazy_static! {
+    static ref BUNDLES: HashMap<&'static str, FluentBundle<FluentResource>> = LOCALES
+        .iter()
+        .map(|(language, source)| (*language, load_bundle(language, source).unwrap()))
+        .collect();
+}
+
+static LANGUAGE: RwLock<String> = RwLock::new(String::new());
+
+/// Translates the output to the language, returns false if there are no translations for it
+pub fn set_language(language: &str) -> bool {
+    let language = language.to_lowercase();
+    let supported = BUNDLES.contains_key(language.as_str());
+    if supported {
+        *LANGUAGE.write().unwrap() = language;
+    }
+    supported
+}
+
+/// Language code of the locale set in the environment, like de for de_DE.UTF-8
+pub fn language_from_env() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|locale| !locale.is_empty())
+        .map(|locale| locale.split(['_', '.', '-', '@']).next().unwrap_or_default().to_lowercase())
+        .filter(|language| !language.is_empty() && language != "c" && language != "posix")
+}
+
+pub fn supported_languages() -> Vec<&'static str> {
+    LOCALES.iter().map(|(language, _)| *language).collect()
+}
+
+/// Parses a locale file into a bundle, failing with the syntax errors and duplicate messages in it
+pub fn load_bundle(language: &str, source: &str) -> Result<FluentBundle<FluentResource>, String> {
+    let language_identifier = language.parse::<LanguageIdentifier>().map_err(|error| error.to_string())?;
+    let resource = FluentResource::try_new(source.to_string())
+        .map_err(|(_, errors)| format!("{}: {:?}", language, errors))?;
+    let mut bundle = FluentBundle::new_concurrent(vec![language_identifier]);
+    // Isolation marks around placeholders show up as garbage in most terminals
+    bundle.set_use_isolating(false);
+    bundle.set_formatter(Some(format_value));
+    bundle.add_resource(resource).map_err(|errors| format!("{}: {:?}", language, errors))?;
+    Ok(bundle)
+}
+
+/// Groups the digits of whole numbers like the rest of the output, see [format_count]
+fn format_value(value: &FluentValue, _: &IntlLangMemoizer) -> Option<String> {
+    match value {
+        FluentValue::Number(number) if number.value >= 0.0 && number.value.fract() == 0.0 => {
+            Some(format_count(number.value as usize))
+        }
+        _ => None,
+    }
+}
+
+/// Formats a message of the bundle, None if it doesn't have it
+pub fn format_message(
+    bundle: &FluentBundle<FluentResource>,
+    key: &str,
+    arguments: Option<&FluentArgs>,
+) -> Option<String> {
+    let pattern = bundle.get_message(key)?.value()?;
+    let mut errors = Vec::new();
+    Some(bundle.format_pattern(pattern, arguments, &mut errors).to_string())
+}
+
+/// The message in the current language, see [tr!](crate::tr)
+pub fn translate(key: &str, arguments: Option<&FluentArgs>) -> String {
+    let language = LANGUAGE.read().unwrap();
+    BUNDLES
+        .get(language.as_str())
+        .and_then(|bundle| format_message(bundle, key, arguments))
+        .or_else(|| format_message(&BUNDLES["en"], key, arguments))
+        .unwrap_or_else(|| key.to_string())
+}
+
+/// Translates a message of the locale files, like `tr!("plan-summary", process = 3, rename = 0, delete = 1)`
+///
+/// Numbers are passed as numbers, so the message can pick its plural form by them
+#[macro_export]
+macro_rules! tr {
+    ($key:expr) => {
+        $crate::i18n::translate($key, None)
+    };
+    ($key:expr, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut arguments = $crate::i18n::FluentArgs::new();
+        $(arguments.set(stringify!($name), $value);)+
+        $crate::i18n::translate($key, Some(&arguments))
+    }};
+}
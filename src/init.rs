@@ -0,0 +1,146 @@
+use std::io;
+
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+
+/// Encoding presets offered by `init`, with the extension and the ffmpeg params they encode to
+pub const PRESETS: [(&str, &str, &str); 4] = [
+    ("Opus 128 kbps, transparent for most music", "ogg", "-c:a libopus -b:a 128K -vn"),
+    ("Opus 96 kbps, smaller files for phones", "ogg", "-c:a libopus -b:a 96K -vn"),
+    ("MP3 V0, playable almost everywhere, like car head units", "mp3", "-c:a libmp3lame -q:a 0 -vn"),
+    ("AAC 256 kbps, for Apple devices", "m4a", "-c:a aac -b:a 256K -vn"),
+];
+
+/// Kinds of brackets which can be removed from file names, with the config option removing them
+const BRACKETS: [(&str, &str); 4] = [
+    ("Round brackets, like \"Song (Original Mix)\"", "removeRoundBrackets"),
+    ("Square brackets, like \"Song [Remastered]\"", "removeSquareBrackets"),
+    ("Curly brackets, like \"Song {Bonus}\"", "removeCurlyBrackets"),
+    ("Angle brackets, like \"Song <Live>\"", "removeAngleBrackets"),
+];
+
+/// Settings chosen by the user of `init`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitAnswers {
+    pub input_directory: String,
+    /// Local directory or rclone remote, like Phone:Music
+    pub output_directory: String,
+    /// Only asked for when a remote is used
+    pub temp_directory: Option<String>,
+    pub extensions_to_encode: Vec<String>,
+    /// Index into [PRESETS]
+    pub preset: usize,
+    /// Whether each kind of [BRACKETS] is removed
+    pub remove_brackets: [bool; 4],
+    pub copy_covers: bool,
+}
+
+impl InitAnswers {
+    /// Asks for every setting in the terminal
+    pub fn prompt() -> io::Result<Self> {
+        let input_directory: String = Input::new()
+            .with_prompt("Music library directory, or rclone remote like MyStorage:FLAC")
+            .interact_text()?;
+        let output_directory: String = Input::new()
+            .with_prompt("Directory to create the encoded library in, or rclone remote like Phone:Music")
+            .interact_text()?;
+        let temp_directory = if is_remote(&input_directory) || is_remote(&output_directory) {
+            Some(
+                Input::new()
+                    .with_prompt("Temp directory for files downloaded from and uploaded to remotes")
+                    .default(String::from("temp"))
+                    .interact_text()?
+            )
+        } else {
+            None
+        };
+        let extensions: String = Input::new()
+            .with_prompt("Extensions to encode, separated by commas, other files are copied")
+            .default(String::from("flac,wav,alac"))
+            .interact_text()?;
+        let preset = Select::new()
+            .with_prompt("Encode to")
+            .items(&PRESETS.map(|(description, _, _)| description))
+            .default(0)
+            .interact()?;
+        let selected_brackets = MultiSelect::new()
+            .with_prompt("Remove from file names (space to select)")
+            .items(&BRACKETS.map(|(description, _)| description))
+            .interact()?;
+        let copy_covers = Confirm::new()
+            .with_prompt("Copy covers to the encoded files?")
+            .default(true)
+            .interact()?;
+        let mut remove_brackets = [false; 4];
+        for index in selected_brackets {
+            remove_brackets[index] = true;
+        }
+        Ok(
+            Self {
+                input_directory,
+                output_directory,
+                temp_directory,
+                extensions_to_encode: extensions
+                    .split(',')
+                    .map(|extension| extension.trim().trim_start_matches('.').to_string())
+                    .filter(|extension| !extension.is_empty())
+                    .collect(),
+                preset,
+                remove_brackets,
+                copy_covers,
+            }
+        )
+    }
+    /// The config file with the chosen settings, commented so it can be edited later
+    pub fn render(&self) -> String {
+        let (_, encoded_extension, ffmpeg_params) = PRESETS[self.preset];
+        let mut config = String::from("{\n");
+        config.push_str("  // Local directories, or rclone remotes written like remote:path\n");
+        config.push_str(&format!("  \"inputDirectory\": {},\n", directory_json(&self.input_directory)));
+        config.push_str(&format!("  \"outputDirectory\": {},\n", directory_json(&self.output_directory)));
+        if let Some(temp_directory) = &self.temp_directory {
+            config.push_str("  // Files are downloaded from and uploaded to remotes through this directory\n");
+            config.push_str(&format!("  \"tempDirectory\": {},\n", json_string(temp_directory)));
+        }
+        config.push_str("  // Files with these extensions are encoded, all other files are copied as they are\n");
+        config.push_str(&format!(
+            "  \"extensionsToEncode\": [{}],\n",
+            self.extensions_to_encode.iter().map(|extension| json_string(extension)).collect::<Vec<String>>().join(", ")
+        ));
+        config.push_str("  // The extension and ffmpeg params of the encoded files, ffmpeg is run like:\n");
+        config.push_str("  // ffmpeg -nostdin -y -i <INPUT> <PARAMS> <OUTPUT>\n");
+        config.push_str(&format!("  \"encodedExtension\": {},\n", json_string(encoded_extension)));
+        config.push_str(&format!("  \"ffmpegParams\": {},\n", json_string(ffmpeg_params)));
+        config.push_str("  // Copy the covers of the sources to the encoded files\n");
+        config.push_str(&format!("  \"copyCovers\": {},\n", self.copy_covers));
+        config.push_str("  // Remove brackets and their contents from file names\n");
+        let brackets = BRACKETS
+            .iter()
+            .zip(self.remove_brackets)
+            .map(|((_, option), remove)| format!("  \"{}\": {}", option, remove))
+            .collect::<Vec<String>>();
+        config.push_str(&brackets.join(",\n"));
+        config.push_str("\n  // See README.md for all other options\n}\n");
+        config
+    }
+}
+
+/// Whether the directory is an rclone remote, a drive letter like C: isn't one
+fn is_remote(directory: &str) -> bool {
+    match directory.split_once(':') {
+        Some((remote, _)) => remote.len() > 1,
+        None => false,
+    }
+}
+
+/// A directory in the config, local paths containing a colon are written as an object so they aren't read as remotes
+fn directory_json(directory: &str) -> String {
+    if directory.contains(':') && !is_remote(directory) {
+        format!("{{ \"remote\": \"\", \"path\": {} }}", json_string(directory))
+    } else {
+        json_string(directory)
+    }
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
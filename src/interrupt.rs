@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Exit code of runs stopped with Ctrl+C, as used by shells for SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Files being written right now, see [PartialFile]
+static PARTIAL_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Makes Ctrl+C stop the run after the current action instead of killing it, so the state can be saved
+///
+/// ffmpeg gets the signal as well and stops, a second Ctrl+C exits immediately after removing the files which were
+/// still being written
+pub fn install_handler() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            remove_partial_files();
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("Interrupted, saving the state of the done actions - press Ctrl+C again to exit immediately");
+    })
+}
+
+/// Whether Ctrl+C was pressed
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// A file being written, like the partial output of ffmpeg, removed if the run exits before it's complete
+///
+/// std::process::exit skips the cleanup after a failed write, so the files are tracked until this is dropped
+pub struct PartialFile {
+    path: PathBuf,
+}
+
+impl PartialFile {
+    pub fn track(path: &Path) -> Self {
+        PARTIAL_FILES.lock().unwrap().push(path.to_path_buf());
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        let mut partial_files = PARTIAL_FILES.lock().unwrap();
+        if let Some(index) = partial_files.iter().position(|path| *path == self.path) {
+            partial_files.remove(index);
+        }
+    }
+}
+
+/// Files which are being written right now
+pub fn partial_files() -> Vec<PathBuf> {
+    PARTIAL_FILES.lock().unwrap().clone()
+}
+
+/// Removes the files which are still being written, returns how many were removed
+pub fn remove_partial_files() -> usize {
+    let partial_files = match PARTIAL_FILES.lock() {
+        Ok(mut partial_files) => std::mem::take(&mut *partial_files),
+        Err(_) => return 0,
+    };
+    partial_files.iter().filter(|path| fs::remove_file(path).is_ok()).count()
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::FileHash;
+use crate::state::save_json;
+
+/// Name of the file in the temp directory listing the files in it which are complete
+pub const LEFTOVERS_FILE_NAME: &str = "leftovers.json";
+
+/// Files completed in the temp directory but not moved on yet, so the next run can reuse the ones an interrupted run
+/// left behind instead of downloading or encoding them again
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Leftovers {
+    /// Input file name -> the source copied from the remote input
+    pub sources: BTreeMap<String, LeftoverSource>,
+    /// Input file name -> the output encoded for the remote output
+    pub encodes: BTreeMap<String, LeftoverEncode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LeftoverSource {
+    pub size: u64,
+    /// Hash of the source in the input directory, only taken with detectChanges
+    pub source_hash: Option<FileHash>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LeftoverEncode {
+    pub output_file_name: String,
+    /// ffmpegParams of the config the output was encoded with
+    pub ffmpeg_params: String,
+    pub size: u64,
+    /// Hash of the source in the input directory, only taken with detectChanges
+    pub source_hash: Option<FileHash>,
+}
+
+impl Leftovers {
+    /// Reads the list of the temp directory, an unreadable list is treated like an empty one so nothing is reused
+    pub fn load(temp_directory: &str) -> Self {
+        File::open(Path::new(temp_directory).join(LEFTOVERS_FILE_NAME))
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+    pub fn save(&self, temp_directory: &str) -> io::Result<()> {
+        save_json(Path::new(temp_directory).join(LEFTOVERS_FILE_NAME), self, false)
+    }
+    /// Forgets the files which were moved on or removed since they were listed
+    pub fn retain_existing(&mut self, temp_directory: &str) {
+        let exists = |file_name: &str| Path::new(temp_directory).join(file_name).is_file();
+        self.sources.retain(|input_file_name, _| exists(input_file_name));
+        self.encodes.retain(|_, encode| exists(&encode.output_file_name));
+    }
+}
@@ -0,0 +1,57 @@
+//! Creates an encoded music folder out of a library and keeps it updated using as least ffmpeg runs as possible
+//!
+//! [SyncPlanner] compares the library with the output and the state of the last run, the resulting
+//! [SyncPlan] lists every encode, copy, rename and delete, and [Executor] runs them.
+
+pub mod albums;
+pub mod capabilities;
+pub mod config;
+pub mod content_store;
+pub mod cost;
+pub mod device;
+pub mod display;
+pub mod doctor;
+pub mod events;
+pub mod failures;
+pub mod format;
+pub mod fs_wrapper;
+pub mod hashing;
+pub mod health;
+pub mod i18n;
+pub mod init;
+pub mod interrupt;
+pub mod leftovers;
+pub mod lint;
+pub mod lock;
+pub mod manifest;
+pub mod mirrors;
+mod mqtt;
+pub mod naming;
+pub mod ogg_cover;
+pub mod output_info;
+pub mod overrides;
+pub mod plan;
+pub mod playlists;
+pub mod programs;
+pub mod rclone_filter;
+pub mod rclone_rc;
+pub mod redact;
+pub mod progress;
+pub mod script;
+pub mod secret;
+pub mod server;
+pub mod service;
+pub mod snapshot;
+pub mod state;
+pub mod state_archive;
+pub mod sync;
+pub mod support;
+pub mod tags;
+pub mod telemetry;
+mod tests;
+pub mod theme;
+pub mod throttle;
+pub mod warnings;
+pub mod watch;
+
+pub use sync::{ExecutedActions, Executor, SyncPlan, SyncPlanner};
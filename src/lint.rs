@@ -0,0 +1,397 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+use crate::capabilities::{audio_codec, container, output_format};
+use crate::config::Config;
+use crate::fs_wrapper::RclonePath;
+use crate::health::Severity;
+use crate::theme;
+
+/// A risky setting found in the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintIssue {
+    pub severity: Severity,
+    /// What's wrong, naming the affected setting
+    pub message: String,
+    /// Why it's a problem and how to fix it
+    pub explanation: &'static str,
+}
+
+/// Checks the config for settings which are likely to cause problems, the most severe first
+///
+/// Every output is checked separately when outputs are used
+pub fn lint_config(config: &Config) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+    match &config.outputs {
+        Some(outputs) => {
+            if outputs.is_empty() {
+                issues.push(LintIssue {
+                    severity: Severity::High,
+                    message: String::from("outputs is empty"),
+                    explanation: "Nothing is synced, add an output or use outputDirectory instead",
+                });
+            }
+            for output in outputs {
+                for mut issue in lint_output(&config.for_output(output)) {
+                    issue.message = format!("{}: {}", output.name, issue.message);
+                    issues.push(issue);
+                }
+            }
+        }
+        None if !config.output_directory.is_remote() && config.output_directory.clone().path_string().is_empty() => {
+            issues.push(LintIssue {
+                severity: Severity::High,
+                message: String::from("Neither outputDirectory nor outputs are set"),
+                explanation: "There is nothing to sync to, set outputDirectory",
+            });
+        }
+        None => issues.extend(lint_output(config)),
+    }
+    if config.settle_directories == Some(true) && config.settle_seconds.unwrap_or(0) == 0 {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("settleDirectories is set without settleSeconds"),
+            explanation: "Files are only checked for being written when settleSeconds is set",
+        });
+    }
+    if let Err(error) = config.rclone_filter() {
+        issues.push(LintIssue {
+            severity: Severity::High,
+            message: format!("rcloneFilterFile can't be read: {}", error),
+            explanation: "Syncs fail until the filter file exists and every rule starts with \"+ \" or \"- \"",
+        });
+    }
+    if let Some(nice) = config.nice.filter(|nice| !(-20..=19).contains(nice)) {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: format!("nice {} is outside of -20 to 19", nice),
+            explanation: "The niceness is clamped to this range, use 19 for the lowest priority",
+        });
+    }
+    if config.input_snapshot.is_some() && config.input_directory.is_remote() {
+        issues.push(LintIssue {
+            severity: Severity::Medium,
+            message: String::from("inputSnapshot is set for a remote input directory"),
+            explanation: "Only local filesystems can be snapshotted, the remote is read directly",
+        });
+    }
+    for flag in config.rclone_flags.iter().flatten() {
+        if flag.starts_with('-') && flag.contains(' ') {
+            issues.push(LintIssue {
+                severity: Severity::Medium,
+                message: format!("rcloneFlags entry \"{}\" contains a space", flag),
+                explanation: "Every entry is passed as one argument, give the flag and its value as separate entries or as --flag=value",
+            });
+        }
+    }
+    if let Some(throttle) = &config.throttle {
+        if let (Some(max_temperature), Some(resume_temperature)) = (throttle.max_temperature, throttle.resume_temperature) {
+            if resume_temperature >= max_temperature {
+                issues.push(LintIssue {
+                    severity: Severity::Medium,
+                    message: String::from("throttle.resumeTemperature isn't below maxTemperature"),
+                    explanation: "Encodes resume as soon as they're paused, set resumeTemperature a few degrees lower",
+                });
+            }
+        }
+    }
+    issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+    issues
+}
+
+fn lint_output(config: &Config) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    if config.extensions_to_encode.contains(&config.encoded_extension) {
+        issues.push(LintIssue {
+            severity: Severity::High,
+            message: format!("encodedExtension {} is listed in extensionsToEncode", config.encoded_extension),
+            explanation: "Already encoded files are encoded again, losing quality every time the input is synced. \
+                Remove it from extensionsToEncode to copy these files",
+        });
+    }
+    for encode_rule in config.encode_rules.iter().flatten() {
+        if encode_rule.extensions.contains(&encode_rule.encoded_extension) {
+            issues.push(LintIssue {
+                severity: Severity::Medium,
+                message: format!("Encode rule for {} encodes to the same extension", encode_rule.extensions.join(", ")),
+                explanation: "Files already in the target format are encoded again, losing quality. \
+                    Remove the extension from the rule unless re-encoding them is intended",
+            });
+        }
+    }
+    let speeds = config
+        .encode_rules
+        .iter()
+        .flatten()
+        .map(|encode_rule| ("encodeRules", encode_rule.speed))
+        .chain(config.audiobooks.iter().map(|audiobooks| ("audiobooks", audiobooks.speed)));
+    for (option, speed) in speeds {
+        if let Some(speed) = speed.filter(|speed| !speed.is_finite() || *speed <= 0.0) {
+            issues.push(LintIssue {
+                severity: Severity::High,
+                message: format!("speed {} of {} isn't a positive number", speed, option),
+                explanation: "The speed is ignored and the audio is encoded at its original speed. \
+                    Use a factor like 1.25 to speed it up or 0.8 to slow it down",
+            });
+        }
+    }
+
+    // Every encoded extension with the params producing it
+    let mut encodings = vec![("ffmpegParams", config.encoded_extension.as_str(), config.ffmpeg_params.as_str())];
+    encodings.extend(config.encode_rules.iter().flatten().map(|encode_rule| {
+        ("encodeRules", encode_rule.encoded_extension.as_str(), encode_rule.ffmpeg_params.as_str())
+    }));
+    encodings.extend(config.audiobooks.iter().map(|audiobooks| {
+        ("audiobooks", audiobooks.encoded_extension.as_str(), audiobooks.ffmpeg_params.as_str())
+    }));
+    for (setting, extension, params) in encodings {
+        if extension.starts_with('.') {
+            issues.push(LintIssue {
+                severity: Severity::Medium,
+                message: format!("The encoded extension {} of {} starts with a dot", extension, setting),
+                explanation: "Names would end with two dots, so the dot is removed before encoding. \
+                    Remove it from the config",
+            });
+        }
+        let extension = extension.trim_start_matches('.');
+        let container = match container(extension) {
+            Some(container) => container,
+            None => continue,
+        };
+        if let Some(codec) = audio_codec(params).filter(|codec| !container.fits(codec)) {
+            issues.push(LintIssue {
+                severity: Severity::High,
+                message: format!("{} encodes with {}, which can't be stored in .{} files", setting, codec, extension),
+                explanation: "ffmpeg fails on every file, pick an encoder fitting the encoded extension \
+                    like libopus for ogg, libmp3lame for mp3 or aac for m4a",
+            });
+        }
+        if let Some(format) = output_format(params).filter(|format| !container.formats.contains(format)) {
+            issues.push(LintIssue {
+                severity: Severity::Medium,
+                message: format!("{} forces the {} format, which doesn't write .{} files", setting, format, extension),
+                explanation: "Outputs would be named like another container than they are, so -f is removed \
+                    before encoding. Remove it from the params, ffmpeg picks the format from the extension",
+            });
+        }
+        if config.copy_covers == Some(true) && !container.pictures {
+            issues.push(LintIssue {
+                severity: Severity::Medium,
+                message: format!("copyCovers is set, but .{} files of {} can't store covers", extension, setting),
+                explanation: "Covers aren't copied to these files, use a container like ogg, mp3 or m4a to keep them",
+            });
+        }
+        if params.split(' ').collect::<Vec<&str>>().windows(2).any(|pair| pair == ["-map_chapters", "0"])
+            && !container.chapters
+        {
+            issues.push(LintIssue {
+                severity: Severity::Low,
+                message: format!("{} keeps chapters, but .{} files can't store them", setting, extension),
+                explanation: "The chapters are lost, use a container like ogg or m4b to keep them",
+            });
+        }
+        if !container.gapless {
+            issues.push(LintIssue {
+                severity: Severity::Low,
+                message: format!(".{} files of {} can't be played gaplessly", extension, setting),
+                explanation: "The encoder delay isn't stored, so albums get short silences between tracks. \
+                    Use a container like m4a instead",
+            });
+        }
+    }
+
+    let mut all_params = vec![("ffmpegParams", config.ffmpeg_params.as_str())];
+    all_params.extend(config.encode_rules.iter().flatten().map(|encode_rule| ("encodeRules", encode_rule.ffmpeg_params.as_str())));
+    for (setting, params) in all_params {
+        let params = params.split(' ').collect::<Vec<&str>>();
+        for flag in ["-y", "-n", "-nostdin"] {
+            if params.contains(&flag) {
+                issues.push(LintIssue {
+                    severity: Severity::Medium,
+                    message: format!("{} contains {}", setting, flag),
+                    explanation: "ffmpeg is always run with -nostdin and either -y or -n, use overwriteOutput instead",
+                });
+            }
+        }
+        if params.contains(&"-i") {
+            issues.push(LintIssue {
+                severity: Severity::High,
+                message: format!("{} contains -i", setting),
+                explanation: "The input file is passed by ffmusicsync, another input is mixed into every encoded file",
+            });
+        }
+        if params.contains(&"") {
+            issues.push(LintIssue {
+                severity: Severity::Low,
+                message: format!("{} contains repeated or surrounding spaces", setting),
+                explanation: "The params are split on single spaces, ffmpeg gets empty arguments and fails",
+            });
+        }
+    }
+
+    if is_inside(&config.output_directory, &config.input_directory) {
+        issues.push(LintIssue {
+            severity: Severity::High,
+            message: String::from("outputDirectory is inside inputDirectory"),
+            explanation: "Encoded files become input of the next run and are synced into the output again",
+        });
+    } else if is_inside(&config.input_directory, &config.output_directory) {
+        issues.push(LintIssue {
+            severity: Severity::High,
+            message: String::from("inputDirectory is inside outputDirectory"),
+            explanation: "The input has no encoded counterpart in the output, so it's deleted as a stray file",
+        });
+    }
+
+    if let (Some(output), Some(trash)) = (
+        local_path(&config.output_directory),
+        config.trash_directory.as_ref().and_then(local_path),
+    ) {
+        if trash.starts_with(&output) {
+            issues.push(LintIssue {
+                severity: Severity::High,
+                message: String::from("trashDirectory is inside outputDirectory"),
+                explanation: "Trashed files are listed as outputs without a source and moved to the trash again",
+            });
+        }
+    }
+    if config.trash_days.is_some() && config.trash_directory.is_none() {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("trashDays is set without trashDirectory"),
+            explanation: "Deleted outputs are removed right away, set trashDirectory to keep them",
+        });
+    }
+
+    if (config.input_directory.is_remote() || config.output_directory.is_remote()) && config.temp_directory.is_none() {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("A remote is used without tempDirectory"),
+            explanation: "Files are downloaded to and uploaded from ./temp, set tempDirectory to a disk with enough space",
+        });
+    }
+
+    let brackets = [
+        config.remove_round_brackets,
+        config.remove_square_brackets,
+        config.remove_curly_brackets,
+        config.remove_angle_brackets,
+    ];
+    if brackets.contains(&Some(true)) {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("Brackets are removed from file names"),
+            explanation: "Versions of a song like \"Song (Live)\" and \"Song (Remix)\" get the same name, \
+                the sync is aborted when names collide",
+        });
+    }
+
+    if config.copy_retries.is_some() && config.verify_copies != Some(true) {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("copyRetries is set without verifyCopies"),
+            explanation: "Copies are only retried when they are verified, set verifyCopies to true",
+        });
+    }
+    if config.album_replay_gain == Some(true) && config.output_directory.is_remote() {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("albumReplayGain is set for a remote outputDirectory"),
+            explanation: "Albums are only measured and tagged in local output directories",
+        });
+    }
+    if config.export_folder_covers == Some(true)
+        && (config.input_directory.is_remote() || config.output_directory.is_remote())
+    {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("exportFolderCovers is set with a remote inputDirectory or outputDirectory"),
+            explanation: "Covers are only read from local sources and written to local output directories",
+        });
+    }
+    if config.reencode_unknown_params == Some(true) && config.reencode_on_param_change != Some(true) {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("reencodeUnknownParams is set without reencodeOnParamChange"),
+            explanation: "Params are only recorded and compared when reencodeOnParamChange is true",
+        });
+    }
+    if config.skip_lossy_transcode_max_bitrate.is_some() && config.skip_lossy_transcode != Some(true) {
+        issues.push(LintIssue {
+            severity: Severity::Low,
+            message: String::from("skipLossyTranscodeMaxBitrate is set without skipLossyTranscode"),
+            explanation: "Lossy sources are only copied when skipLossyTranscode is true",
+        });
+    }
+    if config.output_directory.is_remote() {
+        if config.output_device.is_some() {
+            issues.push(LintIssue {
+                severity: Severity::High,
+                message: String::from("outputDevice is set with a remote outputDirectory"),
+                explanation: "The output directory must be local and relative to where the device is mounted",
+            });
+        }
+        if config.flush_output == Some(true) || config.eject_output == Some(true) {
+            issues.push(LintIssue {
+                severity: Severity::Low,
+                message: String::from("flushOutput or ejectOutput is set with a remote outputDirectory"),
+                explanation: "Only local output directories are flushed and ejected",
+            });
+        }
+    }
+    issues
+}
+
+/// Settings making every sync encode the outputs of the previous one again, losing quality each time, syncs refuse
+/// to start with any of them
+pub fn reencode_loops(config: &Config) -> Vec<String> {
+    let mut loops = Vec::new();
+    if config.extensions_to_encode.contains(&config.encoded_extension) {
+        loops.push(format!("encodedExtension {} is listed in extensionsToEncode", config.encoded_extension));
+    }
+    if is_inside(&config.output_directory, &config.input_directory) {
+        loops.push(String::from("outputDirectory is inside inputDirectory"));
+    }
+    loops
+}
+
+/// Whether the directory is the other one or inside it, for local directories and directories of the same remote
+pub fn is_inside(directory: &RclonePath, other: &RclonePath) -> bool {
+    match (directory, other) {
+        (RclonePath::Remote(remote, path), RclonePath::Remote(other_remote, other_path)) => {
+            remote == other_remote && Path::new(path.trim_matches('/')).starts_with(other_path.trim_matches('/'))
+        }
+        (RclonePath::Local(_), RclonePath::Local(_)) => match (local_path(directory), local_path(other)) {
+            (Some(path), Some(other_path)) => path.starts_with(other_path),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Absolute path of a local directory, resolving symlinks if it exists
+fn local_path(directory: &RclonePath) -> Option<PathBuf> {
+    if directory.is_remote() {
+        return None;
+    }
+    let path = directory.clone().path_string();
+    let path = Path::new(&path);
+    fs::canonicalize(path)
+        .ok()
+        .or_else(|| std::env::current_dir().ok().map(|current_dir| current_dir.join(path)))
+}
+
+/// Prints all issues, returns whether any of them has a high severity
+pub fn print_issues(issues: &[LintIssue]) -> bool {
+    if issues.is_empty() {
+        println!("{}", theme::success().apply_to("No problems found in the config"));
+        return false;
+    }
+    for issue in issues {
+        println!("{} ({:?})", theme::severity(issue.severity).apply_to(&issue.message), issue.severity);
+        println!("  {}", issue.explanation);
+    }
+    issues.iter().any(|issue| issue.severity == Severity::High)
+}
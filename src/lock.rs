@@ -0,0 +1,103 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// Advisory lock of an output file, held while ffmusicsync changes it
+///
+/// Only programs which lock files too respect it
+pub enum OutputLock {
+    /// The file doesn't exist or its filesystem doesn't support locks
+    Unlocked,
+    Locked(File),
+    /// Another program holds a lock on the file, with the reason shown in the warning
+    Busy(String),
+}
+
+/// Locks a local output file, without waiting for another program to release it
+pub fn lock_output<P: AsRef<Path>>(path: P) -> OutputLock {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return OutputLock::Unlocked,
+    };
+    match file.try_lock() {
+        Ok(()) => OutputLock::Locked(file),
+        Err(TryLockError::WouldBlock) => OutputLock::Busy(String::from("locked by another program")),
+        Err(TryLockError::Error(_)) => OutputLock::Unlocked,
+    }
+}
+
+/// Lock of the state files of an output, held for the whole run so two runs can't sync the same output at once
+///
+/// The lock file stays next to the state file, only the lock on it is released. The system releases it when the
+/// process exits for any reason, so a run which panicked or was killed never leaves a stale lock behind
+pub struct RunLock {
+    _file: File,
+}
+
+pub enum RunLockError {
+    /// Another run holds the lock, with its process ID if it could be read
+    Held(Option<u32>),
+    Failed(io::Error),
+}
+
+/// The lock file of a state file, encoded.json is locked with encoded.json.lock
+pub fn run_lock_path(state_file: &str) -> String {
+    format!("{}.lock", state_file)
+}
+
+/// The state files in the order they're locked in, each once
+///
+/// Every run locks in the same order of their absolute paths, so two runs sharing outputs never wait for each other
+/// while each holds a lock the other needs
+pub fn run_lock_order(state_files: Vec<String>) -> Vec<String> {
+    let mut state_files = state_files
+        .into_iter()
+        .map(|state_file| {
+            let absolute = std::path::absolute(&state_file).unwrap_or_else(|_| Path::new(&state_file).to_path_buf());
+            (absolute, state_file)
+        })
+        .collect::<Vec<(PathBuf, String)>>();
+    state_files.sort();
+    state_files.dedup_by(|(second, _), (first, _)| first == second);
+    state_files.into_iter().map(|(_, state_file)| state_file).collect()
+}
+
+/// Locks the state file for this run, failing right away if another run holds the lock
+pub fn try_lock_run(state_file: &str) -> Result<RunLock, RunLockError> {
+    let mut file = open_run_lock(state_file).map_err(RunLockError::Failed)?;
+    match file.try_lock() {
+        Ok(()) => write_process_id(file).map_err(RunLockError::Failed),
+        Err(TryLockError::WouldBlock) => {
+            // Windows doesn't allow reading the locked file, the process ID is only shown elsewhere
+            let mut process_id = String::new();
+            let process_id = file.read_to_string(&mut process_id).ok().and_then(|_| process_id.trim().parse().ok());
+            Err(RunLockError::Held(process_id))
+        }
+        Err(TryLockError::Error(error)) => Err(RunLockError::Failed(error)),
+    }
+}
+
+/// Locks the state file for this run, waiting until another run holding the lock exits
+pub fn lock_run(state_file: &str) -> io::Result<RunLock> {
+    let file = open_run_lock(state_file)?;
+    file.lock()?;
+    write_process_id(file)
+}
+
+fn open_run_lock(state_file: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(run_lock_path(state_file))
+}
+
+/// Writes the process ID to the lock file, so the error of another run can tell which one holds it
+fn write_process_id(mut file: File) -> io::Result<RunLock> {
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(std::process::id().to_string().as_bytes())?;
+    Ok(RunLock { _file: file })
+}
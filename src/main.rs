@@ -1,596 +1,1163 @@
-use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::BufWriter;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
-use console::{set_colors_enabled, set_colors_enabled_stderr, Style};
+use clap::{Parser, Subcommand};
+use console::{set_colors_enabled, set_colors_enabled_stderr};
 use dialoguer::Confirm;
 use json_comments::StripComments;
-use lazy_static::lazy_static;
-use regex::Regex;
 
-use crate::config::Config;
-use crate::fs_wrapper::RclonePath;
-use crate::ogg_cover::copy_pictures;
+use ffmusicsync::config::{is_config_url, read_config_source, Config, Glob, OutputProfile};
+use ffmusicsync::display;
+use ffmusicsync::doctor::{print_checks, run_checks};
+use ffmusicsync::events::{Event, EventSink};
+use ffmusicsync::format::parse_duration;
+use ffmusicsync::fs_wrapper;
+use ffmusicsync::fs_wrapper::RclonePath;
+use ffmusicsync::i18n;
+use ffmusicsync::init::InitAnswers;
+use ffmusicsync::interrupt;
+use ffmusicsync::interrupt::INTERRUPTED_EXIT_CODE;
+use ffmusicsync::lint;
+use ffmusicsync::lint::{lint_config, print_issues};
+use ffmusicsync::lock::{lock_run, run_lock_order, try_lock_run, RunLock, RunLockError};
+use ffmusicsync::manifest::Manifest;
+use ffmusicsync::programs;
+use ffmusicsync::rclone_rc;
+use ffmusicsync::redact;
+use ffmusicsync::server;
+use ffmusicsync::service;
+use ffmusicsync::service::Schedule;
+use ffmusicsync::snapshot::Snapshot;
+use ffmusicsync::state_archive::StateArchive;
+use ffmusicsync::support::{
+    format_checks, log_tail, redact_config, redact_failed_files, state_file_stats, SupportBundle,
+};
+use ffmusicsync::sync::list_input;
+use ffmusicsync::telemetry::{config_features, error_category, Telemetry};
+use ffmusicsync::theme;
+use ffmusicsync::theme::Theme;
+use ffmusicsync::tr;
+use ffmusicsync::warnings::{WarningKind, Warnings};
+use ffmusicsync::watch::InputWatcher;
 
-mod config;
-mod tests;
-mod ogg_cover;
-mod fs_wrapper;
+mod sync_output;
+
+use sync_output::{sync_output, StateFiles};
 
 /// A simple utility which creates an encoded music folder out of your library and keeps it updated
 /// using as least ffmpeg runs as possible.
-/// Requires ffmpeg to be installed and in PATH
+/// Requires ffmpeg to be installed, in PATH or set with ffmpegPath
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Specify the config file - See README.md for examples
+    /// Specify the config file, "-" to read it from stdin or a http(s) URL to fetch it - See README.md for examples
     #[clap(short, long, default_value = "config.json")]
     config: String,
     /// Specify the file storing info which songs are already encoded
     #[clap(short, long, default_value = "encoded.json")]
     encoded: String,
-    /// Force colors to be enabled
+    /// Specify the file storing info which songs failed to encode in the last run
+    #[clap(long, default_value = "failed.json")]
+    failed: String,
+    /// Specify the file storing hashes of the processed songs, used by detectChanges
+    #[clap(long, default_value = "hashes.json")]
+    hashes: String,
+    /// Specify the file storing the ffmpeg params of the encoded songs, used by reencodeOnParamChange
+    #[clap(long, default_value = "params.json")]
+    params: String,
+    /// Specify the file storing the changes which could not be pushed to mirrors yet
+    #[clap(long, default_value = "mirrors.json")]
+    mirrors: String,
+    /// Specify the file storing the sizes and durations of the encoded songs, used by verify --shallow
+    #[clap(long, default_value = "sizes.json")]
+    sizes: String,
+    /// Run this ffmpeg instead of the one in PATH, overrides ffmpegPath of the config
+    #[clap(long)]
+    ffmpeg_path: Option<String>,
+    /// Run this rclone instead of the one in PATH, overrides rclonePath of the config
+    #[clap(long)]
+    rclone_path: Option<String>,
+    /// Only process the songs which failed to encode in the last run, without scanning the whole library
     #[clap(long)]
+    retry_failed: bool,
+    /// Only sync the songs matching this glob, can be given multiple times
+    #[clap(long)]
+    only: Vec<Glob>,
+    /// Force colors to be enabled
+    #[clap(long, conflicts_with = "no-color")]
     color: bool,
+    /// Disable colors, the same as setting NO_COLOR
+    #[clap(long)]
+    no_color: bool,
+    /// Colors used in the output: default or colorblind
+    #[clap(long, default_value = "default")]
+    theme: Theme,
+    /// Language of the prompts and summaries, like en or de, defaults to the one of the locale (LANG)
+    #[clap(long)]
+    language: Option<String>,
     /// Always assume "yes" as the answer to all prompts and run non-interactively
     #[clap(short, long)]
     yes: bool,
+    /// Ask separately whether to encode/copy, rename and delete files instead of once
+    #[clap(long)]
+    confirm_each: bool,
+    /// Encode and copy files without asking, implies --confirm-each
+    #[clap(long, conflicts_with = "no-encode")]
+    yes_encode: bool,
+    /// Rename files without asking, implies --confirm-each
+    #[clap(long, conflicts_with = "no-rename")]
+    yes_rename: bool,
+    /// Delete files without asking, implies --confirm-each
+    #[clap(long, conflicts_with = "no-delete")]
+    yes_delete: bool,
+    /// Don't encode or copy any files, implies --confirm-each
+    #[clap(long)]
+    no_encode: bool,
+    /// Don't rename any files, implies --confirm-each
+    #[clap(long)]
+    no_rename: bool,
+    /// Don't delete any files, implies --confirm-each
+    #[clap(long)]
+    no_delete: bool,
+    /// Delete files even if there are more than maxDeleteCount or maxDeletePercent allow
+    #[clap(long)]
+    force_delete: bool,
     /// Suppress ffmpeg output
     #[clap(short, long)]
     quiet: bool,
+    /// Print every action instead of showing progress bars
+    #[clap(long)]
+    no_progress: bool,
     /// Do a trial run with no actual changes
     #[clap(long)]
     dry_run: bool,
+    /// Stop starting new actions after this long, like 2h or 1h30m, the rest is left for the next run
+    #[clap(long, parse(try_from_str = parse_duration))]
+    max_duration: Option<Duration>,
+    /// Keep running and sync again whenever the input changes, polling it this often, like 30s or 5m
+    #[clap(long, parse(try_from_str = parse_duration))]
+    watch: Option<Duration>,
+    /// Don't encode one second with every ffmpeg params before the run to check them
+    #[clap(long)]
+    no_test_encode: bool,
+    /// Copy covers from the source files to already encoded files without re-encoding them
+    #[clap(long)]
+    refresh_covers: bool,
+    /// Copy tags from the source files to already encoded files without re-encoding them
+    #[clap(long)]
+    refresh_tags: bool,
+    /// Wait until the output device is connected instead of failing
+    #[clap(long)]
+    wait_for_device: bool,
+    /// Wait until another run syncing the same output finishes instead of failing
+    #[clap(long)]
+    wait: bool,
+    /// Stream progress events as JSON lines to clients of a Unix domain socket at this path
+    #[clap(long)]
+    events_socket: Option<String>,
+    /// Keep a manifest of all encoded variants of every source, shared by multiple configs
+    #[clap(long)]
+    manifest: Option<String>,
+    /// Write the planned actions as JSON to this file - See README.md for the format
+    #[clap(long, alias = "plan-out")]
+    export_plan: Option<String>,
+    /// Open the planned actions in $VISUAL or $EDITOR before asking whether to continue, removed actions are skipped
+    #[clap(long)]
+    edit_plan: bool,
+    /// Write a shell script with the commands the sync would run to this file, for review or running it by hand
+    #[clap(long)]
+    export_script: Option<String>,
+    /// Replace directory, remote and file names with hashes in the output and the exported plans and reports,
+    /// for sharing them in bug reports
+    #[clap(long)]
+    redact: bool,
+    /// Show files with their input or output directory in the console, like "Phone:Music/Song.ogg", instead of
+    /// relative to it
+    #[clap(long)]
+    absolute_paths: bool,
+    /// Write the planned actions with their outcome as JSON to this file after the run
+    #[clap(long)]
+    export_report: Option<String>,
+    /// Specify the file counting the used options, warnings and errors of the runs, when the config sets telemetry
+    #[clap(long, default_value = "telemetry.json")]
+    telemetry: String,
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Sync the input to the output, the default when no command is given
+    Sync,
+    /// Print the planned actions as JSON, one line per output, without changing anything
+    Status,
+    /// Check the output against the state of the last run, exits with 1 if any file is missing or broken
+    Verify {
+        /// Only compare the sizes of the outputs, checking only the ones which don't match further
+        #[clap(long)]
+        shallow: bool,
+        /// Decode every local encoded output with ffmpeg and compare its duration to the source
+        #[clap(long)]
+        decode: bool,
+        /// Delete the broken outputs and remove them from the state, so the next sync processes them again
+        #[clap(long)]
+        requeue: bool,
+    },
+    /// Delete outputs without a source and files left in the temp directory by interrupted runs
+    Clean,
+    /// Execute a plan written by --export-plan, refusing to run if the directories changed since then
+    Apply {
+        /// Plan file, with outputs the name of each output is inserted like for --export-plan
+        plan: String,
+    },
+    /// Serve an HTTP API to trigger, monitor and cancel syncs
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Check the library for problems and suggest how to fix them, without changing anything
+    Health,
+    /// Check the config for risky settings, exits with 1 if any of them is severe
+    LintConfig,
+    /// Create the config file by answering a few questions
+    Init,
+    /// Check that ffmpeg, its encoders, rclone and its remotes needed by the config are available
+    Doctor,
+    /// Write the config without credentials, the doctor checks, the telemetry and state file stats to an archive
+    /// for attaching it to bug reports
+    SupportBundle {
+        /// Archive to write
+        #[clap(long, default_value = "ffmusicsync-support.tar")]
+        output: String,
+        /// Log of the scheduled task, its last lines are included
+        #[clap(long, default_value = "ffmusicsync.log")]
+        log: String,
+    },
+    /// Write the config, the state files of every output, the telemetry and the manifest to a tar archive
+    /// (compressed with zstd if it ends with .zst), for moving the syncs to another computer
+    ExportState {
+        /// Archive to write, like state.tar.zst
+        archive: String,
+    },
+    /// Restore the files written by export-state, to the paths given by --config and the state file arguments
+    ImportState {
+        /// Archive written by export-state
+        archive: String,
+        /// Replace files which already exist instead of refusing to import
+        #[clap(long)]
+        force: bool,
+    },
+    /// Register the sync or the server as a Windows scheduled task, running in the current directory
+    Service {
+        #[clap(subcommand)]
+        action: ServiceAction,
+    },
+    /// List the targets in the manifest having files from a source file or directory
+    Locate {
+        /// Full source path, as in inputDirectory/path
+        source: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServiceAction {
+    /// Create or replace the task, the arguments given before `service` are passed to every run
+    Install {
+        /// Name of the task
+        #[clap(long, default_value = "ffmusicsync")]
+        name: String,
+        /// Sync repeatedly with this long between the starts, like 30m or 6h
+        #[clap(long)]
+        every: Option<String>,
+        /// Sync once a day at this time, like 03:00
+        #[clap(long)]
+        at: Option<String>,
+        /// Run the server on this address from logon instead of syncing on a schedule
+        #[clap(long)]
+        serve: Option<String>,
+        /// File the output of the runs is appended to
+        #[clap(long, default_value = "ffmusicsync.log")]
+        log: String,
+    },
+    /// Remove the task
+    Uninstall {
+        /// Name of the task
+        #[clap(long, default_value = "ffmusicsync")]
+        name: String,
+    },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // CLion does not auto-detect args type
     let args: Args = Args::parse();
 
-    // Enable colors when running in a console or --color was passed
+    // Enable colors when running in a console or --color was passed, unless disabled by --no-color or NO_COLOR
     if args.color {
         set_colors_enabled(true);
         set_colors_enabled_stderr(true);
+    } else if args.no_color || theme::no_color_env() {
+        set_colors_enabled(false);
+        set_colors_enabled_stderr(false);
+    }
+    theme::set_theme(args.theme);
+    if args.redact {
+        redact::enable();
+    }
+    if args.absolute_paths {
+        display::enable_absolute();
+    }
+    match &args.language {
+        Some(language) if !i18n::set_language(language) => eprintln!(
+            "{}",
+            theme::warning().apply_to(format!(
+                "No translations for {}, available are {}",
+                language,
+                i18n::supported_languages().join(", ")
+            ))
+        ),
+        Some(_) => {}
+        // Unsupported locales fall back to English without a warning
+        None => {
+            if let Some(language) = i18n::language_from_env() {
+                i18n::set_language(&language);
+            }
+        }
     }
 
     // Styles used
-    let bold_red = Style::new().bold().red();
-    let bold_green = Style::new().bold().green();
+    let error_style = theme::error();
+    let success_style = theme::success();
 
-    // Fail if the config file does not exist
-    if !Path::new(&args.config).exists() {
-        eprintln!("{}", bold_red.apply_to("Config file not found"));
+    if let Some(Commands::Serve { listen }) = &args.command {
+        // Every sync reads the config again, stdin can only be read once
+        if args.config == "-" {
+            eprintln!("{}", error_style.apply_to("The config can't be read from stdin in server mode"));
+            std::process::exit(1);
+        }
+        server::serve(listen, sync_args(&args))?;
+        return Ok(());
+    }
+
+    if let Some(Commands::Locate { source }) = &args.command {
+        let manifest = Manifest::load(args.manifest.as_deref().unwrap_or("manifest.json"))?;
+        let targets = manifest.locate(source);
+        if targets.is_empty() {
+            println!("{} is not on any target", source);
+        }
+        for (target, count) in targets {
+            println!("{} ({} files)", target, count);
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Init) = &args.command {
+        if args.config == "-" || is_config_url(&args.config) {
+            eprintln!("{}", error_style.apply_to("init needs a config file to write to"));
+            std::process::exit(1);
+        }
+        if Path::new(&args.config).exists()
+            && !Confirm::new()
+                .with_prompt(format!("{} already exists, overwrite it?", args.config))
+                .default(false)
+                .interact()?
+        {
+            println!("Aborting");
+            std::process::exit(3);
+        }
+        let contents = InitAnswers::prompt()?.render();
+        std::fs::write(&args.config, &contents)?;
+        println!("{}", success_style.apply_to(format!("Wrote {}", args.config)));
+        let config: Config = serde_json::from_reader(StripComments::new(contents.as_bytes()))?;
+        let issues = lint_config(&config);
+        if !issues.is_empty() {
+            print_issues(&issues);
+        }
+        println!("Run ffmusicsync --dry-run to see what the first sync does");
+        return Ok(());
+    }
+
+    if let Some(Commands::Doctor) = &args.command {
+        let mut config = read_config_source(&args.config)
+            .map_err(|error| format!("{} couldn't be read: {}", args.config, error))
+            .and_then(|config_contents| {
+                serde_json::from_reader::<_, Config>(StripComments::new(config_contents.as_bytes()))
+                    .map_err(|error| format!("{} is invalid: {}", args.config, error))
+            });
+        if let Ok(config) = &mut config {
+            override_program_paths(&args, config);
+        }
+        if print_checks(&run_checks(config.as_ref().map_err(String::clone))) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::SupportBundle { output, log }) = &args.command {
+        let bundle = support_bundle(&args, log);
+        bundle.write_tar(BufWriter::new(File::create(output)?))?;
+        println!("{}", success_style.apply_to(format!("Wrote {}", output)));
+        for file_name in bundle.file_names() {
+            println!(" - {}", file_name);
+        }
+        if !args.redact {
+            println!("Check the files before attaching the archive, the failed files list paths of the library");
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::ExportState { archive }) = &args.command {
+        match export_state(&args, archive) {
+            Ok(file_names) => {
+                println!("{}", success_style.apply_to(format!("Wrote {}", archive)));
+                for file_name in file_names {
+                    println!(" - {}", file_name);
+                }
+                println!("The config is exported as it is, keep the archive private if it contains credentials");
+            }
+            Err(error) => {
+                eprintln!("{}", error_style.apply_to(format!("Failed to export the state: {}", error)));
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::ImportState { archive, force }) = &args.command {
+        match import_state(&args, archive, *force) {
+            Ok(paths) => {
+                println!("{}", success_style.apply_to(format!("Imported {}", archive)));
+                for path in paths {
+                    println!(" - {}", path);
+                }
+                println!("Check the directories of the config, then run ffmusicsync --dry-run to see what the next sync does");
+            }
+            Err(error) => {
+                eprintln!("{}", error_style.apply_to(format!("Failed to import the state: {}", error)));
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    // Other commands run once, and retrying the failed files doesn't read the input --watch polls
+    if args.watch.is_some() && (!matches!(args.command, None | Some(Commands::Sync)) || args.retry_failed) {
+        eprintln!("{}", error_style.apply_to("--watch only works with sync and without --retry-failed"));
         std::process::exit(1);
     }
 
-    // Fail if ffmpeg is not found
-    let ffmpeg_test = Command::new("ffmpeg").arg("-version").output();
-    if ffmpeg_test.is_err() {
-        eprintln!("{}", bold_red.apply_to("ffmpeg not found"));
+    // Fail if the config file does not exist
+    if args.config != "-" && !is_config_url(&args.config) && !Path::new(&args.config).exists() {
+        eprintln!("{}", error_style.apply_to(tr!("config-not-found")));
         std::process::exit(1);
     }
 
-    // Read config from file
-    let config_file = File::open(&args.config)?;
-    let config_reader = BufReader::new(config_file);
-    let config_reader_no_comments = StripComments::new(config_reader);
-    let config: Config = serde_json::from_reader(config_reader_no_comments)?;
+    if let Some(Commands::Service { action }) = &args.command {
+        if args.config == "-" {
+            eprintln!("{}", error_style.apply_to("The config can't be read from stdin by a scheduled task"));
+            std::process::exit(1);
+        }
+        if let Err(error) = service(&args, action) {
+            eprintln!("{}", error_style.apply_to(error));
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    // Fail if rclone is not found and should be used
-    if config.input_directory.is_remote() || config.output_directory.is_remote() {
-        let rclone_test = Command::new("rclone").arg("version").output();
-        if rclone_test.is_err() {
-            eprintln!("{}", bold_red.apply_to("rclone not found"));
+    // Read config from file, stdin or a URL
+    let config_contents = match read_config_source(&args.config) {
+        Ok(config_contents) => config_contents,
+        Err(error) => {
+            eprintln!("{}", error_style.apply_to(tr!("config-unreadable", error = error.to_string())));
+            std::process::exit(1);
+        }
+    };
+    let config_reader_no_comments = StripComments::new(config_contents.as_bytes());
+    let mut config: Config = serde_json::from_reader(config_reader_no_comments)?;
+    override_program_paths(&args, &mut config);
+    if let Some(Commands::LintConfig) = &args.command {
+        if print_issues(&lint_config(&config)) {
             std::process::exit(1);
         }
+        return Ok(());
+    }
+    if config.outputs.is_none() && !config.output_directory.is_remote() && config.output_directory.clone().path_string().is_empty() {
+        eprintln!("{}", error_style.apply_to(tr!("config-without-output")));
+        std::process::exit(1);
+    }
+    let rclone_filter = match config.rclone_filter() {
+        Ok(rclone_filter) => rclone_filter,
+        Err(error) => {
+            eprintln!("{}", error_style.apply_to(format!("Failed to read rcloneFilterFile: {}", error)));
+            std::process::exit(1);
+        }
+    };
+    let output_configs = match &config.outputs {
+        Some(outputs) => outputs.iter().map(|output| config.for_output(output)).collect(),
+        None => vec![config.clone()],
+    };
+    let reencode_loops = output_configs.iter().flat_map(lint::reencode_loops).collect::<Vec<String>>();
+    if !reencode_loops.is_empty() {
+        eprintln!("{}", error_style.apply_to(tr!("reencode-loops")));
+        for reencode_loop in reencode_loops {
+            eprintln!(" - {}", reencode_loop);
+        }
+        std::process::exit(1);
+    }
+    let run_locks = lock_state_files(&args, &config);
+
+    let mut warnings = Warnings::new();
+    let mut events = EventSink::new();
+    if let Some(events_socket) = &args.events_socket {
+        events.bind_socket(PathBuf::from(events_socket))?;
+    }
+    if let Some(mqtt) = &config.mqtt {
+        if let Err(error) = events.connect_mqtt(mqtt) {
+            warnings.push(WarningKind::StatusPublishFailed, mqtt.broker.clone(), Some(error.to_string()));
+        }
+    }
+
+    let snapshot = snapshot_input(&args, &mut config);
+    if config.rclone_rc == Some(true) {
+        if let Err(error) = rclone_rc::start() {
+            eprintln!(
+                "{}",
+                theme::warning().apply_to(format!("Failed to start rclone rcd, running rclone for every operation: {}", error))
+            );
+        }
     }
 
-    // Read already processed songs
-    let mut encoded: HashMap<String, String> = if let Ok(encoded_file) = File::open(&args.encoded) {
-        let encoded_reader = BufReader::new(encoded_file);
-        serde_json::from_reader(encoded_reader)?
-    } else {
-        // None are processed if the file doesn't exist
-        HashMap::new()
+    // Every output is synced with its own config and state files
+    let outputs = match &config.outputs {
+        Some(outputs) => outputs
+            .iter()
+            .map(|output| {
+                let state_files = StateFiles {
+                    encoded: encoded_state_file(&args, Some(output)),
+                    failed: output_state_file(&args.failed, &output.name),
+                    hashes: output_state_file(&args.hashes, &output.name),
+                    params: output_state_file(&args.params, &output.name),
+                    mirrors: output_state_file(&args.mirrors, &output.name),
+                    sizes: output_state_file(&args.sizes, &output.name),
+                    export_plan: args.export_plan.as_ref().map(|export_plan| output_state_file(export_plan, &output.name)),
+                    export_script: args.export_script.as_ref().map(|export_script| output_state_file(export_script, &output.name)),
+                    export_report: args.export_report.as_ref().map(|export_report| output_state_file(export_report, &output.name)),
+                    apply_plan: apply_plan(&args).map(|apply_plan| output_state_file(apply_plan, &output.name)),
+                };
+                (Some(output.name.clone()), config.for_output(output), state_files)
+            })
+            .collect::<Vec<_>>(),
+        None => {
+            let state_files = StateFiles {
+                encoded: encoded_state_file(&args, None),
+                failed: args.failed.clone(),
+                hashes: args.hashes.clone(),
+                params: args.params.clone(),
+                mirrors: args.mirrors.clone(),
+                sizes: args.sizes.clone(),
+                export_plan: args.export_plan.clone(),
+                export_script: args.export_script.clone(),
+                export_report: args.export_report.clone(),
+                apply_plan: apply_plan(&args).map(String::from),
+            };
+            vec![(None, config.clone(), state_files)]
+        }
     };
 
-    // Read songs that are present in the filesystem already
-    let input = fs_wrapper::list_files_recursively(&config.input_directory)
-        .into_iter()
-        .map(|file| {
-            Path::new(&file.path_string())
-                .strip_prefix(config.input_directory.clone().path_string()).unwrap()
-                .to_string_lossy().to_string()
-        })
-        .collect::<HashSet<String>>();
-    let output = fs_wrapper::list_files_recursively(&config.output_directory)
-        .into_iter()
-        .map(|file| {
-            Path::new(&file.path_string())
-                .strip_prefix(config.output_directory.clone().path_string()).unwrap()
-                .to_string_lossy().to_string()
-        })
-        .collect::<HashSet<String>>();
-
-    // Check for name collisions
-    let encoded_names = input
-        .iter()
-        .map(|input_file_name| create_output_file_name(input_file_name.to_string(), &config))
-        .collect::<HashSet<String>>();
-    if encoded_names.len() != input.len() {
-        eprintln!(
-            "{}",
-            bold_red.apply_to("Found a name collision with the current settings, aborting")
-        );
-        let encoded = create_final_encoded_map(input, &config);
-        // Find and print the colliding names
-        encoded_names
-            .into_iter()
-            .for_each(|search_output_file_name| {
-                let duplicates = encoded
-                    .clone()
-                    .into_iter()
-                    .filter_map(|(input_file_name, output_file_name)| {
-                        if output_file_name == search_output_file_name {
-                            Some(input_file_name)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<String>>();
-                if duplicates.len() != 1 {
-                    eprintln!(
-                        "{} is the resulting file name for:",
-                        search_output_file_name
-                    );
-                    duplicates.into_iter().for_each(|file_name| {
-                        eprintln!(" - {}", file_name);
+    interrupt::install_handler()?;
+    let started = Instant::now();
+    let telemetry = config.telemetry == Some(true);
+    let settle_time = Duration::from_secs(config.settle_seconds.unwrap_or(0));
+    // The first poll is taken before the first sync, so files written while it runs are synced by the next one
+    let mut watcher = match args.watch {
+        Some(_) => Some(InputWatcher::new(settle_time, fs_wrapper::list_file_sizes(&config.input_directory)?)),
+        None => None,
+    };
+    let mut unsettled = None;
+    let mut failed = 0;
+    let mut pending = false;
+    let mut exit_code = None;
+    loop {
+        let sync_started = Instant::now();
+        let previously_failed = failed;
+        let deadline = args.max_duration.map(|max_duration| sync_started + max_duration);
+        // List the input only once for all outputs, and with the files --watch saw being written
+        let input = if !args.retry_failed && (outputs.len() > 1 || watcher.is_some()) {
+            match list_input(&config, rclone_filter.as_ref(), unsettled.as_ref(), &mut warnings) {
+                Ok(input) => Some(input),
+                Err(error) => {
+                    let error = Box::<dyn std::error::Error>::from(error);
+                    if telemetry {
+                        record_telemetry(&args, &config_contents, started, &warnings, failed, Some(error.as_ref()));
+                    }
+                    events.emit(Event::Completed {
+                        warnings: warnings.len(),
+                        failed,
+                        exit_code: None,
+                        error: Some(&error.to_string()),
                     });
-                    eprintln!();
+                    return Err(error);
+                }
+            }
+        } else {
+            None
+        };
+        for (name, config, state_files) in &outputs {
+            if interrupt::is_interrupted() {
+                exit_code = Some(INTERRUPTED_EXIT_CODE);
+                break;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                println!("{}", tr!("out-of-time-outputs"));
+                break;
+            }
+            if let (Some(name), false) = (name, matches!(args.command, Some(Commands::Status))) {
+                println!("{}", success_style.apply_to(tr!("syncing-output", name = name.as_str())));
+            }
+            match sync_output(&args, config.clone(), state_files, input.clone(), deadline, &events, &mut warnings) {
+                Ok(summary) => {
+                    failed += summary.failed;
+                    pending |= summary.pending;
+                    if summary.exit_code.is_some() {
+                        exit_code = summary.exit_code;
+                        break;
+                    }
+                }
+                Err(error) => {
+                    if telemetry {
+                        record_telemetry(&args, &config_contents, started, &warnings, failed, Some(error.as_ref()));
+                    }
+                    events.emit(Event::Completed {
+                        warnings: warnings.len(),
+                        failed,
+                        exit_code: None,
+                        error: Some(&error.to_string()),
+                    });
+                    return Err(error);
                 }
-            });
-        std::process::exit(2);
-    }
-
-    // Find which songs need to be processed
-    let encoded_output = encoded.values().cloned().collect::<HashSet<String>>();
-    let removed_output = encoded_output
-        .difference(&output)
-        .cloned()
-        .collect::<HashSet<String>>();
-    let encoded_reverse = encoded
-        .clone()
-        .into_iter()
-        .map(|(input_file_name, output_file_name)| (output_file_name, input_file_name))
-        .collect::<HashMap<String, String>>();
-
-    // Songs removed from the destination directory but encoded previously
-    for removed_file in removed_output {
-        if let Some(file_to_recode) = encoded_reverse.get(&removed_file) {
-            encoded.remove(file_to_recode);
-        }
-    }
-
-    // Songs removed from the encoded.json but present in the output dir with the correct name
-    let encoded_not_saved_output: HashSet<String> =
-        output.difference(&encoded_output).cloned().collect();
-    for input_file_name in input.clone() {
-        let output_file_name = create_output_file_name(input_file_name.clone(), &config);
-        if encoded_not_saved_output.contains(&output_file_name) {
-            encoded.insert(input_file_name, output_file_name);
-        }
-    }
-
-    // Songs encoded with the wrong extension
-    for (input_file, output_file) in encoded.clone() {
-        let input_file_extension = Path::new(&input_file)
-            .extension()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string();
-        if (&config)
-            .extensions_to_encode
-            .contains(&input_file_extension)
-        {
-            let output_file_extension = Path::new(&output_file)
-                .extension()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string();
-            if output_file_extension != (&config).encoded_extension {
-                encoded.remove(&input_file);
             }
         }
+        let (Some(watch), Some(watcher), None) = (args.watch, &mut watcher, exit_code) else {
+            break;
+        };
+        // Every sync reports its own warnings, the failed files are retried by the next sync
+        events.emit(Event::Completed {
+            warnings: warnings.len(),
+            failed: failed - previously_failed,
+            exit_code: None,
+            error: None,
+        });
+        warnings.print();
+        warnings = Warnings::new();
+        println!("{}", tr!("watching-input"));
+        if !watcher.wait_for_changes(&config.input_directory, watch, sync_started) {
+            exit_code = Some(INTERRUPTED_EXIT_CODE);
+            break;
+        }
+        unsettled = Some(watcher.unsettled(Instant::now()));
     }
-
-    // Encoded songs with an incorrect name (After a config change) - rename without re-encoding
-    let mut output_to_rename = HashMap::new();
-    for input_file in encoded.keys() {
-        let new_name = create_output_file_name(input_file.to_string(), &config);
-        let old_name = encoded.get(input_file).unwrap().to_string();
-        if new_name != old_name {
-            output_to_rename.insert(old_name, new_name);
+    // Exiting skips destructors, so the run only exits once everything is cleaned up
+    drop(snapshot);
+    drop(run_locks);
+    rclone_rc::stop();
+    if telemetry {
+        record_telemetry(&args, &config_contents, started, &warnings, failed, None);
+    }
+    events.emit(Event::Completed {
+        warnings: warnings.len(),
+        failed,
+        exit_code,
+        error: None,
+    });
+    if let Some(exit_code) = exit_code {
+        warnings.print();
+        std::process::exit(exit_code);
+    }
+    match &args.command {
+        Some(Commands::Health) => return Ok(()),
+        Some(Commands::Status) => {
+            // Scripts can tell whether a sync would change anything without parsing the plan
+            if pending {
+                std::process::exit(5);
+            }
+            return Ok(());
+        }
+        Some(Commands::Verify { .. }) => {
+            if failed > 0 {
+                std::process::exit(1);
+            }
+            return Ok(());
         }
+        _ => {}
     }
 
-    // All input songs which are not present in encoded need to be processed
-    // All output songs which are not present in encoded need to be deleted
-    let encoded_input: HashSet<String> = encoded.keys().cloned().collect();
-    let encoded_output: HashSet<String> = encoded.values().cloned().collect();
-    let input_to_process: HashSet<String> = input.difference(&encoded_input).cloned().collect();
-    let output_to_delete: HashSet<String> = output.difference(&encoded_output).cloned().collect();
+    warnings.print();
 
-    // Ask user whether to continue
-    println!(
-        "{}",
-        bold_green.apply_to(format!(
-            "{} songs to encode/copy, {} to rename and {} to delete",
-            input_to_process.len(),
-            output_to_rename.len(),
-            output_to_delete.len()
-        ))
-    );
+    // The other files were synced, but the run still has to be noticed by scripts
+    if failed > 0 {
+        eprintln!("{}", error_style.apply_to(tr!("failed-encodes", count = failed)));
+        std::process::exit(4);
+    }
+    if args.dry_run && pending {
+        std::process::exit(5);
+    }
 
-    if !args.yes {
-        if !Confirm::new()
-            .with_prompt("Do you want to continue?")
-            .interact()?
-        {
-            println!("Aborting");
-            std::process::exit(3);
-        }
+    Ok(())
+}
+
+/// The encoded.json of an output, or of the only one without outputs
+fn encoded_state_file(args: &Args, output: Option<&OutputProfile>) -> String {
+    match output {
+        Some(output) => output
+            .encoded
+            .clone()
+            .unwrap_or_else(|| output_state_file(&args.encoded, &output.name)),
+        None => args.encoded.clone(),
     }
+}
 
-    // Create required directories
-    let output_directories = input_to_process.clone().into_iter()
-        .map(
-            |path| {
-                Path::new(&path).parent().unwrap_or(Path::new("")).to_string_lossy().to_string()
+/// Locks the state files of every output for the run, exiting if another run holds a lock unless --wait is given
+///
+/// Runs which don't save any state don't take the locks, another run changing the state doesn't affect them
+fn lock_state_files(args: &Args, config: &Config) -> Vec<RunLock> {
+    let error_style = theme::error();
+    let read_only = args.dry_run
+        || matches!(
+            args.command,
+            Some(Commands::Status | Commands::Health | Commands::Verify { requeue: false, .. })
+        );
+    if read_only {
+        return Vec::new();
+    }
+    let encoded_state_files = match &config.outputs {
+        Some(outputs) => outputs.iter().map(|output| encoded_state_file(args, Some(output))).collect(),
+        None => vec![encoded_state_file(args, None)],
+    };
+    // Waiting with --wait while holding locks another run waits for would never end
+    let encoded_state_files = run_lock_order(encoded_state_files);
+    let mut run_locks = Vec::new();
+    for encoded_state_file in encoded_state_files {
+        let run_lock = match try_lock_run(&encoded_state_file) {
+            Ok(run_lock) => Ok(run_lock),
+            Err(RunLockError::Held(process_id)) => {
+                let holder = process_id.map(|process_id| format!(" (process {})", process_id)).unwrap_or_default();
+                if !args.wait {
+                    eprintln!(
+                        "{}",
+                        error_style.apply_to(format!(
+                            "Another run{} is syncing {}, pass --wait to wait for it to finish",
+                            holder, encoded_state_file
+                        ))
+                    );
+                    std::process::exit(1);
+                }
+                println!("Waiting for the other run{} syncing {} to finish", holder, encoded_state_file);
+                lock_run(&encoded_state_file)
             }
-        ).collect::<HashSet<String>>();
-
-    let temp_directory = config.temp_directory.clone().unwrap_or(String::from("temp"));
-    if config.input_directory.is_remote() || config.output_directory.is_remote() {
-        if args.dry_run {
-            eprintln!("Skipping creation of temp directory as --dry-run is set");
-        } else {
-            println!("Creating directory {}", temp_directory);
-            fs_wrapper::create_dir_all(
-                &RclonePath::Local(temp_directory.clone())
-            )?;
-            for output_directory in output_directories.clone() {
-                println!("Creating directory {}", output_directory);
-                fs_wrapper::create_dir_all(
-                    &RclonePath::Local(
-                        format!(
-                            "{}/{}",
-                            temp_directory.clone(),
-                            output_directory
-                        )
-                    )
-                )?;
+            Err(RunLockError::Failed(error)) => Err(error),
+        };
+        match run_lock {
+            Ok(run_lock) => run_locks.push(run_lock),
+            Err(error) => {
+                eprintln!("{}", error_style.apply_to(format!("Failed to lock {}: {}", encoded_state_file, error)));
+                std::process::exit(1);
             }
         }
     }
+    run_locks
+}
 
-    if args.dry_run {
-        eprintln!("Skipping creation of output directory as --dry-run is set");
-    } else {
-        println!("Creating output directory");
-        fs_wrapper::create_dir_all(&config.output_directory)?;
+/// Puts the ffmpeg and rclone paths given on the command line in the config, they override ffmpegPath and rclonePath
+fn override_program_paths(args: &Args, config: &mut Config) {
+    if let Some(ffmpeg_path) = &args.ffmpeg_path {
+        config.ffmpeg_path = Some(ffmpeg_path.clone());
+    }
+    if let Some(rclone_path) = &args.rclone_path {
+        config.rclone_path = Some(rclone_path.clone());
+    }
+    programs::use_config(config);
+}
+
+/// Arguments of the syncs started by the server or a scheduled task, the state files and options given to this run
+fn sync_args(args: &Args) -> Vec<String> {
+    let mut sync_args = vec![
+        String::from("--config"),
+        args.config.clone(),
+        String::from("--encoded"),
+        args.encoded.clone(),
+        String::from("--failed"),
+        args.failed.clone(),
+        String::from("--hashes"),
+        args.hashes.clone(),
+        String::from("--params"),
+        args.params.clone(),
+        String::from("--mirrors"),
+        args.mirrors.clone(),
+        String::from("--sizes"),
+        args.sizes.clone(),
+        String::from("--telemetry"),
+        args.telemetry.clone(),
+        String::from("--yes"),
+    ];
+    if args.quiet {
+        sync_args.push(String::from("--quiet"));
+    }
+    if args.redact {
+        sync_args.push(String::from("--redact"));
+    }
+    if args.absolute_paths {
+        sync_args.push(String::from("--absolute-paths"));
+    }
+    if let Some(language) = &args.language {
+        sync_args.extend([String::from("--language"), language.clone()]);
     }
+    for (arg, path) in [("--ffmpeg-path", &args.ffmpeg_path), ("--rclone-path", &args.rclone_path)] {
+        if let Some(path) = path {
+            sync_args.extend([String::from(arg), path.clone()]);
+        }
+    }
+    sync_args
+}
 
-    for output_directory in output_directories {
-        if output_directory != "" {
+/// Installs or uninstalls the scheduled task, only printing what would be registered with --dry-run
+fn service(args: &Args, action: &ServiceAction) -> Result<(), String> {
+    if !cfg!(windows) && !args.dry_run {
+        return Err(String::from(
+            "service registers a Windows scheduled task, use a cron job or systemd timer on this system \
+             (--dry-run prints the task)",
+        ));
+    }
+    let (name, every, at, serve, log) = match action {
+        ServiceAction::Install { name, every, at, serve, log } => (name, every, at, serve, log),
+        ServiceAction::Uninstall { name } => {
             if args.dry_run {
-                eprintln!("Skipping creation of output directory {} as --dry-run is set", output_directory);
-            } else {
-                println!("Creating output directory {}", output_directory);
-                fs_wrapper::create_dir_all(
-                    &config.output_directory.with_path(
-                        format!(
-                            "{}/{}",
-                            config.output_directory.clone().path_string(),
-                            output_directory
-                        )
-                    )
-                )?;
+                println!("schtasks /Delete /TN {} /F", service::cmd_quote(name));
+                return Ok(());
             }
+            let status = Command::new("schtasks")
+                .args(["/Delete", "/TN", name, "/F"])
+                .status()
+                .map_err(|error| format!("Failed to run schtasks: {}", error))?;
+            if !status.success() {
+                return Err(format!("Failed to remove the task {}", name));
+            }
+            println!("Removed the task {}, its script and log are left in place", name);
+            return Ok(());
         }
+    };
+    let every = every.as_deref().map(parse_duration).transpose()?;
+    let schedule = Schedule::new(every, at.as_deref(), serve.is_some())?;
+    let working_directory = std::env::current_dir().map_err(|error| error.to_string())?;
+    let program = std::env::current_exe().map_err(|error| error.to_string())?;
+    // Colors would end up as escape codes in the log
+    let mut arguments = sync_args(args);
+    arguments.push(String::from("--no-color"));
+    if let Some(listen) = serve {
+        arguments.extend([String::from("serve"), String::from("--listen"), listen.clone()]);
+    }
+    let script_path = working_directory.join(format!("{}-task.cmd", name));
+    let script = service::task_script(
+        &working_directory.to_string_lossy(),
+        &program.to_string_lossy(),
+        &arguments,
+        log,
+    );
+    let user = match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+        (Ok(domain), Ok(user)) => format!("{}\\{}", domain, user),
+        (_, user) => user.unwrap_or_default(),
+    };
+    let xml = service::task_xml(&schedule, &user, &script_path.to_string_lossy(), &working_directory.to_string_lossy());
+    if args.dry_run {
+        println!("{}:\n{}", script_path.display(), script.replace("\r\n", "\n"));
+        println!("Task {}:\n{}", name, xml);
+        return Ok(());
+    }
+    std::fs::write(&script_path, script).map_err(|error| format!("Failed to write {}: {}", script_path.display(), error))?;
+    let xml_path = std::env::temp_dir().join(format!("{}-task.xml", name));
+    std::fs::write(&xml_path, service::encode_utf16(&xml)).map_err(|error| error.to_string())?;
+    let status = Command::new("schtasks")
+        .args(["/Create", "/TN", name, "/XML"])
+        .arg(&xml_path)
+        .arg("/F")
+        .status();
+    std::fs::remove_file(&xml_path).ok();
+    let status = status.map_err(|error| format!("Failed to run schtasks: {}", error))?;
+    if !status.success() {
+        return Err(format!("Failed to register the task {}", name));
+    }
+    println!("Registered the task {}, running {}", name, script_path.display());
+    println!("The output of every run is appended to {}", working_directory.join(log).display());
+    Ok(())
+}
+
+/// Name of the command for the telemetry
+fn command_name(args: &Args) -> &'static str {
+    match &args.command {
+        None | Some(Commands::Sync) => "sync",
+        Some(Commands::Status) => "status",
+        Some(Commands::Verify { .. }) => "verify",
+        Some(Commands::Clean) => "clean",
+        Some(Commands::Apply { .. }) => "apply",
+        Some(Commands::Serve { .. }) => "serve",
+        Some(Commands::Health) => "health",
+        Some(Commands::LintConfig) => "lint-config",
+        Some(Commands::Init) => "init",
+        Some(Commands::Doctor) => "doctor",
+        Some(Commands::SupportBundle { .. }) => "support-bundle",
+        Some(Commands::ExportState { .. }) => "export-state",
+        Some(Commands::ImportState { .. }) => "import-state",
+        Some(Commands::Service { .. }) => "service",
+        Some(Commands::Locate { .. }) => "locate",
     }
+}
 
-    // Process all files
+/// Adds the run to the --telemetry file, a file which can't be written only warns
+fn record_telemetry(
+    args: &Args,
+    config_contents: &str,
+    started: Instant,
+    warnings: &Warnings,
+    failed: usize,
+    error: Option<&(dyn std::error::Error + 'static)>,
+) {
+    let config = serde_json::from_reader::<_, serde_json::Value>(StripComments::new(config_contents.as_bytes()))
+        .unwrap_or_default();
+    let result = Telemetry::load(&args.telemetry).and_then(|mut telemetry| {
+        telemetry.record_run(
+            command_name(args),
+            &config_features(&config),
+            started.elapsed().as_secs(),
+            warnings,
+            failed,
+            error.map(error_category).as_deref(),
+        );
+        telemetry.save(&args.telemetry)
+    });
+    if let Err(error) = result {
+        eprintln!("{}", theme::warning().apply_to(format!("Failed to save {}: {}", args.telemetry, error)));
+    }
+}
 
-    // Delete files
-    for file_to_delete in output_to_delete {
-        println!("Deleting {}", file_to_delete);
-        if args.dry_run {
-            eprintln!("Skipping delete as --dry-run is set");
-        } else {
-            fs_wrapper::remove_file(
-                &config.output_directory.with_path(
-                    format!(
-                        "{}/{}",
-                        config.output_directory.clone().path_string(),
-                        file_to_delete
-                    )
-                )
-            )?;
-        }
-    }
-
-    // Rename already encoded
-    for (old_file_name, new_file_name) in output_to_rename {
-        println!("Renaming {} to {}", old_file_name, new_file_name);
-        if args.dry_run {
-            eprintln!("Skipping rename as --dry-run is set");
-        } else {
-            fs_wrapper::rename(
-                &config.output_directory.with_path(
-                    format!(
-                        "{}/{}",
-                        config.output_directory.clone().path_string(),
-                        old_file_name
-                    )
-                ),
-                &config.output_directory.with_path(
-                    format!(
-                        "{}/{}",
-                        config.output_directory.clone().path_string(),
-                        new_file_name
-                    )
-                ),
-            )?;
-        }
-    }
-
-    // Encode or copy
-    for input_file_name in input_to_process {
-        let file_extension = Path::new(&input_file_name)
-            .extension()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string();
-        let output_file_name = create_output_file_name(input_file_name.clone(), &config);
-        if (&config).extensions_to_encode.contains(&file_extension) {
-            println!(
-                "Encoding {} to {} with ffmpeg params {}",
-                input_file_name, output_file_name, config.ffmpeg_params
+/// Collects what's needed to look into a bug report, see [SupportBundle]
+fn support_bundle(args: &Args, log: &str) -> SupportBundle {
+    let mut bundle = SupportBundle::new();
+    bundle.add(
+        "version.txt",
+        format!(
+            "ffmusicsync {}\n{} {}\n",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ),
+    );
+    let config_contents = read_config_source(&args.config)
+        .map_err(|error| format!("{} couldn't be read: {}", args.config, error));
+    let config_value = config_contents.clone().and_then(|config_contents| {
+        serde_json::from_reader::<_, serde_json::Value>(StripComments::new(config_contents.as_bytes()))
+            .map_err(|error| format!("{} is invalid: {}", args.config, error))
+    });
+    let mut config = config_value.clone().and_then(|config_value| {
+        serde_json::from_value::<Config>(config_value).map_err(|error| format!("{} is invalid: {}", args.config, error))
+    });
+    if let Ok(config) = &mut config {
+        override_program_paths(args, config);
+    }
+    match config_value {
+        Ok(mut config_value) => {
+            redact_config(&mut config_value);
+            bundle.add("config.json", serde_json::to_string_pretty(&config_value).unwrap_or_default());
+        }
+        Err(error) => bundle.add("config-error.txt", error),
+    }
+    bundle.add("doctor.txt", format_checks(&run_checks(config.as_ref().map_err(String::clone))));
+    if let Ok(telemetry) = std::fs::read(&args.telemetry) {
+        bundle.add("telemetry.json", telemetry);
+    }
+    // The state files of every output, named like the sync names them
+    let output_names = config
+        .iter()
+        .flat_map(|config| config.outputs.iter().flatten())
+        .map(|output| Some(output.name.clone()))
+        .collect::<Vec<Option<String>>>();
+    let output_names = if output_names.is_empty() { vec![None] } else { output_names };
+    let mut state_stats = String::new();
+    for name in &output_names {
+        let state_file = |path: &str| match name {
+            Some(name) => output_state_file(path, name),
+            None => path.to_string(),
+        };
+        for path in [&args.encoded, &args.failed, &args.hashes, &args.params, &args.mirrors, &args.sizes] {
+            state_stats.push_str(&state_file_stats(&state_file(path)));
+        }
+        // The failed files list paths of the library, only their structure is kept
+        let failed = state_file(&args.failed);
+        let failed_files = std::fs::read(&failed).ok().and_then(|contents| serde_json::from_slice(&contents).ok());
+        if let Some(failed_files) = failed_files {
+            bundle.add(
+                &Path::new(&failed).file_name().unwrap_or_default().to_string_lossy(),
+                serde_json::to_string_pretty(&redact_failed_files(&failed_files)).unwrap_or_default(),
             );
-            if args.dry_run {
-                eprintln!("Skipping encode as --dry-run is set");
-            } else {
-                let input_file_path = if config.input_directory.is_remote() {
-                    println!("Copying source file to temp directory before encoding");
-                    fs_wrapper::copy(
-                        &config.input_directory.with_path(
-                            format!(
-                                "{}/{}",
-                                config.input_directory.clone().path_string(),
-                                input_file_name
-                            )
-                        ),
-                        &RclonePath::Local(
-                            format!(
-                                "{}/{}",
-                                temp_directory,
-                                input_file_name
-                            )
-                        ),
-                    )?;
-                    PathBuf::from(
-                        format!(
-                            "{}/{}",
-                            temp_directory,
-                            input_file_name
-                        )
-                    )
-                } else {
-                    Path::new(&config.input_directory.clone().path_string()).join(input_file_name.clone())
-                };
-                let output_file_path = if config.output_directory.is_remote() {
-                    PathBuf::from(
-                        format!(
-                            "{}/{}",
-                            temp_directory,
-                            output_file_name
-                        )
-                    )
-                } else {
-                    Path::new(&config.output_directory.clone().path_string()).join(output_file_name.clone())
-                };
-                let mut params = vec!["-i", input_file_path.to_str().unwrap()];
-                let mut config_params: Vec<&str> = (&config.ffmpeg_params).split(" ").collect();
-                params.append(&mut config_params);
-                params.push(output_file_path.to_str().unwrap());
-                let mut command = Command::new("ffmpeg");
-                command.args(params);
-                if args.quiet {
-                    command.output().expect("Failed to execute ffmpeg");
-                } else {
-                    command.status().expect("Failed to execute ffmpeg");
-                }
-                if config.copy_covers == Some(true) {
-                    println!("Copying audio cover");
-                    copy_pictures(input_file_path, output_file_path)?;
-                }
-                if config.input_directory.is_remote() {
-                    fs_wrapper::remove_file(
-                        &RclonePath::Local(
-                            format!(
-                                "{}/{}",
-                                temp_directory,
-                                input_file_name
-                            )
-                        )
-                    )?;
-                }
-                if config.output_directory.is_remote() {
-                    fs_wrapper::rename(
-                        &RclonePath::Local(
-                            format!(
-                                "{}/{}",
-                                temp_directory,
-                                output_file_name
-                            )
-                        ),
-                        &config.output_directory.with_path(
-                            format!(
-                                "{}/{}",
-                                config.output_directory.clone().path_string(),
-                                output_file_name
-                            )
-                        ),
-                    )?;
+        }
+    }
+    bundle.add("state.txt", state_stats);
+    // Lines which could mention a path are left out with --redact
+    if let Ok(tail) = log_tail(log, redact::is_enabled()) {
+        bundle.add("log.txt", tail);
+    }
+    bundle
+}
+
+/// Files of a state archive and where they're kept with these arguments and config, see [StateArchive]
+///
+/// The state files are named in the archive like with the default arguments, so they can be imported to other paths
+fn state_archive_paths(args: &Args, config: Option<&Config>) -> Vec<(String, String)> {
+    let state_files = [
+        ("encoded.json", &args.encoded),
+        ("failed.json", &args.failed),
+        ("hashes.json", &args.hashes),
+        ("params.json", &args.params),
+        ("mirrors.json", &args.mirrors),
+        ("sizes.json", &args.sizes),
+    ];
+    let mut paths = Vec::new();
+    match config.and_then(|config| config.outputs.as_ref()) {
+        Some(outputs) => {
+            for output in outputs {
+                for (file_name, path) in state_files {
+                    let path = match (file_name, &output.encoded) {
+                        ("encoded.json", Some(encoded)) => encoded.clone(),
+                        _ => output_state_file(path, &output.name),
+                    };
+                    paths.push((output_state_file(file_name, &output.name), path));
                 }
             }
-        } else {
-            println!("Copying {} to {}", input_file_name, output_file_name);
-            if args.dry_run {
-                eprintln!("Skipping copy as --dry-run is set");
-            } else {
-                fs_wrapper::copy(
-                    &config.input_directory.with_path(
-                        format!(
-                            "{}/{}",
-                            config.input_directory.clone().path_string(),
-                            input_file_name
-                        )
-                    ),
-                    &config.output_directory.with_path(
-                        format!(
-                            "{}/{}",
-                            config.output_directory.clone().path_string(),
-                            output_file_name
-                        )
-                    ),
-                )?;
-            }
         }
+        None => paths.extend(state_files.map(|(file_name, path)| (file_name.to_string(), path.clone()))),
     }
+    paths.push((String::from("telemetry.json"), args.telemetry.clone()));
+    if let Some(manifest) = &args.manifest {
+        paths.push((String::from("manifest.json"), manifest.clone()));
+    }
+    paths
+}
 
-    // Remove empty directories
-    if args.dry_run {
-        eprintln!("Skipping removal of empty output and temp directories as --dry-run is set");
-    } else {
-        fs_wrapper::remove_empty_dirs(&config.output_directory)?;
-        if config.input_directory.is_remote() || config.output_directory.is_remote() {
-            fs_wrapper::remove_empty_dirs(
-                &RclonePath::Local(temp_directory)
-            )?;
+/// Writes the state archive, returning the names of the files in it
+fn export_state(args: &Args, archive_path: &str) -> Result<Vec<String>, String> {
+    let mut archive = StateArchive::new();
+    let config_contents = read_config_source(&args.config)
+        .map_err(|error| format!("{} couldn't be read: {}", args.config, error))?;
+    let config = serde_json::from_reader::<_, Config>(StripComments::new(config_contents.as_bytes()))
+        .map_err(|error| format!("{} is invalid: {}", args.config, error))?;
+    archive.add("config.json", config_contents.into_bytes());
+    for (file_name, path) in state_archive_paths(args, Some(&config)) {
+        match std::fs::read(&path) {
+            Ok(contents) => archive.add(&file_name, contents),
+            // Outputs which were never synced have no state yet
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => return Err(format!("{} couldn't be read: {}", path, error)),
         }
     }
+    archive.write(archive_path).map_err(|error| error.to_string())?;
+    Ok(archive.files.into_iter().map(|(file_name, _)| file_name).collect())
+}
 
-    // Save info about processed files to a JSON
-    println!("{}", bold_green.apply_to("Done processing files"));
-    if args.dry_run {
-        eprintln!("Skipping save to JSON as --dry-run is set");
-    } else {
-        let encoded = create_final_encoded_map(input, &config);
-        let encoded_file = File::create(args.encoded)?;
-        let encoded_file_writer = BufWriter::new(encoded_file);
-        serde_json::to_writer(encoded_file_writer, &encoded)?;
+/// Writes the files of a state archive to their paths, returning the paths written
+///
+/// Nothing is written if any of them exists already and `force` isn't set, as replacing the state of a working
+/// setup can make it re-encode or delete everything
+fn import_state(args: &Args, archive_path: &str, force: bool) -> Result<Vec<String>, String> {
+    let archive = StateArchive::read(archive_path).map_err(|error| error.to_string())?;
+    let config_is_file = args.config != "-" && !is_config_url(&args.config);
+    let archived_config = archive.get("config.json").filter(|_| config_is_file);
+    // The outputs decide where their state files go, so the config has to be known first
+    let config_contents = match archived_config {
+        Some(contents) => String::from_utf8_lossy(contents).to_string(),
+        None => read_config_source(&args.config).map_err(|error| format!("{} couldn't be read: {}", args.config, error))?,
+    };
+    let config = serde_json::from_reader::<_, Config>(StripComments::new(config_contents.as_bytes()))
+        .map_err(|error| format!("The config is invalid: {}", error))?;
+
+    let mut destinations = Vec::new();
+    if let Some(contents) = archived_config {
+        destinations.push((String::from("config.json"), args.config.clone(), contents));
     }
+    let paths = state_archive_paths(args, Some(&config));
+    for (file_name, contents) in &archive.files {
+        match paths.iter().find(|(name, _)| name == file_name) {
+            Some((_, path)) => destinations.push((file_name.clone(), path.clone(), contents.as_slice())),
+            None if file_name == "config.json" => {}
+            None => eprintln!(
+                "{}",
+                theme::warning().apply_to(format!("Skipping {}, the config has no output it belongs to", file_name))
+            ),
+        }
+    }
+    let existing = destinations
+        .iter()
+        .filter(|(_, path, _)| Path::new(path).exists())
+        .map(|(_, path, _)| path.as_str())
+        .collect::<Vec<&str>>();
+    if !force && !existing.is_empty() {
+        return Err(format!("{} already exist, pass --force to replace them", existing.join(", ")));
+    }
+    for (_, path, contents) in &destinations {
+        if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|error| format!("{} couldn't be created: {}", parent.display(), error))?;
+        }
+        std::fs::write(path, contents).map_err(|error| format!("{} couldn't be written: {}", path, error))?;
+    }
+    Ok(destinations.into_iter().map(|(_, path, _)| path).collect())
+}
 
-    Ok(())
+/// Takes the snapshot of the input set by inputSnapshot for syncs, the config reads the input from it afterwards
+fn snapshot_input(args: &Args, config: &mut Config) -> Option<Snapshot> {
+    let kind = config.input_snapshot?;
+    if !matches!(args.command, None | Some(Commands::Sync | Commands::Apply { .. })) {
+        return None;
+    }
+    if config.input_directory.is_remote() {
+        eprintln!("{}", theme::warning().apply_to("inputSnapshot is ignored for remote input directories"));
+        return None;
+    }
+    if args.watch.is_some() {
+        eprintln!("{}", theme::warning().apply_to("inputSnapshot is ignored with --watch, every sync reads the input"));
+        return None;
+    }
+    if args.dry_run {
+        eprintln!("Skipping snapshot of the input as --dry-run is set");
+        return None;
+    }
+    println!("Taking a snapshot of the input");
+    match Snapshot::take(kind, Path::new(&config.input_directory.clone().path_string())) {
+        Ok(snapshot) => {
+            config.snapshot_of = Some(config.input_directory.clone());
+            config.input_directory = RclonePath::Local(snapshot.input_directory.to_string_lossy().to_string());
+            Some(snapshot)
+        }
+        Err(error) => {
+            eprintln!("{}", theme::error().apply_to(format!("Failed to take a snapshot of the input: {}", error)));
+            std::process::exit(1);
+        }
+    }
 }
 
-fn create_final_encoded_map(input: HashSet<String>, config: &Config) -> HashMap<String, String> {
-    input
-        .into_iter()
-        .map(|input_file_name| {
-            (
-                input_file_name.clone(),
-                create_output_file_name(input_file_name, &config),
-            )
-        })
-        .collect()
+/// Plan file given to the apply command
+fn apply_plan(args: &Args) -> Option<&str> {
+    match &args.command {
+        Some(Commands::Apply { plan }) => Some(plan),
+        _ => None,
+    }
 }
 
-fn create_output_file_name(input_file_name: String, config: &Config) -> String {
-    let input_file_extension = Path::new(&input_file_name)
-        .extension()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    let input_file_stem = Path::new(&input_file_name)
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    let input_file_folder = Path::new(&input_file_name)
-        .parent()
-        .unwrap_or(Path::new(""))
-        .to_str()
-        .unwrap()
-        .to_string();
-    let mut new_file_name = if config.extensions_to_encode.contains(&input_file_extension) {
-        format!("{}.{}", input_file_stem, &config.encoded_extension)
-    } else {
-        format!("{}.{}", input_file_stem, input_file_extension)
+/// Inserts the output name before the extension, encoded.json becomes encoded.phone.json
+fn output_state_file(path: &str, name: &str) -> String {
+    let path = Path::new(path);
+    let path = match path.extension() {
+        Some(extension) => path.with_extension(format!("{}.{}", name, extension.to_string_lossy())),
+        None => path.with_extension(name),
     };
-    if config.remove_round_brackets == Some(true) {
-        lazy_static! {
-            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" \(.*?\)").unwrap();
-            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"\(.*?\) ").unwrap();
-            static ref REGEX: Regex = Regex::new(r"\(.*?\)").unwrap();
-        }
-        new_file_name = REGEX_SPACE_FIRST
-            .replace_all(&new_file_name, "")
-            .to_string();
-        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
-        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
-    }
-    if config.remove_square_brackets == Some(true) {
-        lazy_static! {
-            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" \[.*?\]").unwrap();
-            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"\[.*?\] ").unwrap();
-            static ref REGEX: Regex = Regex::new(r"\[.*?\]").unwrap();
-        }
-        new_file_name = REGEX_SPACE_FIRST
-            .replace_all(&new_file_name, "")
-            .to_string();
-        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
-        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
-    }
-    if config.remove_curly_brackets == Some(true) {
-        lazy_static! {
-            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" \{.*?\}").unwrap();
-            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"\{.*?\} ").unwrap();
-            static ref REGEX: Regex = Regex::new(r"\{.*?\}").unwrap();
-        }
-        new_file_name = REGEX_SPACE_FIRST
-            .replace_all(&new_file_name, "")
-            .to_string();
-        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
-        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
-    }
-    if config.remove_angle_brackets == Some(true) {
-        lazy_static! {
-            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" <.*?>").unwrap();
-            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"<.*?> ").unwrap();
-            static ref REGEX: Regex = Regex::new(r"<.*?>").unwrap();
-        }
-        new_file_name = REGEX_SPACE_FIRST
-            .replace_all(&new_file_name, "")
-            .to_string();
-        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
-        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
-    }
-    if input_file_folder != "" {
-        new_file_name = format!(
-            "{}/{}",
-            input_file_folder,
-            new_file_name
-        );
-    }
-    new_file_name
-}
\ No newline at end of file
+    path.to_string_lossy().to_string()
+}
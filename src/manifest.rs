@@ -0,0 +1,56 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::save_json;
+
+/// Maps every source file to its encoded variants on all targets, shared by all configs
+/// syncing the same library
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+    /// Full source path -> target (output directory) -> output file name
+    pub sources: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save_json(path, self, true)
+    }
+    /// Replaces everything known about the target with the given state
+    pub fn update_target(&mut self, input_directory: &str, target: &str, encoded: &HashMap<String, String>) {
+        for targets in self.sources.values_mut() {
+            targets.remove(target);
+        }
+        for (input_file_name, output_file_name) in encoded {
+            self.sources
+                .entry(format!("{}/{}", input_directory, input_file_name))
+                .or_default()
+                .insert(target.to_string(), output_file_name.clone());
+        }
+        self.sources.retain(|_, targets| !targets.is_empty());
+    }
+    /// Returns all targets having the source file or at least one file inside the source directory, with the file
+    /// count
+    ///
+    /// Paths are compared by whole components, so "Artist/Album" doesn't include "Artist/Album 2"
+    pub fn locate(&self, source: &str) -> BTreeMap<String, usize> {
+        let mut targets = BTreeMap::new();
+        for (_, source_targets) in self.sources.iter().filter(|(path, _)| Path::new(path).starts_with(source)) {
+            for target in source_targets.keys() {
+                *targets.entry(target.clone()).or_default() += 1;
+            }
+        }
+        targets
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::fs_wrapper;
+use crate::fs_wrapper::RclonePath;
+use crate::redact;
+use crate::state::save_json;
+use crate::sync::ExecutedActions;
+use crate::warnings::{WarningKind, Warnings};
+
+/// Changes of the output a mirror still has to get, as output file names
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingChanges {
+    pub upload: BTreeSet<String>,
+    pub delete: BTreeSet<String>,
+}
+
+impl PendingChanges {
+    pub fn is_empty(&self) -> bool {
+        self.upload.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// Changes every mirror still has to get, by the mirror path, so a failed mirror catches up on the next run
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(transparent)]
+pub struct MirrorState {
+    pub mirrors: BTreeMap<String, PendingChanges>,
+}
+
+impl MirrorState {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save_json(path, self, true)
+    }
+    /// Adds the changes made to the output to every mirror, newer changes of a file replace older ones
+    ///
+    /// Renamed files are uploaded under their new name and deleted under the old one, refreshed files are uploaded
+    /// again
+    pub fn record(&mut self, mirrors: &[RclonePath], executed: &ExecutedActions) {
+        let uploaded = executed
+            .processed
+            .values()
+            .chain(executed.renamed.values())
+            .chain(&executed.refreshed)
+            .cloned()
+            .collect::<BTreeSet<String>>();
+        let deleted = executed
+            .deleted
+            .iter()
+            .chain(executed.renamed.keys())
+            .filter(|output_file_name| !uploaded.contains(*output_file_name))
+            .cloned()
+            .collect::<BTreeSet<String>>();
+        for mirror in mirrors {
+            let pending = self.mirrors.entry(mirror.to_string()).or_default();
+            pending.upload.retain(|output_file_name| !deleted.contains(output_file_name));
+            pending.delete.retain(|output_file_name| !uploaded.contains(output_file_name));
+            pending.upload.extend(uploaded.iter().cloned());
+            pending.delete.extend(deleted.iter().cloned());
+        }
+        self.mirrors.retain(|_, pending| !pending.is_empty());
+    }
+    /// Pushes the pending changes from the output directory to every mirror, the ones which fail stay pending
+    ///
+    /// Returns the number of changes which failed
+    pub fn push(&mut self, config: &Config, warnings: &mut Warnings) -> usize {
+        let mut failed = 0;
+        for mirror in config.mirrors.iter().flatten() {
+            let pending = match self.mirrors.get_mut(&mirror.to_string()) {
+                Some(pending) => pending,
+                None => continue,
+            };
+            println!(
+                "Pushing {} changes to {}",
+                pending.upload.len() + pending.delete.len(),
+                redact::path(&mirror.to_string())
+            );
+            let mirror_path = mirror.clone().path_string();
+            for output_file_name in pending.delete.clone() {
+                let mirror_file = mirror.with_path(format!("{}/{}", mirror_path, output_file_name));
+                match fs_wrapper::remove_file(&mirror_file) {
+                    Ok(()) => {}
+                    // Already gone, nothing left to do
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                    // rclone doesn't tell a file which is gone already apart from other failures, kept pending it
+                    // would fail on every run
+                    Err(error) if mirror.is_remote() => {
+                        warnings.push(WarningKind::MirrorFailed, mirror_file.to_string(), Some(error.to_string()));
+                    }
+                    Err(error) => {
+                        warnings.push(WarningKind::MirrorFailed, mirror_file.to_string(), Some(error.to_string()));
+                        failed += 1;
+                        continue;
+                    }
+                }
+                pending.delete.remove(&output_file_name);
+            }
+            for output_file_name in pending.upload.clone() {
+                let output_file = config.output_directory.with_path(
+                    format!("{}/{}", config.output_directory.clone().path_string(), output_file_name)
+                );
+                let mirror_file = mirror.with_path(format!("{}/{}", mirror_path, output_file_name));
+                let result = if mirror.is_remote() {
+                    fs_wrapper::copy(&output_file, &mirror_file)
+                } else {
+                    Path::new(&mirror_file.clone().path_string())
+                        .parent()
+                        .map(|parent| fs_wrapper::create_dir_all(&RclonePath::Local(parent.to_string_lossy().to_string())))
+                        .unwrap_or(Ok(()))
+                        .and_then(|()| fs_wrapper::copy(&output_file, &mirror_file))
+                };
+                if let Err(error) = result {
+                    warnings.push(WarningKind::MirrorFailed, mirror_file.to_string(), Some(error.to_string()));
+                    failed += 1;
+                    continue;
+                }
+                pending.upload.remove(&output_file_name);
+            }
+        }
+        self.mirrors.retain(|_, pending| !pending.is_empty());
+        failed
+    }
+}
@@ -0,0 +1,121 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use crate::config::MqttConfig;
+
+/// Seconds the broker waits for a packet before it drops the connection
+const KEEP_ALIVE: u16 = 60;
+
+/// Minimal MQTT 3.1.1 client, only able to publish with QoS 0
+pub struct MqttPublisher {
+    config: MqttConfig,
+    will_topic: String,
+    stream: Arc<Mutex<TcpStream>>,
+}
+
+impl MqttPublisher {
+    /// Connects to the broker, which publishes a retained "offline" to `will_topic` once the connection is lost
+    pub fn connect(config: &MqttConfig, will_topic: &str) -> io::Result<Self> {
+        let stream = Arc::new(Mutex::new(open_connection(config, will_topic)?));
+        let pinged = Arc::downgrade(&stream);
+        std::thread::spawn(move || keep_alive(pinged));
+        Ok(Self {
+            config: config.clone(),
+            will_topic: String::from(will_topic),
+            stream,
+        })
+    }
+    pub fn publish(&mut self, topic: &str, payload: &str, retain: bool) -> io::Result<()> {
+        let mut packet = encode_string(topic);
+        packet.extend(payload.as_bytes());
+        let mut stream = self.stream.lock().unwrap();
+        // The broker may have dropped the connection in the meantime, e.g. while the computer was asleep
+        if write_packet(&mut stream, 0x30 | retain as u8, &packet).is_err() {
+            *stream = open_connection(&self.config, &self.will_topic)?;
+            write_packet(&mut stream, 0x30 | retain as u8, &packet)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MqttPublisher {
+    fn drop(&mut self) {
+        let _ = write_packet(&mut self.stream.lock().unwrap(), 0xE0, &[]);
+    }
+}
+
+fn open_connection(config: &MqttConfig, will_topic: &str) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(&config.broker)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+
+    let mut connect_flags = 0x02 | 0x04 | 0x20; // Clean session, retained will with QoS 0
+    let mut payload = encode_string(config.client_id.as_deref().unwrap_or("ffmusicsync"));
+    payload.extend(encode_string(will_topic));
+    payload.extend(encode_string("offline"));
+    if let Some(username) = &config.username {
+        connect_flags |= 0x80;
+        payload.extend(encode_string(username.expose()));
+    }
+    if let Some(password) = &config.password {
+        connect_flags |= 0x40;
+        payload.extend(encode_string(password.expose()));
+    }
+    let mut packet = encode_string("MQTT");
+    packet.push(4); // Protocol level 3.1.1
+    packet.push(connect_flags);
+    packet.extend(KEEP_ALIVE.to_be_bytes());
+    packet.extend(payload);
+    write_packet(&mut stream, 0x10, &packet)?;
+
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack)?;
+    if connack[0] != 0x20 || connack[3] != 0 {
+        return Err(io::Error::other(format!("MQTT broker refused the connection with code {}", connack[3])));
+    }
+    Ok(stream)
+}
+
+/// Pings the broker while the publisher exists, so it doesn't drop the connection between syncs
+fn keep_alive(stream: Weak<Mutex<TcpStream>>) {
+    loop {
+        std::thread::sleep(Duration::from_secs(KEEP_ALIVE as u64 / 2));
+        let Some(stream) = stream.upgrade() else {
+            return;
+        };
+        let mut stream = stream.lock().unwrap();
+        // A failed ping is left to the next publish, which reconnects
+        if write_packet(&mut stream, 0xC0, &[]).is_ok() {
+            let mut pingresp = [0u8; 2];
+            let _ = stream.read_exact(&mut pingresp);
+        }
+    }
+}
+
+fn encode_string(string: &str) -> Vec<u8> {
+    let mut encoded = (string.len() as u16).to_be_bytes().to_vec();
+    encoded.extend(string.as_bytes());
+    encoded
+}
+
+fn write_packet(stream: &mut TcpStream, header: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![header];
+    let mut remaining_length = body.len();
+    loop {
+        let mut byte = (remaining_length % 128) as u8;
+        remaining_length /= 128;
+        if remaining_length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining_length == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    stream.write_all(&packet)?;
+    stream.flush()
+}
@@ -0,0 +1,363 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::capabilities::is_audio_file;
+use crate::config::{Config, TrackNumbers};
+use crate::overrides::Overrides;
+
+pub fn create_output_file_name(input_file_name: String, config: &Config) -> String {
+    let input_file_extension = Path::new(&input_file_name)
+        .extension()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let mut input_file_stem = Path::new(&input_file_name)
+        .file_stem()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    if config.track_numbers == Some(TrackNumbers::Strip) {
+        input_file_stem = strip_track_number(&input_file_stem);
+    }
+    let mut input_file_folder = Path::new(&input_file_name)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .to_str()
+        .unwrap()
+        .to_string();
+    let mut new_file_name = if config.extensions_to_encode.contains(&input_file_extension) {
+        format!("{}.{}", input_file_stem, &config.encoded_extension)
+    } else {
+        format!("{}.{}", input_file_stem, input_file_extension)
+    };
+    if config.remove_round_brackets == Some(true) {
+        lazy_static! {
+            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" \(.*?\)").unwrap();
+            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"\(.*?\) ").unwrap();
+            static ref REGEX: Regex = Regex::new(r"\(.*?\)").unwrap();
+        }
+        new_file_name = REGEX_SPACE_FIRST
+            .replace_all(&new_file_name, "")
+            .to_string();
+        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
+        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
+    }
+    if config.remove_square_brackets == Some(true) {
+        lazy_static! {
+            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" \[.*?\]").unwrap();
+            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"\[.*?\] ").unwrap();
+            static ref REGEX: Regex = Regex::new(r"\[.*?\]").unwrap();
+        }
+        new_file_name = REGEX_SPACE_FIRST
+            .replace_all(&new_file_name, "")
+            .to_string();
+        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
+        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
+    }
+    if config.remove_curly_brackets == Some(true) {
+        lazy_static! {
+            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" \{.*?\}").unwrap();
+            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"\{.*?\} ").unwrap();
+            static ref REGEX: Regex = Regex::new(r"\{.*?\}").unwrap();
+        }
+        new_file_name = REGEX_SPACE_FIRST
+            .replace_all(&new_file_name, "")
+            .to_string();
+        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
+        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
+    }
+    if config.remove_angle_brackets == Some(true) {
+        lazy_static! {
+            static ref REGEX_SPACE_FIRST: Regex = Regex::new(r" <.*?>").unwrap();
+            static ref REGEX_SPACE_LAST: Regex = Regex::new(r"<.*?> ").unwrap();
+            static ref REGEX: Regex = Regex::new(r"<.*?>").unwrap();
+        }
+        new_file_name = REGEX_SPACE_FIRST
+            .replace_all(&new_file_name, "")
+            .to_string();
+        new_file_name = REGEX_SPACE_LAST.replace_all(&new_file_name, "").to_string();
+        new_file_name = REGEX.replace_all(&new_file_name, "").to_string();
+    }
+    if config.merge_disc_folders == Some(true) {
+        lazy_static! {
+            static ref REGEX_DISC_FOLDER: Regex = Regex::new(r"(?i)^(?:cd|disc|disk)\s*0*(\d+)$").unwrap();
+        }
+        let folder_path = Path::new(&input_file_folder);
+        let disc_number = folder_path
+            .file_name()
+            .and_then(|folder_name| REGEX_DISC_FOLDER.captures(folder_name.to_str().unwrap()))
+            .map(|captures| captures[1].to_string());
+        if let Some(disc_number) = disc_number {
+            new_file_name = format!("{}-{}", disc_number, new_file_name);
+            input_file_folder = folder_path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_str()
+                .unwrap()
+                .to_string();
+        }
+    }
+    if !input_file_folder.is_empty() {
+        new_file_name = format!(
+            "{}/{}",
+            input_file_folder,
+            new_file_name
+        );
+    }
+    new_file_name
+}
+
+/// Removes a leading track number ("01 - ", "01. ", "1-01 ") from the file stem
+///
+/// A number followed by just a space is part of the title, like in "99 Luftballons", only disc and track numbers
+/// like "1-01 " are stripped without a separator
+pub fn strip_track_number(file_stem: &str) -> String {
+    lazy_static! {
+        static ref REGEX_TRACK_NUMBER: Regex =
+            Regex::new(r"^(?:\d{1,2}-\d{2,3}(?:\s*-\s+|\.\s+|\s+)|\d{1,3}(?:\s*-\s+|\.\s+))").unwrap();
+    }
+    let stripped = REGEX_TRACK_NUMBER.replace(file_stem, "").to_string();
+    // Keep names which are just a number
+    if stripped.is_empty() {
+        file_stem.to_string()
+    } else {
+        stripped
+    }
+}
+
+/// Prefixes the file name (not the folders) with a zero-padded track number,
+/// unless it already starts with one
+pub fn add_track_number(output_file_name: &str, track_number: u32) -> String {
+    let (folder, file_name) = match output_file_name.rsplit_once('/') {
+        Some((folder, file_name)) => (Some(folder), file_name),
+        None => (None, output_file_name),
+    };
+    if strip_track_number(file_name) != file_name {
+        return output_file_name.to_string();
+    }
+    let file_name = format!("{:02} - {}", track_number, file_name);
+    match folder {
+        Some(folder) => format!("{}/{}", folder, file_name),
+        None => file_name,
+    }
+}
+
+/// Resolves output file names with everything that depends on more than the config:
+/// per-directory overrides, track numbers from tags, files that must keep their track number and sources which are
+/// copied instead of being encoded
+pub struct OutputNamer {
+    config: Config,
+    overrides: Overrides,
+    track_numbers: HashMap<String, u32>,
+    keep_track_numbers: HashSet<String>,
+    /// Sources copied instead of being encoded, see skipLossyTranscode and copyIfBitrateBelow
+    copied_sources: HashSet<String>,
+    /// Sidecar file -> track it belongs to, both as input file names
+    sidecars: HashMap<String, String>,
+    /// Sidecar files without a track, like covers and album cue sheets, which belong to their folder
+    folder_sidecars: HashMap<String, FolderSidecar>,
+    /// Output file names decided by an earlier run, used when retrying failed files
+    pub(crate) known_names: HashMap<String, String>,
+}
+
+impl OutputNamer {
+    pub fn new(
+        config: Config,
+        overrides: Overrides,
+        input: &HashSet<String>,
+        track_numbers: HashMap<String, u32>,
+        copied_sources: HashSet<String>,
+    ) -> Self {
+        let mut namer = Self {
+            config,
+            overrides,
+            track_numbers,
+            keep_track_numbers: HashSet::new(),
+            copied_sources,
+            sidecars: HashMap::new(),
+            folder_sidecars: HashMap::new(),
+            known_names: HashMap::new(),
+        };
+        namer.sidecars = find_sidecars(&namer.config, input);
+        // Files whose names would collide after stripping the track number keep it
+        let mut names: HashMap<String, Vec<String>> = HashMap::new();
+        for input_file_name in input {
+            names
+                .entry(namer.output_file_name(input_file_name))
+                .or_default()
+                .push(input_file_name.clone());
+        }
+        namer.keep_track_numbers = names
+            .into_values()
+            .filter(|input_file_names| input_file_names.len() > 1)
+            .flatten()
+            .filter(|input_file_name| namer.file_config(input_file_name).track_numbers == Some(TrackNumbers::Strip))
+            .collect();
+        namer.folder_sidecars = find_folder_sidecars(&namer.config, input, &namer.sidecars);
+        // Merged disc folders can bring several covers into one folder, only the first keeps the plain name
+        let mut folder_sidecars: Vec<&String> = namer.folder_sidecars.keys().collect();
+        folder_sidecars.sort();
+        let mut taken = HashSet::new();
+        let colliding: Vec<String> = folder_sidecars
+            .into_iter()
+            .filter(|sidecar| !taken.insert(namer.output_file_name(sidecar)))
+            .cloned()
+            .collect();
+        for sidecar in colliding {
+            namer.folder_sidecars.remove(&sidecar);
+        }
+        namer
+    }
+    /// The global config, without any overrides
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    /// The config with all overrides applying to the input file
+    pub fn file_config(&self, input_file_name: &str) -> Config {
+        let mut file_config = self.overrides.config_for(&self.config, input_file_name);
+        if self.keep_track_numbers.contains(input_file_name) {
+            file_config.track_numbers = Some(TrackNumbers::Keep);
+        }
+        if self.copied_sources.contains(input_file_name) {
+            if let Some(extension) = Path::new(input_file_name).extension() {
+                let extension = extension.to_string_lossy();
+                file_config.extensions_to_encode.retain(|extension_to_encode| *extension_to_encode != extension);
+            }
+        }
+        file_config
+    }
+    pub fn output_file_name(&self, input_file_name: &str) -> String {
+        if let Some(output_file_name) = self.known_names.get(input_file_name) {
+            return output_file_name.clone();
+        }
+        // Sidecars follow their track, so lyrics and cue sheets still match it after brackets or track numbers change
+        if let Some(track) = self.sidecars.get(input_file_name) {
+            let extension = Path::new(input_file_name).extension().unwrap().to_string_lossy();
+            return Path::new(&self.output_file_name(track))
+                .with_extension(extension.as_ref())
+                .to_string_lossy()
+                .to_string();
+        }
+        let file_config = self.file_config(input_file_name);
+        // Folder sidecars go where the tracks of their folder go, named after that folder if they were before
+        if let Some(folder_sidecar) = self.folder_sidecars.get(input_file_name) {
+            let track_output = self.output_file_name(&folder_sidecar.track);
+            let folder = Path::new(&track_output).parent().unwrap_or(Path::new(""));
+            let input_path = Path::new(input_file_name);
+            let file_name = match folder.file_name() {
+                Some(folder_name) if folder_sidecar.named_after_folder => format!(
+                    "{}.{}",
+                    folder_name.to_string_lossy(),
+                    input_path.extension().unwrap().to_string_lossy()
+                ),
+                _ => create_output_file_name(input_path.file_name().unwrap().to_string_lossy().to_string(), &file_config),
+            };
+            return folder.join(file_name).to_string_lossy().replace('\\', "/");
+        }
+        let output_file_name = create_output_file_name(input_file_name.to_string(), &file_config);
+        match self.track_numbers.get(input_file_name) {
+            Some(track_number) if file_config.track_numbers == Some(TrackNumbers::Add) => {
+                add_track_number(&output_file_name, *track_number)
+            }
+            _ => output_file_name,
+        }
+    }
+}
+
+/// Finds the track of every sidecar file, the file in the same directory with the same stem and
+/// an extension which isn't a sidecar extension
+fn find_sidecars(config: &Config, input: &HashSet<String>) -> HashMap<String, String> {
+    let sidecar_extensions = match &config.sidecar_extensions {
+        Some(sidecar_extensions) if !sidecar_extensions.is_empty() => sidecar_extensions,
+        _ => return HashMap::new(),
+    };
+    let is_sidecar = |file: &str| {
+        Path::new(file)
+            .extension()
+            .is_some_and(|extension| sidecar_extensions.contains(&extension.to_string_lossy().to_string()))
+    };
+    // Tracks by their path without the extension, the first one by name if there are several
+    let mut tracks: HashMap<String, String> = HashMap::new();
+    for track in input.iter().filter(|file| !is_sidecar(file)) {
+        let key = Path::new(track).with_extension("").to_string_lossy().to_string();
+        let entry = tracks.entry(key).or_insert_with(|| track.clone());
+        if track < entry {
+            *entry = track.clone();
+        }
+    }
+    input
+        .iter()
+        .filter(|file| is_sidecar(file))
+        .filter_map(|sidecar| {
+            let key = Path::new(sidecar).with_extension("").to_string_lossy().to_string();
+            Some((sidecar.clone(), tracks.get(&key)?.clone()))
+        })
+        .collect()
+}
+
+/// A sidecar file belonging to its folder instead of a single track
+struct FolderSidecar {
+    /// A track in the same folder, whose output folder the sidecar follows
+    track: String,
+    /// The sidecar is named like its folder, like "Album (Deluxe)/Album (Deluxe).cue"
+    named_after_folder: bool,
+}
+
+/// Finds the sidecar files without a track in folders which have tracks, they're kept together with those tracks when
+/// disc folders are merged and keep the name of their folder when it's used as the file name
+fn find_folder_sidecars(
+    config: &Config,
+    input: &HashSet<String>,
+    sidecars: &HashMap<String, String>,
+) -> HashMap<String, FolderSidecar> {
+    let sidecar_extensions = match &config.sidecar_extensions {
+        Some(sidecar_extensions) if !sidecar_extensions.is_empty() => sidecar_extensions,
+        _ => return HashMap::new(),
+    };
+    let is_sidecar = |file: &str| {
+        Path::new(file)
+            .extension()
+            .is_some_and(|extension| sidecar_extensions.contains(&extension.to_string_lossy().to_string()))
+    };
+    // Only encoded or audio files are tracks, a cover.jpg or .nfo would take the sidecar somewhere else
+    let is_track = |file: &str| {
+        let encoded = Path::new(file).extension().is_some_and(|extension| {
+            config
+                .extensions_to_encode
+                .iter()
+                .any(|extension_to_encode| extension_to_encode.eq_ignore_ascii_case(&extension.to_string_lossy()))
+        });
+        !is_sidecar(file) && (encoded || is_audio_file(file))
+    };
+    // The first track of every folder by name
+    let mut tracks: HashMap<&Path, &String> = HashMap::new();
+    for track in input.iter().filter(|file| is_track(file)) {
+        let folder = Path::new(track).parent().unwrap_or(Path::new(""));
+        let entry = tracks.entry(folder).or_insert(track);
+        if track < *entry {
+            *entry = track;
+        }
+    }
+    input
+        .iter()
+        .filter(|file| is_sidecar(file) && !sidecars.contains_key(*file))
+        .filter_map(|sidecar| {
+            let path = Path::new(sidecar);
+            let folder = path.parent().unwrap_or(Path::new(""));
+            let track = tracks.get(folder)?;
+            let named_after_folder = folder.file_name().is_some() && folder.file_name() == path.file_stem();
+            Some((
+                sidecar.clone(),
+                FolderSidecar {
+                    track: track.to_string(),
+                    named_after_folder,
+                },
+            ))
+        })
+        .collect()
+}
@@ -2,16 +2,58 @@ use std::path::PathBuf;
 
 use lofty::{Probe, TagExt};
 
+use crate::capabilities::container_of;
+use crate::tags::primary_tag_or_new;
+
 pub fn copy_pictures(input: PathBuf, output: PathBuf) -> lofty::error::Result<()> {
+    // Nothing to copy into, not an error as the config linter already reports it
+    if container_of(&output).is_some_and(|container| !container.pictures) {
+        return Ok(());
+    }
     let input_tagged_file = Probe::open(input)?.guess_file_type()?.read(false)?;
-    let input_tag = input_tagged_file.primary_tag().unwrap();
-    let mut output_tagged_file = Probe::open(&output)?.guess_file_type()?.read(true)?;
-    let output_tag = output_tagged_file.primary_tag_mut().unwrap();
-    input_tag.pictures().into_iter().cloned().for_each(
+    // Untagged sources have no pictures
+    let Some(input_tag) = input_tagged_file.primary_tag() else {
+        return Ok(());
+    };
+    let output_tagged_file = Probe::open(&output)?.guess_file_type()?.read(true)?;
+    let mut output_tag = primary_tag_or_new(&output_tagged_file);
+    input_tag.pictures().iter().cloned().for_each(
         |picture| {
             output_tag.push_picture(picture);
         }
     );
     output_tag.save_to_path(output)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Replaces the pictures of the output with the ones from the input
+///
+/// Returns whether the output file was modified - it's left untouched if the pictures already match
+pub fn refresh_pictures(input: PathBuf, output: PathBuf) -> lofty::error::Result<bool> {
+    if container_of(&output).is_some_and(|container| !container.pictures) {
+        return Ok(false);
+    }
+    let input_tagged_file = Probe::open(input)?.guess_file_type()?.read(false)?;
+    let input_tag = primary_tag_or_new(&input_tagged_file);
+    let output_tagged_file = Probe::open(&output)?.guess_file_type()?.read(true)?;
+    let mut output_tag = primary_tag_or_new(&output_tagged_file);
+    if input_tag.pictures() == output_tag.pictures() {
+        return Ok(false);
+    }
+    let old_picture_types = output_tag.pictures()
+        .iter()
+        .map(|picture| picture.pic_type())
+        .collect::<Vec<_>>();
+    old_picture_types.into_iter().for_each(
+        |picture_type| {
+            output_tag.remove_picture_type(picture_type);
+        }
+    );
+    input_tag.pictures().iter().cloned().for_each(
+        |picture| {
+            output_tag.push_picture(picture);
+        }
+    );
+    output_tag.save_to_path(output)?;
+    Ok(true)
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tags::read_duration;
+
+/// How much the duration of an output may differ from the recorded one, in seconds
+const DURATION_TOLERANCE: f64 = 1.0;
+
+/// Size and duration of an encoded output when it was written, used by the shallow verify
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OutputInfo {
+    pub size: u64,
+    /// Duration in seconds, None if it couldn't be read when the file was encoded
+    pub duration: Option<f64>,
+}
+
+impl OutputInfo {
+    /// Reads the size and duration of a local file, None if it doesn't exist
+    pub fn read(path: &Path) -> Option<Self> {
+        let size = path.metadata().ok()?.len();
+        Some(
+            Self {
+                size,
+                duration: read_duration(path.to_path_buf()).map(|duration| duration.as_secs_f64()),
+            }
+        )
+    }
+    /// Whether a local file still has the recorded duration, read from its headers without decoding it
+    ///
+    /// Returns None if no duration was recorded, so the file can't be checked
+    pub fn duration_matches(&self, path: &Path) -> Option<bool> {
+        let recorded = self.duration?;
+        let duration = read_duration(path.to_path_buf()).map(|duration| duration.as_secs_f64());
+        Some(duration.is_some_and(|duration| (duration - recorded).abs() <= DURATION_TOLERANCE))
+    }
+}
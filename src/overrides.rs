@@ -0,0 +1,95 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use json_comments::StripComments;
+
+use crate::config::{Config, ConfigOverride};
+use crate::fs_wrapper;
+use crate::fs_wrapper::RclonePath;
+
+/// Name of the per-directory config files inside the input directory
+pub const OVERRIDE_FILE_NAME: &str = ".ffmusicsync.json";
+
+/// All per-directory config files found in the input directory, by the directory they apply to
+#[derive(Default, Debug)]
+pub struct Overrides {
+    overrides: HashMap<String, ConfigOverride>,
+}
+
+impl Overrides {
+    /// Reads all override files present in the input file list
+    pub fn load(input_directory: &RclonePath, input: &HashSet<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut overrides = HashMap::new();
+        for file in input.iter().filter(|file| is_override_file(file)) {
+            let contents = fs_wrapper::read_to_string(
+                &input_directory.with_path(
+                    format!(
+                        "{}/{}",
+                        input_directory.clone().path_string(),
+                        file
+                    )
+                )
+            )?;
+            let config_override: ConfigOverride = serde_json::from_reader(
+                StripComments::new(contents.as_bytes())
+            ).map_err(|error| format!("Invalid {}: {}", file, error))?;
+            overrides.insert(parent_directory(file), config_override);
+        }
+        Ok(Self { overrides })
+    }
+    /// Applies the overrides of all directories containing the file, outermost first,
+    /// and then the encode rule matching it
+    pub fn config_for(&self, config: &Config, file: &str) -> Config {
+        let mut config = config.clone();
+        for directory in ancestors(file) {
+            if let Some(config_override) = self.overrides.get(&directory) {
+                config = config.with_override(config_override);
+            }
+        }
+        config.with_encode_rule(file)
+    }
+    /// Directories whose config file makes their files encoded to an extension which is encoded again, with the
+    /// extension
+    pub fn reencoding_directories(&self, config: &Config) -> Vec<(String, String)> {
+        let mut directories = self.overrides
+            .keys()
+            .filter_map(|directory| {
+                let file = Path::new(directory).join(OVERRIDE_FILE_NAME).to_string_lossy().to_string();
+                let directory_config = self.config_for(config, &file);
+                directory_config
+                    .extensions_to_encode
+                    .contains(&directory_config.encoded_extension)
+                    .then(|| (directory.clone(), directory_config.encoded_extension))
+            })
+            .collect::<Vec<(String, String)>>();
+        directories.sort();
+        directories
+    }
+    /// Whether the file is in a directory marked to be skipped
+    pub fn is_skipped(&self, file: &str) -> bool {
+        ancestors(file)
+            .into_iter()
+            .rev()
+            .find_map(|directory| self.overrides.get(&directory).and_then(|config_override| config_override.skip))
+            .unwrap_or(false)
+    }
+}
+
+pub fn is_override_file(file: &str) -> bool {
+    Path::new(file).file_name().map(|file_name| file_name == OVERRIDE_FILE_NAME).unwrap_or(false)
+}
+
+fn parent_directory(file: &str) -> String {
+    Path::new(file).parent().unwrap_or(Path::new("")).to_string_lossy().to_string()
+}
+
+/// All directories containing the file, starting with the input directory itself ("")
+fn ancestors(file: &str) -> Vec<String> {
+    let mut ancestors = Path::new(file)
+        .ancestors()
+        .skip(1)
+        .map(|directory| directory.to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    ancestors.reverse();
+    ancestors
+}
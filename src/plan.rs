@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::redact;
+use crate::redact::redact_path;
+
+/// Version of the exported plan and report format, see schema/plan-v1.schema.json
+///
+/// Fields may be added within a version, it's only increased when a field is removed or changes its meaning
+pub const PLAN_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ActionKind {
+    Encode,
+    Copy,
+    Rename,
+    Delete,
+    CreateDirectory,
+}
+
+/// Why an action is needed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ActionReason {
+    /// The source was never processed, or the directory doesn't exist yet
+    New,
+    /// The source was processed before, but the output is missing or has the wrong extension
+    OutputChanged,
+    /// The contents of the source changed since it was processed
+    SourceChanged,
+    /// The source was encoded with different ffmpeg params
+    ParamsChanged,
+    /// The source was encoded to another extension than the current one
+    ExtensionChanged,
+    /// Processing the source failed in the last run
+    Failed,
+    /// The naming settings changed
+    NameChanged,
+    /// The output has no source anymore
+    NoSource,
+    /// The source is left out of the output by a retention rule
+    Pruned,
+}
+
+impl ActionReason {
+    /// Short description used in the summary before confirming
+    pub fn description(&self) -> String {
+        let key = match self {
+            Self::New => "reason-new",
+            Self::OutputChanged => "reason-output-changed",
+            Self::SourceChanged => "reason-source-changed",
+            Self::ParamsChanged => "reason-params-changed",
+            Self::ExtensionChanged => "reason-extension-changed",
+            Self::Failed => "reason-failed",
+            Self::NameChanged => "reason-name-changed",
+            Self::NoSource => "reason-no-source",
+            Self::Pruned => "reason-pruned",
+        };
+        crate::tr!(key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ActionStatus {
+    Done,
+    Failed,
+    /// Not done because of --dry-run
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedAction {
+    pub kind: ActionKind,
+    /// Input file name, None for deletes
+    pub source: Option<String>,
+    /// Output file name, the old one for renames and deletes
+    pub destination: Option<String>,
+    /// New output file name of renames
+    pub new_destination: Option<String>,
+    pub reason: ActionReason,
+    /// Size in bytes of the source file, or of the deleted file, if known
+    pub size: Option<u64>,
+    /// Outcome of the action, only set in reports
+    pub status: Option<ActionStatus>,
+    /// Seconds the action took, only set in reports for encodes exceeding slowFileBudget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_seconds: Option<u64>,
+}
+
+/// All actions of a run, exported for review and auditing tools
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedPlan {
+    pub version: u32,
+    pub input_directory: String,
+    pub output_directory: String,
+    /// Checksum of the input and output file listings the plan was made from, used to refuse applying outdated plans
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listing_checksum: Option<String>,
+    pub actions: Vec<PlannedAction>,
+}
+
+impl ExportedPlan {
+    pub fn new(input_directory: String, output_directory: String) -> Self {
+        Self {
+            version: PLAN_FORMAT_VERSION,
+            input_directory,
+            output_directory,
+            listing_checksum: None,
+            actions: Vec::new(),
+        }
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+    /// Marks every action as done unless it failed, or as skipped in a dry run
+    pub fn set_statuses(&mut self, failed_files: &BTreeMap<String, String>, dry_run: bool) {
+        for action in &mut self.actions {
+            let failed = action.source.as_ref().map(|source| failed_files.contains_key(source)).unwrap_or(false);
+            action.status = Some(if dry_run {
+                ActionStatus::Skipped
+            } else if failed {
+                ActionStatus::Failed
+            } else {
+                ActionStatus::Done
+            });
+        }
+    }
+    /// Flags the encodes which took longer than slowFileBudget, by their source
+    pub fn mark_slow(&mut self, slow_files: &BTreeMap<String, u64>) {
+        for action in &mut self.actions {
+            action.slow_seconds = action.source.as_ref().and_then(|source| slow_files.get(source)).copied();
+        }
+    }
+    /// Writes the plan, with its names redacted if --redact is given
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if redact::is_enabled() {
+            self.redacted().write(path)
+        } else {
+            self.write(path)
+        }
+    }
+    fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+    /// The plan with the directories and file names replaced by hashes, see [redact_path]
+    ///
+    /// It can't be applied anymore, but still shows which actions share a directory
+    pub fn redacted(&self) -> Self {
+        let redact = |path: &Option<String>| path.as_deref().map(redact_path);
+        Self {
+            version: self.version,
+            input_directory: redact_path(&self.input_directory),
+            output_directory: redact_path(&self.output_directory),
+            listing_checksum: self.listing_checksum.clone(),
+            actions: self
+                .actions
+                .iter()
+                .map(|action| PlannedAction {
+                    source: redact(&action.source),
+                    destination: redact(&action.destination),
+                    new_destination: redact(&action.new_destination),
+                    ..action.clone()
+                })
+                .collect(),
+        }
+    }
+    /// Opens the plan in $VISUAL or $EDITOR and passes it to `apply` as it was saved, see [ExportedPlan::edit_with]
+    pub fn edit<T>(&self, apply: impl FnOnce(&Self) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| String::from(if cfg!(windows) { "notepad" } else { "vi" }));
+        self.edit_with(&editor, apply)
+    }
+    /// Opens the plan in the editor, which may be given with arguments like `code --wait`, and passes it to `apply` as
+    /// it was saved
+    ///
+    /// The plan is edited in a new file with a random name in the temp directory. If the saved plan can't be read or
+    /// applied, the file is kept so the edits aren't lost
+    pub fn edit_with<T>(
+        &self,
+        editor: &str,
+        apply: impl FnOnce(&Self) -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        let (path, file) = create_edit_file()?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        let mut editor_parts = editor.split_whitespace();
+        let program = editor_parts.next().unwrap_or("vi");
+        match Command::new(program).args(editor_parts).arg(&path).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                std::fs::remove_file(&path)?;
+                return Err(Box::from(format!("{} exited with {}", program, status)));
+            }
+            Err(error) => {
+                std::fs::remove_file(&path)?;
+                return Err(Box::from(format!("Failed to execute {}: {}", program, error)));
+            }
+        }
+        match Self::load(&path).map_err(Box::<dyn Error>::from).and_then(|edited_plan| apply(&edited_plan)) {
+            Ok(applied) => {
+                std::fs::remove_file(&path)?;
+                Ok(applied)
+            }
+            Err(error) => Err(Box::from(format!("{}, the edited plan is kept in {}", error, path.display()))),
+        }
+    }
+}
+
+/// Creates the file a plan is edited in, named randomly so neither another run nor another user can have it open
+fn create_edit_file() -> io::Result<(PathBuf, File)> {
+    loop {
+        let mut suffix = [0u8; 8];
+        getrandom::getrandom(&mut suffix).map_err(|error| io::Error::other(error.to_string()))?;
+        let suffix = suffix.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let path = std::env::temp_dir().join(format!("ffmusicsync-plan-{}.json", suffix));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
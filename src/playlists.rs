@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use lofty::FileType;
+
+use crate::naming::OutputNamer;
+
+/// Extensions of playlists whose entries are rewritten, compared ignoring their case
+const PLAYLIST_EXTENSIONS: [&str; 2] = ["m3u", "m3u8"];
+
+pub fn is_playlist(file: &str) -> bool {
+    Path::new(file)
+        .extension()
+        .is_some_and(|extension| PLAYLIST_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Decodes a playlist, older ones and m3u files written by Windows players are often Latin-1 instead of UTF-8
+pub fn decode_playlist(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(contents) => contents.to_string(),
+        Err(_) => bytes.iter().map(|&byte| char::from(byte)).collect(),
+    }
+}
+
+/// Rewrites the entries of a playlist to the output names of the files they point at, relative to the output
+/// of the playlist
+///
+/// Comments, URLs, absolute paths and entries which aren't part of the input are kept as they are
+pub fn rewrite_playlist(contents: &str, playlist: &str, namer: &OutputNamer, input: &HashSet<String>) -> String {
+    let playlist_directory = Path::new(playlist).parent().unwrap_or_else(|| Path::new(""));
+    let output_playlist = namer.output_file_name(playlist);
+    let output_playlist_directory = Path::new(&output_playlist).parent().unwrap_or_else(|| Path::new(""));
+    contents
+        .split_inclusive('\n')
+        .map(|line| {
+            let entry = line.trim_end_matches(['\r', '\n']);
+            let line_ending = &line[entry.len()..];
+            let trimmed = entry.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.contains("://") {
+                return line.to_string();
+            }
+            // Playlists made on Windows use backslashes
+            let entry_path = trimmed.replace('\\', "/");
+            if Path::new(&entry_path).is_absolute() {
+                return line.to_string();
+            }
+            let input_file_name = match normalize(&playlist_directory.join(&entry_path)) {
+                Some(input_file_name) if input.contains(&input_file_name) => input_file_name,
+                _ => return line.to_string(),
+            };
+            let output_file_name = namer.output_file_name(&input_file_name);
+            format!("{}{}", relative_path(output_playlist_directory, Path::new(&output_file_name)), line_ending)
+        })
+        .collect()
+}
+
+/// Creates an m3u8 playlist of the audio files, relative to where the playlist is in the output directory
+///
+/// Files are ordered by their folder, then by their name
+pub fn create_playlist<'a>(playlist: &str, output_files: impl Iterator<Item = &'a String>) -> String {
+    let playlist_directory = Path::new(playlist).parent().unwrap_or_else(|| Path::new(""));
+    let mut output_files = output_files
+        .filter(|output_file_name| FileType::from_path(output_file_name).is_some())
+        .map(|output_file_name| {
+            let path = Path::new(output_file_name);
+            (path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(), path.file_name().unwrap_or_default().to_os_string())
+        })
+        .collect::<Vec<_>>();
+    output_files.sort();
+    let mut contents = String::from("#EXTM3U\n");
+    for (directory, file_name) in output_files {
+        contents.push_str(&relative_path(playlist_directory, &directory.join(file_name)));
+        contents.push('\n');
+    }
+    contents
+}
+
+/// Resolves `.` and `..` of a path relative to the input directory, None if it leaves the input directory
+fn normalize(path: &Path) -> Option<String> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized.to_string_lossy().to_string())
+}
+
+/// Path of the file relative to the directory, both relative to the output directory
+fn relative_path(directory: &Path, file: &Path) -> String {
+    let directory = directory.components().collect::<Vec<Component>>();
+    let file = file.components().collect::<Vec<Component>>();
+    let common = directory
+        .iter()
+        .zip(&file)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts = vec![String::from(".."); directory.len() - common];
+    parts.extend(file[common..].iter().map(|part| part.as_os_str().to_string_lossy().to_string()));
+    parts.join("/")
+}
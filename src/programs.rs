@@ -0,0 +1,70 @@
+use std::path::Path;
+use std::process::Command;
+use std::sync::RwLock;
+
+use crate::config::Config;
+
+static RCLONE_PATH: RwLock<Option<String>> = RwLock::new(None);
+static RCLONE_FLAGS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Runs rclone from rclonePath with the rcloneFlags of this config
+///
+/// rclone is run from everywhere the files are read or written, so unlike ffmpeg it's set for the whole process, by
+/// the planner and the executor before they run
+pub fn use_config(config: &Config) {
+    *RCLONE_PATH.write().unwrap() = config.rclone_path.clone();
+    set_rclone_flags(config.rclone_flags.clone().unwrap_or_default());
+}
+
+/// Adds these arguments to every rclone command, set from rcloneFlags
+pub fn set_rclone_flags(flags: Vec<String>) {
+    *RCLONE_FLAGS.write().unwrap() = flags;
+}
+
+/// ffmpegPath of the config, ffmpeg is looked up in PATH without it
+pub fn ffmpeg_path(config: &Config) -> String {
+    config.ffmpeg_path.clone().unwrap_or_else(|| String::from("ffmpeg"))
+}
+
+/// ffprobe next to the configured ffmpeg, as they come together in every build
+pub fn ffprobe_path(config: &Config) -> String {
+    sibling_program(&ffmpeg_path(config), "ffprobe")
+}
+
+pub fn rclone_path() -> String {
+    RCLONE_PATH.read().unwrap().clone().unwrap_or_else(|| String::from("rclone"))
+}
+
+pub fn ffmpeg(config: &Config) -> Command {
+    Command::new(ffmpeg_path(config))
+}
+
+pub fn ffprobe(config: &Config) -> Command {
+    Command::new(ffprobe_path(config))
+}
+
+/// rclone with the flags of the config, to add its command and arguments to
+pub fn rclone_command_line() -> Vec<String> {
+    let mut command_line = vec![rclone_path()];
+    command_line.extend(RCLONE_FLAGS.read().unwrap().iter().cloned());
+    command_line
+}
+
+pub fn rclone() -> Command {
+    let mut command = Command::new(rclone_path());
+    command.args(RCLONE_FLAGS.read().unwrap().iter());
+    command
+}
+
+/// Path of another program in the directory of `program`, keeping its extension like .exe
+pub fn sibling_program(program: &str, name: &str) -> String {
+    let path = Path::new(program);
+    let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) else {
+        return name.to_string();
+    };
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}.{}", name, extension.to_string_lossy()),
+        None => name.to_string(),
+    };
+    parent.join(file_name).to_string_lossy().to_string()
+}
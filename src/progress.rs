@@ -0,0 +1,108 @@
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::redact;
+
+/// Progress bars shown while running a plan, an overall one for all actions and one for the file being encoded
+///
+/// When disabled, messages are printed as plain lines instead
+pub struct Progress {
+    enabled: bool,
+    bars: MultiProgress,
+    overall: ProgressBar,
+}
+
+impl Progress {
+    pub fn new(total_actions: usize, enabled: bool) -> Self {
+        let bars = MultiProgress::with_draw_target(if enabled {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        });
+        let overall = bars.add(ProgressBar::new(total_actions as u64));
+        overall.set_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} files {wide_msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        Self {
+            enabled,
+            bars,
+            overall,
+        }
+    }
+    /// Prints a line above the progress bars
+    pub fn println<S: AsRef<str>>(&self, message: S) {
+        if self.enabled {
+            let _ = self.bars.println(message);
+        } else {
+            println!("{}", message.as_ref());
+        }
+    }
+    /// Prints a line to stderr, above the progress bars
+    pub fn eprintln<S: AsRef<str>>(&self, message: S) {
+        if self.enabled {
+            let _ = self.bars.println(message);
+        } else {
+            eprintln!("{}", message.as_ref());
+        }
+    }
+    /// Moves the overall bar to the next action
+    pub fn start_action(&self, file: &str) {
+        self.overall.inc(1);
+        self.overall.set_message(redact::path(file));
+    }
+    /// Adds a bar for encoding a file, its length is the duration of the file in milliseconds if known
+    pub fn encode_bar(&self, duration: Option<Duration>) -> ProgressBar {
+        let bar = self.bars.add(ProgressBar::new(duration.map(|duration| duration.as_millis() as u64).unwrap_or(0)));
+        let template = if duration.is_some() {
+            "{bar:40.green/white} {percent:>3}% ETA {eta} {msg}"
+        } else {
+            "{spinner} {msg}"
+        };
+        bar.set_style(ProgressStyle::with_template(template).unwrap().progress_chars("=> "));
+        bar
+    }
+    pub fn finish(&self) {
+        self.overall.finish_and_clear();
+    }
+}
+
+/// Runs ffmpeg with `-progress pipe:1` in its arguments, moving the bar along with the encoded duration
+/// and showing the encode speed
+///
+/// The ffmpeg log is returned instead of printed, as it would mess up the progress bars
+pub fn run_ffmpeg(command: &mut Command, bar: &ProgressBar) -> io::Result<(ExitStatus, String)> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stderr = child.stderr.take().unwrap();
+    let log = thread::spawn(move || {
+        let mut log = String::new();
+        let _ = stderr.read_to_string(&mut log);
+        log
+    });
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let line = line?;
+        match line.split_once('=') {
+            // Despite the name, out_time_ms is in microseconds as well
+            Some(("out_time_us", value)) => {
+                if let Ok(microseconds) = value.trim().parse::<u64>() {
+                    bar.set_position(microseconds / 1000);
+                }
+            }
+            Some(("speed", value)) => bar.set_message(format!("{} speed", value.trim())),
+            _ => bar.tick(),
+        }
+    }
+    let status = child.wait()?;
+    bar.finish_and_clear();
+    Ok((status, log.join().unwrap_or_default()))
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+
+use regex::Regex;
+
+/// Rules of an rclone filter file, applied to local directories the way rclone applies them to remotes with
+/// --filter-from
+///
+/// Every line is `+ pattern` to include or `- pattern` to exclude matching paths, the first matching rule wins and
+/// paths matching no rule are included. `!` clears the rules before it, lines starting with `#` or `;` are comments
+#[derive(Debug, Clone)]
+pub struct RcloneFilter {
+    /// Path of the filter file, which rclone reads itself for remote directories
+    path: String,
+    /// Rules added in front of the ones of the file, see [RcloneFilter::including]
+    extra_rules: Vec<String>,
+    rules: Vec<FilterRule>,
+}
+
+#[derive(Debug, Clone)]
+struct FilterRule {
+    include: bool,
+    regex: Regex,
+}
+
+impl RcloneFilter {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(path, &contents)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, error)))
+    }
+    /// Parses the contents of the filter file at `path`
+    pub fn parse(path: &str, contents: &str) -> Result<Self, String> {
+        let mut filter = Self {
+            path: path.to_string(),
+            extra_rules: Vec::new(),
+            rules: Vec::new(),
+        };
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line == "!" {
+                filter.rules.clear();
+                continue;
+            }
+            let (include, pattern) = match line.split_at_checked(2) {
+                Some(("+ ", pattern)) => (true, pattern),
+                Some(("- ", pattern)) => (false, pattern),
+                _ => return Err(format!("line {} doesn't start with \"+ \" or \"- \"", index + 1)),
+            };
+            let pattern = match pattern.strip_suffix('/') {
+                // Excluding a directory excludes everything in it
+                Some(directory) if !include => format!("{}/**", directory),
+                // Including a directory only makes rclone look into it, its files still need a rule of their own
+                Some(_) => continue,
+                None => pattern.to_string(),
+            };
+            let regex = glob_to_regex(&pattern).map_err(|error| format!("line {}: {}", index + 1, error))?;
+            filter.rules.push(FilterRule { include, regex });
+        }
+        Ok(filter)
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// Includes the files matching the pattern whatever the filter file says, like the config files of the input
+    pub fn including(mut self, pattern: &str) -> Result<Self, String> {
+        let regex = glob_to_regex(pattern)?;
+        self.rules.insert(0, FilterRule { include: true, regex });
+        self.extra_rules.push(format!("+ {}", pattern));
+        Ok(self)
+    }
+    /// Rules to pass to rclone in front of the filter file, as `--filter` or the FilterRule option of the API
+    pub fn extra_rules(&self) -> &[String] {
+        &self.extra_rules
+    }
+    /// Whether the file, given relative to the filtered directory, is included
+    pub fn includes(&self, file: &str) -> bool {
+        let file = file.replace('\\', "/");
+        self.rules
+            .iter()
+            .find(|rule| rule.regex.is_match(&file))
+            .map(|rule| rule.include)
+            .unwrap_or(true)
+    }
+}
+
+/// Translates an rclone glob to a regex matching paths relative to the filtered directory
+///
+/// Patterns starting with `/` match from the root, others match the end of the path, like `*.flac` matching files in
+/// every directory. `*` and `?` don't match `/`, `**` does, `{a,b}` matches either and `[...]` is a character class
+pub fn glob_to_regex(glob: &str) -> Result<Regex, String> {
+    let mut regex = String::new();
+    let glob = match glob.strip_prefix('/') {
+        Some(glob) => {
+            regex.push('^');
+            glob
+        }
+        None => {
+            regex.push_str("(^|/)");
+            glob
+        }
+    };
+    let mut chars = glob.chars().peekable();
+    let mut in_braces = false;
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => match chars.next() {
+                Some(escaped) => regex.push_str(&regex::escape(&escaped.to_string())),
+                None => return Err(format!("{} ends with a backslash", glob)),
+            },
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'*') {
+                    return Err(format!("too many stars in {}", glob));
+                }
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                // Classes like [[:alpha:]] contain brackets themselves
+                let mut depth = 1;
+                while depth > 0 {
+                    let char = chars.next().ok_or_else(|| format!("mismatched '[' in {}", glob))?;
+                    match char {
+                        '[' => depth += 1,
+                        ']' => depth -= 1,
+                        _ => {}
+                    }
+                    regex.push(char);
+                }
+            }
+            ']' => return Err(format!("mismatched ']' in {}", glob)),
+            '{' if in_braces => return Err(format!("nested '{{' in {}", glob)),
+            '{' => {
+                in_braces = true;
+                regex.push('(');
+            }
+            '}' if !in_braces => return Err(format!("mismatched '}}' in {}", glob)),
+            '}' => {
+                in_braces = false;
+                regex.push(')');
+            }
+            ',' if in_braces => regex.push('|'),
+            _ => regex.push_str(&regex::escape(&char.to_string())),
+        }
+    }
+    if in_braces {
+        return Err(format!("mismatched '{{' in {}", glob));
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|error| error.to_string())
+}
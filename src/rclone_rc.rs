@@ -0,0 +1,232 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Stdio};
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::fs_wrapper::RclonePath;
+use crate::programs;
+
+/// User the daemon is started with, the password is random for every run
+const USER: &str = "ffmusicsync";
+/// How long connecting to the daemon and sending a request may take before it's considered hung
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+static ENDPOINT: RwLock<Option<Endpoint>> = RwLock::new(None);
+static DAEMON: Mutex<Option<Child>> = Mutex::new(None);
+
+/// Address of the running `rclone rcd` and the Authorization header to send to it
+#[derive(Clone)]
+struct Endpoint {
+    address: SocketAddr,
+    authorization: String,
+}
+
+unsafe extern "C" {
+    fn atexit(callback: extern "C" fn()) -> std::os::raw::c_int;
+}
+
+extern "C" fn stop_at_exit() {
+    if let Ok(mut daemon) = DAEMON.lock() {
+        if let Some(mut child) = daemon.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Starts `rclone rcd` on a free local port, the fs_wrapper operations on remotes go through it afterwards
+///
+/// Listing, copying, moving and deleting then reuse the connections to the remotes instead of starting rclone and
+/// connecting again for every file. The daemon only accepts requests with a password made up for this run
+pub fn start() -> io::Result<()> {
+    // The port is given back right before rclone takes it
+    let address = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+    let mut secret = [0u8; 16];
+    getrandom::getrandom(&mut secret).map_err(|error| io::Error::other(error.to_string()))?;
+    let password = secret.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    // Passed in the environment, as arguments can be read by every user of the computer
+    let mut child = programs::rclone()
+        .arg("rcd")
+        .arg("--rc-addr")
+        .arg(address.to_string())
+        .env("RCLONE_RC_USER", USER)
+        .env("RCLONE_RC_PASS", &password)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let endpoint = Endpoint {
+        address,
+        authorization: format!("Basic {}", base64(format!("{}:{}", USER, password).as_bytes())),
+    };
+
+    let started = Instant::now();
+    while let Err(error) = post(endpoint.address, &endpoint.authorization, "rc/noop", &json!({})) {
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!("rclone rcd exited with {}", status)));
+        }
+        if started.elapsed() > Duration::from_secs(10) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::other(format!("rclone rcd didn't answer in 10 seconds: {}", error)));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    *DAEMON.lock().unwrap() = Some(child);
+    *ENDPOINT.write().unwrap() = Some(endpoint);
+    // std::process::exit skips destructors, the daemon must not outlive the run however it ends
+    unsafe {
+        atexit(stop_at_exit);
+    }
+    Ok(())
+}
+
+/// Stops the daemon, the operations start rclone for every call again afterwards
+pub fn stop() {
+    *ENDPOINT.write().unwrap() = None;
+    stop_at_exit();
+}
+
+pub fn is_running() -> bool {
+    ENDPOINT.read().unwrap().is_some()
+}
+
+/// Calls a method of the running daemon like `operations/copyfile`, returning its JSON result
+pub fn call(method: &str, parameters: &Value) -> io::Result<Value> {
+    let endpoint = ENDPOINT
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| io::Error::other("rclone rcd is not running"))?;
+    post(endpoint.address, &endpoint.authorization, method, parameters)
+}
+
+/// How long rclone may take to answer a call, it sends nothing until a transfer is done
+pub fn response_timeout(method: &str) -> Duration {
+    match method {
+        "rc/noop" => Duration::from_secs(5),
+        "operations/copyfile" | "operations/movefile" => Duration::from_secs(60 * 60),
+        _ => Duration::from_secs(10 * 60),
+    }
+}
+
+/// Sends one request to the remote control API
+///
+/// HTTP/1.0 makes rclone end the response by closing the connection instead of sending it in chunks. A daemon which
+/// stops answering fails the call after [REQUEST_TIMEOUT] or the [response_timeout] of the method instead of hanging
+/// the run
+pub fn post(address: SocketAddr, authorization: &str, method: &str, parameters: &Value) -> io::Result<Value> {
+    let body = serde_json::to_vec(parameters)?;
+    let mut stream = TcpStream::connect_timeout(&address, REQUEST_TIMEOUT)?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_read_timeout(Some(response_timeout(method)))?;
+    write!(
+        stream,
+        "POST /{} HTTP/1.0\r\nHost: {}\r\nAuthorization: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        method,
+        address,
+        authorization,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    parse_response(&response)
+}
+
+/// Parses a response of the API, errors are JSON objects with an error message like `{"error": "...", ...}`
+pub fn parse_response(response: &[u8]) -> io::Result<Value> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid response from rclone rcd");
+    let header_end = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or_else(invalid)?;
+    let status_line = String::from_utf8_lossy(&response[..header_end]);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+    let body: Value = serde_json::from_slice(&response[header_end + 4..])?;
+    match status {
+        200 => Ok(body),
+        _ => {
+            let message = body["error"].as_str().map(String::from).unwrap_or_else(|| format!("HTTP {}", status));
+            let kind = if status == 404 { io::ErrorKind::NotFound } else { io::ErrorKind::Other };
+            Err(io::Error::new(kind, format!("rclone: {}", message)))
+        }
+    }
+}
+
+/// The fs and remote parameters naming a single file, like `remote:` and `Music/Song.ogg`
+///
+/// Local files are named relative to their directory, as the API expects an fs to be a directory
+pub fn file_parameters(path: &RclonePath) -> (String, String) {
+    match path {
+        RclonePath::Remote(remote, path) => (format!("{}:", remote), path.clone()),
+        RclonePath::Local(path) => {
+            let path = Path::new(path);
+            let directory = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map(|parent| parent.to_string_lossy().to_string())
+                .unwrap_or_else(|| String::from("."));
+            let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            (directory, file_name)
+        }
+    }
+}
+
+/// Parameters of a method taking a source and a destination file, like `operations/copyfile`
+pub fn transfer_parameters(from: &RclonePath, to: &RclonePath) -> Value {
+    let (source_fs, source_remote) = file_parameters(from);
+    let (destination_fs, destination_remote) = file_parameters(to);
+    json!({
+        "srcFs": source_fs,
+        "srcRemote": source_remote,
+        "dstFs": destination_fs,
+        "dstRemote": destination_remote,
+    })
+}
+
+/// Calls a method taking a source and a destination file, like `operations/movefile`
+pub fn transfer_file(method: &str, from: &RclonePath, to: &RclonePath) -> io::Result<()> {
+    call(method, &transfer_parameters(from, to)).map(|_| ())
+}
+
+/// Lets the call copy between two remotes of the same backend on the provider's side, like
+/// --server-side-across-configs
+pub fn across_configs(mut parameters: Value) -> Value {
+    parameters["_config"] = json!({ "ServerSideAcrossConfigs": true });
+    parameters
+}
+
+/// Calls a method taking one directory or file, like `operations/mkdir`
+pub fn on_path(method: &str, path: &RclonePath) -> io::Result<Value> {
+    let (fs, remote) = match path {
+        RclonePath::Remote(remote, path) => (format!("{}:", remote), path.clone()),
+        RclonePath::Local(path) => (path.clone(), String::new()),
+    };
+    call(method, &json!({ "fs": fs, "remote": remote }))
+}
+
+/// Standard base64 with padding, for the Authorization header
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| group | (*byte as u32) << (16 - index * 8));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - index * 6) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
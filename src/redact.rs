@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+static REDACT: AtomicBool = AtomicBool::new(false);
+/// Key of the hashes, random for every run so the hashes can't be looked up in a list of common names
+static SALT: OnceLock<[u8; 32]> = OnceLock::new();
+
+/// Makes [path] hide the directory and file names, for output and reports shared in bug reports
+pub fn enable() {
+    REDACT.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    REDACT.load(Ordering::SeqCst)
+}
+
+/// The path with its names redacted if --redact is given, see [redact_path]
+pub fn path(path: &str) -> String {
+    if is_enabled() {
+        redact_path(path)
+    } else {
+        path.to_string()
+    }
+}
+
+/// A message of a warning, left out if --redact is given and it could mention a path
+pub fn message(message: &str) -> Option<String> {
+    if is_enabled() && (message.contains('/') || message.contains('\\')) {
+        None
+    } else {
+        Some(message.to_string())
+    }
+}
+
+/// Replaces every name in the path with a hash, keeping the separators, the remote and file extensions
+///
+/// The same name always gets the same hash in one run, so files of one album still share their directory,
+/// like `Phone:Music/Artist/Song.ogg` becoming `remote-1a2b3c4d:5e6f7a8b/9c0d1e2f/3a4b5c6d.ogg`
+pub fn redact_path(path: &str) -> String {
+    let (remote, path) = match path.split_once(':') {
+        // Drive letters aren't remotes
+        Some((remote, path)) if remote.len() > 1 && !remote.contains(['/', '\\']) => (Some(remote), path),
+        _ => (None, path),
+    };
+    let mut redacted = remote.map(|remote| format!("remote-{}:", hash(remote))).unwrap_or_default();
+    let mut name = String::new();
+    for character in path.chars().chain(std::iter::once('/')) {
+        if character == '/' || character == '\\' {
+            redacted.push_str(&redact_name(&name));
+            name.clear();
+            redacted.push(character);
+        } else {
+            name.push(character);
+        }
+    }
+    redacted.pop();
+    redacted
+}
+
+/// Hash of a file or directory name, keeping its extension and drive letters like C:
+fn redact_name(name: &str) -> String {
+    if name.is_empty() || name == "." || name == ".." || (name.len() == 2 && name.ends_with(':')) {
+        return name.to_string();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.len() <= 5 => {
+            format!("{}.{}", hash(stem), extension)
+        }
+        _ => hash(name),
+    }
+}
+
+fn hash(name: &str) -> String {
+    let salt = SALT.get_or_init(|| {
+        let mut salt = [0; 32];
+        if getrandom::getrandom(&mut salt).is_err() {
+            // Still differs between runs, just not as unpredictably
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+            salt = *blake3::hash(format!("{}-{}", std::process::id(), now.as_nanos()).as_bytes()).as_bytes();
+        }
+        salt
+    });
+    blake3::keyed_hash(salt, name.as_bytes()).to_hex()[..8].to_string()
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use crate::fs_wrapper::{partial_path, RclonePath};
+use crate::programs;
+
+/// Shell script doing the same file operations as the executor, built by [crate::SyncPlan::export_script]
+///
+/// Local operations use the coreutils, remotes are handled with rclone like fs_wrapper does
+pub struct ShellScript {
+    lines: Vec<String>,
+    /// Depth of the blocks the next line is in, see [ShellScript::begin_unless_exists]
+    depth: usize,
+}
+
+impl ShellScript {
+    pub fn new(input_directory: &RclonePath, output_directory: &RclonePath) -> Self {
+        Self {
+            lines: vec![
+                String::from("#!/bin/sh"),
+                format!("# Sync of {} to {}, exported by ffmusicsync", input_directory, output_directory),
+                String::from("# Running it by hand doesn't update the state files, the next sync plans everything again"),
+                String::from("set -e"),
+            ],
+            depth: 0,
+        }
+    }
+    /// Starts a new section of the script
+    pub fn section(&mut self, title: &str) {
+        self.lines.push(String::new());
+        self.comment(title);
+    }
+    /// Comments are kept on one line, a file name with a line break would otherwise end it and run the rest
+    pub fn comment(&mut self, comment: &str) {
+        self.line(format!("# {}", comment.replace(['\r', '\n'], " ")));
+    }
+    pub fn command<S: AsRef<str>>(&mut self, arguments: &[S]) {
+        self.line(
+            arguments
+                .iter()
+                .map(|argument| shell_quote(argument.as_ref()))
+                .collect::<Vec<String>>()
+                .join(" ")
+        );
+    }
+    /// Runs the following lines up to [ShellScript::end_block] only if the local file doesn't exist
+    pub fn begin_unless_exists(&mut self, file: &RclonePath) {
+        self.line(format!("if [ ! -e {} ]; then", shell_quote(&file.to_string())));
+        self.depth += 1;
+    }
+    pub fn end_block(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        self.line(String::from("fi"));
+    }
+    fn line(&mut self, line: String) {
+        self.lines.push(format!("{}{}", "  ".repeat(self.depth), line));
+    }
+    pub fn create_dir_all(&mut self, directory: &RclonePath) {
+        if directory.is_remote() {
+            self.rclone(&["mkdir", &directory.to_string()]);
+        } else {
+            self.command(&["mkdir", "-p", &directory.to_string()]);
+        }
+    }
+    /// Creates the parent directory of a local file, rclone creates them by itself
+    pub fn create_parent(&mut self, file: &RclonePath) {
+        if file.is_remote() {
+            return;
+        }
+        if let Some(parent) = Path::new(&file.to_string()).parent() {
+            if !parent.as_os_str().is_empty() {
+                self.command(&["mkdir", "-p", &parent.to_string_lossy()]);
+            }
+        }
+    }
+    pub fn copy(&mut self, from: &RclonePath, to: &RclonePath) {
+        if from.is_remote() || to.is_remote() {
+            self.rclone(&["copyto", &from.to_string(), &to.to_string()]);
+        } else {
+            // Copied to a partial name first like by the executor, rclone does the same by itself
+            let partial = RclonePath::Local(partial_path(Path::new(&to.to_string())).to_string_lossy().to_string());
+            self.command(&["cp", &from.to_string(), &partial.to_string()]);
+            self.rename(&partial, to);
+        }
+    }
+    pub fn rename(&mut self, from: &RclonePath, to: &RclonePath) {
+        if from.is_remote() || to.is_remote() {
+            self.rclone(&["moveto", &from.to_string(), &to.to_string()]);
+        } else {
+            self.command(&["mv", &from.to_string(), &to.to_string()]);
+        }
+    }
+    pub fn remove_file(&mut self, file: &RclonePath) {
+        if file.is_remote() {
+            self.rclone(&["deletefile", &file.to_string()]);
+        } else {
+            self.command(&["rm", &file.to_string()]);
+        }
+    }
+    /// rclone with the flags of the config, like fs_wrapper runs it
+    fn rclone(&mut self, arguments: &[&str]) {
+        let mut command_line = programs::rclone_command_line();
+        command_line.extend(arguments.iter().map(|argument| argument.to_string()));
+        self.command(&command_line);
+    }
+    pub fn render(&self) -> String {
+        let mut script = self.lines.join("\n");
+        script.push('\n');
+        script
+    }
+}
+
+/// Quotes an argument for POSIX shells, arguments without special characters are kept as they are
+pub fn shell_quote(argument: &str) -> String {
+    let is_plain = |character: char| character.is_ascii_alphanumeric() || "-_./:=+,@%".contains(character);
+    if !argument.is_empty() && argument.chars().all(is_plain) {
+        argument.to_string()
+    } else {
+        format!("'{}'", argument.replace('\'', "'\\''"))
+    }
+}
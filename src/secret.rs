@@ -0,0 +1,86 @@
+use std::fmt;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::process::Command;
+
+use serde::{Deserialize, Deserializer};
+
+/// A sensitive config value, never printed
+///
+/// The value in the config can reference where the secret is stored:
+/// - `env:NAME` - the environment variable NAME
+/// - `@/path/to/secret` - contents of the file, without the trailing newline
+/// - `keyring:service/account` - the system keyring (secret-tool on Linux, security on macOS)
+///
+/// Anything else is used as is
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Resolves a secret reference, the error never contains the secret itself
+    pub fn resolve(value: &str) -> Result<Self, String> {
+        if let Some(name) = value.strip_prefix("env:") {
+            std::env::var(name)
+                .map(Self)
+                .map_err(|_| format!("environment variable {} is not set", name))
+        } else if let Some(path) = value.strip_prefix('@') {
+            std::fs::read_to_string(path)
+                .map(|contents| Self(contents.trim_end_matches(['\r', '\n']).to_string()))
+                .map_err(|error| format!("failed to read secret file {}: {}", path, error))
+        } else if let Some(entry) = value.strip_prefix("keyring:") {
+            let (service, account) = entry
+                .split_once('/')
+                .ok_or_else(|| format!("keyring entry {} is not in the service/account format", entry))?;
+            read_keyring(service, account).map(Self)
+        } else {
+            Ok(Self(value.to_string()))
+        }
+    }
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::resolve(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_keyring(service: &str, account: &str) -> Result<String, String> {
+    let output = Command::new("secret-tool")
+        .args(["lookup", "service", service, "account", account])
+        .output()
+        .map_err(|error| format!("failed to run secret-tool: {}", error))?;
+    keyring_output(output, service, account)
+}
+
+#[cfg(target_os = "macos")]
+fn read_keyring(service: &str, account: &str) -> Result<String, String> {
+    let output = Command::new("security")
+        .args(["find-generic-password", "-s", service, "-a", account, "-w"])
+        .output()
+        .map_err(|error| format!("failed to run security: {}", error))?;
+    keyring_output(output, service, account)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn read_keyring(_service: &str, _account: &str) -> Result<String, String> {
+    Err(String::from("reading secrets from the keyring is not supported on this platform"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn keyring_output(output: std::process::Output, service: &str, account: &str) -> Result<String, String> {
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches(['\r', '\n']).to_string())
+    } else {
+        Err(format!("no keyring entry for service {} and account {}", service, account))
+    }
+}
@@ -0,0 +1,304 @@
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::format::format_duration;
+
+/// How long a cancelled sync gets to save its state before it's killed
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a client may take to send its request or read the response, requests are handled one at a time
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Priority of a queued job, higher priorities run first
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    fn from_query(query: &str) -> Option<Self> {
+        let priority = query
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix("priority="))
+            .unwrap_or("normal");
+        match priority {
+            "low" => Some(Self::Low),
+            "normal" => Some(Self::Normal),
+            "high" => Some(Self::High),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: u64,
+    priority: Priority,
+}
+
+/// State of the sync runs started through the API
+#[derive(Default)]
+pub struct ServerState {
+    child: Option<Child>,
+    running_job: Option<Job>,
+    queue: Vec<Job>,
+    output: Arc<Mutex<String>>,
+    last_exit_code: Option<i32>,
+    /// When the running sync was started
+    started: Option<Instant>,
+    /// When the running sync was asked to stop
+    cancelled: Option<Instant>,
+    last_duration: Option<Duration>,
+    runs: u64,
+}
+
+impl ServerState {
+    /// Removes the job with the highest priority from the queue, the oldest one if there are more
+    pub fn next_job(&mut self) -> Option<Job> {
+        let index = self.queue
+            .iter()
+            .enumerate()
+            .max_by(|(a_index, a), (b_index, b)| a.priority.cmp(&b.priority).then(b_index.cmp(a_index)))
+            .map(|(index, _)| index)?;
+        Some(self.queue.remove(index))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    state: &'static str,
+    runs: u64,
+    last_exit_code: Option<i32>,
+    /// h:mm:ss since the running sync was started
+    running_for: Option<String>,
+    /// h:mm:ss the last sync took
+    last_duration: Option<String>,
+    running_job: Option<Job>,
+    queue: Vec<Job>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report {
+    running: bool,
+    exit_code: Option<i32>,
+    output: String,
+}
+
+/// Serves a small HTTP API to trigger and monitor sync runs, each run is a separate
+/// non-interactive process of this executable with the given arguments
+///
+/// - `POST /sync?priority=low|normal|high` - queue a sync, queued syncs run one at a time by priority
+/// - `GET /status` - whether a sync is running, the queue and how the last one ended
+/// - `GET /report` - output of the current or last sync
+/// - `POST /cancel` - stop the running sync
+pub fn serve(listen: &str, sync_args: Vec<String>) -> io::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    println!("Listening on {}", listen);
+    let state = Arc::new(Mutex::new(ServerState::default()));
+
+    // Reap finished runs and start queued ones
+    let monitored_state = state.clone();
+    thread::spawn(move || loop {
+        {
+            let mut state = monitored_state.lock().unwrap();
+            let timed_out = state.cancelled.is_some_and(|cancelled| cancelled.elapsed() >= CANCEL_TIMEOUT);
+            if let Some(child) = &mut state.child {
+                match child.try_wait() {
+                    Ok(Some(status)) => {
+                        state.last_exit_code = status.code();
+                        state.last_duration = state.started.take().map(|started| started.elapsed());
+                        state.cancelled = None;
+                        state.child = None;
+                        state.running_job = None;
+                    }
+                    // Killed runs are reaped on the next poll
+                    Ok(None) if timed_out => {
+                        let _ = child.kill();
+                    }
+                    _ => {}
+                }
+            }
+            if state.child.is_none() {
+                if let Some(job) = state.next_job() {
+                    match start_sync(&mut state, &sync_args) {
+                        Ok(()) => state.running_job = Some(job),
+                        Err(error) => eprintln!("Failed to start job {}: {}", job.id, error),
+                    }
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(500));
+    });
+
+    let mut next_job_id = 1;
+    for stream in listener.incoming().flatten() {
+        if let Err(error) = handle_connection(stream, &state, &mut next_job_id) {
+            eprintln!("Failed to handle request: {}", error);
+        }
+    }
+    Ok(())
+}
+
+/// Answers a single request to the API, syncs are only queued here and started by the monitor thread of [serve]
+pub fn handle_connection(stream: TcpStream, state: &Arc<Mutex<ServerState>>, next_job_id: &mut u64) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, no endpoint takes a body
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut request = request_line.split_whitespace();
+    let method = request.next().unwrap_or("");
+    let target = request.next().unwrap_or("");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut state = state.lock().unwrap();
+    let (status_code, body) = match (method, path) {
+        ("POST", "/sync") => {
+            match Priority::from_query(query) {
+                Some(priority) => {
+                    let job = Job {
+                        id: *next_job_id,
+                        priority,
+                    };
+                    *next_job_id += 1;
+                    state.queue.push(job.clone());
+                    (202, serde_json::to_string(&job).unwrap())
+                }
+                None => (400, error_body("priority must be low, normal or high")),
+            }
+        }
+        ("GET", "/status") => (200, status_body(&state)),
+        ("GET", "/report") => {
+            let report = Report {
+                running: state.child.is_some(),
+                exit_code: state.last_exit_code,
+                output: state.output.lock().unwrap().clone(),
+            };
+            (200, serde_json::to_string(&report).unwrap())
+        }
+        ("POST", "/cancel") => {
+            if let Some(child) = &state.child {
+                // Like Ctrl+C, so the run saves its state. The monitor thread reaps it or kills it after a while
+                interrupt(child)?;
+                state.cancelled = Some(Instant::now());
+                (202, status_body(&state))
+            } else {
+                (409, error_body("No sync is running"))
+            }
+        }
+        _ => (404, error_body("Not found")),
+    };
+    drop(state);
+    let reason = match status_code {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_code,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn start_sync(state: &mut ServerState, sync_args: &[String]) -> io::Result<()> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(sync_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Only a process group of its own can be sent Ctrl+Break without the server getting it too
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x200;
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+    let mut child = command.spawn()?;
+    let output = Arc::new(Mutex::new(String::new()));
+    let stdout = child.stdout.take().map(|stdout| Box::new(stdout) as Box<dyn Read + Send>);
+    let stderr = child.stderr.take().map(|stderr| Box::new(stderr) as Box<dyn Read + Send>);
+    for pipe in [stdout, stderr].into_iter().flatten() {
+        let output = output.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                let mut output = output.lock().unwrap();
+                output.push_str(&line);
+                output.push('\n');
+            }
+        });
+    }
+    state.child = Some(child);
+    state.output = output;
+    state.last_exit_code = None;
+    state.started = Some(Instant::now());
+    state.runs += 1;
+    Ok(())
+}
+
+/// Sends SIGINT to a sync, or Ctrl+Break on Windows where new process groups ignore Ctrl+C
+#[cfg(unix)]
+fn interrupt(child: &Child) -> io::Result<()> {
+    let status = Command::new("kill").args(["-INT", &child.id().to_string()]).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("kill exited with {}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn interrupt(child: &Child) -> io::Result<()> {
+    const CTRL_BREAK_EVENT: u32 = 1;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GenerateConsoleCtrlEvent(ctrl_event: u32, process_group_id: u32) -> i32;
+    }
+    // The process group of a child started with CREATE_NEW_PROCESS_GROUP has its process ID
+    if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, child.id()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn status_body(state: &ServerState) -> String {
+    let status = Status {
+        state: if state.child.is_some() { "running" } else { "idle" },
+        runs: state.runs,
+        last_exit_code: state.last_exit_code,
+        running_for: state.started.map(|started| format_duration(started.elapsed())),
+        last_duration: state.last_duration.map(format_duration),
+        running_job: state.running_job.clone(),
+        queue: state.queue.clone(),
+    };
+    serde_json::to_string(&status).unwrap()
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
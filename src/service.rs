@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+/// When a scheduled task registered by `service install` runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Repeated with this many minutes between the starts
+    Every(u64),
+    /// Once a day at this time, like 03:00
+    Daily(String),
+    /// When the user logs on, for the long-running server
+    Logon,
+}
+
+impl Schedule {
+    /// The schedule of --every, --at or --serve, exactly one of them has to be given
+    pub fn new(every: Option<Duration>, at: Option<&str>, serve: bool) -> Result<Self, String> {
+        match (every, at, serve) {
+            (Some(every), None, false) => {
+                let minutes = every.as_secs() / 60;
+                // Task Scheduler repeats tasks at most every minute and at least every 31 days
+                if every.as_secs() % 60 != 0 || !(1..=31 * 24 * 60).contains(&minutes) {
+                    return Err(String::from("--every has to be whole minutes between 1m and 31 days"));
+                }
+                Ok(Self::Every(minutes))
+            }
+            (None, Some(at), false) => {
+                let valid = at.split_once(':').is_some_and(|(hours, minutes)| {
+                    hours.len() == 2
+                        && minutes.len() == 2
+                        && hours.parse::<u8>().is_ok_and(|hours| hours < 24)
+                        && minutes.parse::<u8>().is_ok_and(|minutes| minutes < 60)
+                });
+                if !valid {
+                    return Err(format!("invalid time {}, use HH:MM like 03:00", at));
+                }
+                Ok(Self::Daily(at.to_string()))
+            }
+            (None, None, true) => Ok(Self::Logon),
+            _ => Err(String::from("Give exactly one of --every, --at and --serve")),
+        }
+    }
+    /// Trigger element of the task XML, `user` is the one logging on for [Schedule::Logon]
+    fn trigger(&self, user: &str) -> String {
+        match self {
+            Self::Every(minutes) => format!(
+                "<TimeTrigger><StartBoundary>2000-01-01T00:00:00</StartBoundary>\
+                 <Repetition><Interval>PT{}M</Interval></Repetition></TimeTrigger>",
+                minutes
+            ),
+            Self::Daily(at) => format!(
+                "<CalendarTrigger><StartBoundary>2000-01-01T{}:00</StartBoundary>\
+                 <ScheduleByDay><DaysInterval>1</DaysInterval></ScheduleByDay></CalendarTrigger>",
+                at
+            ),
+            Self::Logon => format!("<LogonTrigger><UserId>{}</UserId></LogonTrigger>", xml_escape(user)),
+        }
+    }
+}
+
+/// Batch file run by the task, changing to the working directory and appending the output to the log
+///
+/// Task Scheduler starts programs in System32 and drops their output, which makes relative state files end up there
+/// and failed runs impossible to look into. The script is written as UTF-8 and switches cmd to it before the first
+/// line with a path, cmd would read the paths in the OEM code page otherwise
+pub fn task_script(working_directory: &str, program: &str, arguments: &[String], log: &str) -> String {
+    let command = std::iter::once(program)
+        .chain(arguments.iter().map(String::as_str))
+        .map(cmd_quote)
+        .collect::<Vec<String>>()
+        .join(" ");
+    let log = cmd_quote(log);
+    [
+        String::from("@echo off"),
+        String::from("chcp 65001 > nul"),
+        String::from("rem Run by the scheduled task registered by ffmusicsync service install"),
+        format!("cd /d {}", cmd_quote(working_directory)),
+        format!("echo ==== %DATE% %TIME% >> {}", log),
+        format!("{} >> {} 2>&1", command, log),
+        String::new(),
+    ]
+    .join("\r\n")
+}
+
+/// Definition of the task for `schtasks /Create /XML`, running the script in the working directory
+///
+/// Unlike the defaults of schtasks, the task isn't stopped after 72 hours, starts when a run was missed while the
+/// computer was off and doesn't start a second sync while one is still running
+pub fn task_xml(schedule: &Schedule, user: &str, script: &str, working_directory: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>Registered by ffmusicsync service install</Description>
+  </RegistrationInfo>
+  <Triggers>
+    {}
+  </Triggers>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <StartWhenAvailable>true</StartWhenAvailable>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <Enabled>true</Enabled>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{}</Command>
+      <WorkingDirectory>{}</WorkingDirectory>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        schedule.trigger(user),
+        xml_escape(script),
+        xml_escape(working_directory)
+    )
+}
+
+/// schtasks only reads task XML encoded as UTF-16 with a byte order mark
+pub fn encode_utf16(text: &str) -> Vec<u8> {
+    std::iter::once(0xFEFF)
+        .chain(text.encode_utf16())
+        .flat_map(|unit: u16| unit.to_le_bytes())
+        .collect()
+}
+
+/// Quotes an argument for a batch file, doubling % as it would be expanded otherwise
+pub fn cmd_quote(argument: &str) -> String {
+    let argument = argument.replace('%', "%%");
+    let is_plain = |character: char| !" \t&|<>^()\",;=".contains(character);
+    if !argument.is_empty() && argument.chars().all(is_plain) {
+        argument
+    } else {
+        format!("\"{}\"", argument.replace('"', "\"\""))
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,208 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, Once};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::SnapshotKind;
+
+/// Start of the names of the btrfs subvolumes and ZFS snapshots, followed by the process ID and the start time so
+/// concurrent runs never remove each other's snapshot
+pub const SNAPSHOT_PREFIX: &str = "ffmusicsync-snapshot";
+
+/// Snapshots which weren't removed yet, removed at exit if the run ends with std::process::exit
+static TAKEN: Mutex<Vec<(SnapshotKind, String)>> = Mutex::new(Vec::new());
+static REMOVE_AT_EXIT: Once = Once::new();
+
+unsafe extern "C" {
+    fn atexit(callback: extern "C" fn()) -> std::os::raw::c_int;
+}
+
+extern "C" fn remove_at_exit() {
+    let taken = match TAKEN.lock() {
+        Ok(mut taken) => std::mem::take(&mut *taken),
+        Err(_) => return,
+    };
+    for (kind, id) in taken {
+        if let Err(error) = remove(kind, &id) {
+            eprintln!("Failed to remove the snapshot {} of the input: {}", id, error);
+        }
+    }
+}
+
+/// Name of the snapshot of this run
+pub fn snapshot_name() -> String {
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    format!("{}-{}-{}", SNAPSHOT_PREFIX, std::process::id(), started)
+}
+
+/// A snapshot of the input directory, which stays unchanged while the library is edited during the sync
+///
+/// Removed when dropped or when the process exits, taking and removing snapshots usually needs root or administrator
+/// rights
+#[derive(Debug)]
+pub struct Snapshot {
+    kind: SnapshotKind,
+    /// The btrfs subvolume, ZFS snapshot or shadow copy ID to remove
+    id: String,
+    /// Where the input directory is found in the snapshot
+    pub input_directory: PathBuf,
+}
+
+impl Snapshot {
+    pub fn take(kind: SnapshotKind, input_directory: &Path) -> io::Result<Self> {
+        let input_directory = input_directory.canonicalize()?;
+        let name = snapshot_name();
+        let snapshot = match kind {
+            SnapshotKind::Btrfs => take_btrfs(&input_directory, &name),
+            SnapshotKind::Zfs => take_zfs(&input_directory, &name),
+            SnapshotKind::Vss => take_vss(&input_directory),
+        }?;
+        TAKEN.lock().unwrap().push((kind, snapshot.id.clone()));
+        // std::process::exit skips destructors, the snapshot must not outlive the run however it ends
+        REMOVE_AT_EXIT.call_once(|| unsafe {
+            atexit(remove_at_exit);
+        });
+        Ok(snapshot)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        TAKEN.lock().unwrap().retain(|(_, id)| *id != self.id);
+        if let Err(error) = remove(self.kind, &self.id) {
+            eprintln!("Failed to remove the snapshot {} of the input: {}", self.id, error);
+        }
+    }
+}
+
+fn remove(kind: SnapshotKind, id: &str) -> io::Result<()> {
+    match kind {
+        SnapshotKind::Btrfs => run(Command::new("btrfs").args(["subvolume", "delete", id])).map(|_| ()),
+        SnapshotKind::Zfs => run(Command::new("zfs").args(["destroy", id])).map(|_| ()),
+        SnapshotKind::Vss => run(Command::new("powershell").args([
+            "-NoProfile",
+            "-Command",
+            &format!("Get-CimInstance Win32_ShadowCopy | Where-Object ID -eq '{}' | Remove-CimInstance", id),
+        ]))
+        .map(|_| ()),
+    }
+}
+
+/// Snapshots the subvolume the input is in into a read-only subvolume
+fn take_btrfs(input_directory: &Path, name: &str) -> io::Result<Snapshot> {
+    let subvolume = btrfs_subvolume(input_directory)?;
+    let snapshot = btrfs_snapshot_path(&subvolume, input_directory, name);
+    run(Command::new("btrfs").args(["subvolume", "snapshot", "-r"]).arg(&subvolume).arg(&snapshot))?;
+    Ok(Snapshot {
+        kind: SnapshotKind::Btrfs,
+        id: snapshot.to_string_lossy().to_string(),
+        input_directory: snapshot.join(input_directory.strip_prefix(&subvolume).unwrap_or(Path::new(""))),
+    })
+}
+
+/// Where the snapshot of a subvolume is made, at the top of the subvolume unless the input is the subvolume itself
+///
+/// The snapshot would be listed as part of the input then, so it's made next to the subvolume instead
+pub fn btrfs_snapshot_path(subvolume: &Path, input_directory: &Path, name: &str) -> PathBuf {
+    match subvolume.parent() {
+        Some(parent) if subvolume == input_directory => parent.join(format!(".{}", name)),
+        _ => subvolume.join(format!(".{}", name)),
+    }
+}
+
+/// The subvolume containing the directory, the roots of btrfs subvolumes always have the inode number 256
+#[cfg(unix)]
+fn btrfs_subvolume(directory: &Path) -> io::Result<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    directory
+        .ancestors()
+        .find(|ancestor| ancestor.metadata().is_ok_and(|metadata| metadata.ino() == 256))
+        .map(Path::to_path_buf)
+        .ok_or_else(|| io::Error::other(format!("{} is not on a btrfs subvolume", directory.display())))
+}
+
+#[cfg(not(unix))]
+fn btrfs_subvolume(_directory: &Path) -> io::Result<PathBuf> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "btrfs snapshots are only supported on Linux"))
+}
+
+fn take_zfs(input_directory: &Path, name: &str) -> io::Result<Snapshot> {
+    let datasets = run(Command::new("zfs").args(["list", "-H", "-o", "name,mountpoint", "-t", "filesystem"]))?;
+    let (dataset, mount_point) = zfs_dataset(&datasets, input_directory)
+        .ok_or_else(|| io::Error::other(format!("{} is not on a mounted ZFS dataset", input_directory.display())))?;
+    let id = format!("{}@{}", dataset, name);
+    run(Command::new("zfs").args(["snapshot", &id]))?;
+    Ok(Snapshot {
+        kind: SnapshotKind::Zfs,
+        id,
+        input_directory: zfs_snapshot_path(&mount_point, input_directory, name),
+    })
+}
+
+/// The dataset mounted closest to the directory and its mount point, from the output of `zfs list -H -o name,mountpoint`
+pub fn zfs_dataset(datasets: &str, directory: &Path) -> Option<(String, PathBuf)> {
+    datasets
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(name, mount_point)| (name.to_string(), PathBuf::from(mount_point)))
+        .filter(|(_, mount_point)| mount_point.is_absolute() && directory.starts_with(mount_point))
+        .max_by_key(|(_, mount_point)| mount_point.components().count())
+}
+
+/// Where a directory of a dataset is found in its snapshot, the .zfs directory is there even if it's hidden
+pub fn zfs_snapshot_path(mount_point: &Path, directory: &Path, name: &str) -> PathBuf {
+    mount_point
+        .join(".zfs/snapshot")
+        .join(name)
+        .join(directory.strip_prefix(mount_point).unwrap_or(Path::new("")))
+}
+
+fn take_vss(input_directory: &Path) -> io::Result<Snapshot> {
+    let path = input_directory.to_string_lossy();
+    // canonicalize returns verbatim paths like \\?\C:\Music
+    let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+    let volume = path
+        .get(..3)
+        .filter(|volume| volume.ends_with(":\\"))
+        .ok_or_else(|| io::Error::other(format!("{} is not on a drive with a letter", path)))?;
+    let output = run(Command::new("powershell").args([
+        "-NoProfile",
+        "-Command",
+        &format!(
+            "$created = Invoke-CimMethod -ClassName Win32_ShadowCopy -MethodName Create \
+             -Arguments @{{Volume = '{}'; Context = 'ClientAccessible'}}; \
+             $copy = Get-CimInstance Win32_ShadowCopy | Where-Object ID -eq $created.ShadowID; \
+             Write-Output \"$($copy.ID)|$($copy.DeviceObject)\"",
+            volume
+        ),
+    ]))?;
+    let (id, device) = parse_shadow_copy(&output)
+        .ok_or_else(|| io::Error::other(format!("Failed to create a shadow copy of {}: {}", volume, output.trim())))?;
+    Ok(Snapshot {
+        kind: SnapshotKind::Vss,
+        id,
+        input_directory: PathBuf::from(format!("{}\\{}", device, &path[3..])),
+    })
+}
+
+/// The ID and device of a created shadow copy, printed as `ID|DeviceObject`
+pub fn parse_shadow_copy(output: &str) -> Option<(String, String)> {
+    let (id, device) = output.trim().split_once('|')?;
+    if id.is_empty() || !device.starts_with(r"\\?\GLOBALROOT\") {
+        return None;
+    }
+    Some((id.to_string(), device.to_string()))
+}
+
+/// Runs a snapshot command, returning its output or its error message
+fn run(command: &mut Command) -> io::Result<String> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(io::Error::other(if error.is_empty() { output.status.to_string() } else { error }))
+    }
+}
@@ -0,0 +1,25 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Writes a state file through a temporary file next to it, which is renamed into place once it's complete
+///
+/// A crash or power loss while writing leaves either the old or the new file behind, never a half-written one
+pub fn save_json<T: Serialize, P: AsRef<Path>>(path: P, value: &T, pretty: bool) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    if pretty {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+    } else {
+        serde_json::to_writer(&mut writer, value)?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&temp_path, path)
+}
@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::support::{read_tar, write_tar};
+
+/// Describes the archive, written as its first file
+pub const INFO_FILE: &str = "ffmusicsync-state.json";
+/// Layout of the archive, increased when files are renamed or change their meaning
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveInfo {
+    pub version: u32,
+    /// Version of ffmusicsync which exported the state
+    pub exported_by: String,
+    /// Unix time of the export
+    pub exported: u64,
+}
+
+/// The config and state of all outputs, for moving the syncs to another computer without adopting the outputs again
+#[derive(Default, Debug)]
+pub struct StateArchive {
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl StateArchive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(&mut self, name: &str, contents: Vec<u8>) {
+        self.files.push((name.to_string(), contents));
+    }
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(file_name, _)| file_name == name)
+            .map(|(_, contents)| contents.as_slice())
+    }
+    /// Writes the archive as tar, compressed with zstd if the path ends with .zst
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let info = ArchiveInfo {
+            version: ARCHIVE_VERSION,
+            exported_by: env!("CARGO_PKG_VERSION").to_string(),
+            exported: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs())
+                .unwrap_or_default(),
+        };
+        let mut files = vec![(INFO_FILE.to_string(), serde_json::to_vec_pretty(&info)?)];
+        files.extend(self.files.iter().cloned());
+        let mut tar = Vec::new();
+        write_tar(&files, &mut tar)?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        if path.ends_with(".zst") {
+            ruzstd::encoding::compress(tar.as_slice(), &mut writer, ruzstd::encoding::CompressionLevel::Fastest);
+        } else {
+            writer.write_all(&tar)?;
+        }
+        writer.flush()
+    }
+    /// Reads an archive written by [StateArchive::write], refusing ones of newer versions of ffmusicsync
+    pub fn read(path: &str) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut files = if path.ends_with(".zst") {
+            let decoder = ruzstd::decoding::StreamingDecoder::new(reader)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+            read_tar(decoder)?
+        } else {
+            read_tar(reader)?
+        };
+        let info_index = files
+            .iter()
+            .position(|(name, _)| name == INFO_FILE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is no state archive", path)))?;
+        let info: ArchiveInfo = serde_json::from_slice(&files.remove(info_index).1)?;
+        if info.version > ARCHIVE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} was exported by ffmusicsync {}, update to import it", path, info.exported_by),
+            ));
+        }
+        Ok(Self { files })
+    }
+}
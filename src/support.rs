@@ -0,0 +1,216 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::doctor::DoctorCheck;
+use crate::failures::FailedFiles;
+use crate::redact::redact_path;
+
+/// Parts of option names whose values are replaced in the config of a support bundle
+const SECRET_OPTIONS: [&str; 5] = ["password", "username", "token", "secret", "apikey"];
+/// Parts of command line flag names whose values are replaced, like rclone's `--sftp-pass` or `--s3-access-key-id`
+const SECRET_FLAGS: [&str; 5] = ["pass", "user", "token", "secret", "key"];
+/// Lines kept from the end of the log
+const LOG_TAIL_LINES: usize = 500;
+/// Bytes read from the end of the log to find the lines, so a log grown over years isn't read whole
+const LOG_TAIL_BYTES: u64 = 256 * 1024;
+
+/// Files collected for a bug report, written as one tar archive
+#[derive(Default)]
+pub struct SupportBundle {
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl SupportBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(&mut self, name: &str, contents: impl Into<Vec<u8>>) {
+        self.files.push((name.to_string(), contents.into()));
+    }
+    pub fn file_names(&self) -> Vec<&str> {
+        self.files.iter().map(|(name, _)| name.as_str()).collect()
+    }
+    /// Writes the files as an uncompressed ustar archive, which every platform can open
+    pub fn write_tar<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_tar(&self.files, writer)
+    }
+}
+
+/// Writes files as a ustar archive, their names have to fit into the 100 bytes of the header
+pub fn write_tar<W: Write>(files: &[(String, Vec<u8>)], mut writer: W) -> io::Result<()> {
+    let modified = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    for (name, contents) in files {
+        writer.write_all(&tar_header(name, contents.len() as u64, modified)?)?;
+        writer.write_all(contents)?;
+        let padding = (512 - contents.len() % 512) % 512;
+        writer.write_all(&vec![0; padding])?;
+    }
+    // Two empty blocks end the archive
+    writer.write_all(&[0; 1024])?;
+    writer.flush()
+}
+
+/// Reads the regular files of a tar archive, like the ones written by [write_tar]
+pub fn read_tar<R: Read>(mut reader: R) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut files = Vec::new();
+    let mut header = [0u8; 512];
+    loop {
+        reader.read_exact(&mut header).map_err(|_| invalid("The tar archive ends early"))?;
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(files);
+        }
+        let text = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).trim().to_string()
+        };
+        let size = u64::from_str_radix(&text(124..136), 8).map_err(|_| invalid("Invalid size in the tar archive"))?;
+        // The size comes from the archive, a made up one can't allocate more than the archive holds
+        let mut contents = Vec::new();
+        (&mut reader).take(size).read_to_end(&mut contents)?;
+        if (contents.len() as u64) < size {
+            return Err(invalid("The tar archive ends early"));
+        }
+        let padding = (512 - size % 512) % 512;
+        io::copy(&mut (&mut reader).take(padding), &mut io::sink())?;
+        // Directories, links and the like aren't written by ffmusicsync
+        if matches!(header[156], b'0' | 0) {
+            files.push((text(0..100), contents));
+        }
+    }
+}
+
+/// Header of a regular file in a ustar archive
+pub fn tar_header(name: &str, size: u64, modified: u64) -> io::Result<[u8; 512]> {
+    if name.len() > 100 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is too long for a tar archive", name)));
+    }
+    let mut header = [0u8; 512];
+    let mut field = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, format!("{:011o}\0", modified).as_bytes());
+    // The checksum is calculated with its own field filled with spaces
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+    let checksum = header.iter().map(|byte| *byte as u32).sum::<u32>();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
+
+/// Replaces the values of options holding credentials, like mqtt.password, with `<redacted>`
+///
+/// Credentials given as flags in arrays, like `"--sftp-pass=..."` in rcloneFlags, are redacted too
+pub fn redact_config(config: &mut serde_json::Value) {
+    match config {
+        serde_json::Value::Object(options) => {
+            for (option, value) in options.iter_mut() {
+                let option = option.to_lowercase();
+                if SECRET_OPTIONS.iter().any(|secret| option.contains(secret)) && !value.is_null() {
+                    *value = serde_json::Value::String(String::from("<redacted>"));
+                } else {
+                    redact_config(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            redact_flags(values);
+            values.iter_mut().for_each(redact_config)
+        }
+        _ => {}
+    }
+}
+
+/// Replaces the values of secret flags, both `--flag=value` and `--flag value`
+fn redact_flags(values: &mut [serde_json::Value]) {
+    let mut redact_next = false;
+    for value in values {
+        let argument = match value {
+            serde_json::Value::String(argument) => argument,
+            _ => {
+                redact_next = false;
+                continue;
+            }
+        };
+        if !argument.starts_with('-') {
+            if redact_next {
+                *argument = String::from("<redacted>");
+            }
+            redact_next = false;
+            continue;
+        }
+        let (flag, has_value) = match argument.split_once('=') {
+            Some((flag, _)) => (flag.to_string(), true),
+            None => (argument.clone(), false),
+        };
+        let secret = SECRET_FLAGS.iter().any(|secret| flag.to_lowercase().contains(secret));
+        redact_next = secret && !has_value;
+        if secret && has_value {
+            *argument = format!("{}=<redacted>", flag);
+        }
+    }
+}
+
+/// Failed files with every path redacted, see [redact_path]
+pub fn redact_failed_files(failed: &FailedFiles) -> FailedFiles {
+    FailedFiles {
+        override_files: failed.override_files.iter().map(|path| redact_path(path)).collect(),
+        files: failed.files.iter().map(|(input, output)| (redact_path(input), redact_path(output))).collect(),
+    }
+}
+
+/// The last lines of a log, with the lines which could mention a path left out if `redact` is set
+pub fn log_tail(path: &str, redact: bool) -> io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let start = file.metadata()?.len().saturating_sub(LOG_TAIL_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let mut lines = contents.lines().collect::<Vec<&str>>();
+    // The first line is cut in the middle when the log is longer than what's read
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    let lines = lines
+        .into_iter()
+        .filter(|line| !redact || !(line.contains('/') || line.contains('\\')))
+        .collect::<Vec<&str>>();
+    Ok(lines[lines.len().saturating_sub(LOG_TAIL_LINES)..].iter().map(|line| format!("{}\n", line)).collect())
+}
+
+/// Doctor checks as plain text, one per line
+pub fn format_checks(checks: &[DoctorCheck]) -> String {
+    checks
+        .iter()
+        .map(|check| match &check.problem {
+            None => format!("{}: {}\n", check.name, check.detail),
+            Some(problem) => format!("{}: {} ({:?})\n  {}\n", check.name, check.detail, problem.severity, problem.fix),
+        })
+        .collect()
+}
+
+/// Size and number of entries of a state file, without its contents
+pub fn state_file_stats(path: &str) -> String {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) => return format!("{}: {}\n", path, error),
+    };
+    let entries = match serde_json::from_slice::<serde_json::Value>(&contents) {
+        Ok(serde_json::Value::Object(entries)) => entries.len().to_string(),
+        Ok(serde_json::Value::Array(entries)) => entries.len().to_string(),
+        Ok(_) => String::from("?"),
+        Err(error) => format!("invalid ({})", error),
+    };
+    format!("{}: {} bytes, {} entries\n", path, contents.len(), entries)
+}
@@ -0,0 +1,3498 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::albums::FOLDER_COVER_NAMES;
+use crate::capabilities::{can_change_extension, container_of, encodes_lossy, is_lossy_source, remux_params};
+use crate::config::{Config, Glob, TrackNumbers};
+use crate::content_store::verify_store;
+use crate::cost::CostEstimate;
+use crate::display;
+use crate::events::{Event, EventSink};
+use crate::failures::FailedFiles;
+use crate::format::format_duration;
+use crate::fs_wrapper;
+use crate::fs_wrapper::{partial_path, RclonePath, PARTIAL_SUFFIX};
+use crate::hashing::{find_changed_files, hash_files, hash_local_file, FileHash};
+use crate::health::{HealthReport, Severity};
+use crate::interrupt;
+use crate::interrupt::PartialFile;
+use crate::leftovers::{LeftoverEncode, LeftoverSource, Leftovers};
+use crate::lock::{lock_output, OutputLock};
+use crate::naming::OutputNamer;
+use crate::ogg_cover::{copy_pictures, refresh_pictures};
+use crate::output_info::OutputInfo;
+use crate::overrides::{is_override_file, Overrides, OVERRIDE_FILE_NAME};
+use crate::plan::{ActionKind, ActionReason, ExportedPlan, PlannedAction};
+use crate::playlists::{create_playlist, decode_playlist, is_playlist, rewrite_playlist};
+use crate::programs;
+use crate::progress::{run_ffmpeg, Progress};
+use crate::rclone_filter::RcloneFilter;
+use crate::redact;
+use crate::script::ShellScript;
+use crate::tags::{read_audio_codec, read_audio_info, read_duration, read_track_number, refresh_tags};
+use crate::throttle;
+use crate::warnings::{check_file_name, WarningKind, Warnings};
+
+/// Extensions of files which are still being downloaded
+const PARTIAL_EXTENSIONS: [&str; 6] = ["part", "partial", "crdownload", "download", "tmp", "!qb"];
+
+/// Compares the input directory with the output directory and the state of the last run
+pub struct SyncPlanner {
+    config: Config,
+    encoded: HashMap<String, String>,
+    failures: FailedFiles,
+    hashes: HashMap<String, FileHash>,
+    params: HashMap<String, String>,
+    output_info: HashMap<String, OutputInfo>,
+    input: Option<HashSet<String>>,
+    retry_failed: bool,
+    only: Vec<Glob>,
+}
+
+impl SyncPlanner {
+    /// `encoded` maps input file names to output file names of all files processed by earlier runs
+    pub fn new(config: Config, encoded: HashMap<String, String>) -> Self {
+        Self {
+            config,
+            encoded,
+            failures: FailedFiles::default(),
+            hashes: HashMap::new(),
+            params: HashMap::new(),
+            output_info: HashMap::new(),
+            input: None,
+            retry_failed: false,
+            only: Vec::new(),
+        }
+    }
+    /// Files which failed to process in the last run
+    pub fn with_failures(mut self, failures: FailedFiles) -> Self {
+        self.failures = failures;
+        self
+    }
+    /// Hashes of the sources taken by the last run, used to find changed sources with detectChanges
+    pub fn with_hashes(mut self, hashes: HashMap<String, FileHash>) -> Self {
+        self.hashes = hashes;
+        self
+    }
+    /// ffmpeg params the sources were encoded with by earlier runs, used by reencodeOnParamChange
+    pub fn with_params(mut self, params: HashMap<String, String>) -> Self {
+        self.params = params;
+        self
+    }
+    /// Sizes and durations of the encoded outputs recorded by earlier runs, used by the shallow verify
+    pub fn with_output_info(mut self, output_info: HashMap<String, OutputInfo>) -> Self {
+        self.output_info = output_info;
+        self
+    }
+    /// Input files listed with [list_input] before, so the input directory isn't listed again
+    pub fn with_input(mut self, input: HashSet<String>) -> Self {
+        self.input = Some(input);
+        self
+    }
+    /// Only plan the failed files, without listing the input and output directories
+    pub fn retry_failed(mut self, retry_failed: bool) -> Self {
+        self.retry_failed = retry_failed;
+        self
+    }
+    /// Only sync the sources matching any of the globs, the rest of the output is left as it is
+    pub fn only(mut self, only: Vec<Glob>) -> Self {
+        self.only = only;
+        self
+    }
+    /// Lists the input and output directories and finds all actions needed to sync them
+    ///
+    /// If output file names collide, the plan has no actions and the collisions have to be resolved first
+    pub fn plan(self, warnings: &mut Warnings) -> Result<SyncPlan, Box<dyn Error>> {
+        let SyncPlanner {
+            mut config,
+            encoded,
+            failures,
+            hashes,
+            params,
+            output_info,
+            input,
+            retry_failed,
+            only,
+        } = self;
+        programs::use_config(&config);
+
+        // Params writing another container would leave broken or wrongly named outputs
+        for (setting, correction) in config.reconcile_params()? {
+            warnings.push(WarningKind::ParamsCorrected, setting.to_string(), Some(correction));
+        }
+
+        // Read songs that are present in the filesystem already, or only the failed ones when retrying
+        let input = if retry_failed {
+            failures.files
+                .keys()
+                .chain(&failures.override_files)
+                .cloned()
+                .collect::<HashSet<String>>()
+        } else if let Some(input) = input {
+            input
+        } else {
+            list_input(&config, config.rclone_filter()?.as_ref(), None, warnings)?
+        };
+        let override_files = input
+            .iter()
+            .filter(|file| is_override_file(file))
+            .cloned()
+            .collect::<BTreeSet<String>>();
+
+        // Apply per-directory config files found in the input directory
+        let overrides = Overrides::load(&config.input_directory, &input)?;
+        if let Some((directory, extension)) = overrides.reencoding_directories(&config).into_iter().next() {
+            return Err(Box::from(format!(
+                "The config file of {} encodes to {} which is listed in its extensionsToEncode, outputs would be \
+                encoded again on every sync",
+                redact::path(&Path::new(&directory).join(OVERRIDE_FILE_NAME).to_string_lossy()),
+                extension
+            )));
+        }
+        let input = input
+            .into_iter()
+            .filter(|file| !is_override_file(file) && !overrides.is_skipped(file))
+            .collect::<HashSet<String>>();
+
+        // Sources left out by retention rules, their outputs are deleted like the ones of removed sources
+        let pruned = if retry_failed {
+            HashSet::new()
+        } else {
+            find_pruned(&config, &input)?
+        };
+        let input = input
+            .difference(&pruned)
+            .cloned()
+            .collect::<HashSet<String>>();
+
+        // Mirror mode - nothing is encoded so ffmpeg isn't needed at all
+        let mirror_mode = input
+            .iter()
+            .all(|file| overrides.config_for(&config, file).extensions_to_encode.is_empty());
+
+        // Read track numbers of files which should be prefixed with them
+        let mut track_numbers = HashMap::new();
+        let mut files_to_number = input
+            .iter()
+            .filter(|file| overrides.config_for(&config, file).track_numbers == Some(TrackNumbers::Add))
+            .peekable();
+        if config.input_directory.is_remote() {
+            if files_to_number.peek().is_some() {
+                warnings.push(
+                    WarningKind::MissingTrackNumber,
+                    config.input_directory.to_string(),
+                    Some(String::from("track numbers can't be read from remotes")),
+                );
+            }
+        } else {
+            for file in files_to_number {
+                match read_track_number(Path::new(&config.input_directory.clone().path_string()).join(file)) {
+                    Some(track_number) => {
+                        track_numbers.insert(file.clone(), track_number);
+                    }
+                    None => {
+                        warnings.push(WarningKind::MissingTrackNumber, file.clone(), None);
+                    }
+                }
+            }
+        }
+
+        // Sources copied instead of being encoded with a loss of quality or to a bigger file
+        let copied_sources = find_copied_sources(&config, &overrides, &input, &encoded, warnings);
+        let mut namer = OutputNamer::new(config.clone(), overrides, &input, track_numbers, copied_sources);
+
+        // Only the failed files are processed when retrying, nothing is renamed or deleted
+        let output = if retry_failed {
+            namer.known_names = failures.files.clone().into_iter().collect();
+            HashSet::new()
+        } else {
+            // The exported playlist is written by every run, it isn't an output without a source
+            let exported_playlist = config.export_playlist.as_deref().map(normalize_output_file_name);
+            fs_wrapper::list_files_recursively(&config.output_directory, warnings)?
+                .into_iter()
+                .map(|file| {
+                    Path::new(&file.path_string())
+                        .strip_prefix(config.output_directory.clone().path_string()).unwrap()
+                        .to_string_lossy().to_string()
+                })
+                .filter(|output_file_name| exported_playlist.as_deref() != Some(output_file_name.as_str()))
+                .collect::<HashSet<String>>()
+        };
+
+        let mut names: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for input_file_name in &input {
+            names
+                .entry(namer.output_file_name(input_file_name))
+                .or_default()
+                .push(input_file_name.clone());
+        }
+        let collisions = names
+            .iter()
+            .filter(|(_, input_file_names)| input_file_names.len() > 1)
+            .map(|(output_file_name, input_file_names)| {
+                let mut input_file_names = input_file_names.clone();
+                input_file_names.sort();
+                (output_file_name.clone(), input_file_names)
+            })
+            .collect::<BTreeMap<String, Vec<String>>>();
+
+        let mut plan = SyncPlan {
+            namer,
+            input,
+            output,
+            override_files,
+            mirror_mode,
+            collisions,
+            previous_sources: encoded
+                .iter()
+                .map(|(input_file_name, output_file_name)| (output_file_name.clone(), input_file_name.clone()))
+                .collect(),
+            previous_encoded: encoded.clone(),
+            encoded: encoded
+                .into_iter()
+                .filter(|(input_file_name, _)| !pruned.contains(input_file_name))
+                .collect(),
+            pruned,
+            failures,
+            previous_hashes: hashes,
+            hashes: HashMap::new(),
+            changed: HashSet::new(),
+            previous_params: params,
+            params_changed: HashSet::new(),
+            remuxable: HashSet::new(),
+            previous_output_info: output_info,
+            retry_failed,
+            only,
+            to_process: HashSet::new(),
+            to_rename: HashMap::new(),
+            to_delete: HashSet::new(),
+            leftover_sources: HashSet::new(),
+            leftover_encodes: HashSet::new(),
+        };
+        if !plan.collisions.is_empty() {
+            return Ok(plan);
+        }
+        for output_file_name in names.keys() {
+            if let Some(reason) = check_file_name(output_file_name) {
+                warnings.push(WarningKind::SuspiciousName, output_file_name.clone(), Some(reason));
+            }
+        }
+        plan.find_actions()?;
+        Ok(plan)
+    }
+}
+
+/// Everything a sync is going to do, created by [SyncPlanner] and run by [Executor]
+pub struct SyncPlan {
+    namer: OutputNamer,
+    /// Input file names, relative to the input directory
+    pub input: HashSet<String>,
+    /// Output file names, relative to the output directory
+    pub output: HashSet<String>,
+    /// Per-directory config files found in the input directory
+    pub override_files: BTreeSet<String>,
+    /// Nothing is encoded, so ffmpeg isn't needed
+    pub mirror_mode: bool,
+    /// Output file names resulting from multiple input files
+    pub collisions: BTreeMap<String, Vec<String>>,
+    previous_encoded: HashMap<String, String>,
+    /// Output file name -> input file name of the last run, to find the source of an output without a scan
+    previous_sources: HashMap<String, String>,
+    /// Files which are already processed and are kept
+    encoded: HashMap<String, String>,
+    /// Sources left out of the output by retention rules
+    pub pruned: HashSet<String>,
+    failures: FailedFiles,
+    previous_hashes: HashMap<String, FileHash>,
+    /// Current hashes of the unchanged sources
+    hashes: HashMap<String, FileHash>,
+    /// Sources whose contents changed since they were processed
+    pub changed: HashSet<String>,
+    previous_params: HashMap<String, String>,
+    /// Sources encoded with different ffmpeg params than the current ones
+    pub params_changed: HashSet<String>,
+    /// Sources encoded again for a changed extension, whose outputs could be remuxed with remuxOnExtensionChange
+    pub remuxable: HashSet<String>,
+    previous_output_info: HashMap<String, OutputInfo>,
+    retry_failed: bool,
+    /// Globs limiting the synced sources, empty to sync all of them
+    only: Vec<Glob>,
+    /// Input files to encode or copy
+    pub to_process: HashSet<String>,
+    /// Old output file name -> new output file name
+    pub to_rename: HashMap<String, String>,
+    /// Output files to delete
+    pub to_delete: HashSet<String>,
+    /// Sources to encode whose complete download an interrupted run left in the temp directory
+    pub leftover_sources: HashSet<String>,
+    /// Sources to encode whose finished output an interrupted run left in the temp directory before uploading it
+    pub leftover_encodes: HashSet<String>,
+}
+
+impl SyncPlan {
+    pub fn config(&self) -> &Config {
+        self.namer.config()
+    }
+    pub fn namer(&self) -> &OutputNamer {
+        &self.namer
+    }
+    pub fn total_actions(&self) -> usize {
+        self.to_process.len() + self.to_rename.len() + self.to_delete.len()
+    }
+    /// Whether a sync would change the output, --dry-run and status exit with code 5 then
+    pub fn is_pending(&self) -> bool {
+        self.total_actions() > 0
+    }
+    /// Order the sources are encoded or copied in, by name unless newSourcesPerCycle interleaves the new ones
+    pub fn process_order(&self) -> Vec<String> {
+        let mut to_process = self.to_process.iter().cloned().collect::<Vec<String>>();
+        to_process.sort();
+        let new_per_cycle = match self.config().new_sources_per_cycle {
+            Some(new_per_cycle) if new_per_cycle > 0 => new_per_cycle,
+            _ => return to_process,
+        };
+        let (mut new, backlog): (Vec<String>, Vec<String>) = to_process
+            .into_iter()
+            .partition(|input_file_name| self.process_reason(input_file_name) == ActionReason::New);
+        if new.is_empty() || backlog.is_empty() {
+            return new.into_iter().chain(backlog).collect();
+        }
+        // Only the order depends on them, a listing which failed leaves the new files sorted by name
+        let modification_times = fs_wrapper::list_modification_times(&self.config().input_directory)
+            .unwrap_or_default();
+        // Newest first, files of an album keep their order as they share the time of the download
+        new.sort_by_key(|input_file_name| std::cmp::Reverse(modification_times.get(input_file_name).copied()));
+        interleave(new, backlog, new_per_cycle)
+    }
+    /// Whether the source is synced by this plan, see [SyncPlanner::only]
+    fn in_scope(&self, input_file_name: &str) -> bool {
+        self.only.is_empty() || self.only.iter().any(|glob| glob.matches(input_file_name))
+    }
+    fn find_actions(&mut self) -> Result<(), Box<dyn Error>> {
+        let config = self.namer.config();
+        let namer = &self.namer;
+        let input = &self.input;
+        let output = &self.output;
+        let encoded = &mut self.encoded;
+        let retry_failed = self.retry_failed;
+
+        // Find which songs need to be processed
+        let encoded_output = encoded.values().cloned().collect::<HashSet<String>>();
+        let removed_output = encoded_output
+            .difference(output)
+            .cloned()
+            .collect::<HashSet<String>>();
+        let encoded_reverse = encoded
+            .clone()
+            .into_iter()
+            .map(|(input_file_name, output_file_name)| (output_file_name, input_file_name))
+            .collect::<HashMap<String, String>>();
+
+        // Songs removed from the destination directory but encoded previously
+        for removed_file in removed_output {
+            if let Some(file_to_recode) = encoded_reverse.get(&removed_file) {
+                encoded.remove(file_to_recode);
+            }
+        }
+
+        // Songs removed from the encoded.json but present in the output dir with the correct name
+        let encoded_not_saved_output: HashSet<String> =
+            output.difference(&encoded_output).cloned().collect();
+        for input_file_name in input.clone() {
+            let output_file_name = namer.output_file_name(&input_file_name);
+            if encoded_not_saved_output.contains(&output_file_name) {
+                // Copied files must match their source, otherwise they are copied again
+                let file_extension = Path::new(&input_file_name)
+                    .extension()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                if !namer.file_config(&input_file_name).extensions_to_encode.contains(&file_extension)
+                    && !fs_wrapper::files_match(
+                        &config.input_directory.with_path(
+                            format!(
+                                "{}/{}",
+                                config.input_directory.clone().path_string(),
+                                input_file_name
+                            )
+                        ),
+                        &config.output_directory.with_path(
+                            format!(
+                                "{}/{}",
+                                config.output_directory.clone().path_string(),
+                                output_file_name
+                            )
+                        ),
+                    )?
+                {
+                    continue;
+                }
+                encoded.insert(input_file_name, output_file_name);
+            }
+        }
+
+        // Songs encoded with the wrong extension
+        for (input_file, output_file) in encoded.clone() {
+            let file_config = namer.file_config(&input_file);
+            let input_file_extension = Path::new(&input_file)
+                .extension()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            if file_config
+                .extensions_to_encode
+                .contains(&input_file_extension)
+            {
+                let output_file_extension = Path::new(&output_file)
+                    .extension()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                if output_file_extension != file_config.encoded_extension {
+                    let remuxable = can_change_extension(
+                        &output_file,
+                        &file_config.encoded_extension,
+                        self.previous_params.get(&input_file).map(String::as_str),
+                        &file_config.ffmpeg_params,
+                    );
+                    // Kept, so it's renamed to the new extension below
+                    if remuxable && config.remux_on_extension_change == Some(true) {
+                        continue;
+                    }
+                    if remuxable {
+                        self.remuxable.insert(input_file.clone());
+                    }
+                    encoded.remove(&input_file);
+                }
+            } else if Path::new(&output_file).extension() != Path::new(&input_file).extension() {
+                // Encoded before, but copied now, like with skipLossyTranscode or copyIfBitrateBelow
+                encoded.remove(&input_file);
+            }
+        }
+
+        // Songs whose source changed - deleted and processed again
+        if config.detect_changes == Some(true) && !retry_failed {
+            let kept_input = encoded
+                .keys()
+                .filter(|input_file_name| input.contains(*input_file_name))
+                .cloned()
+                .collect::<HashSet<String>>();
+            let (changed, hashes) = find_changed_files(&config.input_directory, &kept_input, &self.previous_hashes)?;
+            for input_file_name in &changed {
+                encoded.remove(input_file_name);
+            }
+            self.changed = changed;
+            self.hashes = hashes;
+        }
+
+        // Songs encoded with different ffmpeg params - deleted and encoded again
+        if config.reencode_on_param_change == Some(true) && !retry_failed {
+            for (input_file_name, params) in &self.previous_params {
+                if encoded.contains_key(input_file_name) && *params != namer.file_config(input_file_name).ffmpeg_params {
+                    encoded.remove(input_file_name);
+                    self.params_changed.insert(input_file_name.clone());
+                }
+            }
+            // Songs encoded before their params were recorded are assumed to be current unless asked otherwise
+            if config.reencode_unknown_params == Some(true) {
+                let unknown = encoded
+                    .keys()
+                    .filter(|input_file_name| {
+                        !self.previous_params.contains_key(*input_file_name)
+                            && Path::new(input_file_name).extension().is_some_and(|extension| {
+                                namer
+                                    .file_config(input_file_name)
+                                    .extensions_to_encode
+                                    .contains(&extension.to_string_lossy().to_string())
+                            })
+                    })
+                    .cloned()
+                    .collect::<Vec<String>>();
+                for input_file_name in unknown {
+                    encoded.remove(&input_file_name);
+                    self.params_changed.insert(input_file_name);
+                }
+            }
+        }
+
+        // Encoded songs with an incorrect name (After a config change) - rename without re-encoding
+        let mut output_to_rename = HashMap::new();
+        for input_file in encoded.keys() {
+            let new_name = namer.output_file_name(input_file);
+            let old_name = encoded.get(input_file).unwrap().to_string();
+            if new_name != old_name {
+                output_to_rename.insert(old_name, new_name);
+            }
+        }
+
+        // All input songs which are not present in encoded need to be processed
+        // All output songs which are not present in encoded need to be deleted
+        let encoded_input: HashSet<String> = encoded.keys().cloned().collect();
+        let encoded_output: HashSet<String> = encoded.values().cloned().collect();
+        self.to_process = input.difference(&encoded_input).cloned().collect();
+        self.to_delete = output.difference(&encoded_output).cloned().collect();
+        self.to_rename = output_to_rename;
+
+        // Covers written by exportFolderCovers have no source, they stay as long as their album has outputs
+        if config.export_folder_covers == Some(true) {
+            let directories = encoded_output
+                .iter()
+                .map(|output_file_name| split_directory(output_file_name).0)
+                .collect::<HashSet<&str>>();
+            self.to_delete.retain(|output_file_name| {
+                let (directory, file_name) = split_directory(output_file_name);
+                !FOLDER_COVER_NAMES.contains(&file_name) || !directories.contains(directory)
+            });
+        }
+
+        // Outputs of sources outside of --only are left alone, as are the ones whose source is unknown
+        if !self.only.is_empty() {
+            let output_in_scope = |output_file_name: &String| {
+                self.previous_sources
+                    .get(output_file_name)
+                    .is_some_and(|input_file_name| self.in_scope(input_file_name))
+            };
+            let to_process = self.to_process.iter().filter(|file| self.in_scope(file)).cloned().collect();
+            let to_rename = self.to_rename.clone().into_iter().filter(|(old_name, _)| output_in_scope(old_name)).collect();
+            let to_delete = self.to_delete.iter().filter(|file| output_in_scope(file)).cloned().collect();
+            let changed = self.changed.iter().filter(|file| self.in_scope(file)).cloned().collect();
+            let params_changed = self.params_changed.iter().filter(|file| self.in_scope(file)).cloned().collect();
+            let remuxable = self.remuxable.iter().filter(|file| self.in_scope(file)).cloned().collect();
+            self.to_process = to_process;
+            self.to_rename = to_rename;
+            self.to_delete = to_delete;
+            self.changed = changed;
+            self.params_changed = params_changed;
+            self.remuxable = remuxable;
+        }
+        Ok(())
+    }
+    /// Describes which of maxDeleteCount and maxDeletePercent the planned deletions exceed, if any
+    pub fn exceeded_delete_limit(&self) -> Option<String> {
+        let config = self.config();
+        let deletions = self.to_delete.len();
+        if let Some(max_delete_count) = config.max_delete_count {
+            if deletions > max_delete_count {
+                return Some(format!("more than maxDeleteCount {}", max_delete_count));
+            }
+        }
+        if let Some(max_delete_percent) = config.max_delete_percent {
+            let percent = deletions as f64 * 100.0 / self.output.len().max(1) as f64;
+            if deletions > 0 && percent > max_delete_percent {
+                return Some(format!("{:.0}%, more than maxDeletePercent {}%", percent, max_delete_percent));
+            }
+        }
+        None
+    }
+    /// Estimates the traffic and operations on paid remotes
+    pub fn estimate_cost(&self) -> CostEstimate {
+        let config = self.config();
+        let mut cost_estimate = CostEstimate::default();
+        // Without the sizes only the operations are estimated
+        let input_sizes = if config.input_directory.is_remote() || config.output_directory.is_remote() {
+            fs_wrapper::list_file_sizes(&config.input_directory).unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+        for directory in [&config.input_directory, &config.output_directory] {
+            if let Some(remote) = directory.remote() {
+                // Listing the directory
+                cost_estimate.add_operations(remote, 1);
+            }
+        }
+        for input_file_name in &self.to_process {
+            let size = *input_sizes.get(input_file_name).unwrap_or(&0);
+            if let Some(remote) = config.input_directory.remote() {
+                cost_estimate.add_egress(remote, size);
+                cost_estimate.add_operations(remote, 1);
+            }
+            // Encoded files are usually smaller than the source, so this is an upper bound
+            if let Some(remote) = config.output_directory.remote() {
+                cost_estimate.add_ingress(remote, size);
+                cost_estimate.add_operations(remote, 1);
+            }
+        }
+        if let Some(remote) = config.output_directory.remote() {
+            cost_estimate.add_operations(remote, (self.to_rename.len() + self.to_delete.len()) as u64);
+        }
+        cost_estimate
+    }
+    /// The plan in the versioned format for review and auditing tools
+    pub fn export(&self) -> ExportedPlan {
+        let config = self.config();
+        let namer = &self.namer;
+        let input_to_process = &self.to_process;
+        let output_to_rename = &self.to_rename;
+        let output_to_delete = &self.to_delete;
+        let mut exported_plan = ExportedPlan::new(config.source_directory().to_string(), config.output_directory.to_string());
+        exported_plan.listing_checksum = Some(self.listing_checksum());
+        // The sizes are only shown, actions without one are still exported
+        let input_sizes = if input_to_process.is_empty() {
+            HashMap::new()
+        } else {
+            fs_wrapper::list_file_sizes(&config.input_directory).unwrap_or_default()
+        };
+        let output_sizes = if output_to_delete.is_empty() {
+            HashMap::new()
+        } else {
+            fs_wrapper::list_file_sizes(&config.output_directory).unwrap_or_default()
+        };
+
+        for directory in self.directories_to_create() {
+            exported_plan.actions.push(
+                PlannedAction {
+                    kind: ActionKind::CreateDirectory,
+                    source: None,
+                    destination: Some(directory),
+                    new_destination: None,
+                    reason: ActionReason::New,
+                    size: None,
+                    status: None,
+                    slow_seconds: None,
+                }
+            );
+        }
+
+        let mut output_to_delete = output_to_delete.iter().collect::<Vec<&String>>();
+        output_to_delete.sort();
+        for output_file_name in output_to_delete {
+            exported_plan.actions.push(
+                PlannedAction {
+                    kind: ActionKind::Delete,
+                    source: None,
+                    destination: Some(output_file_name.clone()),
+                    new_destination: None,
+                    reason: self.delete_reason(output_file_name),
+                    size: output_sizes.get(output_file_name).copied(),
+                    status: None,
+                    slow_seconds: None,
+                }
+            );
+        }
+
+        let mut output_to_rename = output_to_rename.iter().collect::<Vec<(&String, &String)>>();
+        output_to_rename.sort();
+        for (old_file_name, new_file_name) in output_to_rename {
+            let input_file_name = self.previous_sources.get(old_file_name).cloned();
+            exported_plan.actions.push(
+                PlannedAction {
+                    kind: ActionKind::Rename,
+                    source: input_file_name,
+                    destination: Some(old_file_name.clone()),
+                    new_destination: Some(new_file_name.clone()),
+                    reason: self.rename_reason(old_file_name, new_file_name),
+                    size: None,
+                    status: None,
+                    slow_seconds: None,
+                }
+            );
+        }
+
+        let mut input_to_process = input_to_process.iter().collect::<Vec<&String>>();
+        input_to_process.sort();
+        for input_file_name in input_to_process {
+            let kind = self.export_kind(input_file_name);
+            let reason = self.process_reason(input_file_name);
+            exported_plan.actions.push(
+                PlannedAction {
+                    kind,
+                    source: Some(input_file_name.clone()),
+                    destination: Some(namer.output_file_name(input_file_name)),
+                    new_destination: None,
+                    reason,
+                    size: input_sizes.get(input_file_name).copied(),
+                    status: None,
+                    slow_seconds: None,
+                }
+            );
+        }
+        exported_plan
+    }
+    /// Shell script with the ffmpeg, rclone and file commands the executor would run for the plan
+    ///
+    /// Covers and rewritten playlists are written by ffmusicsync itself, the script only mentions them
+    pub fn export_script(&self) -> String {
+        let config = self.config();
+        let namer = &self.namer;
+        let temp_directory = config.temp_directory.clone().unwrap_or(String::from("temp"));
+        let in_directory = |directory: &RclonePath, file_name: &str| {
+            directory.with_path(format!("{}/{}", directory.clone().path_string(), file_name))
+        };
+        let in_temp = |file_name: &str| RclonePath::Local(format!("{}/{}", temp_directory, file_name));
+        let mut script = ShellScript::new(config.source_directory(), &config.output_directory);
+
+        script.section("Create directories");
+        if config.source_directory().is_remote() || config.output_directory.is_remote() {
+            script.create_dir_all(&RclonePath::Local(temp_directory.clone()));
+        }
+        script.create_dir_all(&config.output_directory);
+        for directory in self.directories_to_create() {
+            script.create_dir_all(&in_directory(&config.output_directory, &directory));
+        }
+
+        let mut output_to_delete = self.to_delete.iter().collect::<Vec<&String>>();
+        output_to_delete.sort();
+        // Deletes are deferred to the end of the script like by the executor
+        let replaced = self
+            .to_rename
+            .values()
+            .cloned()
+            .chain(self.to_process.iter().map(|input_file_name| namer.output_file_name(input_file_name)))
+            .collect::<HashSet<String>>();
+        let (output_to_delete, deferred_deletes): (Vec<&String>, Vec<&String>) = output_to_delete
+            .into_iter()
+            .partition(|output_file_name| config.defer_deletes == Some(false) || replaced.contains(*output_file_name));
+        let trash_run = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+        let delete_outputs = |script: &mut ShellScript, output_to_delete: Vec<&String>| {
+            if !output_to_delete.is_empty() {
+                script.section("Delete outputs");
+            }
+            for output_file_name in output_to_delete {
+                let output_file = in_directory(&config.output_directory, output_file_name);
+                match &config.trash_directory {
+                    Some(trash_directory) => {
+                        let trashed_file = in_directory(trash_directory, &format!("{}/{}", trash_run, output_file_name));
+                        script.create_parent(&trashed_file);
+                        script.rename(&output_file, &trashed_file);
+                    }
+                    None => script.remove_file(&output_file),
+                }
+            }
+        };
+        delete_outputs(&mut script, output_to_delete);
+
+        let mut output_to_rename = self.to_rename.iter().collect::<Vec<(&String, &String)>>();
+        output_to_rename.sort();
+        if !output_to_rename.is_empty() {
+            script.section("Rename outputs");
+        }
+        for (old_file_name, new_file_name) in output_to_rename {
+            let old_file = in_directory(&config.output_directory, old_file_name);
+            let new_file = in_directory(&config.output_directory, new_file_name);
+            if container_of(old_file_name) == container_of(new_file_name) {
+                script.rename(&old_file, &new_file);
+                continue;
+            }
+            // Outputs getting the extension of another container are remuxed like by the executor
+            let (old_path, new_path) = if config.output_directory.is_remote() {
+                script.create_parent(&in_temp(old_file_name));
+                script.create_parent(&in_temp(new_file_name));
+                script.copy(&old_file, &in_temp(old_file_name));
+                (in_temp(old_file_name), in_temp(new_file_name))
+            } else {
+                (old_file.clone(), new_file.clone())
+            };
+            script.command(&[
+                &programs::ffmpeg_path(config), "-nostdin", "-y", "-i", &old_path.to_string(), "-map", "0", "-c", "copy", &new_path.to_string(),
+            ]);
+            if config.output_directory.is_remote() {
+                script.rename(&new_path, &new_file);
+                script.remove_file(&old_path);
+            }
+            script.remove_file(&old_file);
+        }
+
+        let mut input_to_process = self.to_process.iter().collect::<Vec<&String>>();
+        input_to_process.sort();
+        if !input_to_process.is_empty() {
+            script.section("Encode and copy sources");
+        }
+        for input_file_name in input_to_process {
+            let file_config = namer.file_config(input_file_name);
+            let output_file_name = namer.output_file_name(input_file_name);
+            let input_file = in_directory(config.source_directory(), input_file_name);
+            let output_file = in_directory(&config.output_directory, &output_file_name);
+            let file_extension = Path::new(input_file_name)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if file_config.extensions_to_encode.contains(&file_extension) {
+                let input_path = if config.source_directory().is_remote() {
+                    script.create_parent(&in_temp(input_file_name));
+                    script.copy(&input_file, &in_temp(input_file_name));
+                    in_temp(input_file_name)
+                } else {
+                    input_file.clone()
+                };
+                let output_path = if config.output_directory.is_remote() {
+                    script.create_parent(&in_temp(&output_file_name));
+                    in_temp(&output_file_name)
+                } else {
+                    output_file.clone()
+                };
+                // Outputs kept with overwriteOutput false are skipped like by the executor, it can only tell for
+                // local outputs
+                let keep_output = file_config.overwrite_output == Some(false) && !config.output_directory.is_remote();
+                if keep_output {
+                    script.begin_unless_exists(&output_file);
+                }
+                // The remux is decided on the source, which is only downloaded when the script runs
+                let ffmpeg_params = if config.source_directory().is_remote() {
+                    if file_config.remux_when_possible == Some(true) {
+                        script.comment("ffmusicsync copies the audio instead if the source already holds the codec of the output");
+                    }
+                    file_config.ffmpeg_params.clone()
+                } else {
+                    match source_remux_params(&file_config, Path::new(&input_path.to_string()), &output_file_name) {
+                        Some((codec, remux_params)) => {
+                            script.comment(&format!("{} already holds {}, its audio is copied", input_file_name, codec));
+                            remux_params
+                        }
+                        None => file_config.ffmpeg_params.clone(),
+                    }
+                };
+                // ffmpeg writes to a partial name which is renamed once the output is complete, like by the executor
+                let partial_output_path = RclonePath::Local(
+                    partial_path(Path::new(&output_path.to_string())).to_string_lossy().to_string()
+                );
+                let input_path_string = input_path.to_string();
+                let partial_output_path_string = partial_output_path.to_string();
+                let command_line = encode_command_line(config);
+                let mut params = command_line.iter().map(String::as_str).collect::<Vec<&str>>();
+                params.extend(["-nostdin", "-y", "-i", &input_path_string]);
+                params.extend(ffmpeg_params.split(' '));
+                params.push(&partial_output_path_string);
+                script.command(&params);
+                if file_config.copy_covers == Some(true) {
+                    script.comment(&format!("ffmusicsync copies the cover of {} to {} here", input_file_name, output_file_name));
+                }
+                script.rename(&partial_output_path, &output_path);
+                if config.source_directory().is_remote() {
+                    script.remove_file(&input_path);
+                }
+                if config.output_directory.is_remote() {
+                    script.rename(&output_path, &output_file);
+                }
+                if keep_output {
+                    script.end_block();
+                }
+            } else if config.rewrite_playlists == Some(true) && is_playlist(input_file_name) {
+                script.comment(&format!(
+                    "ffmusicsync writes {} to {} with its entries pointing to the outputs here",
+                    input_file_name, output_file_name
+                ));
+            } else {
+                script.copy(&input_file, &output_file);
+            }
+        }
+        delete_outputs(&mut script, deferred_deletes);
+        script.render()
+    }
+    /// Encodes one second with every ffmpeg params the plan uses, so invalid params stop the run before any file
+    /// is changed instead of failing file by file
+    ///
+    /// The first source of each params is used, a generated tone for remote inputs. Returns the params and the
+    /// error printed by ffmpeg on the first failure
+    pub fn probe_encodes(&self) -> Result<(), (String, String)> {
+        let config = self.config();
+        let mut input_to_process = self.to_process.iter().collect::<Vec<&String>>();
+        input_to_process.sort();
+        let mut probes = BTreeMap::new();
+        for input_file_name in input_to_process {
+            if self.export_kind(input_file_name) == ActionKind::Encode {
+                let extension = Path::new(&self.namer.output_file_name(input_file_name))
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let ffmpeg_params = self.namer.file_config(input_file_name).ffmpeg_params;
+                probes.entry((ffmpeg_params, extension)).or_insert(input_file_name);
+            }
+        }
+        for ((ffmpeg_params, extension), input_file_name) in probes {
+            let output_file_path = std::env::temp_dir().join(format!("ffmusicsync-probe-{}.{}", std::process::id(), extension));
+            let _partial_file = PartialFile::track(&output_file_path);
+            let mut command = encode_command(config);
+            command.args(["-nostdin", "-y", "-hide_banner", "-loglevel", "error"]);
+            if config.input_directory.is_remote() {
+                command.args(["-f", "lavfi", "-i", "sine=duration=1"]);
+            } else {
+                command.arg("-i").arg(Path::new(&config.input_directory.clone().path_string()).join(input_file_name));
+            }
+            command
+                .args(["-t", "1"])
+                .args(ffmpeg_params.split(' ').filter(|param| !param.is_empty()))
+                .arg(&output_file_path);
+            let output = command.output();
+            if output_file_path.exists() {
+                let _ = std::fs::remove_file(&output_file_path);
+            }
+            match output {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => {
+                    let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                    return Err((ffmpeg_params, if error.is_empty() { format!("ffmpeg {}", output.status) } else { error }));
+                }
+                Err(error) => return Err((ffmpeg_params, format!("Failed to execute ffmpeg: {}", error))),
+            }
+        }
+        Ok(())
+    }
+    /// Whether an input file is encoded or copied
+    fn export_kind(&self, input_file_name: &str) -> ActionKind {
+        let file_extension = Path::new(input_file_name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if self.namer.file_config(input_file_name).extensions_to_encode.contains(&file_extension) {
+            ActionKind::Encode
+        } else {
+            ActionKind::Copy
+        }
+    }
+    /// Checksum of the input and output file listings, changes when any file is added, removed or renamed
+    pub fn listing_checksum(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        for (name, files) in [("input", &self.input), ("output", &self.output)] {
+            let mut files = files.iter().collect::<Vec<&String>>();
+            files.sort();
+            hasher.update(name.as_bytes());
+            for file in files {
+                hasher.update(b"\n");
+                hasher.update(file.as_bytes());
+            }
+            hasher.update(b"\0");
+        }
+        format!("blake3:{}", hasher.finalize().to_hex())
+    }
+    /// Replaces the actions with the ones of a plan exported before, to apply it verbatim
+    ///
+    /// Actions may have been removed from the plan, edited ones are checked against the input and output. Fails if
+    /// the directories changed since the plan was exported or the current config names the files differently
+    pub fn use_exported(&mut self, exported_plan: &ExportedPlan) -> Result<(), Box<dyn Error>> {
+        let config = self.config();
+        if exported_plan.input_directory != config.source_directory().to_string()
+            || exported_plan.output_directory != config.output_directory.to_string()
+        {
+            return Err(Box::from("the plan was exported for other directories"));
+        }
+        match &exported_plan.listing_checksum {
+            Some(listing_checksum) if *listing_checksum == self.listing_checksum() => {}
+            Some(_) => return Err(Box::from("the input or output directory changed since the plan was exported")),
+            None => return Err(Box::from("the plan has no listing checksum, it's a report or from an older version")),
+        }
+        let mut to_process = HashSet::new();
+        let mut to_rename = HashMap::new();
+        let mut to_delete = HashSet::new();
+        // Outputs written by the actions, an edited plan could write one twice
+        let mut written = HashSet::new();
+        for action in &exported_plan.actions {
+            match (action.kind, &action.source, &action.destination, &action.new_destination) {
+                (ActionKind::Encode | ActionKind::Copy, Some(source), Some(destination), _) => {
+                    if !self.input.contains(source) {
+                        return Err(format!("{} is not in the input", source).into());
+                    }
+                    if self.namer.output_file_name(source) != *destination {
+                        return Err(format!("{} is named differently with the current config", source).into());
+                    }
+                    let encoded = self.export_kind(source) == ActionKind::Encode;
+                    if encoded != (action.kind == ActionKind::Encode) {
+                        let kind = if encoded { "encoded" } else { "copied" };
+                        return Err(format!("{} is {} with the current config", source, kind).into());
+                    }
+                    if !written.insert(destination.clone()) {
+                        return Err(format!("{} is written by multiple actions", destination).into());
+                    }
+                    to_process.insert(source.clone());
+                }
+                (ActionKind::Rename, source, Some(destination), Some(new_destination)) => {
+                    if !self.output.contains(destination) {
+                        return Err(format!("{} is not in the output", destination).into());
+                    }
+                    // The state would map the source to the old name otherwise, renaming it back in the next run
+                    if let Some(source) = source {
+                        if self.namer.output_file_name(source) != *new_destination {
+                            return Err(format!("{} is named differently with the current config", source).into());
+                        }
+                    }
+                    if !written.insert(new_destination.clone()) {
+                        return Err(format!("{} is written by multiple actions", new_destination).into());
+                    }
+                    if to_rename.insert(destination.clone(), new_destination.clone()).is_some() {
+                        return Err(format!("{} is renamed multiple times", destination).into());
+                    }
+                }
+                (ActionKind::Delete, _, Some(destination), _) => {
+                    if !self.output.contains(destination) {
+                        return Err(format!("{} is not in the output", destination).into());
+                    }
+                    to_delete.insert(destination.clone());
+                }
+                // Directories are created by the executor for the processed files
+                (ActionKind::CreateDirectory, _, _, _) => {}
+                _ => return Err(format!("invalid {:?} action", action.kind).into()),
+            }
+        }
+        if let Some(destination) = to_rename.keys().find(|destination| to_delete.contains(*destination)) {
+            return Err(format!("{} is both renamed and deleted", destination).into());
+        }
+        self.to_process = to_process;
+        self.to_rename = to_rename;
+        self.to_delete = to_delete;
+        Ok(())
+    }
+    /// Directories created in the output for the processed files, relative to the output directory
+    pub fn directories_to_create(&self) -> BTreeSet<String> {
+        let existing = self
+            .output
+            .iter()
+            .flat_map(|output_file_name| Path::new(output_file_name).ancestors().skip(1))
+            .map(|directory| directory.to_string_lossy().to_string())
+            .collect::<HashSet<String>>();
+        // The same directories as the executor creates, named like the input ones
+        self.to_process
+            .iter()
+            .map(|input_file_name| Path::new(input_file_name).parent().unwrap_or(Path::new("")).to_string_lossy().to_string())
+            .filter(|directory| !directory.is_empty() && !existing.contains(directory))
+            .collect()
+    }
+    /// Why an input file is encoded or copied
+    pub fn process_reason(&self, input_file_name: &str) -> ActionReason {
+        let file_config = self.namer.file_config(input_file_name);
+        let encoded_with_other_extension = self.previous_encoded.get(input_file_name).is_some_and(|output_file_name| {
+            let extension = |file_name: &str| {
+                Path::new(file_name).extension().map(|extension| extension.to_string_lossy().to_string())
+            };
+            extension(input_file_name).is_some_and(|extension| file_config.extensions_to_encode.contains(&extension))
+                && extension(output_file_name) != Some(file_config.encoded_extension.clone())
+        });
+        if self.failures.files.contains_key(input_file_name) {
+            ActionReason::Failed
+        } else if self.changed.contains(input_file_name) {
+            ActionReason::SourceChanged
+        } else if self.params_changed.contains(input_file_name) {
+            ActionReason::ParamsChanged
+        } else if encoded_with_other_extension {
+            ActionReason::ExtensionChanged
+        } else if self.previous_encoded.contains_key(input_file_name) {
+            ActionReason::OutputChanged
+        } else {
+            ActionReason::New
+        }
+    }
+    /// Why an output file is renamed, outputs which get another extension are remuxed if the container changes
+    pub fn rename_reason(&self, old_file_name: &str, new_file_name: &str) -> ActionReason {
+        let extension = |file_name: &str| Path::new(file_name).extension().map(|extension| extension.to_os_string());
+        if extension(old_file_name) != extension(new_file_name) {
+            ActionReason::ExtensionChanged
+        } else {
+            ActionReason::NameChanged
+        }
+    }
+    /// Why an output file is deleted, outputs of sources processed again get the reason of their source
+    pub fn delete_reason(&self, output_file_name: &str) -> ActionReason {
+        match self.previous_sources.get(output_file_name) {
+            Some(input_file_name) if self.pruned.contains(input_file_name) => ActionReason::Pruned,
+            Some(input_file_name) if self.to_process.contains(input_file_name) => self.process_reason(input_file_name),
+            _ => ActionReason::NoSource,
+        }
+    }
+    /// Leaves only deleting the outputs without a source in the plan, for the clean command
+    ///
+    /// Outputs of sources processed again are kept, clean doesn't encode the files replacing them
+    pub fn keep_orphans(&mut self) {
+        let reprocessed = self
+            .to_process
+            .iter()
+            .filter_map(|input_file_name| self.previous_encoded.get(input_file_name))
+            .cloned()
+            .collect::<HashSet<String>>();
+        self.to_delete.retain(|output_file_name| !reprocessed.contains(output_file_name));
+        self.to_process.clear();
+        self.to_rename.clear();
+        self.remuxable.clear();
+        self.leftover_sources.clear();
+        self.leftover_encodes.clear();
+    }
+    /// Leaves nothing but refreshing the tags and covers of the outputs in the plan, for --refresh-covers and
+    /// --refresh-tags, outputs waiting for a rename are refreshed under their current name
+    pub fn refresh_only(&mut self) {
+        self.to_process.clear();
+        self.to_rename.clear();
+        self.to_delete.clear();
+        self.remuxable.clear();
+        self.leftover_sources.clear();
+        self.leftover_encodes.clear();
+    }
+    /// Leaves only deleting the broken outputs found by [SyncPlan::verify] in the plan, executed like the deletes of
+    /// a sync so trashDirectory and the delete limits apply to them
+    pub fn requeue(&mut self, broken: &[String]) {
+        self.to_process.clear();
+        self.to_rename.clear();
+        self.remuxable.clear();
+        self.leftover_sources.clear();
+        self.leftover_encodes.clear();
+        self.to_delete = broken.iter().cloned().collect();
+    }
+    /// The state of the last run without the outputs deleted by a [SyncPlan::requeue] plan, so the next sync encodes
+    /// or copies their sources again
+    pub fn requeued_encoded(&self, executed: &ExecutedActions) -> HashMap<String, String> {
+        let mut encoded = self.previous_encoded.clone();
+        encoded.retain(|_, output_file_name| !executed.deleted.contains(output_file_name));
+        encoded
+    }
+    /// Whether a local output is as long as its local source, true if either duration can't be read
+    fn duration_matches_source(&self, input_file_name: &str, output_path: &str) -> bool {
+        let input_path = Path::new(&self.config().input_directory.clone().path_string()).join(input_file_name);
+        match (read_duration(input_path), read_duration(PathBuf::from(output_path))) {
+            (Some(source), Some(output)) => durations_match(source, output),
+            _ => true,
+        }
+    }
+    /// Checks the library for problems, without changing anything
+    pub fn health_report(&self) -> HealthReport {
+        let config = self.config();
+        let namer = &self.namer;
+        let input = &self.input;
+        let output = &self.output;
+        let failures = &self.failures;
+        let mut report = HealthReport::default();
+
+        report.add(
+            "Failed encodes",
+            Severity::High,
+            failures.files
+                .keys()
+                .filter(|input_file_name| input.contains(*input_file_name))
+                .cloned()
+                .collect(),
+            "ffmpeg failed on these files in the last run, check them and process them again with --retry-failed",
+        );
+
+        let mut names: HashMap<String, Vec<String>> = HashMap::new();
+        for input_file_name in input {
+            names
+                .entry(namer.output_file_name(input_file_name))
+                .or_default()
+                .push(input_file_name.clone());
+        }
+        report.add(
+            "Name collisions",
+            Severity::High,
+            names
+                .iter()
+                .filter(|(_, input_file_names)| input_file_names.len() > 1)
+                .map(|(output_file_name, input_file_names)| {
+                    format!("{} <- {}", output_file_name, input_file_names.join(", "))
+                })
+                .collect(),
+            "Disable some bracket removal options or rename the sources, syncing aborts until this is fixed",
+        );
+
+        report.add(
+            "Orphan outputs",
+            Severity::Medium,
+            output
+                .iter()
+                .filter(|output_file_name| !names.contains_key(*output_file_name))
+                .cloned()
+                .collect(),
+            "These files have no source and will be deleted by the next sync",
+        );
+
+        let output_directory_length = config.output_directory.clone().path_string().len();
+        report.add(
+            "Over-length names",
+            Severity::Medium,
+            names
+                .keys()
+                .filter(|output_file_name| {
+                    output_file_name.split('/').any(|component| component.len() > 255)
+                        || output_directory_length + 1 + output_file_name.len() > 260
+                })
+                .cloned()
+                .collect(),
+            "Names longer than 255 bytes or paths longer than 260 characters break on some devices, \
+            enable bracket removal or trackNumbers \"strip\"",
+        );
+
+        // Tags and properties can only be read from local files
+        if !config.input_directory.is_remote() {
+            let mut untagged = Vec::new();
+            let mut fake_lossless = Vec::new();
+            for input_file_name in input {
+                let file_extension = Path::new(input_file_name)
+                    .extension()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_lowercase();
+                let is_lossless = ["flac", "wav", "aiff", "aif", "ape", "wv"].contains(&file_extension.as_str());
+                if !is_lossless && !namer.file_config(input_file_name).extensions_to_encode.contains(&file_extension) {
+                    continue;
+                }
+                let audio_info = read_audio_info(
+                    Path::new(&config.input_directory.clone().path_string()).join(input_file_name)
+                );
+                if let Some(audio_info) = audio_info {
+                    if !audio_info.tagged {
+                        untagged.push(input_file_name.clone());
+                    }
+                    // Lossless stereo music rarely goes below 400 kbps, transcoded lossy files often do
+                    if is_lossless && audio_info.audio_bitrate.map(|bitrate| bitrate < 400).unwrap_or(false) {
+                        fake_lossless.push(input_file_name.clone());
+                    }
+                }
+            }
+            report.add(
+                "Missing title or artist tags",
+                Severity::Low,
+                untagged,
+                "Tag the sources (e.g. with MusicBrainz Picard) and run a sync with --refresh-tags",
+            );
+            report.add(
+                "Fake lossless suspects",
+                Severity::Low,
+                fake_lossless,
+                "Their bitrate is unusually low for lossless audio, check the spectrum and consider replacing them",
+            );
+        }
+
+        report
+    }
+    /// Checks the output against the state of the last run, without changing anything
+    ///
+    /// Copies are compared to their sources, encoded files are checked for being empty and, if the output is local,
+    /// for still having the duration recorded when they were encoded.
+    /// A shallow verify only compares the sizes of the outputs with their sources and the recorded sizes, only the
+    /// outputs whose size doesn't match are checked like by a full verify
+    pub fn verify(&self, shallow: bool, decode: bool) -> Result<HealthReport, Box<dyn Error>> {
+        let config = self.config();
+        let (input_sizes, output_sizes) = if shallow {
+            (
+                fs_wrapper::list_file_sizes(&config.input_directory)?,
+                fs_wrapper::list_file_sizes(&config.output_directory)?,
+            )
+        } else {
+            (HashMap::new(), HashMap::new())
+        };
+        let mut missing = Vec::new();
+        let mut differing = Vec::new();
+        let mut empty = Vec::new();
+        let mut changed_duration = Vec::new();
+        let mut changed_size = Vec::new();
+        let mut undecodable = Vec::new();
+        let mut differing_duration = Vec::new();
+        for (input_file_name, output_file_name) in &self.previous_encoded {
+            // Outputs of removed sources are cleaned up by the next sync
+            if !self.input.contains(input_file_name) {
+                continue;
+            }
+            if !self.output.contains(output_file_name) {
+                missing.push(output_file_name.clone());
+                continue;
+            }
+            // Rewritten playlists differ from their source on purpose
+            if config.rewrite_playlists == Some(true) && is_playlist(input_file_name) {
+                continue;
+            }
+            let output_path = format!("{}/{}", config.output_directory.clone().path_string(), output_file_name);
+            let file_extension = Path::new(input_file_name)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let encoded = self.namer.file_config(input_file_name).extensions_to_encode.contains(&file_extension);
+            let output_info = self.previous_output_info.get(output_file_name);
+            if shallow {
+                let size = output_sizes.get(output_file_name);
+                let expected_size = if encoded {
+                    output_info.map(|output_info| output_info.size)
+                } else {
+                    input_sizes.get(input_file_name).copied()
+                };
+                // Outputs without a recorded size can't be told apart from intact ones
+                let suspect = size == Some(&0) || expected_size.is_some_and(|expected_size| size != Some(&expected_size));
+                if !suspect {
+                    continue;
+                }
+            }
+            if encoded {
+                if config.output_directory.is_remote() {
+                    // Remote outputs can't be checked without downloading them
+                    if shallow {
+                        changed_size.push(output_file_name.clone());
+                    }
+                } else if Path::new(&output_path).metadata().map(|metadata| metadata.len() == 0).unwrap_or(false) {
+                    empty.push(output_file_name.clone());
+                } else if decode && decode_error(config, Path::new(&output_path))?.is_some() {
+                    undecodable.push(output_file_name.clone());
+                } else if decode && !config.input_directory.is_remote()
+                    && !changes_length(&self.namer.file_config(input_file_name).ffmpeg_params)
+                    && !self.duration_matches_source(input_file_name, &output_path)
+                {
+                    differing_duration.push(output_file_name.clone());
+                } else {
+                    match output_info.and_then(|output_info| output_info.duration_matches(Path::new(&output_path))) {
+                        Some(false) => changed_duration.push(output_file_name.clone()),
+                        // Likely only the tags were edited
+                        Some(true) => {}
+                        None if shallow => changed_size.push(output_file_name.clone()),
+                        None => {}
+                    }
+                }
+            } else if !fs_wrapper::files_match(
+                &config.input_directory.with_path(
+                    format!("{}/{}", config.input_directory.clone().path_string(), input_file_name)
+                ),
+                &config.output_directory.with_path(output_path),
+            )? {
+                differing.push(output_file_name.clone());
+            }
+        }
+        let known_output = self.previous_encoded.values().collect::<HashSet<&String>>();
+        let untracked = self.output
+            .iter()
+            .filter(|output_file_name| !known_output.contains(output_file_name))
+            .cloned()
+            .collect();
+
+        let mut broken: Vec<String> = [&differing, &empty, &changed_duration, &undecodable, &differing_duration]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        broken.sort();
+        let mut report = HealthReport {
+            broken,
+            ..HealthReport::default()
+        };
+        report.add(
+            "Missing outputs",
+            Severity::High,
+            missing,
+            "These files were removed from the output, the next sync processes them again",
+        );
+        report.add(
+            "Copies differing from their source",
+            Severity::High,
+            differing,
+            "Delete them from the output and remove them from the encoded file, the next sync copies them again",
+        );
+        report.add(
+            "Empty outputs",
+            Severity::High,
+            empty,
+            "Delete them from the output and remove them from the encoded file, the next sync encodes them again",
+        );
+        report.add(
+            "Outputs with a different duration than when they were encoded",
+            Severity::High,
+            changed_duration,
+            "They are likely truncated or broken, delete them from the output and remove them from the encoded file, \
+            the next sync encodes them again",
+        );
+        report.add(
+            "Outputs which don't decode cleanly",
+            Severity::High,
+            undecodable,
+            "ffmpeg found errors decoding them, they're likely corrupt. verify --requeue makes the next sync encode \
+            them again",
+        );
+        report.add(
+            "Outputs with a different duration than their source",
+            Severity::High,
+            differing_duration,
+            "They are likely truncated, verify --requeue makes the next sync encode them again",
+        );
+        report.add(
+            "Outputs with a different size than when they were encoded",
+            Severity::Medium,
+            changed_size,
+            "They were modified after the sync and couldn't be checked further, play them or delete them from the \
+            output and remove them from the encoded file, the next sync encodes them again",
+        );
+        report.add(
+            "Outputs missing from the state",
+            Severity::Low,
+            untracked,
+            "These files aren't known to have a source, clean deletes the ones without one",
+        );
+        if let Some(content_store) = &config.content_store {
+            let damaged_objects = verify_store(content_store, (!shallow).then_some(&config.output_directory))?
+                .into_iter()
+                .map(|(output_file_name, problem)| format!("{} ({})", output_file_name, problem))
+                .collect();
+            report.add(
+                "Outputs missing or damaged in the content store",
+                Severity::High,
+                damaged_objects,
+                "The next sync uploads them to the content store again",
+            );
+        }
+        Ok(report)
+    }
+    /// Finds the files an interrupted run left in the temp directory which can be reused instead of downloading or
+    /// encoding them again, only called before actually running the plan
+    ///
+    /// Only files the temp directory lists as complete are reused, see [Leftovers]. Encodes also need the current
+    /// ffmpegParams and, unless the params change the length, the duration of the source if it's still at hand. With
+    /// detectChanges the sources are hashed again, leftovers of a source which changed since are ignored
+    pub fn find_leftovers(&mut self) {
+        let config = self.namer.config();
+        if !config.input_directory.is_remote() && !config.output_directory.is_remote() {
+            return;
+        }
+        let temp_directory = config.temp_directory.clone().unwrap_or(String::from("temp"));
+        if !Path::new(&temp_directory).is_dir() {
+            return;
+        }
+        let temp_path = |file_name: &str| Path::new(&temp_directory).join(file_name);
+        let file_size = |path: &Path| path.metadata().ok().filter(|metadata| metadata.is_file()).map(|metadata| metadata.len());
+        let leftovers = Leftovers::load(&temp_directory);
+        let mut candidates = self.to_process
+            .iter()
+            .filter(|input_file_name| is_encoded(&self.namer, input_file_name))
+            .filter(|input_file_name| {
+                leftovers.sources.contains_key(*input_file_name) || leftovers.encodes.contains_key(*input_file_name)
+            })
+            .cloned()
+            .collect::<Vec<String>>();
+        candidates.sort();
+        let source_hashes = if config.detect_changes == Some(true) {
+            // Without the hashes no source can be confirmed unchanged, so no leftover is reused
+            hash_files(&config.input_directory, &candidates).unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+        let source_unchanged = |input_file_name: &String, source_hash: &Option<FileHash>| {
+            config.detect_changes != Some(true)
+                || source_hash.as_ref().is_some_and(|source_hash| source_hashes.get(input_file_name) == Some(source_hash))
+        };
+
+        let mut leftover_sources = HashSet::new();
+        if config.input_directory.is_remote() {
+            leftover_sources = candidates
+                .iter()
+                .filter(|input_file_name| {
+                    leftovers.sources.get(*input_file_name).is_some_and(|source| {
+                        file_size(&temp_path(input_file_name)) == Some(source.size)
+                            && source_unchanged(input_file_name, &source.source_hash)
+                    })
+                })
+                .cloned()
+                .collect();
+        }
+
+        let mut leftover_encodes = HashSet::new();
+        if config.output_directory.is_remote() {
+            leftover_encodes = candidates
+                .iter()
+                .filter(|input_file_name| {
+                    let Some(encode) = leftovers.encodes.get(*input_file_name) else {
+                        return false;
+                    };
+                    let ffmpeg_params = &self.namer.file_config(input_file_name).ffmpeg_params;
+                    let output_path = temp_path(&encode.output_file_name);
+                    if encode.output_file_name != self.namer.output_file_name(input_file_name)
+                        || encode.ffmpeg_params != *ffmpeg_params
+                        || file_size(&output_path) != Some(encode.size)
+                        || !source_unchanged(input_file_name, &encode.source_hash)
+                    {
+                        return false;
+                    }
+                    // The downloaded source is removed once it's encoded
+                    let source_path = if !config.input_directory.is_remote() {
+                        Path::new(&config.input_directory.clone().path_string()).join(input_file_name)
+                    } else if leftover_sources.contains(*input_file_name) {
+                        temp_path(input_file_name)
+                    } else {
+                        return true;
+                    };
+                    is_complete_encode(&source_path, &output_path, ffmpeg_params)
+                })
+                .cloned()
+                .collect();
+        }
+        self.leftover_sources = leftover_sources;
+        self.leftover_encodes = leftover_encodes;
+    }
+    /// Files left in the temp directory by an interrupted run, only the ones named like an input or output file
+    pub fn stale_temp_files(&self, warnings: &mut Warnings) -> Vec<RclonePath> {
+        let temp_directory = self.config().temp_directory.clone().unwrap_or(String::from("temp"));
+        if !Path::new(&temp_directory).is_dir() {
+            return Vec::new();
+        }
+        let mut known_names = self.input.iter().chain(&self.output).cloned().collect::<HashSet<String>>();
+        known_names.extend(self.input.iter().map(|input_file_name| self.namer.output_file_name(input_file_name)));
+        // Listing a local directory doesn't fail, unreadable subdirectories are warned about
+        fs_wrapper::list_files_recursively(&RclonePath::Local(temp_directory.clone()), warnings)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|file| {
+                Path::new(&file.clone().path_string())
+                    .strip_prefix(&temp_directory)
+                    .map(|file_name| known_names.contains(&file_name.to_string_lossy().to_string()))
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+    /// Directories of runs moved to the trash directory longer ago than trashDays, to be removed when cleaning
+    pub fn expired_trash(&self, warnings: &mut Warnings) -> Vec<RclonePath> {
+        let config = self.config();
+        let (trash_directory, trash_days) = match (&config.trash_directory, config.trash_days) {
+            (Some(trash_directory), Some(trash_days)) => (trash_directory, trash_days),
+            _ => return Vec::new(),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+        let trash_path = trash_directory.clone().path_string();
+        // Nothing expires when the trash directory can't be listed, it's cleaned by a later run
+        let files = match fs_wrapper::list_files_recursively(trash_directory, warnings) {
+            Ok(files) => files,
+            Err(error) => {
+                warnings.push(WarningKind::UnreadableDirectory, trash_directory.to_string(), Some(error.to_string()));
+                return Vec::new();
+            }
+        };
+        // Only directories named like a run are removed, anything else in the trash directory is left alone
+        files
+            .into_iter()
+            .filter_map(|file| {
+                let file = file.path_string();
+                let run = Path::new(&file).strip_prefix(&trash_path).ok()?.components().next()?;
+                let run = run.as_os_str().to_string_lossy().to_string();
+                let started = run.parse::<u64>().ok()?;
+                (started.saturating_add(trash_days.saturating_mul(86400)) < now).then_some(run)
+            })
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .map(|run| trash_directory.with_path(format!("{}/{}", trash_path, run)))
+            .collect()
+    }
+    /// Input file name -> output file name of all processed files after the plan was executed,
+    /// to be saved for the next run
+    pub fn final_encoded(&self, executed: &ExecutedActions) -> HashMap<String, String> {
+        // Only the failed files were processed when retrying, everything else stays as it was
+        let mut encoded = if self.retry_failed {
+            self.previous_encoded.clone()
+        } else {
+            // Sources outside of --only weren't synced, their state stays as it was
+            self.previous_encoded
+                .iter()
+                .filter(|(input_file_name, _)| !self.in_scope(input_file_name))
+                .map(|(input_file_name, output_file_name)| (input_file_name.clone(), output_file_name.clone()))
+                .collect()
+        };
+        // Kept files, under their new name if the rename was done, files whose source is gone are forgotten
+        encoded.extend(
+            self.encoded
+                .iter()
+                .filter(|(input_file_name, _)| self.input.contains(*input_file_name) && self.in_scope(input_file_name))
+                .map(|(input_file_name, output_file_name)| {
+                    let output_file_name = executed.renamed.get(output_file_name).unwrap_or(output_file_name);
+                    (input_file_name.clone(), output_file_name.clone())
+                })
+        );
+        // Files which weren't processed, like failed ones, are processed again by the next run
+        encoded.extend(executed.processed.clone());
+        encoded
+    }
+    /// Contents of the exportPlaylist playlist after the plan was executed, all synced audio files except audiobooks
+    pub fn exported_playlist(&self, executed: &ExecutedActions) -> Option<String> {
+        let playlist = self.config().export_playlist.as_ref()?;
+        let output_files = self
+            .final_encoded(executed)
+            .into_iter()
+            .filter(|(input_file_name, _)| !self.namer.file_config(input_file_name).is_audiobook(input_file_name))
+            .map(|(_, output_file_name)| output_file_name)
+            .collect::<Vec<String>>();
+        Some(create_playlist(&normalize_output_file_name(playlist), output_files.iter()))
+    }
+    /// Input file name -> output file name of the encoded tracks of the albums completed by the run, by their output
+    /// directory, for albumReplayGain and exportFolderCovers
+    ///
+    /// An album is completed when the run encoded any of its tracks and none of them failed or was left for the next
+    /// run, so with settleDirectories a ripped album is only completed once all of its tracks are there. Audiobooks
+    /// aren't albums
+    pub fn completed_albums(&self, executed: &ExecutedActions) -> BTreeMap<String, BTreeMap<String, String>> {
+        let is_track = |input_file_name: &str| {
+            is_encoded(&self.namer, input_file_name)
+                && !self.namer.file_config(input_file_name).is_audiobook(input_file_name)
+        };
+        let synced = executed
+            .processed
+            .iter()
+            .filter(|(input_file_name, _)| is_track(input_file_name))
+            .map(|(_, output_file_name)| split_directory(output_file_name).0.to_string())
+            .collect::<HashSet<String>>();
+        let unfinished = self
+            .to_process
+            .iter()
+            .filter(|input_file_name| !executed.processed.contains_key(*input_file_name))
+            .map(|input_file_name| split_directory(&self.namer.output_file_name(input_file_name)).0.to_string())
+            .collect::<HashSet<String>>();
+        let mut albums: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for (input_file_name, output_file_name) in self.final_encoded(executed) {
+            let directory = split_directory(&output_file_name).0.to_string();
+            if synced.contains(&directory) && !unfinished.contains(&directory) && is_track(&input_file_name) {
+                albums.entry(directory).or_default().insert(input_file_name, output_file_name);
+            }
+        }
+        albums
+    }
+    /// ffmpeg params of all encoded sources after the plan was executed, to be saved for the next run
+    ///
+    /// Sources encoded before the params were recorded get the current ones
+    pub fn final_params(&self, executed: &ExecutedActions) -> HashMap<String, String> {
+        self.final_encoded(executed)
+            .into_keys()
+            .filter_map(|input_file_name| {
+                let file_config = self.namer.file_config(&input_file_name);
+                let file_extension = Path::new(&input_file_name).extension()?.to_string_lossy().to_string();
+                if !file_config.extensions_to_encode.contains(&file_extension) {
+                    return None;
+                }
+                let params = if executed.processed.contains_key(&input_file_name) {
+                    file_config.ffmpeg_params
+                } else {
+                    self.previous_params.get(&input_file_name).cloned().unwrap_or(file_config.ffmpeg_params)
+                };
+                Some((input_file_name, params))
+            })
+            .collect()
+    }
+    /// Sizes and durations of all encoded outputs after the plan was executed, to be saved for the next run
+    ///
+    /// Outputs encoded again without a readable result lose their old info, so they aren't reported as changed
+    pub fn final_output_info(&self, executed: &ExecutedActions) -> HashMap<String, OutputInfo> {
+        let final_output = self.final_encoded(executed).into_values().collect::<HashSet<String>>();
+        let processed_output = executed.processed.values().collect::<HashSet<&String>>();
+        let mut output_info = self.previous_output_info
+            .iter()
+            .map(|(output_file_name, info)| {
+                let output_file_name = executed.renamed.get(output_file_name).unwrap_or(output_file_name);
+                (output_file_name.clone(), info.clone())
+            })
+            .filter(|(output_file_name, _)| !processed_output.contains(output_file_name))
+            .collect::<HashMap<String, OutputInfo>>();
+        output_info.extend(executed.output_info.clone());
+        output_info.retain(|output_file_name, _| final_output.contains(output_file_name));
+        output_info
+    }
+    /// Hashes of all processed sources after the plan was executed, to be saved for the next run
+    pub fn final_hashes(&self, executed: &ExecutedActions) -> HashMap<String, FileHash> {
+        let mut hashes = if self.retry_failed {
+            self.previous_hashes.clone()
+        } else {
+            self.hashes.clone()
+        };
+        hashes.extend(
+            self.previous_hashes
+                .iter()
+                .filter(|(input_file_name, _)| !self.in_scope(input_file_name))
+                .map(|(input_file_name, hash)| (input_file_name.clone(), hash.clone()))
+        );
+        let mut processed = executed.processed.keys().cloned().collect::<Vec<String>>();
+        processed.sort();
+        // Sources which couldn't be hashed are hashed again by the next run
+        hashes.extend(hash_files(&self.config().input_directory, &processed).unwrap_or_default());
+        hashes
+    }
+    /// Files which failed to process after the plan was executed, to be saved for the next run
+    ///
+    /// Earlier failures which weren't attempted, like after running out of time, are kept while their source exists
+    pub fn final_failures(&self, executed: &ExecutedActions) -> FailedFiles {
+        let mut files = self.failures.files
+            .iter()
+            .filter(|(input_file_name, _)| {
+                self.input.contains(*input_file_name) && !executed.processed.contains_key(*input_file_name)
+            })
+            .map(|(input_file_name, output_file_name)| (input_file_name.clone(), output_file_name.clone()))
+            .collect::<BTreeMap<String, String>>();
+        files.extend(executed.failed.clone());
+        FailedFiles {
+            override_files: self.override_files.clone(),
+            files,
+        }
+    }
+}
+
+/// What an [Executor] actually did, the state saved for the next run is derived from it
+#[derive(Default, Debug)]
+pub struct ExecutedActions {
+    /// Input file name -> output file name of the encoded or copied files
+    pub processed: BTreeMap<String, String>,
+    /// Old output file name -> new output file name
+    pub renamed: BTreeMap<String, String>,
+    /// Deleted output files
+    pub deleted: BTreeSet<String>,
+    /// Output files whose tags or covers were refreshed
+    pub refreshed: BTreeSet<String>,
+    /// Output file name -> size and duration of the encoded files
+    pub output_info: BTreeMap<String, OutputInfo>,
+    /// Input file name -> output file name of the files which failed to encode
+    pub failed: BTreeMap<String, String>,
+    /// Output file name -> BLAKE3 hash of the outputs written this run, taken from the local file before it was
+    /// uploaded. Only hashed when a content store is used, whose objects are named by it
+    pub content_hashes: BTreeMap<String, FileHash>,
+    /// Input file name -> seconds its encode took, for the encodes exceeding slowFileBudget
+    pub slow: BTreeMap<String, u64>,
+    /// The deadline of the [Executor] passed before all actions were done
+    pub out_of_time: bool,
+}
+
+/// An error which stopped an [Executor] before all actions were done
+#[derive(Debug)]
+pub struct Interrupted {
+    /// Actions done before the error, boxed to keep the error small
+    pub executed: Box<ExecutedActions>,
+    pub error: Box<dyn Error>,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for Interrupted {}
+
+/// Saves the state of a run which is still going on
+pub type Checkpoint<'a> = dyn Fn(&ExecutedActions) -> Result<(), Box<dyn Error>> + 'a;
+
+/// Runs the actions of a [SyncPlan]
+pub struct Executor<'a> {
+    events: &'a EventSink,
+    /// Only print the actions without running them
+    pub dry_run: bool,
+    /// Suppress ffmpeg output
+    pub quiet: bool,
+    /// Copy covers to already encoded files
+    pub refresh_covers: bool,
+    /// Copy tags to already encoded files
+    pub refresh_tags: bool,
+    /// Show progress bars instead of printing every action, with the ffmpeg output hidden unless it fails
+    pub progress: bool,
+    /// Called with the actions done so far every `checkpoint_interval` processed files, to save the state
+    pub checkpoint: Option<&'a Checkpoint<'a>>,
+    pub checkpoint_interval: usize,
+    /// No new action is started after this time, the running one is finished
+    pub deadline: Option<Instant>,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(events: &'a EventSink) -> Self {
+        Self {
+            events,
+            dry_run: false,
+            quiet: false,
+            refresh_covers: false,
+            refresh_tags: false,
+            progress: false,
+            checkpoint: None,
+            checkpoint_interval: 0,
+            deadline: None,
+        }
+    }
+    /// Whether the deadline passed, the remaining actions are left for the next run then
+    fn out_of_time(&self, executed: &mut ExecutedActions) -> bool {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            executed.out_of_time = true;
+        }
+        executed.out_of_time
+    }
+    /// Locks a local output file when lockOutputs is set, see [lock_output]
+    fn lock_output(&self, config: &Config, output_file_name: &str) -> OutputLock {
+        if config.lock_outputs != Some(true) || config.output_directory.is_remote() {
+            return OutputLock::Unlocked;
+        }
+        lock_output(Path::new(&config.output_directory.clone().path_string()).join(output_file_name))
+    }
+    /// Copies the streams of an output into the container of its new extension and removes the old output
+    ///
+    /// Returns the ffmpeg failure if the streams couldn't be copied, the old output is kept then.
+    /// Remote outputs are downloaded to the temp directory and uploaded again, the info of the new output is recorded
+    fn remux(
+        &self,
+        config: &Config,
+        temp_directory: &str,
+        old_file_name: &str,
+        new_file_name: &str,
+        executed: &mut ExecutedActions,
+    ) -> Result<Result<(), String>, Box<dyn Error>> {
+        let output_path = |file_name: &str| {
+            config.output_directory.with_path(
+                format!(
+                    "{}/{}",
+                    config.output_directory.clone().path_string(),
+                    file_name
+                )
+            )
+        };
+        let (old_path, new_path) = if config.output_directory.is_remote() {
+            let old_path = PathBuf::from(format!("{}/{}", temp_directory, old_file_name));
+            let new_path = PathBuf::from(format!("{}/{}", temp_directory, new_file_name));
+            for path in [&old_path, &new_path] {
+                if let Some(parent) = path.parent() {
+                    fs_wrapper::create_dir_all(&RclonePath::Local(parent.to_string_lossy().to_string()))?;
+                }
+            }
+            fs_wrapper::copy(
+                &output_path(old_file_name),
+                &RclonePath::Local(old_path.to_string_lossy().to_string()),
+            )?;
+            (old_path, new_path)
+        } else {
+            let output_directory = config.output_directory.clone().path_string();
+            (Path::new(&output_directory).join(old_file_name), Path::new(&output_directory).join(new_file_name))
+        };
+        let partial_path = partial_path(&new_path);
+        let _partial_file = PartialFile::track(&partial_path);
+        let mut command = programs::ffmpeg(config);
+        command.args(["-nostdin", "-y", "-i"]).arg(&old_path).args(["-map", "0", "-c", "copy"]).arg(&partial_path);
+        let status = if self.quiet {
+            command.output().map(|output| output.status)
+        } else {
+            command.status()
+        };
+        let failure = match status {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(format!("ffmpeg {}", status)),
+            Err(error) => Some(format!("Failed to execute ffmpeg: {}", error)),
+        };
+        if let Some(failure) = failure {
+            if partial_path.exists() {
+                fs_wrapper::remove_file(&RclonePath::Local(partial_path.to_string_lossy().to_string()))?;
+            }
+            if config.output_directory.is_remote() {
+                fs_wrapper::remove_file(&RclonePath::Local(old_path.to_string_lossy().to_string()))?;
+            }
+            return Ok(Err(failure));
+        }
+        std::fs::rename(&partial_path, &new_path)?;
+        fs_wrapper::record_written(&new_path);
+        if let Some(output_info) = OutputInfo::read(&new_path) {
+            executed.output_info.insert(new_file_name.to_string(), output_info);
+        }
+        if let Some(content_hash) = content_hash(config, &new_path) {
+            executed.content_hashes.insert(new_file_name.to_string(), content_hash);
+        }
+        if config.output_directory.is_remote() {
+            fs_wrapper::rename(&RclonePath::Local(new_path.to_string_lossy().to_string()), &output_path(new_file_name))?;
+            fs_wrapper::remove_file(&RclonePath::Local(old_path.to_string_lossy().to_string()))?;
+        }
+        fs_wrapper::remove_file(&output_path(old_file_name))?;
+        Ok(Ok(()))
+    }
+    /// Runs all actions of the plan, returns the ones which were done and the files which failed to encode
+    ///
+    /// Nothing is done in a dry run. If an error interrupts the run, the actions done until then are returned
+    /// with it, so their state can still be saved
+    pub fn execute(&self, plan: &SyncPlan, warnings: &mut Warnings) -> Result<ExecutedActions, Interrupted> {
+        let mut executed = ExecutedActions::default();
+        programs::use_config(plan.config());
+        match self.execute_actions(plan, warnings, &mut executed) {
+            Ok(()) => Ok(executed),
+            Err(error) => Err(Interrupted { executed: Box::new(executed), error }),
+        }
+    }
+    fn execute_actions(
+        &self,
+        plan: &SyncPlan,
+        warnings: &mut Warnings,
+        executed: &mut ExecutedActions,
+    ) -> Result<(), Box<dyn Error>> {
+        let config = plan.config();
+        let namer = &plan.namer;
+        let input = &plan.input;
+        let encoded = &plan.encoded;
+        let mut input_to_process = plan.to_process.clone();
+        let output_to_rename = plan.to_rename.clone();
+        let output_to_delete = plan.to_delete.clone();
+        let total_actions = plan.total_actions();
+        let progress = Progress::new(total_actions, self.progress);
+
+        // Create required directories
+        let output_directories = input_to_process.clone().into_iter()
+            .map(
+                |path| {
+                    Path::new(&path).parent().unwrap_or(Path::new("")).to_string_lossy().to_string()
+                }
+            ).collect::<HashSet<String>>();
+
+        // Only files ffmpeg runs on and rewritten playlists go through the temp directory, other files are copied
+        // by rclone directly, between two remotes without downloading them
+        let temp_directories = input_to_process
+            .iter()
+            .filter(|input_file_name| {
+                let file_extension = Path::new(input_file_name)
+                    .extension()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                namer.file_config(input_file_name).extensions_to_encode.contains(&file_extension)
+                    || (config.rewrite_playlists == Some(true)
+                        && config.output_directory.is_remote()
+                        && is_playlist(input_file_name))
+            })
+            .map(|path| Path::new(path).parent().unwrap_or(Path::new("")).to_string_lossy().to_string())
+            .collect::<HashSet<String>>();
+        let temp_directory = config.temp_directory.clone().unwrap_or(String::from("temp"));
+        if (config.input_directory.is_remote() || config.output_directory.is_remote())
+            && !temp_directories.is_empty()
+        {
+            if self.dry_run {
+                progress.eprintln("Skipping creation of temp directory as --dry-run is set");
+            } else {
+                progress.println(format!("Creating directory {}", redact::path(&temp_directory)));
+                fs_wrapper::create_dir_all(
+                    &RclonePath::Local(temp_directory.clone())
+                )?;
+                for output_directory in temp_directories {
+                    progress.println(format!("Creating directory {}", redact::path(&output_directory)));
+                    fs_wrapper::create_dir_all(
+                        &RclonePath::Local(
+                            format!(
+                                "{}/{}",
+                                temp_directory.clone(),
+                                output_directory
+                            )
+                        )
+                    )?;
+                }
+            }
+        }
+
+        if self.dry_run {
+            progress.eprintln("Skipping creation of output directory as --dry-run is set");
+        } else {
+            progress.println("Creating output directory");
+            fs_wrapper::create_dir_all(&config.output_directory)?;
+        }
+
+        for output_directory in output_directories {
+            if !output_directory.is_empty() {
+                if self.dry_run {
+                    progress.eprintln(format!("Skipping creation of output directory {} as --dry-run is set", redact::path(&output_directory)));
+                } else {
+                    progress.println(format!("Creating output directory {}", display::file(&config.output_directory, &output_directory)));
+                    fs_wrapper::create_dir_all(
+                        &config.output_directory.with_path(
+                            format!(
+                                "{}/{}",
+                                config.output_directory.clone().path_string(),
+                                output_directory
+                            )
+                        )
+                    )?;
+                }
+            }
+        }
+
+        // Process all files
+        let mut current_action = 0;
+        let trash_run = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+
+        // Remote deletes are collected and done with a single rclone run after the loop, as every rclone run
+        // takes a few API calls
+        let batch_deletes = config.output_directory.is_remote()
+            || config.trash_directory.as_ref().is_some_and(|trash_directory| trash_directory.is_remote());
+
+        // Deletes the outputs or moves them to the trash directory, can run twice when deletes are deferred
+        let delete_outputs = |output_to_delete: Vec<String>,
+                              current_action: &mut usize,
+                              warnings: &mut Warnings,
+                              executed: &mut ExecutedActions|
+         -> Result<(), Box<dyn Error>> {
+            let mut deletes = Vec::new();
+            for file_to_delete in output_to_delete {
+                if interrupt::is_interrupted() {
+                    return Err(Box::from("Interrupted by Ctrl+C"));
+                }
+                if self.out_of_time(executed) {
+                    break;
+                }
+                match &config.trash_directory {
+                    Some(trash_directory) => progress.println(format!(
+                        "Moving {} to {}",
+                        display::file(&config.output_directory, &file_to_delete),
+                        redact::path(&trash_directory.to_string())
+                    )),
+                    None => progress.println(format!("Deleting {}", display::file(&config.output_directory, &file_to_delete))),
+                }
+                *current_action += 1;
+                self.events.emit(Event::Progress {
+                    action: "delete",
+                    file: &file_to_delete,
+                    current: *current_action,
+                    total: total_actions,
+                });
+                progress.start_action(&file_to_delete);
+                if self.dry_run {
+                    progress.eprintln("Skipping delete as --dry-run is set");
+                } else {
+                    let output_file = config.output_directory.with_path(
+                        format!(
+                            "{}/{}",
+                            config.output_directory.clone().path_string(),
+                            file_to_delete
+                        )
+                    );
+                    // Held until the file is deleted, the advisory lock doesn't keep it from being removed
+                    let lock = self.lock_output(config, &file_to_delete);
+                    if let OutputLock::Busy(reason) = lock {
+                        warnings.push(WarningKind::OutputBusy, file_to_delete, Some(reason));
+                        continue;
+                    }
+                    if batch_deletes {
+                        deletes.push(file_to_delete);
+                        continue;
+                    }
+                    match &config.trash_directory {
+                        Some(trash_directory) => {
+                            // Every run gets its own directory, named by when it started, so nothing is overwritten
+                            let trashed_file = trash_directory.with_path(
+                                format!(
+                                    "{}/{}/{}",
+                                    trash_directory.clone().path_string(),
+                                    trash_run,
+                                    file_to_delete
+                                )
+                            );
+                            if !trashed_file.is_remote() {
+                                if let Some(parent) = Path::new(&trashed_file.clone().path_string()).parent() {
+                                    fs_wrapper::create_dir_all(&RclonePath::Local(parent.to_string_lossy().to_string()))?;
+                                }
+                            }
+                            fs_wrapper::rename(&output_file, &trashed_file)?;
+                        }
+                        None => fs_wrapper::remove_file(&output_file)?,
+                    }
+                    executed.deleted.insert(file_to_delete);
+                }
+            }
+            if !deletes.is_empty() {
+                progress.println(format!("Deleting {} files with a single rclone run", deletes.len()));
+                let result = match &config.trash_directory {
+                    Some(trash_directory) => fs_wrapper::move_files(
+                        &config.output_directory,
+                        &trash_directory.with_path(format!("{}/{}", trash_directory.clone().path_string(), trash_run)),
+                        &deletes,
+                    ),
+                    None => fs_wrapper::remove_files(&config.output_directory, &deletes),
+                };
+                if let Err(error) = result {
+                    executed.deleted.extend(gone_after_failure(&config.output_directory, deletes));
+                    return Err(Box::new(error));
+                }
+                executed.deleted.extend(deletes);
+            }
+            Ok(())
+        };
+        // With deferDeletes, outputs are only deleted once everything else is done, so an interrupted run never
+        // leaves the output without tracks it had. Outputs replaced by a rename or an encode are still deleted first
+        let replaced = output_to_rename
+            .values()
+            .cloned()
+            .chain(input_to_process.iter().map(|input_file_name| namer.output_file_name(input_file_name)))
+            .collect::<HashSet<String>>();
+        let (output_to_delete, deferred_deletes): (Vec<String>, Vec<String>) = output_to_delete
+            .into_iter()
+            .partition(|file_to_delete| config.defer_deletes == Some(false) || replaced.contains(file_to_delete));
+        delete_outputs(output_to_delete, &mut current_action, warnings, executed)?;
+
+        // Remote renames only moving files to another directory are grouped by their old and new directory,
+        // each group is moved with a single rclone run after the loop
+        let mut moves: BTreeMap<(String, String), Vec<(String, String)>> = BTreeMap::new();
+
+        // Rename already encoded
+        for (old_file_name, new_file_name) in output_to_rename {
+            if interrupt::is_interrupted() {
+                return Err(Box::from("Interrupted by Ctrl+C"));
+            }
+            if self.out_of_time(executed) {
+                break;
+            }
+            // Outputs getting the extension of another container are remuxed, the audio is kept as it is
+            let remux = container_of(&old_file_name) != container_of(&new_file_name);
+            if remux {
+                progress.println(format!(
+                    "Remuxing {}",
+                    display::transfer(&config.output_directory, &old_file_name, &config.output_directory, &new_file_name)
+                ));
+            } else {
+                progress.println(format!(
+                    "Renaming {}",
+                    display::transfer(&config.output_directory, &old_file_name, &config.output_directory, &new_file_name)
+                ));
+            }
+            current_action += 1;
+            self.events.emit(Event::Progress {
+                action: "rename",
+                file: &old_file_name,
+                current: current_action,
+                total: total_actions,
+            });
+            progress.start_action(&old_file_name);
+            if self.dry_run {
+                progress.eprintln("Skipping rename as --dry-run is set");
+            } else {
+                // Held until the file is renamed or remuxed
+                let lock = self.lock_output(config, &old_file_name);
+                if let OutputLock::Busy(reason) = lock {
+                    warnings.push(WarningKind::OutputBusy, old_file_name, Some(reason));
+                    continue;
+                }
+                if remux {
+                    match self.remux(config, &temp_directory, &old_file_name, &new_file_name, executed)? {
+                        Ok(()) => {
+                            executed.renamed.insert(old_file_name, new_file_name);
+                        }
+                        Err(failure) => warnings.push(WarningKind::RemuxFailed, old_file_name, Some(failure)),
+                    }
+                    continue;
+                }
+                if config.output_directory.is_remote() {
+                    let (old_directory, old_name) = split_directory(&old_file_name);
+                    let (new_directory, new_name) = split_directory(&new_file_name);
+                    if old_name == new_name {
+                        moves
+                            .entry((old_directory.to_string(), new_directory.to_string()))
+                            .or_default()
+                            .push((old_file_name.clone(), new_file_name.clone()));
+                        continue;
+                    }
+                }
+                fs_wrapper::rename(
+                    &config.output_directory.with_path(
+                        format!(
+                            "{}/{}",
+                            config.output_directory.clone().path_string(),
+                            old_file_name
+                        )
+                    ),
+                    &config.output_directory.with_path(
+                        format!(
+                            "{}/{}",
+                            config.output_directory.clone().path_string(),
+                            new_file_name
+                        )
+                    ),
+                )?;
+                executed.renamed.insert(old_file_name, new_file_name);
+            }
+        }
+        for ((old_directory, new_directory), renames) in moves {
+            let directory = |directory: &str| {
+                if directory.is_empty() {
+                    config.output_directory.clone()
+                } else {
+                    config.output_directory.with_path(
+                        format!("{}/{}", config.output_directory.clone().path_string(), directory)
+                    )
+                }
+            };
+            let names = renames
+                .iter()
+                .map(|(old_file_name, _)| split_directory(old_file_name).1.to_string())
+                .collect::<Vec<String>>();
+            progress.println(format!(
+                "Moving {} files from {} with a single rclone run",
+                names.len(),
+                display::transfer(&config.output_directory, &old_directory, &config.output_directory, &new_directory)
+            ));
+            if let Err(error) = fs_wrapper::move_files(&directory(&old_directory), &directory(&new_directory), &names) {
+                let moved = gone_after_failure(&directory(&old_directory), names);
+                executed.renamed.extend(
+                    renames.into_iter().filter(|(old_file_name, _)| moved.contains(split_directory(old_file_name).1))
+                );
+                return Err(Box::new(error));
+            }
+            executed.renamed.extend(renames);
+        }
+
+        // Files which are only copied aren't transferred one by one with remotes: the ones keeping their names with
+        // a single rclone run, the renamed ones by copyWorkers rclone runs at once. verifyCopies and lockOutputs are
+        // only done by the copies of single files below
+        if (config.input_directory.is_remote() || config.output_directory.is_remote())
+            && config.verify_copies != Some(true)
+            && config.lock_outputs != Some(true)
+            && !interrupt::is_interrupted()
+            && !self.out_of_time(executed)
+        {
+            let mut files_to_copy = input_to_process
+                .iter()
+                .filter(|input_file_name| {
+                    let file_extension = Path::new(input_file_name)
+                        .extension()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    !namer.file_config(input_file_name).extensions_to_encode.contains(&file_extension)
+                })
+                // Playlists are rewritten one by one
+                .filter(|input_file_name| config.rewrite_playlists != Some(true) || !is_playlist(input_file_name))
+                .cloned()
+                .collect::<Vec<String>>();
+            files_to_copy.sort();
+            let (files_to_copy, renamed_copies): (Vec<String>, Vec<String>) = files_to_copy
+                .into_iter()
+                .partition(|input_file_name| namer.output_file_name(input_file_name) == **input_file_name);
+            if !files_to_copy.is_empty() {
+                progress.println(format!("Copying {} files with unchanged names", files_to_copy.len()));
+                for input_file_name in &files_to_copy {
+                    current_action += 1;
+                    self.events.emit(Event::Progress {
+                        action: "copy",
+                        file: input_file_name,
+                        current: current_action,
+                        total: total_actions,
+                    });
+                    progress.start_action(input_file_name);
+                }
+                if self.dry_run {
+                    progress.eprintln("Skipping copy as --dry-run is set");
+                } else {
+                    fs_wrapper::copy_files(&config.input_directory, &config.output_directory, &files_to_copy)?;
+                    for input_file_name in &files_to_copy {
+                        executed.processed.insert(input_file_name.clone(), input_file_name.clone());
+                    }
+                }
+                for input_file_name in files_to_copy {
+                    input_to_process.remove(&input_file_name);
+                }
+            }
+            let copy_workers = config.copy_workers.unwrap_or(4).clamp(1, renamed_copies.len().max(1));
+            if renamed_copies.len() > 1 && copy_workers > 1 && !interrupt::is_interrupted() && !self.out_of_time(executed) {
+                progress.println(format!(
+                    "Copying {} renamed files with {} rclone runs at once",
+                    renamed_copies.len(),
+                    copy_workers
+                ));
+                for input_file_name in &renamed_copies {
+                    current_action += 1;
+                    self.events.emit(Event::Progress {
+                        action: "copy",
+                        file: input_file_name,
+                        current: current_action,
+                        total: total_actions,
+                    });
+                    progress.start_action(input_file_name);
+                }
+                if self.dry_run {
+                    progress.eprintln("Skipping copy as --dry-run is set");
+                    for input_file_name in renamed_copies {
+                        input_to_process.remove(&input_file_name);
+                    }
+                } else {
+                    let copies = renamed_copies
+                        .iter()
+                        .map(|input_file_name| (input_file_name.clone(), namer.output_file_name(input_file_name)))
+                        .collect::<Vec<(String, String)>>();
+                    let copied = copy_in_parallel(
+                        &config.input_directory,
+                        &config.output_directory,
+                        &copies,
+                        copy_workers,
+                        self.deadline,
+                    );
+                    let mut first_error = None;
+                    for ((input_file_name, output_file_name), result) in copies.into_iter().zip(copied) {
+                        // Copies not started because of Ctrl+C or maxDuration are left to the loop below, which stops
+                        // before them
+                        match result {
+                            Some(Ok(())) => {
+                                input_to_process.remove(&input_file_name);
+                                executed.processed.insert(input_file_name, output_file_name);
+                            }
+                            Some(Err(error)) => {
+                                input_to_process.remove(&input_file_name);
+                                first_error.get_or_insert(error);
+                            }
+                            None => {}
+                        }
+                    }
+                    // Like a failed copy of a single file, after the successful ones are recorded
+                    if let Some(error) = first_error {
+                        return Err(Box::new(error));
+                    }
+                }
+            }
+        }
+
+        // Encode or copy
+        let mut last_checkpoint = executed.processed.len() + executed.failed.len();
+        let process_order = plan
+            .process_order()
+            .into_iter()
+            .filter(|input_file_name| input_to_process.contains(input_file_name))
+            .collect::<Vec<String>>();
+        // With remote directories the next sources are downloaded and the finished outputs uploaded while encoding,
+        // so the network and the CPU are busy at the same time
+        let stop_downloads = AtomicBool::new(false);
+        // Completed files in the temp directory are listed, so a run interrupted before moving them on can reuse them
+        let mut leftovers = if (config.input_directory.is_remote() || config.output_directory.is_remote()) && !self.dry_run {
+            let mut leftovers = Leftovers::load(&temp_directory);
+            leftovers.retain_existing(&temp_directory);
+            Some(leftovers)
+        } else {
+            None
+        };
+        let mut source_hashes = HashMap::new();
+        thread::scope(|scope| -> Result<(), Box<dyn Error>> {
+            let downloads = if config.input_directory.is_remote() && !self.dry_run {
+                let sources = process_order
+                    .iter()
+                    .filter(|input_file_name| is_encoded(namer, input_file_name))
+                    .filter(|input_file_name| !plan.leftover_encodes.contains(*input_file_name))
+                    .cloned()
+                    .collect::<Vec<String>>();
+                // With detectChanges leftovers are only reused if their source is unchanged
+                if config.detect_changes == Some(true) {
+                    // Without the hashes no leftover is reused
+                    source_hashes = hash_files(&config.input_directory, &sources).unwrap_or_default();
+                }
+                let (sender, receiver) = mpsc::sync_channel(1);
+                let (temp_directory, stop_downloads) = (&temp_directory, &stop_downloads);
+                let leftovers = &plan.leftover_sources;
+                scope.spawn(move || download_sources(config, temp_directory, sources, leftovers, stop_downloads, sender));
+                Some(receiver)
+            } else {
+                None
+            };
+            let (uploads, uploaded) = if config.output_directory.is_remote() && !self.dry_run {
+                let (sender, receiver) = mpsc::sync_channel(1);
+                let (results, uploaded) = mpsc::channel();
+                let temp_directory = &temp_directory;
+                scope.spawn(move || upload_outputs(config, temp_directory, receiver, results));
+                (Some(sender), Some(uploaded))
+            } else {
+                (None, None)
+            };
+            let source_hash = |input_file_name: &str| {
+                if config.detect_changes != Some(true) {
+                    None
+                } else if config.input_directory.is_remote() {
+                    source_hashes.get(input_file_name).cloned()
+                } else {
+                    hash_local_file(Path::new(&config.input_directory.clone().path_string()).join(input_file_name)).ok()
+                }
+            };
+            let process = || -> Result<(), Box<dyn Error>> {
+                for input_file_name in process_order {
+                    if interrupt::is_interrupted() {
+                        return Err(Box::from("Interrupted by Ctrl+C"));
+                    }
+                    for (input_file_name, output_file_name, result) in uploaded.iter().flat_map(Receiver::try_iter) {
+                        result?;
+                        executed.processed.insert(input_file_name, output_file_name);
+                    }
+                    if self.out_of_time(executed) {
+                        break;
+                    }
+                    if let Some(checkpoint) = self.checkpoint {
+                        let done = executed.processed.len() + executed.failed.len();
+                        if self.checkpoint_interval > 0 && done >= last_checkpoint + self.checkpoint_interval {
+                            checkpoint(executed)?;
+                            last_checkpoint = done;
+                        }
+                    }
+                    let file_config = namer.file_config(&input_file_name);
+                    current_action += 1;
+                    let file_extension = Path::new(&input_file_name)
+                        .extension()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_string();
+                    let output_file_name = namer.output_file_name(&input_file_name);
+                    if file_config.extensions_to_encode.contains(&file_extension) {
+                        if let Some(throttle) = config.throttle.as_ref().filter(|_| !self.dry_run) {
+                            if !throttle::wait_until_allowed(throttle, self.deadline, |message| progress.println(message)) {
+                                if interrupt::is_interrupted() {
+                                    return Err(Box::from("Interrupted by Ctrl+C"));
+                                }
+                                executed.out_of_time = true;
+                                break;
+                            }
+                        }
+                        progress.println(format!(
+                            "Encoding {} with ffmpeg params {}",
+                            display::transfer(&config.input_directory, &input_file_name, &config.output_directory, &output_file_name),
+                            file_config.ffmpeg_params
+                        ));
+                        self.events.emit(Event::Progress {
+                            action: "encode",
+                            file: &input_file_name,
+                            current: current_action,
+                            total: total_actions,
+                        });
+                        progress.start_action(&input_file_name);
+                        if self.dry_run {
+                            progress.eprintln("Skipping encode as --dry-run is set");
+                        } else if plan.leftover_encodes.contains(&input_file_name) {
+                            progress.println("Reusing the output an interrupted run left in the temp directory");
+                            let output_file_path = PathBuf::from(format!("{}/{}", temp_directory, output_file_name));
+                            if let Some(output_info) = OutputInfo::read(&output_file_path) {
+                                executed.output_info.insert(output_file_name.clone(), output_info);
+                            }
+                            if let Some(content_hash) = content_hash(config, &output_file_path) {
+                                executed.content_hashes.insert(output_file_name.clone(), content_hash);
+                            }
+                            let source_path = Path::new(&temp_directory).join(&input_file_name);
+                            if config.input_directory.is_remote() && source_path.is_file() {
+                                fs_wrapper::remove_file(&RclonePath::Local(source_path.to_string_lossy().to_string()))?;
+                            }
+                            match &uploads {
+                                Some(uploads) => uploads
+                                    .send((input_file_name.clone(), output_file_name.clone()))
+                                    .map_err(|_| "The outputs stopped being uploaded")?,
+                                None => {
+                                    executed.processed.insert(input_file_name.clone(), output_file_name.clone());
+                                }
+                            }
+                        } else {
+                            let started = Instant::now();
+                            let input_file_path = if let Some(downloads) = &downloads {
+                                let (downloaded_file_name, downloaded) = downloads
+                                    .try_recv()
+                                    .or_else(|_| {
+                                        progress.println("Waiting for the source to be copied to the temp directory");
+                                        downloads.recv()
+                                    })
+                                    .map_err(|_| "The sources stopped being copied to the temp directory")?;
+                                // Downloaded in the order they're encoded in, anything else would encode the wrong
+                                // source to this output
+                                if downloaded_file_name != input_file_name {
+                                    return Err(Box::from(format!(
+                                        "Copied {} to the temp directory while {} is encoded",
+                                        downloaded_file_name, input_file_name
+                                    )));
+                                }
+                                let input_file_path = downloaded?;
+                                if let Some(leftovers) = &mut leftovers {
+                                    let size = std::fs::metadata(&input_file_path)?.len();
+                                    let source_hash = source_hash(&input_file_name);
+                                    leftovers.sources.insert(input_file_name.clone(), LeftoverSource { size, source_hash });
+                                    leftovers.save(&temp_directory)?;
+                                }
+                                input_file_path
+                            } else {
+                                Path::new(&config.input_directory.clone().path_string()).join(input_file_name.clone())
+                            };
+                            let output_file_path = if config.output_directory.is_remote() {
+                                PathBuf::from(
+                                    format!(
+                                        "{}/{}",
+                                        temp_directory,
+                                        output_file_name
+                                    )
+                                )
+                            } else {
+                                Path::new(&config.output_directory.clone().path_string()).join(output_file_name.clone())
+                            };
+                            let budget = file_config.slow_file_budget.map(|slow_file_budget| {
+                                let size = std::fs::metadata(&input_file_path).map(|metadata| metadata.len()).unwrap_or_default();
+                                slow_file_budget.budget(size, read_duration(input_file_path.clone()))
+                            });
+                            // ffmpeg writes to a partial name which is renamed once the output is complete, so a crash
+                            // or a failed encode never leaves a truncated file under the name of the output
+                            let partial_file_path = partial_path(&output_file_path);
+                            let _partial_file = PartialFile::track(&partial_file_path);
+                            let failure = if file_config.overwrite_output == Some(false) && output_file_path.exists() {
+                                Some(String::from("The output exists already and overwriteOutput is false"))
+                            } else {
+                                // Never let ffmpeg wait for input, a partial output left by a crash is overwritten
+                                let mut params = vec!["-nostdin", "-y", "-i", input_file_path.to_str().unwrap()];
+                                if self.progress {
+                                    params.extend(["-progress", "pipe:1", "-nostats"]);
+                                }
+                                let ffmpeg_params = match source_remux_params(&file_config, &input_file_path, &output_file_name) {
+                                    Some((codec, remux_params)) => {
+                                        progress.println(format!("Remuxing instead, the source already holds {}", codec));
+                                        remux_params
+                                    }
+                                    None => file_config.ffmpeg_params.clone(),
+                                };
+                                let mut config_params: Vec<&str> = ffmpeg_params.split(' ').collect();
+                                params.append(&mut config_params);
+                                params.push(partial_file_path.to_str().unwrap());
+                                let mut command = encode_command(config);
+                                command.args(params);
+                                let status = if self.progress {
+                                    let encode_bar = progress.encode_bar(read_duration(input_file_path.clone()));
+                                    run_ffmpeg(&mut command, &encode_bar).map(|(status, log)| {
+                                        if !status.success() && !self.quiet {
+                                            progress.eprintln(log.trim_end());
+                                        }
+                                        status
+                                    })
+                                } else if self.quiet {
+                                    command.output().map(|output| output.status)
+                                } else {
+                                    command.status()
+                                };
+                                // A file ffmpeg can't encode is skipped, the rest of the files are still synced
+                                match status {
+                                    Ok(status) if status.success() && config.verify_after_encode == Some(true) => {
+                                        progress.println("Verifying the output");
+                                        verify_encode(config, &input_file_path, &partial_file_path, &ffmpeg_params).err()
+                                    }
+                                    Ok(status) if status.success() => None,
+                                    Ok(status) => Some(format!("ffmpeg {}", status)),
+                                    Err(error) => Some(format!("Failed to execute ffmpeg: {}", error)),
+                                }
+                            };
+                            if failure.is_some() && interrupt::is_interrupted() {
+                                // ffmpeg was stopped by Ctrl+C, the file isn't broken
+                                if partial_file_path.exists() {
+                                    fs_wrapper::remove_file(
+                                        &RclonePath::Local(partial_file_path.to_string_lossy().to_string())
+                                    )?;
+                                }
+                            } else if let Some(failure) = &failure {
+                                warnings.push(
+                                    WarningKind::EncodeFailed,
+                                    input_file_name.clone(),
+                                    Some(failure.clone()),
+                                );
+                                executed.failed.insert(input_file_name.clone(), output_file_name.clone());
+                                if partial_file_path.exists() {
+                                    fs_wrapper::remove_file(
+                                        &RclonePath::Local(partial_file_path.to_string_lossy().to_string())
+                                    )?;
+                                }
+                            } else {
+                                if file_config.copy_covers == Some(true) {
+                                    progress.println("Copying audio cover");
+                                    if let Err(error) = copy_pictures(input_file_path, partial_file_path.clone()) {
+                                        warnings.push(
+                                            WarningKind::CoverCopyFailed,
+                                            output_file_name.clone(),
+                                            Some(error.to_string()),
+                                        );
+                                    }
+                                }
+                                std::fs::rename(&partial_file_path, &output_file_path)?;
+                                fs_wrapper::record_written(&output_file_path);
+                                if let (Some(leftovers), true) = (&mut leftovers, config.output_directory.is_remote()) {
+                                    leftovers.encodes.insert(
+                                        input_file_name.clone(),
+                                        LeftoverEncode {
+                                            output_file_name: output_file_name.clone(),
+                                            ffmpeg_params: file_config.ffmpeg_params.clone(),
+                                            size: std::fs::metadata(&output_file_path)?.len(),
+                                            source_hash: source_hash(&input_file_name),
+                                        },
+                                    );
+                                    leftovers.save(&temp_directory)?;
+                                }
+                                // The encode is done, but whatever made it this slow likely slows down the next ones
+                                if let Some(budget) = budget.filter(|budget| started.elapsed() > *budget) {
+                                    warnings.push(
+                                        WarningKind::SlowFile,
+                                        input_file_name.clone(),
+                                        Some(format!(
+                                            "took {} instead of at most {}",
+                                            format_duration(started.elapsed()),
+                                            format_duration(budget)
+                                        )),
+                                    );
+                                    executed.slow.insert(input_file_name.clone(), started.elapsed().as_secs());
+                                }
+                                // Read before the file is moved to a remote output
+                                if let Some(output_info) = OutputInfo::read(&output_file_path) {
+                                    executed.output_info.insert(output_file_name.clone(), output_info);
+                                }
+                                if let Some(content_hash) = content_hash(config, &output_file_path) {
+                                    executed.content_hashes.insert(output_file_name.clone(), content_hash);
+                                }
+                            }
+                            if config.input_directory.is_remote() {
+                                fs_wrapper::remove_file(
+                                    &RclonePath::Local(
+                                        format!(
+                                            "{}/{}",
+                                            temp_directory,
+                                            input_file_name
+                                        )
+                                    )
+                                )?;
+                            }
+                            if let (Some(uploads), None) = (&uploads, &failure) {
+                                uploads
+                                    .send((input_file_name.clone(), output_file_name.clone()))
+                                    .map_err(|_| "The outputs stopped being uploaded")?;
+                            } else if failure.is_none() {
+                                executed.processed.insert(input_file_name.clone(), output_file_name.clone());
+                            }
+                        }
+                    } else if config.rewrite_playlists == Some(true) && is_playlist(&input_file_name) {
+                        progress.println(format!(
+                            "Rewriting playlist {}",
+                            display::transfer(&config.input_directory, &input_file_name, &config.output_directory, &output_file_name)
+                        ));
+                        self.events.emit(Event::Progress {
+                            action: "copy",
+                            file: &input_file_name,
+                            current: current_action,
+                            total: total_actions,
+                        });
+                        progress.start_action(&input_file_name);
+                        if self.dry_run {
+                            progress.eprintln("Skipping playlist rewrite as --dry-run is set");
+                        } else {
+                            let contents = decode_playlist(&fs_wrapper::read(
+                                &config.input_directory.with_path(
+                                    format!(
+                                        "{}/{}",
+                                        config.input_directory.clone().path_string(),
+                                        input_file_name
+                                    )
+                                )
+                            )?);
+                            let contents = rewrite_playlist(&contents, &input_file_name, namer, input);
+                            if config.output_directory.is_remote() {
+                                let temp_file = format!("{}/{}", temp_directory, output_file_name);
+                                std::fs::write(&temp_file, contents)?;
+                                fs_wrapper::rename(
+                                    &RclonePath::Local(temp_file),
+                                    &config.output_directory.with_path(
+                                        format!(
+                                            "{}/{}",
+                                            config.output_directory.clone().path_string(),
+                                            output_file_name
+                                        )
+                                    ),
+                                )?;
+                            } else {
+                                let output_file_path =
+                                    Path::new(&config.output_directory.clone().path_string()).join(&output_file_name);
+                                let partial_file_path = partial_path(&output_file_path);
+                                std::fs::write(&partial_file_path, contents)?;
+                                std::fs::rename(&partial_file_path, &output_file_path)?;
+                                fs_wrapper::record_written(&output_file_path);
+                            }
+                            executed.processed.insert(input_file_name, output_file_name);
+                        }
+                    } else {
+                        progress.println(format!(
+                            "Copying {}",
+                            display::transfer(&config.input_directory, &input_file_name, &config.output_directory, &output_file_name)
+                        ));
+                        self.events.emit(Event::Progress {
+                            action: "copy",
+                            file: &input_file_name,
+                            current: current_action,
+                            total: total_actions,
+                        });
+                        progress.start_action(&input_file_name);
+                        if self.dry_run {
+                            progress.eprintln("Skipping copy as --dry-run is set");
+                        } else if config.verify_copies == Some(true) {
+                            fs_wrapper::copy_verified(
+                                &config.input_directory.with_path(
+                                    format!(
+                                        "{}/{}",
+                                        config.input_directory.clone().path_string(),
+                                        input_file_name
+                                    )
+                                ),
+                                &config.output_directory.with_path(
+                                    format!(
+                                        "{}/{}",
+                                        config.output_directory.clone().path_string(),
+                                        output_file_name
+                                    )
+                                ),
+                                config.copy_retries.unwrap_or(3),
+                            )?;
+                            executed.processed.insert(input_file_name, output_file_name);
+                        } else {
+                            fs_wrapper::copy(
+                                &config.input_directory.with_path(
+                                    format!(
+                                        "{}/{}",
+                                        config.input_directory.clone().path_string(),
+                                        input_file_name
+                                    )
+                                ),
+                                &config.output_directory.with_path(
+                                    format!(
+                                        "{}/{}",
+                                        config.output_directory.clone().path_string(),
+                                        output_file_name
+                                    )
+                                ),
+                            )?;
+                            executed.processed.insert(input_file_name, output_file_name);
+                        }
+                    }
+                }
+                Ok(())
+            };
+            let processed = process();
+
+            // Sources downloaded ahead aren't encoded anymore when the run stopped early
+            stop_downloads.store(true, Ordering::SeqCst);
+            for (_, downloaded) in downloads.into_iter().flatten() {
+                if let Ok(source_path) = downloaded {
+                    fs_wrapper::remove_file(&RclonePath::Local(source_path.to_string_lossy().to_string()))?;
+                }
+            }
+            // The outputs are processed once they're uploaded
+            drop(uploads);
+            let mut upload_error = None;
+            for (input_file_name, output_file_name, result) in uploaded.into_iter().flatten() {
+                match result {
+                    Ok(()) => {
+                        executed.processed.insert(input_file_name, output_file_name);
+                    }
+                    Err(error) => {
+                        upload_error.get_or_insert(error);
+                    }
+                }
+            }
+            processed?;
+            match upload_error {
+                Some(error) => Err(Box::new(error)),
+                None => Ok(()),
+            }
+        })
+        .inspect_err(|_| {
+            // The directories created for the outputs would be left behind when interrupted, files kept for the next
+            // run stay
+            if leftovers.is_some() && Path::new(&temp_directory).is_dir() {
+                let _ = fs_wrapper::remove_empty_dirs(&RclonePath::Local(temp_directory.clone()));
+            }
+        })?;
+
+        // Refresh covers and tags of already encoded files
+        if self.refresh_covers || self.refresh_tags {
+            let mut files_to_refresh = encoded
+                .keys()
+                .filter(|input_file_name| input.contains(*input_file_name))
+                .filter(|input_file_name| {
+                    let file_extension = Path::new(input_file_name)
+                        .extension()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_string();
+                    namer.file_config(input_file_name).extensions_to_encode.contains(&file_extension)
+                })
+                .cloned()
+                .collect::<Vec<String>>();
+            files_to_refresh.sort();
+            for input_file_name in files_to_refresh {
+                if interrupt::is_interrupted() {
+                    return Err(Box::from("Interrupted by Ctrl+C"));
+                }
+                if self.out_of_time(executed) {
+                    break;
+                }
+                // Renames which weren't done leave the output under its old name
+                let output_file_name = encoded
+                    .get(&input_file_name)
+                    .map(|old_file_name| executed.renamed.get(old_file_name).unwrap_or(old_file_name).clone())
+                    .unwrap_or_else(|| namer.output_file_name(&input_file_name));
+                if self.dry_run {
+                    progress.println(format!("Refreshing {}", display::file(&config.output_directory, &output_file_name)));
+                    progress.eprintln("Skipping refresh as --dry-run is set");
+                    continue;
+                }
+                // Held until the tags and covers are written
+                let lock = self.lock_output(config, &output_file_name);
+                if let OutputLock::Busy(reason) = lock {
+                    warnings.push(WarningKind::OutputBusy, output_file_name, Some(reason));
+                    continue;
+                }
+                let input_file_path = if config.input_directory.is_remote() {
+                    fs_wrapper::copy(
+                        &config.input_directory.with_path(
+                            format!(
+                                "{}/{}",
+                                config.input_directory.clone().path_string(),
+                                input_file_name
+                            )
+                        ),
+                        &RclonePath::Local(
+                            format!(
+                                "{}/{}",
+                                temp_directory,
+                                input_file_name
+                            )
+                        ),
+                    )?;
+                    PathBuf::from(
+                        format!(
+                            "{}/{}",
+                            temp_directory,
+                            input_file_name
+                        )
+                    )
+                } else {
+                    Path::new(&config.input_directory.clone().path_string()).join(input_file_name.clone())
+                };
+                let output_file_path = if config.output_directory.is_remote() {
+                    fs_wrapper::copy(
+                        &config.output_directory.with_path(
+                            format!(
+                                "{}/{}",
+                                config.output_directory.clone().path_string(),
+                                output_file_name
+                            )
+                        ),
+                        &RclonePath::Local(
+                            format!(
+                                "{}/{}",
+                                temp_directory,
+                                output_file_name
+                            )
+                        ),
+                    )?;
+                    PathBuf::from(
+                        format!(
+                            "{}/{}",
+                            temp_directory,
+                            output_file_name
+                        )
+                    )
+                } else {
+                    Path::new(&config.output_directory.clone().path_string()).join(output_file_name.clone())
+                };
+                let mut refreshed = false;
+                if self.refresh_tags {
+                    match refresh_tags(input_file_path.clone(), output_file_path.clone()) {
+                        Ok(true) => {
+                            progress.println(format!("Refreshed tags of {}", display::file(&config.output_directory, &output_file_name)));
+                            refreshed = true;
+                        }
+                        Ok(false) => {}
+                        Err(error) => {
+                            warnings.push(
+                                WarningKind::TagCopyFailed,
+                                output_file_name.clone(),
+                                Some(error.to_string()),
+                            );
+                        }
+                    }
+                }
+                if self.refresh_covers {
+                    match refresh_pictures(input_file_path, output_file_path.clone()) {
+                        Ok(true) => {
+                            progress.println(format!("Refreshed cover of {}", display::file(&config.output_directory, &output_file_name)));
+                            refreshed = true;
+                        }
+                        Ok(false) => {}
+                        Err(error) => {
+                            warnings.push(
+                                WarningKind::CoverCopyFailed,
+                                output_file_name.clone(),
+                                Some(error.to_string()),
+                            );
+                        }
+                    }
+                }
+                if refreshed {
+                    fs_wrapper::record_written(&output_file_path);
+                    executed.refreshed.insert(output_file_name.clone());
+                    if let Some(output_info) = OutputInfo::read(&output_file_path) {
+                        executed.output_info.insert(output_file_name.clone(), output_info);
+                    }
+                    if let Some(content_hash) = content_hash(config, &output_file_path) {
+                        executed.content_hashes.insert(output_file_name.clone(), content_hash);
+                    }
+                }
+                if config.input_directory.is_remote() {
+                    fs_wrapper::remove_file(
+                        &RclonePath::Local(
+                            format!(
+                                "{}/{}",
+                                temp_directory,
+                                input_file_name
+                            )
+                        )
+                    )?;
+                }
+                if config.output_directory.is_remote() {
+                    let temp_output_file = RclonePath::Local(
+                        format!(
+                            "{}/{}",
+                            temp_directory,
+                            output_file_name
+                        )
+                    );
+                    if refreshed {
+                        fs_wrapper::rename(
+                            &temp_output_file,
+                            &config.output_directory.with_path(
+                                format!(
+                                    "{}/{}",
+                                    config.output_directory.clone().path_string(),
+                                    output_file_name
+                                )
+                            ),
+                        )?;
+                    } else {
+                        fs_wrapper::remove_file(&temp_output_file)?;
+                    }
+                }
+            }
+        }
+
+        // Deferred deletes are kept for the next run when not everything could be processed. Sources which already
+        // failed in the last run don't hold them back, one which always fails would keep them forever
+        if !deferred_deletes.is_empty() {
+            let new_failures = executed
+                .failed
+                .keys()
+                .filter(|input_file_name| !plan.failures.files.contains_key(*input_file_name))
+                .count();
+            if new_failures == 0 && !self.out_of_time(executed) {
+                delete_outputs(deferred_deletes, &mut current_action, warnings, executed)?;
+            } else {
+                let reason = if new_failures > 0 {
+                    format!("{} files failed for the first time", new_failures)
+                } else {
+                    String::from("maxDuration was reached")
+                };
+                progress.println(format!(
+                    "Keeping {} outputs to delete until all files are processed as {}, they're deleted by the next \
+                    run or right away with deferDeletes set to false",
+                    deferred_deletes.len(),
+                    reason
+                ));
+            }
+        }
+
+        // Remove empty directories
+        if self.dry_run {
+            progress.eprintln("Skipping removal of empty output and temp directories as --dry-run is set");
+        } else {
+            fs_wrapper::remove_empty_dirs(&config.output_directory)?;
+            // Not created when nothing went through it
+            if (config.input_directory.is_remote() || config.output_directory.is_remote())
+                && Path::new(&temp_directory).is_dir()
+            {
+                fs_wrapper::remove_empty_dirs(
+                    &RclonePath::Local(temp_directory)
+                )?;
+            }
+        }
+
+        progress.finish();
+        Ok(())
+    }
+}
+
+/// Lists all files of the input directory, including per-directory config files, skipping files
+/// which can't be synced yet
+///
+/// `filter` is the rcloneFilterFile of the config, read by the caller so listing for several outputs reads it once.
+/// `unsettled` are the files --watch saw being written, without it the input is listed again after settleSeconds
+pub fn list_input(
+    config: &Config,
+    filter: Option<&RcloneFilter>,
+    unsettled: Option<&HashSet<String>>,
+    warnings: &mut Warnings,
+) -> io::Result<HashSet<String>> {
+    // An input which can't be listed is an error, taking it for an empty one would delete every output
+    let files = match filter {
+        Some(filter) if config.input_directory.is_remote() => {
+            fs_wrapper::list_filtered_entries(&config.input_directory, false, Some(filter))?
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect::<Vec<String>>()
+        }
+        _ => fs_wrapper::list_files_recursively(&config.input_directory, warnings)?
+            .into_iter()
+            .map(|file| {
+                Path::new(&file.path_string())
+                    .strip_prefix(config.input_directory.clone().path_string()).unwrap()
+                    .to_string_lossy().to_string()
+            })
+            .filter(|file| filter.is_none_or(|filter| filter.includes(file)))
+            .collect(),
+    };
+    let input = files
+        .into_iter()
+        // Config files aren't sources, the ones outside of include still apply to the included directories below them
+        .filter(|file| is_override_file(file) || config.is_included(file))
+        .filter(|file| {
+            // Files without an extension can't be classified, skip them
+            match Path::new(file).extension() {
+                None => {
+                    warnings.push(WarningKind::MissingExtension, file.clone(), None);
+                    false
+                }
+                // Unfinished downloads would be synced half-written
+                Some(extension) if PARTIAL_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()) => {
+                    warnings.push(WarningKind::IncompleteFile, file.clone(), None);
+                    false
+                }
+                // Written by an interrupted ffmusicsync, like one syncing to this directory
+                Some(_) if file.contains(&format!(".{}", PARTIAL_SUFFIX)) => {
+                    warnings.push(WarningKind::IncompleteFile, file.clone(), None);
+                    false
+                }
+                Some(_) => true,
+            }
+        })
+        .collect::<HashSet<String>>();
+    // Skip files which are still being written, their size changes while waiting
+    let settle_seconds = match config.settle_seconds {
+        Some(settle_seconds) if settle_seconds > 0 => settle_seconds,
+        _ => return Ok(input),
+    };
+    // --watch already knows from its polls which files are being written, a single run has to wait for them
+    let unsettled = match unsettled {
+        Some(unsettled) => unsettled.clone(),
+        None => find_unsettled_files(&config.input_directory, Duration::from_secs(settle_seconds))?,
+    };
+    // Albums being ripped are synced once all of their tracks are done
+    let unsettled_directories = if config.settle_directories == Some(true) {
+        unsettled
+            .iter()
+            .map(|file| Path::new(file).parent().unwrap_or(Path::new("")).to_path_buf())
+            .collect::<HashSet<PathBuf>>()
+    } else {
+        HashSet::new()
+    };
+    let settled = input
+        .into_iter()
+        .filter(|file| {
+            if unsettled.contains(file) {
+                warnings.push(
+                    WarningKind::IncompleteFile,
+                    file.clone(),
+                    Some(format!("size changed within {} seconds", settle_seconds)),
+                );
+                false
+            } else if unsettled_directories.contains(Path::new(file).parent().unwrap_or(Path::new(""))) {
+                warnings.push(
+                    WarningKind::IncompleteFile,
+                    file.clone(),
+                    Some(String::from("other files in the directory are still being written")),
+                );
+                false
+            } else {
+                true
+            }
+        })
+        .collect();
+    Ok(settled)
+}
+
+/// Sources which are copied instead of being encoded: lossy ones with skipLossyTranscode and the ones whose bitrate
+/// is below copyIfBitrateBelow
+///
+/// Bitrates are read from the properties of the sources, so they're only known for local inputs. Only the sources
+/// which weren't synced yet are read, the others are copied if their output was
+fn find_copied_sources(
+    config: &Config,
+    overrides: &Overrides,
+    input: &HashSet<String>,
+    encoded: &HashMap<String, String>,
+    warnings: &mut Warnings,
+) -> HashSet<String> {
+    let skip_lossy_transcode = config.skip_lossy_transcode == Some(true);
+    if !skip_lossy_transcode && config.copy_if_bitrate_below.is_none() {
+        return HashSet::new();
+    }
+    let mut copied_sources = HashSet::new();
+    // Sources to encode whose bitrate decides whether they're copied, with whether they're lossy transcodes
+    let mut candidates = Vec::new();
+    for file in input {
+        let file_config = overrides.config_for(config, file);
+        let extension = Path::new(file).extension().unwrap().to_string_lossy().to_string();
+        if !file_config.extensions_to_encode.contains(&extension) {
+            continue;
+        }
+        let lossy_transcode = skip_lossy_transcode
+            && is_lossy_source(file)
+            && encodes_lossy(&file_config.encoded_extension, &file_config.ffmpeg_params);
+        if lossy_transcode && config.skip_lossy_transcode_max_bitrate.is_none() {
+            copied_sources.insert(file.clone());
+        } else if lossy_transcode || config.copy_if_bitrate_below.is_some() {
+            match encoded.get(file) {
+                // Reading the bitrate of every source would read the headers of the whole library on every run
+                Some(output_file_name) => {
+                    let copied = Path::new(output_file_name).extension().is_some_and(|extension| {
+                        !extension.to_string_lossy().eq_ignore_ascii_case(&file_config.encoded_extension)
+                    });
+                    if copied {
+                        copied_sources.insert(file.clone());
+                    }
+                }
+                None => candidates.push((file, lossy_transcode)),
+            }
+        }
+    }
+    if config.input_directory.is_remote() {
+        if !candidates.is_empty() {
+            warnings.push(
+                WarningKind::UnknownBitrate,
+                config.input_directory.to_string(),
+                Some(String::from("bitrates can't be read from remotes")),
+            );
+        }
+        return copied_sources;
+    }
+    for (file, lossy_transcode) in candidates {
+        let audio_info = read_audio_info(Path::new(&config.input_directory.clone().path_string()).join(file));
+        let Some(bitrate) = audio_info.and_then(|audio_info| audio_info.audio_bitrate) else {
+            warnings.push(WarningKind::UnknownBitrate, file.clone(), None);
+            continue;
+        };
+        let low_bitrate = config.copy_if_bitrate_below.is_some_and(|copy_if_bitrate_below| bitrate < copy_if_bitrate_below);
+        let lossy_copy = lossy_transcode
+            && config.skip_lossy_transcode_max_bitrate.is_some_and(|max_bitrate| bitrate <= max_bitrate);
+        if low_bitrate || lossy_copy {
+            copied_sources.insert(file.clone());
+        }
+    }
+    copied_sources
+}
+
+/// The codec of the source and the params copying its audio when remuxWhenPossible is set and it already holds
+/// the codec the output is encoded to, see [remux_params]
+fn source_remux_params(config: &Config, input_file_path: &Path, output_file_name: &str) -> Option<(String, String)> {
+    if config.remux_when_possible != Some(true) {
+        return None;
+    }
+    let codec = read_audio_codec(config, input_file_path.to_path_buf())?;
+    let extension = Path::new(output_file_name).extension()?.to_string_lossy().to_string();
+    let params = remux_params(&codec, &extension, &config.ffmpeg_params)?;
+    Some((codec, params))
+}
+
+/// Program and arguments encodes start with: ffmpeg with the ffmpegPre params, run by nice when the niceness is set
+pub fn encode_command_line(config: &Config) -> Vec<String> {
+    let mut command_line = Vec::new();
+    if let Some(nice) = config.nice {
+        command_line.extend([String::from("nice"), String::from("-n"), nice.to_string()]);
+    }
+    command_line.push(programs::ffmpeg_path(config));
+    command_line.extend(
+        config.ffmpeg_pre
+            .iter()
+            .flat_map(|ffmpeg_pre| ffmpeg_pre.split(' '))
+            .filter(|param| !param.is_empty())
+            .map(String::from)
+    );
+    command_line
+}
+
+/// ffmpeg command for encodes, see [encode_command_line]
+///
+/// Windows has no nice, ffmpeg gets a lower priority class instead
+fn encode_command(config: &Config) -> Command {
+    let mut command_line = encode_command_line(config);
+    if cfg!(windows) && config.nice.is_some() {
+        command_line.drain(..3);
+    }
+    let mut command = Command::new(&command_line[0]);
+    command.args(&command_line[1..]);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const IDLE_PRIORITY_CLASS: u32 = 0x40;
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x4000;
+        if let Some(nice) = config.nice.filter(|nice| *nice > 0) {
+            command.creation_flags(if nice >= 10 { IDLE_PRIORITY_CLASS } else { BELOW_NORMAL_PRIORITY_CLASS });
+        }
+    }
+    command
+}
+
+/// Takes `new_per_cycle` new sources for every source of the backlog, until one of them runs out
+pub fn interleave(new: Vec<String>, backlog: Vec<String>, new_per_cycle: usize) -> Vec<String> {
+    let mut order = Vec::with_capacity(new.len() + backlog.len());
+    let mut new = new.into_iter().peekable();
+    let mut backlog = backlog.into_iter();
+    while new.peek().is_some() {
+        order.extend(new.by_ref().take(new_per_cycle));
+        match backlog.next() {
+            Some(input_file_name) => order.push(input_file_name),
+            None => break,
+        }
+    }
+    order.extend(new);
+    order.extend(backlog);
+    order
+}
+
+/// Copies files between two directories with up to `workers` copies running at once, returning the result of each
+///
+/// No more copies are started after Ctrl+C or once `deadline` passed, their results are `None`
+pub fn copy_in_parallel(
+    from: &RclonePath,
+    to: &RclonePath,
+    copies: &[(String, String)],
+    workers: usize,
+    deadline: Option<Instant>,
+) -> Vec<Option<io::Result<()>>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..copies.len()).map(|_| None).collect::<Vec<Option<io::Result<()>>>>());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                if interrupt::is_interrupted() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    break;
+                }
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some((input_file_name, output_file_name)) = copies.get(index) else {
+                    break;
+                };
+                let result = fs_wrapper::copy(
+                    &from.with_path(format!("{}/{}", from.clone().path_string(), input_file_name)),
+                    &to.with_path(format!("{}/{}", to.clone().path_string(), output_file_name)),
+                );
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap()
+}
+
+/// Whether ffmpeg runs on the input file, the other files are copied
+fn is_encoded(namer: &OutputNamer, input_file_name: &str) -> bool {
+    let file_extension = Path::new(input_file_name).extension().unwrap_or_default().to_string_lossy().to_string();
+    namer.file_config(input_file_name).extensions_to_encode.contains(&file_extension)
+}
+
+/// Copies the sources to encode from the remote input to the temp directory in the order they're encoded in, the
+/// bounded channel keeps the downloads at most a file ahead of the encoder
+///
+/// Sources in `leftovers` were downloaded by an interrupted run and aren't copied again. Stops when `stop` is set or
+/// the encoder is gone, a source nobody is going to encode is removed again
+pub fn download_sources(
+    config: &Config,
+    temp_directory: &str,
+    sources: Vec<String>,
+    leftovers: &HashSet<String>,
+    stop: &AtomicBool,
+    downloaded: SyncSender<(String, io::Result<PathBuf>)>,
+) {
+    for input_file_name in sources {
+        if stop.load(Ordering::SeqCst) || interrupt::is_interrupted() {
+            break;
+        }
+        let source_path = PathBuf::from(format!("{}/{}", temp_directory, input_file_name));
+        // Downloaded completely by an interrupted run
+        if leftovers.contains(&input_file_name) {
+            if let Err(mpsc::SendError((_, Ok(source_path)))) = downloaded.send((input_file_name, Ok(source_path))) {
+                let _ = fs_wrapper::remove_file(&RclonePath::Local(source_path.to_string_lossy().to_string()));
+                break;
+            }
+            continue;
+        }
+        let download = fs_wrapper::copy(
+            &config.input_directory.with_path(format!(
+                "{}/{}",
+                config.input_directory.clone().path_string(),
+                input_file_name
+            )),
+            &RclonePath::Local(source_path.to_string_lossy().to_string()),
+        )
+        .map(|()| source_path);
+        if let Err(mpsc::SendError((_, Ok(source_path)))) = downloaded.send((input_file_name, download)) {
+            let _ = fs_wrapper::remove_file(&RclonePath::Local(source_path.to_string_lossy().to_string()));
+            break;
+        }
+    }
+}
+
+/// Moves the encoded outputs from the temp directory to the remote output while the next files are encoded,
+/// reporting the result of every upload
+pub fn upload_outputs(
+    config: &Config,
+    temp_directory: &str,
+    uploads: Receiver<(String, String)>,
+    uploaded: Sender<(String, String, io::Result<()>)>,
+) {
+    for (input_file_name, output_file_name) in uploads {
+        let result = fs_wrapper::rename(
+            &RclonePath::Local(format!("{}/{}", temp_directory, output_file_name)),
+            &config.output_directory.with_path(format!(
+                "{}/{}",
+                config.output_directory.clone().path_string(),
+                output_file_name
+            )),
+        );
+        if uploaded.send((input_file_name, output_file_name, result)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Hash the content store names the object of an output by, taken while the output is a local file
+pub(crate) fn content_hash(config: &Config, path: &Path) -> Option<FileHash> {
+    config.content_store.as_ref()?;
+    hash_local_file(path).ok()
+}
+
+/// Decodes the whole output like `ffmpeg -v error -i output -f null -` and compares its duration to the source, so a
+/// corrupt or truncated output is treated like a failed encode
+///
+/// The durations are only compared when the ffmpeg params don't filter or cut the audio, which changes its length
+fn verify_encode(config: &Config, source: &Path, output: &Path, ffmpeg_params: &str) -> Result<(), String> {
+    match decode_error(config, output) {
+        Ok(Some(error)) => return Err(format!("The output doesn't decode cleanly: {}", error)),
+        Ok(None) => {}
+        Err(error) => return Err(error.to_string()),
+    }
+    if changes_length(ffmpeg_params) {
+        return Ok(());
+    }
+    match (read_duration(source.to_path_buf()), read_duration(output.to_path_buf())) {
+        (Some(source), Some(output)) if !durations_match(source, output) => Err(format!(
+            "The output is {} long instead of {}",
+            format_duration(output),
+            format_duration(source)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Decodes the whole file like `ffmpeg -v error -i file -f null -`, returning the first error ffmpeg reports
+///
+/// Fails if the ffmpeg of the config can't be run, that says nothing about the file
+pub fn decode_error(config: &Config, path: &Path) -> io::Result<Option<String>> {
+    let decoded = programs::ffmpeg(config)
+        .args(["-nostdin", "-v", "error", "-i"])
+        .arg(path)
+        .args(["-f", "null", "-"])
+        .output()
+        .map_err(|error| io::Error::new(error.kind(), format!("Failed to execute ffmpeg: {}", error)))?;
+    let errors = String::from_utf8_lossy(&decoded.stderr);
+    Ok(match errors.lines().map(str::trim).find(|line| !line.is_empty()) {
+        Some(error) => Some(error.to_string()),
+        None if !decoded.status.success() => Some(format!("ffmpeg {}", decoded.status)),
+        None => None,
+    })
+}
+
+/// Whether an output left by an interrupted run is a complete encode of the source, it has to be as long as the
+/// source unless the ffmpeg params change the length, any non-empty file is accepted then
+pub fn is_complete_encode(source: &Path, output: &Path, ffmpeg_params: &str) -> bool {
+    if !output.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.len() > 0) {
+        return false;
+    }
+    // There's no length to compare to
+    if changes_length(ffmpeg_params) {
+        return true;
+    }
+    match (read_duration(source.to_path_buf()), read_duration(output.to_path_buf())) {
+        (Some(source), Some(output)) => durations_match(source, output),
+        _ => false,
+    }
+}
+
+/// Whether ffmpeg params filter or cut the audio, like the atempo filter of speed or -t
+pub fn changes_length(ffmpeg_params: &str) -> bool {
+    ffmpeg_params
+        .split(' ')
+        .any(|param| matches!(param, "-af" | "-filter:a" | "-filter_complex" | "-t" | "-ss" | "-to" | "-sseof"))
+}
+
+/// Whether the duration of an output matches its source, allowing a second or a percent for codec padding
+pub fn durations_match(source: Duration, output: Duration) -> bool {
+    let tolerance = Duration::from_secs(1).max(source / 100);
+    source.abs_diff(output) <= tolerance
+}
+
+/// Splits a relative path of an output into its directory and file name, the directory is empty for top-level files
+pub fn split_directory(file_name: &str) -> (&str, &str) {
+    file_name.rsplit_once('/').unwrap_or(("", file_name))
+}
+
+/// Files of a batch which are gone from the directory after rclone failed partway through it, rclone doesn't tell
+/// which ones it got to. They're recorded like the rest of the batch so the state files still match the output
+///
+/// If the directory can't be listed either, none of them count as gone and the next run finds out
+pub fn gone_after_failure(directory: &RclonePath, files: Vec<String>) -> HashSet<String> {
+    let Ok(remaining) = fs_wrapper::list_file_sizes(directory) else {
+        return HashSet::new();
+    };
+    files.into_iter().filter(|file| !remaining.contains_key(file)).collect()
+}
+
+/// Name of a file in the output directory given like exportPlaylist, as the output listing names it
+fn normalize_output_file_name(path: &str) -> String {
+    Path::new(path)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Finds the sources which are too old to be kept by the retention rules of the config
+///
+/// Every directory is counted on its own, so each podcast keeps its own newest episodes
+fn find_pruned(config: &Config, input: &HashSet<String>) -> io::Result<HashSet<String>> {
+    let mut pruned = HashSet::new();
+    let rules = match &config.retention {
+        Some(rules) if !rules.is_empty() => rules,
+        _ => return Ok(pruned),
+    };
+    let modification_times = fs_wrapper::list_modification_times(&config.input_directory)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    for rule in rules {
+        let mut directories: HashMap<&Path, Vec<(u64, &String)>> = HashMap::new();
+        for file in input {
+            if rule.globs.iter().any(|glob| glob.matches(file)) {
+                let modification_time = modification_times.get(file).copied().unwrap_or_default();
+                directories
+                    .entry(Path::new(file).parent().unwrap_or_else(|| Path::new("")))
+                    .or_default()
+                    .push((modification_time, file));
+            }
+        }
+        for mut files in directories.into_values() {
+            // Newest first, files with the same time are ordered by name so episode numbers still count
+            files.sort_by(|a, b| b.cmp(a));
+            for (index, (modification_time, file)) in files.into_iter().enumerate() {
+                let too_many = rule.keep_last.is_some_and(|keep_last| index >= keep_last);
+                let too_old = rule
+                    .keep_days
+                    .is_some_and(|keep_days| {
+                        modification_time.saturating_add(keep_days.saturating_mul(86400)) < now
+                    });
+                if too_many || too_old {
+                    pruned.insert(file.clone());
+                }
+            }
+        }
+    }
+    Ok(pruned)
+}
+
+/// Lists the file sizes twice, the given time apart, and returns the files whose size changed in between
+fn find_unsettled_files(directory: &RclonePath, settle_time: Duration) -> io::Result<HashSet<String>> {
+    eprintln!("Waiting {} seconds for files being written", settle_time.as_secs());
+    let sizes = fs_wrapper::list_file_sizes(directory)?;
+    thread::sleep(settle_time);
+    let new_sizes = fs_wrapper::list_file_sizes(directory)?;
+    Ok(
+        sizes
+            .keys()
+            .chain(new_sizes.keys())
+            .filter(|file| sizes.get(*file) != new_sizes.get(*file))
+            .cloned()
+            .collect()
+    )
+}
@@ -0,0 +1,738 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use console::Term;
+use dialoguer::Confirm;
+use serde::de::DeserializeOwned;
+
+use ffmusicsync::albums::update_albums;
+use ffmusicsync::config::Config;
+use ffmusicsync::content_store::update_store;
+use ffmusicsync::cost::{format_cost, CostEstimate};
+use ffmusicsync::device;
+use ffmusicsync::events::{Event, EventSink};
+use ffmusicsync::failures::FailedFiles;
+use ffmusicsync::format::{format_count, format_duration, format_size};
+use ffmusicsync::fs_wrapper;
+use ffmusicsync::fs_wrapper::RclonePath;
+use ffmusicsync::hashing::FileHash;
+use ffmusicsync::health::Severity;
+use ffmusicsync::interrupt;
+use ffmusicsync::interrupt::INTERRUPTED_EXIT_CODE;
+use ffmusicsync::manifest::Manifest;
+use ffmusicsync::mirrors::MirrorState;
+use ffmusicsync::output_info::OutputInfo;
+use ffmusicsync::plan::{ActionKind, ActionReason, ExportedPlan};
+use ffmusicsync::programs;
+use ffmusicsync::redact;
+use ffmusicsync::state::save_json;
+use ffmusicsync::theme;
+use ffmusicsync::tr;
+use ffmusicsync::warnings::{WarningKind, Warnings};
+use ffmusicsync::{ExecutedActions, Executor, SyncPlan, SyncPlanner};
+
+use crate::{Args, Commands};
+
+/// Files the state of an output is read from and saved to
+pub struct StateFiles {
+    pub encoded: String,
+    pub failed: String,
+    pub hashes: String,
+    pub params: String,
+    pub mirrors: String,
+    pub sizes: String,
+    pub export_plan: Option<String>,
+    pub export_script: Option<String>,
+    pub export_report: Option<String>,
+    pub apply_plan: Option<String>,
+}
+
+/// What syncing one output ended with
+#[derive(Default)]
+pub struct OutputSummary {
+    /// Files which failed, or the files with severe problems when verifying
+    pub failed: usize,
+    /// Whether the plan had any action, which is left for a real run with --dry-run
+    pub pending: bool,
+    /// Set when the run has to stop, like when the prompt was declined, the process exits with it once the input
+    /// snapshot, rclone rcd and the locks are cleaned up
+    pub exit_code: Option<i32>,
+}
+
+impl OutputSummary {
+    fn exit(exit_code: i32) -> Self {
+        OutputSummary {
+            exit_code: Some(exit_code),
+            ..OutputSummary::default()
+        }
+    }
+}
+
+/// Plans and executes the sync to one output, returns how many files failed to encode
+/// or had severe problems with the verify command
+pub fn sync_output(
+    args: &Args,
+    mut config: Config,
+    state_files: &StateFiles,
+    input: Option<HashSet<String>>,
+    deadline: Option<Instant>,
+    events: &EventSink,
+    warnings: &mut Warnings,
+) -> Result<OutputSummary, Box<dyn std::error::Error>> {
+    // Styles used
+    let error_style = theme::error();
+    let success_style = theme::success();
+    let io_stats_before = fs_wrapper::io_stats();
+
+    // Find where the output device is mounted
+    if let Some(output_device) = &config.output_device {
+        let mount_point = loop {
+            match device::find_mount_point(output_device)? {
+                Some(mount_point) => break mount_point,
+                None if args.wait_for_device => {
+                    println!("{}", tr!("waiting-for-device"));
+                    std::thread::sleep(Duration::from_secs(5));
+                }
+                None => {
+                    eprintln!("{}", error_style.apply_to(tr!("device-not-found")));
+                    return Ok(OutputSummary::exit(1));
+                }
+            }
+        };
+        config.output_directory = RclonePath::Local(
+            Path::new(&mount_point)
+                .join(config.output_directory.clone().path_string())
+                .to_string_lossy()
+                .to_string()
+        );
+    }
+
+    // Fail if rclone is not found and should be used
+    if config.input_directory.is_remote() || config.output_directory.is_remote() {
+        let rclone_test = programs::rclone().arg("version").output();
+        if rclone_test.is_err() {
+            eprintln!("{}", error_style.apply_to(tr!("program-not-found", program = programs::rclone_path())));
+            return Ok(OutputSummary::exit(1));
+        }
+    }
+
+    // Read already processed songs
+    let encoded: HashMap<String, String> = read_state_file(&state_files.encoded)?;
+    let previous_failures = FailedFiles::load(&state_files.failed)?;
+    let hashes: HashMap<String, FileHash> = read_state_file(&state_files.hashes)?;
+    let params: HashMap<String, String> = read_state_file(&state_files.params)?;
+    let output_info: HashMap<String, OutputInfo> = read_state_file(&state_files.sizes)?;
+
+    if args.retry_failed && previous_failures.files.is_empty() {
+        println!("{}", success_style.apply_to(tr!("no-failed-files")));
+        return Ok(OutputSummary::default());
+    }
+    let mut planner = SyncPlanner::new(config, encoded)
+        .with_failures(previous_failures)
+        .with_hashes(hashes)
+        .with_params(params)
+        .with_output_info(output_info)
+        .retry_failed(args.retry_failed)
+        .only(args.only.clone());
+    if let Some(input) = input {
+        planner = planner.with_input(input);
+    }
+    let mut plan = planner.plan(warnings)?;
+    let config = plan.config().clone();
+
+    if let Some(Commands::Health) = &args.command {
+        plan.health_report().print(plan.input.len());
+        return Ok(OutputSummary::default());
+    }
+    if let Some(Commands::Verify { .. }) = &args.command {
+        return verify_output(args, plan, state_files, events, warnings);
+    }
+
+    // Check for name collisions
+    if !plan.collisions.is_empty() {
+        eprintln!("{}", error_style.apply_to(tr!("name-collision")));
+        for (output_file_name, input_file_names) in &plan.collisions {
+            eprintln!("{}", tr!("collision-sources", output = redact::path(output_file_name)));
+            input_file_names.iter().for_each(|file_name| {
+                eprintln!(" - {}", redact::path(file_name));
+            });
+            eprintln!();
+        }
+        return Ok(OutputSummary::exit(2));
+    }
+
+    if let Some(Commands::Status) = &args.command {
+        let exported = plan.export();
+        let exported = if redact::is_enabled() { exported.redacted() } else { exported };
+        println!("{}", serde_json::to_string(&exported)?);
+        return Ok(OutputSummary {
+            pending: plan.is_pending(),
+            ..OutputSummary::default()
+        });
+    }
+    // Only orphaned outputs are deleted when cleaning
+    let cleaning = matches!(args.command, Some(Commands::Clean));
+    if cleaning {
+        plan.keep_orphans();
+    }
+    // Refreshing tags or covers leaves everything else for a sync
+    let refreshing = args.refresh_covers || args.refresh_tags;
+    if refreshing && !cleaning {
+        plan.refresh_only();
+    }
+    let pending = plan.is_pending();
+
+    // Do exactly what was reviewed before
+    if let Some(apply_plan) = &state_files.apply_plan {
+        let exported_plan = ExportedPlan::load(apply_plan)?;
+        if let Err(error) = plan.use_exported(&exported_plan) {
+            eprintln!("{}", error_style.apply_to(format!("Can't apply {}: {}", apply_plan, error)));
+            return Ok(OutputSummary::exit(1));
+        }
+    }
+    if args.edit_plan {
+        if let Err(error) = plan.export().edit(|edited_plan| plan.use_exported(edited_plan)) {
+            eprintln!("{}", error_style.apply_to(format!("Can't use the edited plan: {}", error)));
+            return Ok(OutputSummary::exit(1));
+        }
+    }
+
+    // Files an interrupted run left in the temp directory, only looked for when the plan is actually run
+    if !cleaning && !refreshing && !args.dry_run {
+        plan.find_leftovers();
+    }
+
+    // Fail if ffmpeg is not found
+    if !plan.mirror_mode && !cleaning && !refreshing {
+        let ffmpeg_test = programs::ffmpeg(&config).arg("-version").output();
+        if ffmpeg_test.is_err() {
+            eprintln!("{}", error_style.apply_to(tr!("program-not-found", program = programs::ffmpeg_path(&config))));
+            return Ok(OutputSummary::exit(1));
+        }
+    }
+
+    // Fail before hundreds of encodes fail one by one with the same invalid params
+    if !plan.mirror_mode && !cleaning && !refreshing && !args.no_encode && !args.no_test_encode {
+        if let Err((ffmpeg_params, error)) = plan.probe_encodes() {
+            eprintln!(
+                "{}",
+                error_style.apply_to(tr!("test-encode-failed", params = ffmpeg_params))
+            );
+            eprintln!("{}", error);
+            return Ok(OutputSummary::exit(1));
+        }
+    }
+
+    // Export the plan for review and auditing tools
+    let exported_plan = if state_files.export_plan.is_some() || state_files.export_report.is_some() {
+        let exported_plan = plan.export();
+        if let Some(export_plan) = &state_files.export_plan {
+            exported_plan.save(export_plan)?;
+        }
+        Some(exported_plan)
+    } else {
+        None
+    };
+    if let Some(export_script) = &state_files.export_script {
+        std::fs::write(export_script, plan.export_script())?;
+    }
+
+    // Ask user whether to continue
+    println!(
+        "{}",
+        success_style.apply_to(tr!(
+            "plan-summary",
+            process = plan.to_process.len(),
+            rename = plan.to_rename.len(),
+            delete = plan.to_delete.len()
+        ))
+    );
+    // Show why, so a misconfiguration is noticed before confirming
+    let process_reasons = plan.to_process.iter().map(|input_file_name| plan.process_reason(input_file_name));
+    let rename_reasons = plan.to_rename.iter().map(|(old_name, new_name)| plan.rename_reason(old_name, new_name));
+    let delete_reasons = plan.to_delete.iter().map(|output_file_name| plan.delete_reason(output_file_name));
+    for (action, reasons) in [
+        (tr!("action-process"), format_reasons(process_reasons)),
+        (tr!("action-rename"), format_reasons(rename_reasons)),
+        (tr!("action-delete"), format_reasons(delete_reasons)),
+    ] {
+        if !reasons.is_empty() {
+            println!("  {}: {}", action, reasons);
+        }
+    }
+
+    if !plan.remuxable.is_empty() {
+        println!("{}", tr!("remuxable-outputs", count = plan.remuxable.len()));
+    }
+
+    // A wrong input directory would delete the whole output
+    if let Some(exceeded) = plan.exceeded_delete_limit() {
+        let message = tr!(
+            "delete-limit-exceeded",
+            delete = plan.to_delete.len(),
+            output = plan.output.len(),
+            reason = exceeded
+        );
+        if args.force_delete {
+            eprintln!("{}", theme::warning().apply_to(tr!("delete-limit-forced", message = message)));
+        } else if args.dry_run {
+            eprintln!("{}", theme::warning().apply_to(tr!("delete-limit-dry-run", message = message)));
+        } else {
+            eprintln!("{}", error_style.apply_to(tr!("delete-limit-aborting", message = message)));
+            eprintln!("{}", tr!("delete-limit-hint"));
+            return Ok(OutputSummary::exit(3));
+        }
+    }
+
+    events.emit(Event::Plan {
+        to_process: plan.to_process.len(),
+        to_rename: plan.to_rename.len(),
+        to_delete: plan.to_delete.len(),
+    });
+
+    // Estimate the cost of the run on paid remotes
+    let cost_estimate = config.pricing.as_ref().map(|pricing| {
+        let cost_estimate = plan.estimate_cost();
+        let (total, remotes) = cost_estimate.cost(pricing);
+        println!("{}", tr!("estimated-cost", cost = format_cost(total, &remotes)));
+        cost_estimate
+    });
+
+    let confirm_each = args.confirm_each
+        || args.yes_encode
+        || args.yes_rename
+        || args.yes_delete
+        || args.no_encode
+        || args.no_rename
+        || args.no_delete;
+    let mut skipped_kinds = Vec::new();
+    if confirm_each {
+        // Skipped categories are left out of the plan, their files are handled by the next run
+        if !confirm_category(&tr!("action-process"), plan.to_process.len(), args.yes || args.yes_encode, args.no_encode)? {
+            plan.to_process.clear();
+            skipped_kinds.extend([ActionKind::Encode, ActionKind::Copy, ActionKind::CreateDirectory]);
+        }
+        if !confirm_category(&tr!("action-rename"), plan.to_rename.len(), args.yes || args.yes_rename, args.no_rename)? {
+            plan.to_rename.clear();
+            skipped_kinds.push(ActionKind::Rename);
+        }
+        if !confirm_category(&tr!("action-delete"), plan.to_delete.len(), args.yes || args.yes_delete, args.no_delete)? {
+            plan.to_delete.clear();
+            skipped_kinds.push(ActionKind::Delete);
+        }
+    } else if !args.yes
+        && !Confirm::new()
+            .with_prompt(tr!("prompt-continue"))
+            .interact()?
+    {
+        println!("{}", tr!("aborting"));
+        return Ok(OutputSummary::exit(3));
+    }
+
+    if cleaning {
+        for temp_file in plan.stale_temp_files(warnings) {
+            if args.dry_run {
+                eprintln!("Skipping removal of {} as --dry-run is set", redact::path(&temp_file.to_string()));
+            } else {
+                println!("Removing {}", redact::path(&temp_file.to_string()));
+                fs_wrapper::remove_file(&temp_file)?;
+            }
+        }
+        for trash_run in plan.expired_trash(warnings) {
+            if args.dry_run {
+                eprintln!("Skipping removal of {} as --dry-run is set", redact::path(&trash_run.to_string()));
+            } else {
+                println!("Removing {}", redact::path(&trash_run.to_string()));
+                fs_wrapper::remove_dir_all(&trash_run)?;
+            }
+        }
+    }
+
+    let mut executor = Executor::new(events);
+    executor.dry_run = args.dry_run;
+    // ffmpeg prints the paths of the files
+    executor.quiet = args.quiet || args.redact;
+    executor.refresh_covers = args.refresh_covers;
+    executor.refresh_tags = args.refresh_tags;
+    // Progress bars only make sense in a terminal, logs are easier to read without them
+    executor.progress = !args.no_progress && Term::stderr().is_term();
+    executor.deadline = deadline;
+    let checkpoint = |executed: &ExecutedActions| save_checkpoint(state_files, &plan, executed);
+    if !args.dry_run {
+        executor.checkpoint = Some(&checkpoint);
+        executor.checkpoint_interval = config.state_save_interval.unwrap_or(20);
+    }
+    let started = Instant::now();
+    let mut executed = match executor.execute(&plan, warnings) {
+        Ok(executed) => executed,
+        Err(interrupted) => {
+            // Keep what was done, so the next run doesn't start over
+            eprintln!("{}", error_style.apply_to(tr!("sync-interrupted", error = interrupted.error.to_string())));
+            if !args.dry_run {
+                save_state(args, state_files, &plan, &interrupted.executed)?;
+                // Pushed by the next run
+                update_mirrors(state_files, &config, &interrupted.executed, false, warnings)?;
+            }
+            if interrupt::is_interrupted() {
+                return Ok(OutputSummary::exit(INTERRUPTED_EXIT_CODE));
+            }
+            return Err(interrupted.error);
+        }
+    };
+
+    // Save info about processed files to a JSON
+    println!(
+        "{}",
+        success_style.apply_to(tr!("done-processing", duration = format_duration(started.elapsed())))
+    );
+    if executed.out_of_time {
+        let done = executed.processed.len() + executed.failed.len() + executed.renamed.len() + executed.deleted.len();
+        println!("{}", tr!("out-of-time-actions", count = plan.total_actions().saturating_sub(done)));
+    }
+    let io_stats = fs_wrapper::io_stats().since(io_stats_before);
+    println!(
+        "{}",
+        tr!(
+            "transferred",
+            downloaded = format_size(io_stats.downloaded),
+            uploaded = format_size(io_stats.uploaded),
+            written = format_size(io_stats.written)
+        )
+    );
+    if let (Some(pricing), Some(cost_estimate)) = (&config.pricing, cost_estimate) {
+        // Replace the estimated traffic with the measured one
+        let mut cost = CostEstimate {
+            operations: cost_estimate.operations,
+            ..Default::default()
+        };
+        if let Some(remote) = config.input_directory.remote() {
+            cost.add_egress(remote, io_stats.downloaded);
+        }
+        if let Some(remote) = config.output_directory.remote() {
+            cost.add_ingress(remote, io_stats.uploaded);
+        }
+        let (total, remotes) = cost.cost(pricing);
+        println!("{}", tr!("cost", cost = format_cost(total, &remotes)));
+    }
+    if let (Some(export_report), Some(mut exported_plan)) = (&state_files.export_report, exported_plan) {
+        exported_plan.actions.retain(|action| !skipped_kinds.contains(&action.kind));
+        exported_plan.set_statuses(&executed.failed, args.dry_run);
+        exported_plan.mark_slow(&executed.slow);
+        exported_plan.save(export_report)?;
+    }
+    let failed = executed.failed.len();
+    // Before the state is saved, it records the outputs with their new tags
+    if !args.dry_run {
+        update_albums(&plan, &mut executed, warnings);
+    }
+    if args.dry_run {
+        eprintln!("Skipping save to JSON as --dry-run is set");
+    } else {
+        save_state(args, state_files, &plan, &executed)?;
+    }
+
+    // Write the playlist of the whole output, for players which only play playlists
+    if let (Some(playlist), Some(contents)) = (&config.export_playlist, plan.exported_playlist(&executed)) {
+        write_playlist(args, &config, playlist, &contents)?;
+    }
+
+    // Push the changes to the mirrors once the output has them
+    if config.mirrors.as_ref().is_some_and(|mirrors| !mirrors.is_empty()) {
+        if args.dry_run {
+            eprintln!("Skipping push to mirrors as --dry-run is set");
+        } else {
+            update_mirrors(state_files, &config, &executed, true, warnings)?;
+        }
+    }
+
+    if let Some(content_store) = &config.content_store {
+        update_content_store(args, &config, content_store, &plan, &executed, warnings);
+    }
+    flush_output(args, &config)?;
+
+    Ok(OutputSummary {
+        failed,
+        pending,
+        exit_code: None,
+    })
+}
+
+/// Checks the output against the state, deleting the broken outputs with --requeue so the next sync processes them
+fn verify_output(
+    args: &Args,
+    mut plan: SyncPlan,
+    state_files: &StateFiles,
+    events: &EventSink,
+    warnings: &mut Warnings,
+) -> Result<OutputSummary, Box<dyn std::error::Error>> {
+    let (shallow, decode, requeue) = match args.command {
+        Some(Commands::Verify { shallow, decode, requeue }) => (shallow, decode, requeue),
+        _ => (false, false, false),
+    };
+    // Otherwise every output would look undecodable
+    if decode && programs::ffmpeg(plan.config()).arg("-version").output().is_err() {
+        eprintln!("{}", theme::error().apply_to(format!("{} not found", programs::ffmpeg_path(plan.config()))));
+        return Ok(OutputSummary::exit(1));
+    }
+    let mut report = plan.verify(shallow, decode)?;
+    if report.categories.is_empty() {
+        println!("{}", theme::success().apply_to("The output matches the state"));
+    } else {
+        report.print_problems();
+    }
+    let severe_files = report
+        .categories
+        .iter()
+        .filter(|category| category.severity == Severity::High)
+        .map(|category| category.files.len())
+        .sum();
+    let summary = OutputSummary {
+        failed: severe_files,
+        ..OutputSummary::default()
+    };
+    if !requeue || report.broken.is_empty() {
+        return Ok(summary);
+    }
+    if args.dry_run {
+        eprintln!("Skipping deletion of {} broken outputs as --dry-run is set", format_count(report.broken.len()));
+        return Ok(summary);
+    }
+
+    // Deleted like by a sync, so a wrong verify can't empty the output
+    plan.requeue(&report.broken);
+    if let Some(exceeded) = plan.exceeded_delete_limit() {
+        let message = format!(
+            "Found {} broken outputs of {}, {}",
+            format_count(plan.to_delete.len()),
+            format_count(plan.output.len()),
+            exceeded
+        );
+        if args.force_delete {
+            eprintln!("{}", theme::warning().apply_to(format!("{}, continuing as --force-delete is set", message)));
+        } else {
+            eprintln!("{}", theme::error().apply_to(format!("{}, not deleting them", message)));
+            eprintln!("Check the outputs, or run with --force-delete if the deletions are intended");
+            return Ok(OutputSummary::exit(3));
+        }
+    }
+    println!("Deleting {} broken outputs, the next sync processes them again", format_count(report.broken.len()));
+    let mut executor = Executor::new(events);
+    executor.quiet = args.quiet || args.redact;
+    executor.progress = !args.no_progress && Term::stderr().is_term();
+    let executed = match executor.execute(&plan, warnings) {
+        Ok(executed) => executed,
+        Err(interrupted) => {
+            save_json(&state_files.encoded, &plan.requeued_encoded(&interrupted.executed), false)?;
+            return Err(interrupted.error);
+        }
+    };
+    save_json(&state_files.encoded, &plan.requeued_encoded(&executed), false)?;
+    Ok(summary)
+}
+
+/// Writes the playlist of the whole output, for players which only play playlists
+fn write_playlist(args: &Args, config: &Config, playlist: &str, contents: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if args.dry_run {
+        eprintln!("Skipping export of playlist {} as --dry-run is set", redact::path(playlist));
+    } else {
+        println!("Writing playlist {}", redact::path(playlist));
+        let playlist_file = config.output_directory.with_path(
+            format!("{}/{}", config.output_directory.clone().path_string(), playlist)
+        );
+        if config.output_directory.is_remote() {
+            // The temp directory was removed at the end of the run if it was empty
+            let temp_directory = config.temp_directory.clone().unwrap_or(String::from("temp"));
+            fs_wrapper::create_dir_all(&RclonePath::Local(temp_directory.clone()))?;
+            let file_name = Path::new(playlist).file_name().unwrap_or_default().to_string_lossy().to_string();
+            let temp_file = format!("{}/{}", temp_directory, file_name);
+            std::fs::write(&temp_file, contents)?;
+            fs_wrapper::rename(&RclonePath::Local(temp_file), &playlist_file)?;
+            fs_wrapper::remove_empty_dirs(&RclonePath::Local(temp_directory))?;
+        } else {
+            let playlist_path = playlist_file.path_string();
+            if let Some(parent) = Path::new(&playlist_path).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(playlist_path, contents)?;
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the content-addressed copy of the output up to date
+fn update_content_store(
+    args: &Args,
+    config: &Config,
+    content_store: &RclonePath,
+    plan: &SyncPlan,
+    executed: &ExecutedActions,
+    warnings: &mut Warnings,
+) {
+    if args.dry_run {
+        eprintln!("Skipping update of the content store as --dry-run is set");
+    } else {
+        println!("Updating content store {}", redact::path(&content_store.to_string()));
+        let output_files = plan.final_encoded(executed).into_values().collect::<BTreeSet<String>>();
+        // The store is brought up to date by the next run as well, the output itself is synced
+        match update_store(content_store, &config.output_directory, &output_files, executed) {
+            Ok(update) => {
+                println!(
+                    "Uploaded {} new objects, removed {} unused ones",
+                    format_count(update.uploaded),
+                    format_count(update.removed)
+                );
+                for output_file_name in update.unreadable {
+                    warnings.push(WarningKind::ContentStoreFailed, output_file_name, Some(String::from("couldn't be hashed")));
+                }
+            }
+            Err(error) => warnings.push(WarningKind::ContentStoreFailed, content_store.to_string(), Some(error.to_string())),
+        }
+    }
+}
+
+/// Makes sure removable media can be unplugged safely
+fn flush_output(args: &Args, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    if !config.output_directory.is_remote()
+        && (config.flush_output == Some(true) || config.eject_output == Some(true))
+    {
+        let output_directory = config.output_directory.clone().path_string();
+        if args.dry_run {
+            eprintln!("Skipping flush of output directory as --dry-run is set");
+        } else {
+            println!("Flushing output directory");
+            device::flush(&output_directory)?;
+            if config.eject_output == Some(true) {
+                println!("Ejecting output device");
+                device::eject(&output_directory)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a state file, which is empty when it doesn't exist yet like before the first run
+fn read_state_file<T: DeserializeOwned + Default>(path: &str) -> Result<T, Box<dyn std::error::Error>> {
+    match File::open(path) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        Err(_) => Ok(T::default()),
+    }
+}
+
+/// Counts the reasons of the actions of a category, the most common first, like "2 new source, 1 source removed"
+fn format_reasons(reasons: impl Iterator<Item = ActionReason>) -> String {
+    let mut counts: Vec<(ActionReason, usize)> = Vec::new();
+    for reason in reasons {
+        match counts.iter_mut().find(|(counted_reason, _)| *counted_reason == reason) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((reason, 1)),
+        }
+    }
+    counts.sort_by_key(|(reason, count)| (std::cmp::Reverse(*count), reason.description()));
+    counts
+        .iter()
+        .map(|(reason, count)| format!("{} {}", format_count(*count), reason.description()))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Asks whether to do the actions of a category, unless the answer was given on the command line
+fn confirm_category(action: &str, count: usize, yes: bool, no: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    if count == 0 {
+        return Ok(true);
+    }
+    if no {
+        println!("{}", tr!("skipping-category", action = action, count = count));
+        return Ok(false);
+    }
+    if yes {
+        return Ok(true);
+    }
+    Ok(Confirm::new().with_prompt(tr!("prompt-category", action = action, count = count)).interact()?)
+}
+
+/// Adds the changes of the output to the ones pending for its mirrors and pushes them if `push` is set
+fn update_mirrors(
+    state_files: &StateFiles,
+    config: &Config,
+    executed: &ExecutedActions,
+    push: bool,
+    warnings: &mut Warnings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mirrors = match &config.mirrors {
+        Some(mirrors) if !mirrors.is_empty() => mirrors,
+        _ => return Ok(()),
+    };
+    let mut mirror_state = MirrorState::load(&state_files.mirrors)?;
+    mirror_state.record(mirrors, executed);
+    // Saved first, so the changes aren't lost if pushing them is interrupted
+    mirror_state.save(&state_files.mirrors)?;
+    if push {
+        let failed = mirror_state.push(config, warnings);
+        if failed > 0 {
+            eprintln!(
+                "{}",
+                theme::warning().apply_to(format!("{} changes could not be pushed to mirrors, retrying them next run", format_count(failed)))
+            );
+        }
+        mirror_state.save(&state_files.mirrors)?;
+    }
+    Ok(())
+}
+
+/// Saves the encoded and failed files while the plan is executed, so a killed run doesn't start over
+///
+/// The rest of the state is only saved at the end, hashing every processed source again at each checkpoint would
+/// make long runs quadratic. Sources without a hash are hashed by the next run, without params they get the current
+/// ones
+fn save_checkpoint(
+    state_files: &StateFiles,
+    plan: &SyncPlan,
+    executed: &ExecutedActions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    save_json(&state_files.encoded, &plan.final_encoded(executed), false)?;
+    save_failures(state_files, plan, executed)
+}
+
+/// Saves the state of the output after the plan was executed, for the next run
+fn save_state(
+    args: &Args,
+    state_files: &StateFiles,
+    plan: &SyncPlan,
+    executed: &ExecutedActions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = plan.config();
+    let encoded = plan.final_encoded(executed);
+    save_json(&state_files.encoded, &encoded, false)?;
+    if let Some(manifest_path) = &args.manifest {
+        let mut manifest = Manifest::load(manifest_path)?;
+        manifest.update_target(
+            &config.source_directory().to_string(),
+            &config.output_directory.to_string(),
+            &encoded,
+        );
+        manifest.save(manifest_path)?;
+    }
+    if config.detect_changes == Some(true) {
+        let hashes = plan.final_hashes(executed);
+        save_json(&state_files.hashes, &hashes, false)?;
+    }
+    if config.reencode_on_param_change == Some(true) {
+        let params = plan.final_params(executed);
+        save_json(&state_files.params, &params, false)?;
+    }
+    let output_info = plan.final_output_info(executed);
+    save_json(&state_files.sizes, &output_info, false)?;
+    save_failures(state_files, plan, executed)
+}
+
+fn save_failures(
+    state_files: &StateFiles,
+    plan: &SyncPlan,
+    executed: &ExecutedActions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    plan.final_failures(executed).save(&state_files.failed)?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use lofty::{Accessor, AudioFile, ItemKey, Probe, Tag, TagExt, TaggedFile};
+
+use crate::config::Config;
+use crate::programs;
+
+/// Replaces the tag items of the output with the ones from the input, keeping the output pictures
+///
+/// Items which can't be represented in the output tag format are skipped.
+/// Returns whether the output file was modified - it's left untouched if the tags already match
+pub fn refresh_tags(input: PathBuf, output: PathBuf) -> lofty::error::Result<bool> {
+    let input_tagged_file = Probe::open(input)?.guess_file_type()?.read(false)?;
+    let input_tag = primary_tag_or_new(&input_tagged_file);
+    let output_tagged_file = Probe::open(&output)?.guess_file_type()?.read(true)?;
+    let mut output_tag = primary_tag_or_new(&output_tagged_file);
+    let mut new_tag = Tag::new(output_tag.tag_type());
+    input_tag.items().iter().cloned().for_each(
+        |item| {
+            new_tag.push_item(item);
+        }
+    );
+    if new_tag.items() == output_tag.items() {
+        return Ok(false);
+    }
+    output_tag.retain_items(|_| false);
+    new_tag.into_iter().for_each(
+        |item| {
+            output_tag.push_item_unchecked(item);
+        }
+    );
+    output_tag.save_to_path(output)?;
+    Ok(true)
+}
+
+/// A copy of the primary tag of the file, or an empty tag of the primary type for untagged files
+pub fn primary_tag_or_new(tagged_file: &TaggedFile) -> Tag {
+    tagged_file
+        .primary_tag()
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tagged_file.primary_tag_type()))
+}
+
+/// Reads the track number from the primary tag, returns None if it's missing or not a number
+pub fn read_track_number(input: PathBuf) -> Option<u32> {
+    let tagged_file = Probe::open(input).ok()?.guess_file_type().ok()?.read(false).ok()?;
+    let track_number = tagged_file.primary_tag()?.get_string(&ItemKey::TrackNumber)?;
+    // Some taggers store the track number as "3/12"
+    track_number.split('/').next()?.trim().parse().ok()
+}
+
+/// Basic information about a source file, used by the health check
+pub struct AudioInfo {
+    /// Whether the title and artist are tagged
+    pub tagged: bool,
+    /// Audio bitrate in kbps
+    pub audio_bitrate: Option<u32>,
+}
+
+/// Reads the tags and properties, returns None if the file isn't a supported audio file
+pub fn read_audio_info(input: PathBuf) -> Option<AudioInfo> {
+    let tagged_file = Probe::open(input).ok()?.guess_file_type().ok()?.read(true).ok()?;
+    let tagged = tagged_file
+        .primary_tag()
+        .map(|tag| tag.title().is_some() && tag.artist().is_some())
+        .unwrap_or(false);
+    Some(
+        AudioInfo {
+            tagged,
+            audio_bitrate: tagged_file.properties().audio_bitrate(),
+        }
+    )
+}
+
+/// Reads the duration of the audio, used to show the encode progress
+pub fn read_duration(input: PathBuf) -> Option<Duration> {
+    let tagged_file = Probe::open(input).ok()?.guess_file_type().ok()?.read(true).ok()?;
+    Some(tagged_file.properties().duration())
+}
+
+/// Reads the codec of the first audio stream with the ffprobe of the config, like opus or flac
+pub fn read_audio_codec(config: &Config, input: PathBuf) -> Option<String> {
+    let output = programs::ffprobe(config)
+        .args(["-v", "error", "-select_streams", "a:0", "-show_entries", "stream=codec_name", "-of", "csv=p=0"])
+        .arg(input)
+        .output()
+        .ok()?;
+    let codec = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(codec).filter(|codec| output.status.success() && !codec.is_empty())
+}
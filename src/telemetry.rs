@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::save_json;
+use crate::warnings::Warnings;
+
+/// Number of runs kept in [Telemetry::recent_runs]
+const RECENT_RUNS: usize = 20;
+
+/// Feature usage and problems of all runs, written when the config sets telemetry and never sent anywhere
+///
+/// Only names of config options, warning kinds and error categories are recorded, no paths or messages, so the file
+/// can be attached to bug reports as it is
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Telemetry {
+    pub runs: u64,
+    /// Runs by their command
+    pub commands: BTreeMap<String, u64>,
+    /// Runs by the config options set in them
+    pub features: BTreeMap<String, u64>,
+    /// Warnings by their kind, summed over all runs
+    pub warnings: BTreeMap<String, u64>,
+    /// Runs stopped by an error, by its category
+    pub errors: BTreeMap<String, u64>,
+    pub failed_encodes: u64,
+    /// The last runs, oldest first
+    pub recent_runs: Vec<RunSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    /// Unix timestamp of the end of the run
+    pub finished: u64,
+    pub command: String,
+    pub duration_seconds: u64,
+    pub warnings: BTreeMap<String, u64>,
+    pub failed_encodes: u64,
+    pub error: Option<String>,
+}
+
+impl Telemetry {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save_json(path, self, true)
+    }
+    /// Adds a finished run, `features` are the config options set in it
+    pub fn record_run(
+        &mut self,
+        command: &str,
+        features: &[String],
+        duration_seconds: u64,
+        warnings: &Warnings,
+        failed_encodes: usize,
+        error: Option<&str>,
+    ) {
+        self.runs += 1;
+        *self.commands.entry(command.to_string()).or_default() += 1;
+        for feature in features {
+            *self.features.entry(feature.clone()).or_default() += 1;
+        }
+        let mut run_warnings = BTreeMap::new();
+        for warning in warnings.iter() {
+            *run_warnings.entry(format!("{:?}", warning.kind)).or_default() += 1;
+        }
+        for (kind, count) in &run_warnings {
+            *self.warnings.entry(kind.clone()).or_default() += count;
+        }
+        if let Some(error) = error {
+            *self.errors.entry(error.to_string()).or_default() += 1;
+        }
+        self.failed_encodes += failed_encodes as u64;
+        self.recent_runs.push(RunSummary {
+            finished: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs())
+                .unwrap_or_default(),
+            command: command.to_string(),
+            duration_seconds,
+            warnings: run_warnings,
+            failed_encodes: failed_encodes as u64,
+            error: error.map(String::from),
+        });
+        if self.recent_runs.len() > RECENT_RUNS {
+            self.recent_runs.drain(..self.recent_runs.len() - RECENT_RUNS);
+        }
+    }
+}
+
+/// Config options set in the config, nested ones like `mqtt.discovery` included, without their values
+pub fn config_features(config: &serde_json::Value) -> Vec<String> {
+    let mut features = Vec::new();
+    if let Some(options) = config.as_object() {
+        for (option, value) in options {
+            if value.is_null() {
+                continue;
+            }
+            features.push(option.clone());
+            if let Some(nested) = value.as_object() {
+                features.extend(nested.keys().map(|nested| format!("{}.{}", option, nested)));
+            }
+        }
+    }
+    features.sort();
+    features
+}
+
+/// Category of an error stopping a run, without anything identifying the library
+pub fn error_category(error: &(dyn Error + 'static)) -> String {
+    if let Some(error) = error.downcast_ref::<io::Error>() {
+        return format!("io {:?}", error.kind());
+    }
+    if error.downcast_ref::<serde_json::Error>().is_some() {
+        return String::from("json");
+    }
+    let message = error.to_string().to_lowercase();
+    let category = ["interrupted", "rclone", "ffmpeg", "config", "device", "plan", "delete"]
+        .into_iter()
+        .find(|category| message.contains(category))
+        .unwrap_or("other");
+    category.to_string()
+}
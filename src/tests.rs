@@ -1,21 +1,266 @@
 #[cfg(test)]
-use super::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+#[cfg(test)]
+use std::time::Duration;
+
+#[cfg(test)]
+use crate::albums::{export_folder_cover, parse_ebur128_summary, write_album_gain, AlbumLoudness};
+#[cfg(test)]
+use crate::capabilities::{
+    audio_codec, can_change_extension, container, container_of, encodes_lossy, is_lossy_source, reconcile_params,
+    remux_params,
+};
+#[cfg(test)]
+use crate::config::{
+    is_config_url, read_config_source, with_audio_filter, with_speed, Config, ConfigOverride, Glob, MqttConfig,
+    RemotePricing, SlowFileBudget,
+};
+#[cfg(test)]
+use crate::content_store::{object_name, update_store, verify_store, StoreIndex};
+#[cfg(test)]
+use crate::cost::{format_cost, CostEstimate};
+#[cfg(test)]
+use crate::display::{qualified, transfer};
+#[cfg(test)]
+use crate::doctor::{parse_encoders, parse_remotes, parse_version, required_encoders, required_remotes};
+#[cfg(test)]
+use crate::events::{Event, EventSink};
+#[cfg(test)]
+use crate::failures::FailedFiles;
+#[cfg(test)]
+use crate::format::{format_count, format_duration, format_size, parse_duration};
+#[cfg(test)]
+use crate::fs_wrapper::{
+    check_purge_status, copy_verified, files_match, is_server_side_unsupported, list_entries, list_filtered_entries,
+    move_files, parse_lsjson, parse_remote_types, parse_rfc3339, partial_path, remove_files, server_side_across_configs,
+    RclonePath, RemoteEntry,
+};
+#[cfg(test)]
+use crate::hashing::{find_changed_files, hash_files, hash_local_file};
+#[cfg(test)]
+use crate::health::{HealthReport, Severity};
+#[cfg(test)]
+use crate::i18n::{format_message, load_bundle, FluentArgs, LOCALES};
+#[cfg(test)]
+use crate::init::InitAnswers;
+#[cfg(test)]
+use crate::interrupt;
+#[cfg(test)]
+use crate::interrupt::PartialFile;
+#[cfg(test)]
+use crate::leftovers::{LeftoverEncode, Leftovers};
+#[cfg(test)]
+use crate::lint::{is_inside, lint_config, reencode_loops};
+#[cfg(test)]
+use crate::lock::{lock_output, lock_run, run_lock_order, run_lock_path, try_lock_run, OutputLock, RunLockError};
+#[cfg(test)]
+use crate::manifest::Manifest;
+#[cfg(test)]
+use crate::mirrors::{MirrorState, PendingChanges};
+#[cfg(test)]
+use crate::naming::{add_track_number, create_output_file_name, strip_track_number, OutputNamer};
+#[cfg(test)]
+use crate::output_info::OutputInfo;
+#[cfg(test)]
+use crate::overrides::{is_override_file, Overrides};
+#[cfg(test)]
+use crate::playlists::{create_playlist, decode_playlist, is_playlist, rewrite_playlist};
+#[cfg(test)]
+use crate::programs::{ffmpeg_path, ffprobe_path, rclone_command_line, set_rclone_flags, sibling_program};
+#[cfg(test)]
+use crate::rclone_filter::RcloneFilter;
+#[cfg(test)]
+use crate::rclone_rc::{base64, file_parameters, parse_response, post};
+#[cfg(test)]
+use crate::redact::redact_path;
+#[cfg(test)]
+use crate::script::{shell_quote, ShellScript};
+#[cfg(test)]
+use crate::plan::{ActionKind, ActionReason, ExportedPlan, PlannedAction};
+#[cfg(test)]
+use crate::secret::Secret;
+#[cfg(test)]
+use crate::server::{handle_connection, ServerState};
+#[cfg(test)]
+use crate::service::{cmd_quote, encode_utf16, task_script, task_xml, Schedule};
+#[cfg(test)]
+use crate::snapshot::{btrfs_snapshot_path, parse_shadow_copy, snapshot_name, zfs_dataset, zfs_snapshot_path};
+#[cfg(test)]
+use crate::state::save_json;
+#[cfg(test)]
+use crate::state_archive::{ArchiveInfo, StateArchive, INFO_FILE};
+#[cfg(test)]
+use crate::support::{log_tail, read_tar, redact_config, redact_failed_files, tar_header, write_tar, SupportBundle};
+#[cfg(test)]
+use crate::ogg_cover::{copy_pictures, refresh_pictures};
+#[cfg(test)]
+use crate::tags::{read_duration, refresh_tags};
+#[cfg(test)]
+use crate::sync::{
+    changes_length, copy_in_parallel, download_sources, durations_match, encode_command_line, interleave, is_complete_encode,
+    gone_after_failure, list_input, split_directory, upload_outputs, ExecutedActions,
+};
+#[cfg(test)]
+use crate::telemetry::{config_features, error_category, Telemetry};
+#[cfg(test)]
+use crate::throttle::{parse_temperature, pause_reason};
+#[cfg(test)]
+use crate::warnings::{check_file_name, WarningKind, Warnings};
+#[cfg(test)]
+use crate::watch::InputWatcher;
+#[cfg(test)]
+use crate::{Executor, SyncPlanner};
+
+/// Directory of a test in the temp directory, removed when the test ends, also when an assert fails
+#[cfg(test)]
+struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ffmusicsync_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = std::path::Path;
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<std::path::Path> for TestDir {
+    fn as_ref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// FLAC file with only its stream info and padding and no audio, enough to be tagged
+#[cfg(test)]
+fn empty_flac() -> Vec<u8> {
+    let mut flac = Vec::new();
+    flac.extend(b"fLaC");
+    // STREAMINFO, 34 bytes long
+    flac.extend([0, 0, 0, 34]);
+    flac.extend(4096u16.to_be_bytes());
+    flac.extend(4096u16.to_be_bytes());
+    flac.extend([0; 6]);
+    // 44.1 kHz, stereo, 16 bits, no samples
+    flac.extend(((44100u64 << 44) | (1 << 41) | (15 << 36)).to_be_bytes());
+    flac.extend([0; 16]);
+    // The last block, lofty can't add tags after the stream info
+    flac.extend([0x81, 0, 0, 4, 0, 0, 0, 0]);
+    flac
+}
+
+/// Seconds of silence as 8 kHz 8-bit PCM WAV
+#[cfg(test)]
+fn silent_wav(seconds: u32) -> Vec<u8> {
+    let length = 8000 * seconds;
+    let mut wav = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend((36 + length).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(8000u32.to_le_bytes());
+    wav.extend(8000u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(8u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(length.to_le_bytes());
+    wav.extend(vec![0x80; length as usize]);
+    wav
+}
+
+/// Files in the directory and its subdirectories relative to it, sorted
+#[cfg(test)]
+fn walk_files(directory: &std::path::Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(current) = directories.pop() {
+        for entry in std::fs::read_dir(&current).into_iter().flatten().flatten() {
+            if entry.path().is_dir() {
+                directories.push(entry.path());
+            } else if let Ok(file) = entry.path().strip_prefix(directory) {
+                files.push(file.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Config encoding flac to ogg between two empty directories, with the given fields of the config file on top
+#[cfg(test)]
+fn test_config(fields: serde_json::Value) -> Config {
+    let mut config = serde_json::json!({
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": ""
+    });
+    config.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+    serde_json::from_value(config).unwrap()
+}
+
+#[test]
+fn config_sources() {
+    assert!(is_config_url("https://example.com/ffmusicsync/config.json"));
+    assert!(is_config_url("http://nas.local:8080/config.json"));
+    assert!(!is_config_url("configs/http.json"));
+    assert!(!is_config_url("-"));
+
+    let root = TestDir::new("config_sources");
+    std::fs::write(root.join("config.json"), "{}").unwrap();
+    assert_eq!("{}", read_config_source(&root.join("config.json").to_string_lossy()).unwrap());
+    assert!(read_config_source(&root.join("missing.json").to_string_lossy()).is_err());
+
+    // URLs are fetched with curl, a response other than a success is an error instead of the config
+    if std::process::Command::new("curl").arg("--version").output().is_err() {
+        return;
+    }
+    let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/config.json", server.local_addr().unwrap());
+    let served = std::thread::spawn(move || {
+        use std::io::{BufRead, Write};
+        for status in ["200 OK", "404 Not Found"] {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut request = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while request.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let body = r#"{"encodedExtension": "ogg"}"#;
+            let headers = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+            stream.write_all(format!("{}{}", headers, body).as_bytes()).unwrap();
+        }
+    });
+    assert_eq!(r#"{"encodedExtension": "ogg"}"#, read_config_source(&url).unwrap());
+    assert!(read_config_source(&url).is_err());
+    served.join().unwrap();
+}
 
 #[test]
 fn destination_file_names() {
-    let config = Config {
-        input_directory: RclonePath::Local("".to_string()),
-        output_directory: RclonePath::Local("".to_string()),
-        temp_directory: None,
-        extensions_to_encode: vec!["flac".to_string()],
-        encoded_extension: "ogg".to_string(),
-        copy_covers: None,
-        ffmpeg_params: "".to_string(),
-        remove_round_brackets: Some(false),
-        remove_square_brackets: Some(true),
-        remove_curly_brackets: None,
-        remove_angle_brackets: None,
-    };
+    let config = test_config(serde_json::json!({
+        "removeRoundBrackets": false,
+        "removeSquareBrackets": true
+    }));
 
     let input = "Test - Song (Original Mix) [2022] <Test> {}.flac".to_string();
     assert_eq!(
@@ -29,19 +274,12 @@ fn destination_file_names() {
         create_output_file_name(input, &config)
     );
 
-    let config = Config {
-        input_directory: RclonePath::Local("".to_string()),
-        output_directory: RclonePath::Local("".to_string()),
-        temp_directory: None,
-        extensions_to_encode: vec!["flac".to_string()],
-        encoded_extension: "ogg".to_string(),
-        copy_covers: None,
-        ffmpeg_params: "".to_string(),
-        remove_round_brackets: Some(true),
-        remove_square_brackets: Some(true),
-        remove_curly_brackets: Some(true),
-        remove_angle_brackets: Some(true),
-    };
+    let config = test_config(serde_json::json!({
+        "removeRoundBrackets": true,
+        "removeSquareBrackets": true,
+        "removeCurlyBrackets": true,
+        "removeAngleBrackets": true
+    }));
 
     let input = "Test - Song (Original Mix) [2022] <Test> {}.mp3".to_string();
     assert_eq!(
@@ -55,3 +293,3509 @@ fn destination_file_names() {
         create_output_file_name(input, &config)
     );
 }
+
+#[test]
+fn suspicious_file_names() {
+    assert_eq!(None, check_file_name("Some/Folder/Test - Song.ogg"));
+    assert!(check_file_name("Some/Folder/.ogg").is_some());
+    assert!(check_file_name("Test - Song .ogg").is_some());
+    assert!(check_file_name("Test: Song.ogg").is_some());
+}
+
+#[test]
+fn config_overrides() {
+    let config = test_config(serde_json::json!({
+        "ffmpegParams": "-c:a libopus -b:a 128K",
+        "removeRoundBrackets": true,
+        "removeSquareBrackets": true
+    }));
+    let config_override = ConfigOverride {
+        ffmpeg_params: Some("-c:a libopus -b:a 64K".to_string()),
+        remove_square_brackets: Some(false),
+        ..Default::default()
+    };
+    let config = config.with_override(&config_override);
+    assert_eq!("-c:a libopus -b:a 64K", config.ffmpeg_params);
+    assert_eq!(vec!["flac".to_string()], config.extensions_to_encode);
+    assert_eq!(Some(true), config.remove_round_brackets);
+    assert_eq!(Some(false), config.remove_square_brackets);
+    assert!(is_override_file("Audiobooks/.ffmusicsync.json"));
+    assert!(!is_override_file("Audiobooks/Book.flac"));
+}
+
+#[test]
+fn disc_folders() {
+    let config = test_config(serde_json::json!({
+        "removeSquareBrackets": true,
+        "mergeDiscFolders": true
+    }));
+
+    let input = "Various Artists/Album [2022]/CD2/01 Song [Remaster].flac".to_string();
+    assert_eq!(
+        "Various Artists/Album [2022]/2-01 Song.ogg".to_string(),
+        create_output_file_name(input, &config)
+    );
+
+    let input = "Artist/Album/Disc 01/01 Song.mp3".to_string();
+    assert_eq!(
+        "Artist/Album/1-01 Song.mp3".to_string(),
+        create_output_file_name(input, &config)
+    );
+
+    let input = "Artist/CD Collection/01 Song.mp3".to_string();
+    assert_eq!(
+        "Artist/CD Collection/01 Song.mp3".to_string(),
+        create_output_file_name(input, &config)
+    );
+}
+
+#[test]
+fn track_numbers() {
+    assert_eq!("Song", strip_track_number("01 - Song"));
+    assert_eq!("Song", strip_track_number("01. Song"));
+    assert_eq!("Song", strip_track_number("1-01 Song"));
+    assert_eq!("Song", strip_track_number("1-01 - Song"));
+    // Numbers which are part of the title are kept
+    assert_eq!("99 Luftballons", strip_track_number("99 Luftballons"));
+    assert_eq!("3.14 Pi", strip_track_number("3.14 Pi"));
+    assert_eq!("Song", strip_track_number("Song"));
+    assert_eq!("1999", strip_track_number("1999"));
+
+    assert_eq!("Album/03 - Song.ogg", add_track_number("Album/Song.ogg", 3));
+    assert_eq!("Album/03 - Song.ogg", add_track_number("Album/03 - Song.ogg", 3));
+    assert_eq!("Album/03 - 99 Luftballons.ogg", add_track_number("Album/99 Luftballons.ogg", 3));
+
+    let config = test_config(serde_json::json!({
+        "trackNumbers": "strip"
+    }));
+    let overrides = Overrides::default();
+    let input = vec![
+        "Album/01 - Intro.flac".to_string(),
+        "Album/02 - Song.flac".to_string(),
+        "Album/09 - Intro.flac".to_string(),
+    ].into_iter().collect::<HashSet<String>>();
+    let namer = OutputNamer::new(config, overrides, &input, HashMap::new(), HashSet::new());
+    assert_eq!("Album/Song.ogg", namer.output_file_name("Album/02 - Song.flac"));
+    // Stripping would make both intros collide, so they keep their numbers
+    assert_eq!("Album/01 - Intro.ogg", namer.output_file_name("Album/01 - Intro.flac"));
+    assert_eq!("Album/09 - Intro.ogg", namer.output_file_name("Album/09 - Intro.flac"));
+}
+
+#[test]
+fn cost_estimate() {
+    let pricing = vec![
+        (
+            "B2".to_string(),
+            RemotePricing {
+                egress_per_gb: Some(0.01),
+                ingress_per_gb: None,
+                per_thousand_operations: Some(0.004),
+            },
+        ),
+    ].into_iter().collect::<HashMap<String, RemotePricing>>();
+    let mut cost_estimate = CostEstimate::default();
+    cost_estimate.add_egress("B2", 50_000_000_000);
+    cost_estimate.add_ingress("B2", 10_000_000_000);
+    cost_estimate.add_operations("B2", 2000);
+    cost_estimate.add_egress("Free", 10_000_000_000);
+    let (total, remotes) = cost_estimate.cost(&pricing);
+    assert!((total - 0.508).abs() < 1e-9);
+    assert_eq!("$0.51 (B2: $0.51)", format_cost(total, &remotes));
+}
+
+#[test]
+fn manifest_targets() {
+    let mut manifest = Manifest::default();
+    let phone = vec![
+        ("Artist/Album/Song.flac".to_string(), "Artist/Album/Song.ogg".to_string()),
+        ("Artist/Other/Song.flac".to_string(), "Artist/Other/Song.ogg".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    let car = vec![
+        ("Artist/Album/Song.flac".to_string(), "Artist/Album/Song.mp3".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    manifest.update_target("Music", "Phone:Music", &phone);
+    manifest.update_target("Music", "/media/car", &car);
+    assert_eq!(2, manifest.locate("Music/Artist/Album").len());
+    assert_eq!(Some(&2), manifest.locate("Music/Artist").get("Phone:Music"));
+    assert_eq!(1, manifest.locate("Music/Artist/Album/Song.flac")["/media/car"]);
+    // Only whole directories match, not the ones starting with the same name
+    manifest.update_target("Music", "/media/stick", &vec![
+        ("Artist/Album 2/Song.flac".to_string(), "Artist/Album 2/Song.ogg".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>());
+    assert_eq!(2, manifest.locate("Music/Artist/Album").len());
+    assert_eq!(2, manifest.locate("Music/Artist/Album/").len());
+    assert_eq!(0, manifest.locate("Music/Art").len());
+    manifest.update_target("Music", "/media/stick", &HashMap::new());
+
+    // Updating a target forgets files which are not there anymore
+    manifest.update_target("Music", "/media/car", &HashMap::new());
+    assert_eq!(1, manifest.locate("Music/Artist/Album").len());
+    assert_eq!(2, manifest.sources.len());
+}
+
+#[test]
+fn health_score() {
+    let mut report = HealthReport::default();
+    assert_eq!(100, report.score(0));
+    report.add("Empty", Severity::High, Vec::new(), "");
+    assert!(report.categories.is_empty());
+    report.add("Collisions", Severity::High, vec!["a.ogg <- a.flac, (a).flac".to_string()], "");
+    report.add("Untagged", Severity::Low, vec!["b.flac".to_string(), "c.flac".to_string()], "");
+    // 4 + 2 * 0.25 = 4.5 of 100 files
+    assert_eq!(96, report.score(100));
+    // Lots of problems in a small library don't go below 0
+    assert_eq!(0, report.score(2));
+}
+
+#[test]
+fn exported_plan_format() {
+    let mut exported_plan = ExportedPlan::new(String::from("Music"), String::from("Phone:Music"));
+    exported_plan.actions.push(
+        PlannedAction {
+            kind: ActionKind::Encode,
+            source: Some(String::from("Artist/Song.flac")),
+            destination: Some(String::from("Artist/Song.ogg")),
+            new_destination: None,
+            reason: ActionReason::Failed,
+            size: Some(1024),
+            status: None,
+            slow_seconds: None,
+        }
+    );
+    exported_plan.actions.push(
+        PlannedAction {
+            kind: ActionKind::Delete,
+            source: None,
+            destination: Some(String::from("Artist/Old.ogg")),
+            new_destination: None,
+            reason: ActionReason::NoSource,
+            size: None,
+            status: None,
+            slow_seconds: None,
+        }
+    );
+    exported_plan.set_statuses(
+        &vec![(String::from("Artist/Song.flac"), String::from("Artist/Song.ogg"))].into_iter().collect(),
+        false,
+    );
+    // Changing this breaks downstream tools, bump PLAN_FORMAT_VERSION and add a new schema instead
+    assert_eq!(
+        concat!(
+            r#"{"version":1,"inputDirectory":"Music","outputDirectory":"Phone:Music","actions":["#,
+            r#"{"kind":"encode","source":"Artist/Song.flac","destination":"Artist/Song.ogg","newDestination":null,"#,
+            r#""reason":"failed","size":1024,"status":"failed"},"#,
+            r#"{"kind":"delete","source":null,"destination":"Artist/Old.ogg","newDestination":null,"#,
+            r#""reason":"noSource","size":null,"status":"done"}]}"#,
+        ),
+        serde_json::to_string(&exported_plan).unwrap()
+    );
+}
+
+#[test]
+fn secrets() {
+    std::env::set_var("FFMUSICSYNC_TEST_SECRET", "from env");
+    assert_eq!("from env", Secret::resolve("env:FFMUSICSYNC_TEST_SECRET").unwrap().expose());
+    assert!(Secret::resolve("env:FFMUSICSYNC_TEST_MISSING").is_err());
+
+    let root = TestDir::new("secrets");
+    let secret_file = root.join("secret");
+    std::fs::write(&secret_file, "from file\n").unwrap();
+    let secret = Secret::resolve(&format!("@{}", secret_file.to_string_lossy())).unwrap();
+    assert_eq!("from file", secret.expose());
+
+    assert_eq!("plain", Secret::resolve("plain").unwrap().expose());
+    assert_eq!("Secret(***)", format!("{:?}", secret));
+}
+
+#[test]
+fn sync_plan() {
+    let root = TestDir::new("sync_plan");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    for file in ["Album/New.flac", "Album/Kept.flac", "Album/Renamed (Live).flac", "Album/cover.jpg"] {
+        std::fs::create_dir_all(input_directory.join(file).parent().unwrap()).unwrap();
+        std::fs::write(input_directory.join(file), file).unwrap();
+    }
+    for file in ["Album/Kept.ogg", "Album/Renamed (Live).ogg", "Album/Stray.ogg"] {
+        std::fs::create_dir_all(output_directory.join(file).parent().unwrap()).unwrap();
+        std::fs::write(output_directory.join(file), file).unwrap();
+    }
+    let config = test_config(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "removeRoundBrackets": true
+    }));
+    let encoded = vec![
+        ("Album/Kept.flac".to_string(), "Album/Kept.ogg".to_string()),
+        ("Album/Renamed (Live).flac".to_string(), "Album/Renamed (Live).ogg".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config, encoded).plan(&mut warnings).unwrap();
+
+    assert!(plan.collisions.is_empty());
+    assert_eq!(
+        vec!["Album/New.flac".to_string(), "Album/cover.jpg".to_string()].into_iter().collect::<HashSet<String>>(),
+        plan.to_process
+    );
+    assert_eq!(Some(&"Album/Renamed.ogg".to_string()), plan.to_rename.get("Album/Renamed (Live).ogg"));
+    assert_eq!(
+        vec!["Album/Stray.ogg".to_string()].into_iter().collect::<HashSet<String>>(),
+        plan.to_delete
+    );
+    assert_eq!(4, plan.total_actions());
+    assert_eq!(ActionReason::New, plan.process_reason("Album/New.flac"));
+    assert_eq!(ActionReason::NoSource, plan.delete_reason("Album/Stray.ogg"));
+    // Album already exists in the output
+    assert!(plan.directories_to_create().is_empty());
+    // The state only changes with the actions which were done
+    let encoded = plan.final_encoded(&Default::default());
+    assert_eq!(Some(&"Album/Renamed (Live).ogg".to_string()), encoded.get("Album/Renamed (Live).flac"));
+    assert_eq!(None, encoded.get("Album/New.flac"));
+    let mut executed = ExecutedActions::default();
+    executed.renamed.insert("Album/Renamed (Live).ogg".to_string(), "Album/Renamed.ogg".to_string());
+    executed.processed.insert("Album/cover.jpg".to_string(), "Album/cover.jpg".to_string());
+    executed.failed.insert("Album/New.flac".to_string(), "Album/New.ogg".to_string());
+    let encoded = plan.final_encoded(&executed);
+    assert_eq!(Some(&"Album/Renamed.ogg".to_string()), encoded.get("Album/Renamed (Live).flac"));
+    assert_eq!(Some(&"Album/Kept.ogg".to_string()), encoded.get("Album/Kept.flac"));
+    assert_eq!(Some(&"Album/cover.jpg".to_string()), encoded.get("Album/cover.jpg"));
+    assert_eq!(None, encoded.get("Album/New.flac"));
+}
+
+#[test]
+fn changed_files() {
+    let directory = TestDir::new("changed_files");
+    for (file, contents) in [("same.flac", "same"), ("resized.flac", "old"), ("edited.flac", "old"), ("new.flac", "new")] {
+        std::fs::write(directory.join(file), contents).unwrap();
+    }
+    let directory_path = RclonePath::Local(directory.to_string_lossy().to_string());
+    let files = ["same.flac", "resized.flac", "edited.flac", "new.flac"]
+        .iter()
+        .map(|file| file.to_string())
+        .collect::<HashSet<String>>();
+    let mut previous = hash_files(
+        &directory_path,
+        &["same.flac".to_string(), "resized.flac".to_string(), "edited.flac".to_string()],
+    ).unwrap();
+    previous.remove("new.flac");
+    std::fs::write(directory.join("resized.flac"), "longer").unwrap();
+    std::fs::write(directory.join("edited.flac"), "new").unwrap();
+    let (changed, hashes) = find_changed_files(&directory_path, &files, &previous).unwrap();
+
+    assert_eq!(
+        vec!["resized.flac".to_string(), "edited.flac".to_string()].into_iter().collect::<HashSet<String>>(),
+        changed
+    );
+    // Unchanged and new files get their current hash
+    assert_eq!(previous.get("same.flac"), hashes.get("same.flac"));
+    assert_eq!(Some(3), hashes.get("new.flac").map(|file_hash| file_hash.size));
+    assert!(hashes.get("new.flac").unwrap().hash.starts_with("blake3:"));
+}
+
+#[test]
+fn encode_rules() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K -vn",
+        "encodeRules": [
+            { "globs": ["Audiobooks/**"], "encodedExtension": "ogg", "ffmpegParams": "-c:a libopus -b:a 48K -ac 1 -vn" },
+            { "extensions": ["wav"], "encodedExtension": "flac", "ffmpegParams": "-c:a flac" }
+        ]
+    }"#).unwrap();
+
+    let file_config = config.with_encode_rule("Artist/Song.flac");
+    assert_eq!("-c:a libopus -b:a 128K -vn", file_config.ffmpeg_params);
+    let file_config = config.with_encode_rule("Artist/Song.wav");
+    assert_eq!("-c:a flac", file_config.ffmpeg_params);
+    assert_eq!("Artist/Song.flac", create_output_file_name("Artist/Song.wav".to_string(), &file_config));
+    // The first matching rule wins, even for extensions not in extensionsToEncode
+    let file_config = config.with_encode_rule("Audiobooks/Author/Book.wav");
+    assert_eq!("-c:a libopus -b:a 48K -ac 1 -vn", file_config.ffmpeg_params);
+    assert_eq!("Audiobooks/Author/Book.ogg", create_output_file_name("Audiobooks/Author/Book.wav".to_string(), &file_config));
+    assert_eq!("Artist/Song.mp3", create_output_file_name("Artist/Song.mp3".to_string(), &config.with_encode_rule("Artist/Song.mp3")));
+    // Extensions ignore their case, globs leave the covers and booklets of the folder alone
+    assert_eq!("-c:a flac", config.with_encode_rule("Artist/Song.WAV").ffmpeg_params);
+    assert!(config.with_encode_rule("Artist/Song.WAV").extensions_to_encode.contains(&String::from("WAV")));
+    for file in ["Audiobooks/Author/cover.jpg", "Audiobooks/Author/Booklet.pdf"] {
+        let file_config = config.with_encode_rule(file);
+        assert_eq!(vec![String::from("flac")], file_config.extensions_to_encode);
+        assert_eq!(file, create_output_file_name(file.to_string(), &file_config));
+    }
+
+    let glob = Glob::try_from("*/Live ?.flac".to_string()).unwrap();
+    assert!(glob.matches("Artist/Live 1.flac"));
+    assert!(!glob.matches("Artist/Album/Live 1.flac"));
+    assert!(!glob.matches("Artist/Live 10.flac"));
+}
+
+#[test]
+fn output_profiles() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "Music",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 96K -vn",
+        "outputs": [
+            { "name": "phone", "outputDirectory": "Phone" },
+            { "name": "car", "outputDirectory": { "remote": "Car", "path": "Music" }, "encodedExtension": "mp3", "ffmpegParams": "-b:a 320K" }
+        ]
+    }"#).unwrap();
+    let outputs = config.outputs.clone().unwrap();
+
+    let phone = config.for_output(&outputs[0]);
+    assert_eq!("Phone", phone.output_directory.to_string());
+    assert_eq!("ogg", phone.encoded_extension);
+    assert_eq!("-c:a libopus -b:a 96K -vn", phone.ffmpeg_params);
+    assert!(phone.outputs.is_none());
+    let car = config.for_output(&outputs[1]);
+    assert_eq!("Car:Music", car.output_directory.to_string());
+    assert_eq!("mp3", car.encoded_extension);
+    assert_eq!("-b:a 320K", car.ffmpeg_params);
+    assert_eq!(vec!["flac".to_string()], car.extensions_to_encode);
+}
+
+#[test]
+fn lint_config_issues() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "Music",
+        "outputDirectory": "Music/Encoded",
+        "extensionsToEncode": ["flac", "ogg"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-y -c:a libopus",
+        "copyRetries": 5
+    }"#).unwrap();
+    let issues = lint_config(&config);
+    let messages = issues.iter().map(|issue| issue.message.as_str()).collect::<Vec<&str>>();
+    assert_eq!(
+        vec![
+            "encodedExtension ogg is listed in extensionsToEncode",
+            "outputDirectory is inside inputDirectory",
+            "ffmpegParams contains -y",
+            "copyRetries is set without verifyCopies",
+        ],
+        messages
+    );
+    assert_eq!(Severity::High, issues[0].severity);
+
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "Music",
+        "outputDirectory": "Encoded",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus"
+    }"#).unwrap();
+    assert!(lint_config(&config).is_empty());
+
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "Music",
+        "outputDirectory": "Encoded",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "mp3",
+        "ffmpegParams": "-c:a libopus",
+        "copyCovers": true,
+        "encodeRules": [{ "extensions": ["wav"], "encodedExtension": "aac", "ffmpegParams": "-c:a aac" }],
+        "audiobooks": { "encodedExtension": "flac", "ffmpegParams": "-map_chapters 0 -c:a flac" }
+    }"#).unwrap();
+    let messages = lint_config(&config).into_iter().map(|issue| issue.message).collect::<Vec<String>>();
+    assert_eq!(
+        vec![
+            "ffmpegParams encodes with libopus, which can't be stored in .mp3 files",
+            "copyCovers is set, but .aac files of encodeRules can't store covers",
+            ".aac files of encodeRules can't be played gaplessly",
+            "audiobooks keeps chapters, but .flac files can't store them",
+        ],
+        messages
+    );
+}
+
+#[test]
+fn capabilities() {
+    assert_eq!(Some("libopus"), audio_codec("-c:a libopus -b:a 128K"));
+    assert_eq!(Some("aac"), audio_codec("-acodec libmp3lame -codec:a aac"));
+    assert_eq!(None, audio_codec("-c:a copy"));
+    assert_eq!(None, audio_codec("-b:a 128K"));
+    let ogg = container("OGG").unwrap();
+    assert!(ogg.fits("libvorbis"));
+    assert!(!ogg.fits("libmp3lame"));
+    // Encoders missing from the table can't be checked
+    assert!(ogg.fits("libsomething"));
+    assert!(!container_of("Artist/Song.wav").unwrap().pictures);
+    assert_eq!(None, container_of("Artist/Song.xyz"));
+}
+
+#[test]
+fn params_changed() {
+    let root = TestDir::new("params_changed");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    for file in ["Old.flac", "Same.flac", "Unknown.flac"] {
+        std::fs::write(input_directory.join(file), file).unwrap();
+        std::fs::write(output_directory.join(file.replace("flac", "ogg")), file).unwrap();
+    }
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 160K",
+        "reencodeOnParamChange": true
+    })).unwrap();
+    let encoded = ["Old", "Same", "Unknown"]
+        .iter()
+        .map(|name| (format!("{}.flac", name), format!("{}.ogg", name)))
+        .collect::<HashMap<String, String>>();
+    let params = vec![
+        ("Old.flac".to_string(), "-c:a libopus -b:a 96K".to_string()),
+        ("Same.flac".to_string(), "-c:a libopus -b:a 160K".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config.clone(), encoded.clone()).with_params(params).plan(&mut warnings).unwrap();
+
+    assert_eq!(vec!["Old.flac".to_string()].into_iter().collect::<HashSet<String>>(), plan.to_process);
+    assert_eq!(vec!["Old.ogg".to_string()].into_iter().collect::<HashSet<String>>(), plan.to_delete);
+    assert_eq!(ActionReason::ParamsChanged, plan.process_reason("Old.flac"));
+    assert_eq!(ActionReason::ParamsChanged, plan.delete_reason("Old.ogg"));
+    // Sources encoded before the params were recorded get the current ones
+    let params = plan.final_params(&Default::default());
+    assert_eq!(Some(&"-c:a libopus -b:a 160K".to_string()), params.get("Unknown.flac"));
+    assert_eq!(None, params.get("Old.flac"));
+
+    // Unless they're asked to be encoded again too
+    let mut config = config;
+    config.reencode_unknown_params = Some(true);
+    let params = HashMap::from([
+        (String::from("Old.flac"), String::from("-c:a libopus -b:a 96K")),
+        (String::from("Same.flac"), String::from("-c:a libopus -b:a 160K")),
+    ]);
+    let plan = SyncPlanner::new(config, encoded).with_params(params).plan(&mut warnings).unwrap();
+    assert_eq!(HashSet::from([String::from("Old.flac"), String::from("Unknown.flac")]), plan.to_process);
+    assert_eq!(ActionReason::ParamsChanged, plan.process_reason("Unknown.flac"));
+}
+
+#[test]
+fn clean_orphans() {
+    let root = TestDir::new("clean_orphans");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    std::fs::write(root.join("input/Old.flac"), "flac").unwrap();
+    std::fs::write(root.join("input/New.flac"), "flac").unwrap();
+    for file in ["Old.ogg", "Stray.ogg"] {
+        std::fs::write(root.join("output").join(file), "encoded").unwrap();
+    }
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "ffmpegParams": "-c:a libopus -b:a 160K",
+        "reencodeOnParamChange": true
+    }));
+    let encoded = [(String::from("Old.flac"), String::from("Old.ogg"))].into_iter().collect();
+    let params = [(String::from("Old.flac"), String::from("-c:a libopus -b:a 96K"))].into_iter().collect();
+    let mut warnings = Warnings::new();
+    let mut plan = SyncPlanner::new(config, encoded).with_params(params).plan(&mut warnings).unwrap();
+    plan.keep_orphans();
+    let executed = match Executor::new(&EventSink::new()).execute(&plan, &mut warnings) {
+        Ok(executed) => executed,
+        Err(interrupted) => panic!("{}", interrupted.error),
+    };
+
+    // The output of a source queued for a new encode stays until a sync replaces it
+    assert!(plan.to_process.is_empty());
+    assert_eq!(ActionReason::NoSource, plan.delete_reason("Stray.ogg"));
+    assert_eq!(BTreeSet::from([String::from("Stray.ogg")]), executed.deleted);
+    assert_eq!(vec!["Old.ogg"], walk_files(&root.join("output")));
+}
+
+#[test]
+fn deferred_deletes() {
+    let root = TestDir::new("deferred_deletes");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    std::fs::write(root.join("input/Broken.flac"), "not audio").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "ffmpegParams": "-c:a libopus"
+    }));
+    let run = |failures: FailedFiles| {
+        std::fs::write(root.join("output/Removed.ogg"), "encoded").unwrap();
+        let mut warnings = Warnings::new();
+        let plan = SyncPlanner::new(config.clone(), HashMap::new()).with_failures(failures).plan(&mut warnings).unwrap();
+        match Executor::new(&EventSink::new()).execute(&plan, &mut warnings) {
+            Ok(executed) => executed,
+            Err(interrupted) => panic!("{}", interrupted.error),
+        }
+    };
+
+    // A source failing for the first time could be the new place of the removed output, which is kept
+    let executed = run(FailedFiles::default());
+    assert_eq!(vec![&String::from("Broken.flac")], executed.failed.keys().collect::<Vec<&String>>());
+    assert!(executed.deleted.is_empty());
+    assert!(root.join("output/Removed.ogg").exists());
+    // One which failed before too doesn't hold the deletes back forever
+    let mut failures = FailedFiles::default();
+    failures.files.insert(String::from("Broken.flac"), String::from("Broken.ogg"));
+    let executed = run(failures);
+    assert_eq!(1, executed.failed.len());
+    assert_eq!(BTreeSet::from([String::from("Removed.ogg")]), executed.deleted);
+}
+
+#[test]
+fn kept_failures() {
+    let root = TestDir::new("kept_failures");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    for file in ["Fixed.flac", "Broken.flac", "Later.flac"] {
+        std::fs::write(root.join("input").join(file), "flac").unwrap();
+    }
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy()
+    }));
+    let mut failures = FailedFiles::default();
+    for file in ["Fixed", "Broken", "Later", "Gone"] {
+        failures.files.insert(format!("{}.flac", file), format!("{}.ogg", file));
+    }
+    let plan = SyncPlanner::new(config, HashMap::new()).with_failures(failures).plan(&mut Warnings::new()).unwrap();
+    // The run stopped before getting to Later.flac
+    let mut executed = ExecutedActions::default();
+    executed.processed.insert(String::from("Fixed.flac"), String::from("Fixed.ogg"));
+    executed.failed.insert(String::from("Broken.flac"), String::from("Broken.ogg"));
+
+    // Files which weren't attempted are retried by the next run, unless their source is gone
+    assert_eq!(
+        vec!["Broken.flac", "Later.flac"],
+        plan.final_failures(&executed).files.keys().map(String::as_str).collect::<Vec<&str>>()
+    );
+}
+
+#[test]
+fn failed_batches() {
+    // rclone stopped after deleting Album/First.ogg, only that one is recorded
+    let root = TestDir::new("failed_batches");
+    std::fs::create_dir_all(root.join("Album")).unwrap();
+    std::fs::write(root.join("Album/Second.ogg"), "encoded").unwrap();
+    let output_directory = RclonePath::Local(root.to_string_lossy().to_string());
+    let batch = vec![String::from("Album/First.ogg"), String::from("Album/Second.ogg")];
+    assert_eq!(HashSet::from([String::from("Album/First.ogg")]), gone_after_failure(&output_directory, batch));
+}
+
+#[test]
+fn refresh_only() {
+    let root = TestDir::new("refresh_only");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    for file in ["input/Song (Live).wav", "input/New.wav", "output/Song (Live).ogg", "output/Stray.ogg"] {
+        std::fs::write(root.join(file), silent_wav(1)).unwrap();
+    }
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "extensionsToEncode": ["wav"],
+        "removeRoundBrackets": true
+    }));
+    let encoded = [(String::from("Song (Live).wav"), String::from("Song (Live).ogg"))].into_iter().collect();
+    let mut warnings = Warnings::new();
+    let mut plan = SyncPlanner::new(config, encoded).plan(&mut warnings).unwrap();
+    assert_eq!(3, plan.total_actions());
+    plan.refresh_only();
+    let events = EventSink::new();
+    let mut executor = Executor::new(&events);
+    executor.refresh_tags = true;
+    executor.refresh_covers = true;
+    if let Err(interrupted) = executor.execute(&plan, &mut warnings) {
+        panic!("{}", interrupted.error);
+    }
+
+    // Nothing is encoded, renamed or deleted, the output waiting for its rename is refreshed under its current name
+    assert_eq!(0, plan.total_actions());
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert_eq!(vec!["Song (Live).ogg", "Stray.ogg"], walk_files(&root.join("output")));
+}
+
+#[test]
+fn adopted_outputs() {
+    let root = TestDir::new("adopted_outputs");
+    std::fs::create_dir_all(root.join("input/Album")).unwrap();
+    std::fs::create_dir_all(root.join("output/Album")).unwrap();
+    for (file, contents) in [
+        ("input/Album/Song.flac", "flac"),
+        ("output/Album/Song.ogg", "ogg"),
+        ("input/Album/cover.jpg", "cover"),
+        ("output/Album/cover.jpg", "cover"),
+        ("input/Album/booklet.pdf", "booklet"),
+        ("output/Album/booklet.pdf", "an older booklet"),
+    ] {
+        std::fs::write(root.join(file), contents).unwrap();
+    }
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy()
+    }));
+    let plan = SyncPlanner::new(config, HashMap::new()).plan(&mut Warnings::new()).unwrap();
+
+    // Outputs missing from encoded.json are adopted, copies only if they match their source. The others are replaced
+    assert_eq!(HashSet::from([String::from("Album/booklet.pdf")]), plan.to_process);
+    assert_eq!(HashSet::from([String::from("Album/booklet.pdf")]), plan.to_delete);
+}
+
+#[test]
+fn mirror_mode() {
+    let root = TestDir::new("mirror_mode");
+    for file in ["input/Album/Song.flac", "input/Album/cover.jpg", "input/Single.mp3"] {
+        std::fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+        std::fs::write(root.join(file), file).unwrap();
+    }
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "extensionsToEncode": []
+    }));
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config.clone(), HashMap::new()).plan(&mut warnings).unwrap();
+    assert!(plan.mirror_mode);
+    // Nothing is encoded, so the files are copied without ffmpeg keeping their paths
+    let events = EventSink::new();
+    if let Err(interrupted) = Executor::new(&events).execute(&plan, &mut warnings) {
+        panic!("{}", interrupted.error);
+    }
+    assert_eq!(vec!["Album/Song.flac", "Album/cover.jpg", "Single.mp3"], walk_files(&root.join("output")));
+    assert_eq!("input/Album/Song.flac", std::fs::read_to_string(root.join("output/Album/Song.flac")).unwrap());
+
+    // A directory whose config file encodes again needs ffmpeg
+    std::fs::write(root.join("input/Album/.ffmusicsync.json"), r#"{"extensionsToEncode": ["flac"]}"#).unwrap();
+    let plan = SyncPlanner::new(config, HashMap::new()).plan(&mut warnings).unwrap();
+    assert!(!plan.mirror_mode);
+}
+
+#[test]
+fn input_filters() {
+    let root = TestDir::new("input_filters");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    for directory in ["Albums/A", "Albums/B", "Audiobooks"] {
+        std::fs::create_dir_all(input_directory.join(directory)).unwrap();
+        std::fs::create_dir_all(output_directory.join(directory)).unwrap();
+    }
+    for file in ["Albums/A/Song.flac", "Albums/B/Song.flac", "Audiobooks/Book.flac"] {
+        std::fs::write(input_directory.join(file), file).unwrap();
+    }
+    std::fs::write(output_directory.join("Albums/A/Old.ogg"), "").unwrap();
+    std::fs::write(output_directory.join("Albums/B/Old.ogg"), "").unwrap();
+    std::fs::write(output_directory.join("Stray.ogg"), "").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "exclude": ["Audiobooks/**"],
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": ""
+    })).unwrap();
+    assert!(config.is_included("Albums/A/Song.flac"));
+    assert!(!config.is_included("Audiobooks/Book.flac"));
+    // The config file of the whole input isn't filtered out by include
+    let only_albums = Config {
+        include: Some(vec![Glob::try_from("Albums/**".to_string()).unwrap()]),
+        ..config.clone()
+    };
+    std::fs::write(input_directory.join(".ffmusicsync.json"), r#"{"encodedExtension": "mp3"}"#).unwrap();
+    let input = list_input(&only_albums, None, None, &mut Warnings::new()).unwrap();
+    assert!(input.contains(".ffmusicsync.json"));
+    assert!(!input.contains("Audiobooks/Book.flac"));
+    let plan = SyncPlanner::new(only_albums, HashMap::new()).with_input(input).plan(&mut Warnings::new()).unwrap();
+    assert_eq!("Albums/A/Song.mp3", plan.namer().output_file_name("Albums/A/Song.flac"));
+    std::fs::remove_file(input_directory.join(".ffmusicsync.json")).unwrap();
+
+    let encoded = vec![
+        ("Albums/A/Old.flac".to_string(), "Albums/A/Old.ogg".to_string()),
+        ("Albums/B/Old.flac".to_string(), "Albums/B/Old.ogg".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config, encoded)
+        .only(vec![Glob::try_from("Albums/A/**".to_string()).unwrap()])
+        .plan(&mut warnings)
+        .unwrap();
+
+    assert_eq!(vec!["Albums/A/Song.flac".to_string()].into_iter().collect::<HashSet<String>>(), plan.to_process);
+    // Outputs with an unknown source are left alone
+    assert!(plan.to_delete.is_empty());
+    // Sources outside of --only keep their state, removed ones inside of it are forgotten
+    let encoded = plan.final_encoded(&Default::default());
+    assert_eq!(Some(&"Albums/B/Old.ogg".to_string()), encoded.get("Albums/B/Old.flac"));
+    assert_eq!(None, encoded.get("Albums/A/Old.flac"));
+}
+
+#[test]
+fn number_formatting() {
+    assert_eq!("512 B", format_size(512));
+    assert_eq!("1.50 KiB", format_size(1536));
+    assert_eq!("2.00 GiB", format_size(2 * 1024 * 1024 * 1024));
+    assert_eq!("0", format_count(0));
+    assert_eq!("999", format_count(999));
+    assert_eq!("12,345", format_count(12345));
+    assert_eq!("1,000,000", format_count(1_000_000));
+    assert_eq!("0:00:59", format_duration(Duration::from_secs(59)));
+    assert_eq!("1:02:03", format_duration(Duration::from_secs(3723)));
+    assert_eq!(Ok(Duration::from_secs(7200)), parse_duration("2h"));
+    assert_eq!(Ok(Duration::from_secs(5400)), parse_duration("1h30m"));
+    assert_eq!(Ok(Duration::from_secs(45)), parse_duration("45"));
+    assert!(parse_duration("2 hours").is_err());
+    assert!(parse_duration("h").is_err());
+    assert!(parse_duration("1h30").is_err());
+}
+
+#[test]
+fn audiobooks() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K -vn",
+        "removeRoundBrackets": true,
+        "audiobooks": { "globs": ["Audiobooks/**"] }
+    }"#).unwrap();
+
+    assert!(config.is_audiobook("Author/Book.m4b"));
+    assert!(config.is_audiobook("Audiobooks/Author/Book.flac"));
+    assert!(!config.is_audiobook("Artist/Song.flac"));
+    // The cover of a book is copied, extensions ignore their case
+    assert!(!config.is_audiobook("Audiobooks/Author/cover.jpg"));
+    assert!(config.is_audiobook("Author/Book.M4B"));
+    let file_config = config.with_encode_rule("Author/Book (Unabridged).m4b");
+    assert_eq!("-map_chapters 0 -c:a libopus -b:a 32K -ac 1 -vn", file_config.ffmpeg_params);
+    assert_eq!(
+        "Author/Book (Unabridged).ogg",
+        create_output_file_name("Author/Book (Unabridged).m4b".to_string(), &file_config)
+    );
+    assert_eq!(
+        "Artist/Song.ogg",
+        create_output_file_name("Artist/Song (Live).flac".to_string(), &config.with_encode_rule("Artist/Song (Live).flac"))
+    );
+}
+
+#[test]
+fn speed() {
+    assert_eq!(
+        "-c:a libopus -af atempo=1.25 -map_chapters -1",
+        with_speed("-c:a libopus", 1.25)
+    );
+    assert_eq!(
+        "-map_chapters -1 -c:a libopus -ac 1 -af loudnorm,atempo=1.5",
+        with_speed("-map_chapters 0 -c:a libopus -ac 1 -af loudnorm", 1.5)
+    );
+    assert_eq!("-af atempo=2,atempo=1.5 -map_chapters -1", with_speed("", 3.0));
+    assert_eq!("-af atempo=0.5,atempo=0.8 -map_chapters -1", with_speed("", 0.4));
+    assert_eq!("-c:a libopus", with_speed("-c:a libopus", 0.0));
+
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus",
+        "encodeRules": [{ "globs": ["Podcasts/**"], "encodedExtension": "ogg", "ffmpegParams": "-c:a libopus", "speed": 1.25 }]
+    }"#).unwrap();
+    assert_eq!(
+        "-c:a libopus -af atempo=1.25 -map_chapters -1",
+        config.with_encode_rule("Podcasts/Show/1.mp3").ffmpeg_params
+    );
+    assert_eq!("-c:a libopus", config.with_encode_rule("Artist/Song.flac").ffmpeg_params);
+}
+
+#[test]
+fn trim_silence() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -af loudnorm",
+        "trimSilence": ["Live/**"]
+    }"#).unwrap();
+
+    assert!(config.trims_silence("Live/Concert/1.flac"));
+    assert!(!config.trims_silence("Artist/Song.flac"));
+    let ffmpeg_params = config.with_encode_rule("Live/Concert/1.flac").ffmpeg_params;
+    assert!(ffmpeg_params.starts_with("-c:a libopus -af loudnorm,silenceremove=start_periods=1:"));
+    // Only the start of the audio and of the reversed audio is trimmed, stop periods would shorten pauses too
+    assert_eq!(2, ffmpeg_params.matches("silenceremove=start_periods=1:").count());
+    assert!(ffmpeg_params.ends_with(",areverse"));
+    assert!(!ffmpeg_params.contains("stop_periods"));
+    // The params are split by spaces when running ffmpeg
+    assert_eq!(3, ffmpeg_params.matches(' ').count());
+    assert_eq!("-c:a libopus -af loudnorm", config.with_encode_rule("Artist/Song.flac").ffmpeg_params);
+    assert_eq!("-c:a libopus -af volume=2,areverse", with_audio_filter("-c:a libopus -af volume=2", "areverse"));
+}
+
+#[test]
+fn trim_silence_pauses() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["wav"],
+        "encodedExtension": "raw",
+        "ffmpegParams": "-f u8 -ac 1 -ar 8000",
+        "trimSilence": ["**"]
+    }"#).unwrap();
+    let root = TestDir::new("trim_silence_pauses");
+    // 2 s of silence, a 1 s tone, a 3 s pause, another tone and 2 s of silence
+    let tone = (0..8000).map(|sample| if sample / 10 % 2 == 0 { 0x40 } else { 0xC0 }).collect::<Vec<u8>>();
+    let mut wav = silent_wav(10);
+    wav[44 + 2 * 8000..44 + 3 * 8000].copy_from_slice(&tone);
+    wav[44 + 7 * 8000..44 + 8 * 8000].copy_from_slice(&tone);
+    std::fs::write(root.join("Live.wav"), wav).unwrap();
+
+    let ffmpeg_params = config.with_encode_rule("Live.wav").ffmpeg_params;
+    let encoded = std::process::Command::new("ffmpeg")
+        .args(["-v", "error", "-i"])
+        .arg(root.join("Live.wav"))
+        .args(ffmpeg_params.split(' '))
+        .arg(root.join("Live.raw"))
+        .status();
+    // The filter itself is checked by trim_silence where ffmpeg isn't installed
+    let Ok(encoded) = encoded else {
+        return;
+    };
+    assert!(encoded.success());
+    // The tones and the pause are kept, with at most a second of the silence at both ends
+    let seconds = std::fs::metadata(root.join("Live.raw")).unwrap().len() as f64 / 8000.0;
+    assert!((5.0..=7.1).contains(&seconds), "{} seconds", seconds);
+}
+
+#[test]
+fn retention() {
+    let root = TestDir::new("retention");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(input_directory.join("Podcasts/Show")).unwrap();
+    std::fs::create_dir_all(output_directory.join("Podcasts/Show")).unwrap();
+    std::fs::create_dir_all(input_directory.join("Albums/A")).unwrap();
+    let now = std::time::SystemTime::now();
+    for (file, age_days) in [("Podcasts/Show/1.mp3", 30), ("Podcasts/Show/2.mp3", 20), ("Podcasts/Show/3.mp3", 10), ("Podcasts/Show/4.mp3", 0)] {
+        let file = std::fs::File::create(input_directory.join(file)).unwrap();
+        file.set_modified(now - Duration::from_secs(age_days * 86400)).unwrap();
+    }
+    std::fs::write(input_directory.join("Albums/A/Song.mp3"), "").unwrap();
+    std::fs::write(output_directory.join("Podcasts/Show/1.mp3"), "").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "retention": [{ "globs": ["Podcasts/**"], "keepLast": 3, "keepDays": 15 }],
+        "extensionsToEncode": [],
+        "encodedExtension": "ogg",
+        "ffmpegParams": ""
+    })).unwrap();
+
+    let encoded = vec![
+        ("Podcasts/Show/1.mp3".to_string(), "Podcasts/Show/1.mp3".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config.clone(), encoded).plan(&mut warnings).unwrap();
+
+    // 1 is beyond keepLast, 2 is older than keepDays, files outside of the globs are kept
+    assert_eq!(
+        vec!["Podcasts/Show/1.mp3", "Podcasts/Show/2.mp3"].into_iter().map(String::from).collect::<HashSet<String>>(),
+        plan.pruned
+    );
+    assert_eq!(
+        vec!["Podcasts/Show/3.mp3", "Podcasts/Show/4.mp3", "Albums/A/Song.mp3"].into_iter().map(String::from).collect::<HashSet<String>>(),
+        plan.to_process
+    );
+    // Outputs of pruned sources are deleted right away
+    assert_eq!(vec!["Podcasts/Show/1.mp3".to_string()].into_iter().collect::<HashSet<String>>(), plan.to_delete);
+    assert_eq!(ActionReason::Pruned, plan.delete_reason("Podcasts/Show/1.mp3"));
+    // Keeping files for longer than time can count keeps all of them
+    let keep_forever = Config {
+        retention: Some(
+            serde_json::from_value(serde_json::json!([{ "globs": ["Podcasts/**"], "keepDays": u64::MAX }])).unwrap()
+        ),
+        ..config
+    };
+    assert!(SyncPlanner::new(keep_forever, HashMap::new()).plan(&mut warnings).unwrap().pruned.is_empty());
+}
+
+#[test]
+fn atomic_state_files() {
+    let root = TestDir::new("atomic_state_files");
+    let path = root.join("encoded.json");
+    std::fs::write(&path, "{\"Old.flac\":").unwrap();
+    let encoded = vec![("Song.flac".to_string(), "Song.ogg".to_string())]
+        .into_iter()
+        .collect::<HashMap<String, String>>();
+    save_json(&path, &encoded, false).unwrap();
+    let saved: HashMap<String, String> = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let temp_file_left = root.join("encoded.json.tmp").exists();
+
+    assert_eq!(encoded, saved);
+    assert!(!temp_file_left);
+}
+
+#[test]
+fn delete_limits() {
+    let root = TestDir::new("delete_limits");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    for file in ["1.mp3", "2.mp3", "3.mp3", "4.mp3"] {
+        std::fs::write(output_directory.join(file), "").unwrap();
+    }
+    std::fs::write(input_directory.join("1.mp3"), "").unwrap();
+    let plan = |limits: serde_json::Value| {
+        let mut config = serde_json::json!({
+            "inputDirectory": input_directory.to_string_lossy(),
+            "outputDirectory": output_directory.to_string_lossy(),
+            "extensionsToEncode": [],
+            "encodedExtension": "ogg",
+            "ffmpegParams": ""
+        });
+        config.as_object_mut().unwrap().extend(limits.as_object().unwrap().clone());
+        let config: Config = serde_json::from_value(config).unwrap();
+        SyncPlanner::new(config, HashMap::new()).plan(&mut Warnings::new()).unwrap()
+    };
+    let unlimited = plan(serde_json::json!({}));
+    let count_exceeded = plan(serde_json::json!({ "maxDeleteCount": 2 }));
+    let count_kept = plan(serde_json::json!({ "maxDeleteCount": 3 }));
+    let percent_exceeded = plan(serde_json::json!({ "maxDeletePercent": 50 }));
+    let percent_kept = plan(serde_json::json!({ "maxDeletePercent": 75 }));
+
+    // 1.mp3 matches its source and is kept, the other 3 of 4 outputs have no source
+    assert_eq!(3, unlimited.to_delete.len());
+    assert_eq!(None, unlimited.exceeded_delete_limit());
+    assert_eq!(Some("more than maxDeleteCount 2".to_string()), count_exceeded.exceeded_delete_limit());
+    assert_eq!(None, count_kept.exceeded_delete_limit());
+    assert_eq!(Some("75%, more than maxDeletePercent 50%".to_string()), percent_exceeded.exceeded_delete_limit());
+    assert_eq!(None, percent_kept.exceeded_delete_limit());
+}
+
+#[test]
+fn expired_trash() {
+    let root = TestDir::new("expired_trash");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    let trash_directory = root.join("trash");
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let old_run = (now - 10 * 86400).to_string();
+    let new_run = (now - 86400).to_string();
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    for directory in [format!("{}/Artist", old_run), new_run.clone(), String::from("Kept")] {
+        std::fs::create_dir_all(trash_directory.join(directory)).unwrap();
+    }
+    std::fs::write(trash_directory.join(&old_run).join("Artist/Song.ogg"), "").unwrap();
+    std::fs::write(trash_directory.join(&new_run).join("Song.ogg"), "").unwrap();
+    std::fs::write(trash_directory.join("Kept/Song.ogg"), "").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "trashDirectory": trash_directory.to_string_lossy(),
+        "trashDays": 7,
+        "extensionsToEncode": [],
+        "encodedExtension": "ogg",
+        "ffmpegParams": ""
+    })).unwrap();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config.clone(), HashMap::new()).plan(&mut warnings).unwrap();
+    let expired = plan.expired_trash(&mut warnings);
+
+    // Only runs older than trashDays, directories not named like a run are left alone
+    assert_eq!(
+        vec![format!("{}/{}", trash_directory.to_string_lossy(), old_run)],
+        expired.into_iter().map(|run| run.to_string()).collect::<Vec<String>>()
+    );
+    // Keeping runs for longer than time can count keeps all of them
+    let keep_forever = Config { trash_days: Some(u64::MAX), ..config };
+    let plan = SyncPlanner::new(keep_forever, HashMap::new()).plan(&mut warnings).unwrap();
+    assert!(plan.expired_trash(&mut warnings).is_empty());
+}
+
+#[test]
+fn sidecars() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "",
+        "removeRoundBrackets": true,
+        "trackNumbers": "strip",
+        "sidecarExtensions": ["lrc", "cue"]
+    }"#).unwrap();
+    let input = vec![
+        "Album/01 - Song (Live).flac",
+        "Album/01 - Song (Live).lrc",
+        "Album/Album (Deluxe).cue",
+        "Album/cover (front).jpg",
+    ].into_iter().map(String::from).collect::<HashSet<String>>();
+    let namer = OutputNamer::new(config, Overrides::default(), &input, HashMap::new(), HashSet::new());
+
+    assert_eq!("Album/Song.ogg", namer.output_file_name("Album/01 - Song (Live).flac"));
+    assert_eq!("Album/Song.lrc", namer.output_file_name("Album/01 - Song (Live).lrc"));
+    // Without a track, sidecars and other files are named as usual
+    assert_eq!("Album/Album.cue", namer.output_file_name("Album/Album (Deluxe).cue"));
+    assert_eq!("Album/cover.jpg", namer.output_file_name("Album/cover (front).jpg"));
+}
+
+#[test]
+fn folder_sidecars() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "",
+        "removeRoundBrackets": true,
+        "mergeDiscFolders": true,
+        "sidecarExtensions": ["jpg", "nfo"]
+    }"#).unwrap();
+    let input = vec![
+        "Album (Deluxe)/CD1/Song.flac",
+        "Album (Deluxe)/CD1/cover.jpg",
+        "Album (Deluxe)/CD2/Other.flac",
+        "Album (Deluxe)/CD2/cover.jpg",
+        "Album (Deluxe)/Album (Deluxe).nfo",
+        "Album (Deluxe)/Bonus.flac",
+        "Scans/back.jpg",
+        "Booklet (Scans)/Booklet (Scans).nfo",
+        "Booklet (Scans)/index.txt",
+    ].into_iter().map(String::from).collect::<HashSet<String>>();
+    let namer = OutputNamer::new(config, Overrides::default(), &input, HashMap::new(), HashSet::new());
+
+    assert_eq!("Album (Deluxe)/1-Song.ogg", namer.output_file_name("Album (Deluxe)/CD1/Song.flac"));
+    // Covers of merged discs follow their tracks, the second one keeps the disc number to not replace the first
+    assert_eq!("Album (Deluxe)/cover.jpg", namer.output_file_name("Album (Deluxe)/CD1/cover.jpg"));
+    assert_eq!("Album (Deluxe)/2-cover.jpg", namer.output_file_name("Album (Deluxe)/CD2/cover.jpg"));
+    // Named after the folder, which keeps its brackets
+    assert_eq!("Album (Deluxe)/Album (Deluxe).nfo", namer.output_file_name("Album (Deluxe)/Album (Deluxe).nfo"));
+    // Folders without tracks are named as usual
+    assert_eq!("Scans/back.jpg", namer.output_file_name("Scans/back.jpg"));
+    // Only audio files are tracks, a folder of other files has no sidecars following them
+    assert_eq!("Booklet (Scans)/Booklet.nfo", namer.output_file_name("Booklet (Scans)/Booklet (Scans).nfo"));
+}
+
+#[test]
+fn output_locks() {
+    let root = TestDir::new("output_locks");
+    let path = root.join("Song.ogg");
+    let file = std::fs::File::create(&path).unwrap();
+    // Files just written aren't busy unless they're locked
+    let free = matches!(lock_output(&path), OutputLock::Locked(_));
+    file.lock().unwrap();
+    let locked = lock_output(&path);
+    let missing = lock_output(root.join("Missing.ogg"));
+    drop(file);
+
+    assert!(free);
+    assert!(matches!(locked, OutputLock::Busy(reason) if reason == "locked by another program"));
+    assert!(matches!(missing, OutputLock::Unlocked));
+
+    // The lock is held while the output is renamed and deleted
+    let lock = lock_output(&path);
+    std::fs::rename(&path, root.join("Renamed.ogg")).unwrap();
+    std::fs::remove_file(root.join("Renamed.ogg")).unwrap();
+    assert!(matches!(lock, OutputLock::Locked(_)));
+}
+
+#[test]
+fn run_locks() {
+    let root = TestDir::new("run_locks");
+    let encoded = root.join("encoded.json").to_string_lossy().to_string();
+    let first = try_lock_run(&encoded);
+    let second = try_lock_run(&encoded);
+    let lock_file = std::fs::read_to_string(run_lock_path(&encoded)).unwrap();
+    drop(first);
+    // Released with the first run, the file stays
+    let after_release = try_lock_run(&encoded).is_ok();
+    let waited = lock_run(&encoded).is_ok();
+
+    assert_eq!(format!("{}/encoded.json.lock", root.to_string_lossy()), run_lock_path(&encoded));
+    assert_eq!(std::process::id().to_string(), lock_file);
+    assert!(matches!(second, Err(RunLockError::Held(Some(process_id))) if process_id == std::process::id()));
+    assert!(after_release);
+    assert!(waited);
+
+    // Runs lock in the same order however their config lists the outputs, the same file once
+    let phone = root.join("encoded-phone.json").to_string_lossy().to_string();
+    let car = root.join("encoded-car.json").to_string_lossy().to_string();
+    let order = run_lock_order(vec![phone.clone(), car.clone(), phone.clone()]);
+    assert_eq!(vec![car.clone(), phone.clone()], order);
+    assert_eq!(order, run_lock_order(vec![car, phone]));
+}
+
+#[test]
+fn mirrors() {
+    let root = TestDir::new("mirrors");
+    let output_directory = root.join("output");
+    let mirror_directory = root.join("mirror");
+    std::fs::create_dir_all(output_directory.join("Album")).unwrap();
+    std::fs::create_dir_all(&mirror_directory).unwrap();
+    std::fs::write(output_directory.join("Album/New.ogg"), "new").unwrap();
+    std::fs::write(mirror_directory.join("Old.ogg"), "old").unwrap();
+    let mirror = RclonePath::Local(mirror_directory.to_string_lossy().to_string());
+    let broken_mirror = RclonePath::Local(root.join("file").to_string_lossy().to_string());
+    std::fs::write(root.join("file"), "").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": "",
+        "outputDirectory": output_directory.to_string_lossy(),
+        "mirrors": [mirror.to_string(), broken_mirror.to_string()],
+        "extensionsToEncode": [],
+        "encodedExtension": "ogg",
+        "ffmpegParams": ""
+    })).unwrap();
+
+    let mut mirror_state = MirrorState::default();
+    mirror_state.mirrors.insert(
+        mirror.to_string(),
+        PendingChanges {
+            upload: vec!["Album/Deleted.ogg".to_string()].into_iter().collect(),
+            delete: vec!["Album/New.ogg".to_string()].into_iter().collect(),
+        },
+    );
+    let executed = ExecutedActions {
+        processed: vec![("Album/New.flac".to_string(), "Album/New.ogg".to_string())].into_iter().collect(),
+        deleted: vec!["Old.ogg".to_string(), "Album/Deleted.ogg".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+    mirror_state.record(config.mirrors.as_ref().unwrap(), &executed);
+    // Newer changes replace the pending ones
+    let expected = PendingChanges {
+        upload: vec!["Album/New.ogg".to_string()].into_iter().collect(),
+        delete: vec!["Album/Deleted.ogg".to_string(), "Old.ogg".to_string()].into_iter().collect(),
+    };
+    assert_eq!(Some(&expected), mirror_state.mirrors.get(&mirror.to_string()));
+
+    let mut warnings = Warnings::new();
+    let failed = mirror_state.push(&config, &mut warnings);
+    let uploaded = std::fs::read_to_string(mirror_directory.join("Album/New.ogg")).ok();
+    let old_left = mirror_directory.join("Old.ogg").exists();
+
+    assert_eq!(Some("new".to_string()), uploaded);
+    assert!(!old_left);
+    // Deleting files missing from a mirror isn't a failure, only the broken mirror keeps its changes
+    assert_eq!(3, failed);
+    assert_eq!(vec![broken_mirror.to_string()], mirror_state.mirrors.keys().cloned().collect::<Vec<String>>());
+    assert_eq!(expected, mirror_state.mirrors[&broken_mirror.to_string()]);
+
+    // Refreshed tags and covers reach the mirrors too
+    let executed = ExecutedActions {
+        refreshed: vec!["Album/New.ogg".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+    let mut mirror_state = MirrorState::default();
+    mirror_state.record(std::slice::from_ref(&mirror), &executed);
+    assert_eq!(expected.upload, mirror_state.mirrors[&mirror.to_string()].upload);
+}
+
+#[test]
+fn playlists() {
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "",
+        "outputDirectory": "",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "",
+        "removeRoundBrackets": true
+    }"#).unwrap();
+    let input = vec![
+        "Artist/Album/Song (Live).flac",
+        "Artist/Album/Other.mp3",
+        "Playlists/Mix (2022).m3u8",
+    ].into_iter().map(String::from).collect::<HashSet<String>>();
+    let namer = OutputNamer::new(config, Overrides::default(), &input, HashMap::new(), HashSet::new());
+    let playlist = "#EXTM3U\r\n\
+        #EXTINF:123,Artist - Song\r\n\
+        ../Artist/Album/Song (Live).flac\r\n\
+        ..\\Artist\\Album\\Other.mp3\r\n\
+        ../Artist/Album/Missing.flac\r\n\
+        http://radio.example/stream\r\n\
+        /music/Song.flac";
+
+    assert!(is_playlist("Playlists/Mix.M3U"));
+    assert!(!is_playlist("Artist/Album/Song.flac"));
+    assert_eq!(
+        "#EXTM3U\r\n\
+        #EXTINF:123,Artist - Song\r\n\
+        ../Artist/Album/Song.ogg\r\n\
+        ../Artist/Album/Other.mp3\r\n\
+        ../Artist/Album/Missing.flac\r\n\
+        http://radio.example/stream\r\n\
+        /music/Song.flac",
+        rewrite_playlist(playlist, "Playlists/Mix (2022).m3u8", &namer, &input)
+    );
+    // Playlists which aren't UTF-8 are read as Latin-1
+    assert_eq!("Björk/Jóga.flac", decode_playlist("Björk/Jóga.flac".as_bytes()));
+    assert_eq!("Björk/Jóga.flac", decode_playlist(b"Bj\xf6rk/J\xf3ga.flac"));
+}
+
+#[test]
+fn exported_playlist() {
+    let output = vec![
+        "B/Song.ogg",
+        "A/Z.ogg",
+        "A/Sub/Song.ogg",
+        "A/B.mp3",
+        "A/cover.jpg",
+        "Top.ogg",
+    ].into_iter().map(String::from).collect::<Vec<String>>();
+
+    assert_eq!(
+        "#EXTM3U\nTop.ogg\nA/B.mp3\nA/Z.ogg\nA/Sub/Song.ogg\nB/Song.ogg\n",
+        create_playlist("All.m3u8", output.iter())
+    );
+    assert_eq!(
+        "#EXTM3U\n../Top.ogg\n../A/B.mp3\n../A/Z.ogg\n../A/Sub/Song.ogg\nSong.ogg\n",
+        create_playlist("B/All.m3u8", output.iter())
+    );
+}
+
+#[test]
+fn exported_playlist_kept() {
+    let root = TestDir::new("exported_playlist_kept");
+    std::fs::create_dir_all(root.join("input/Album")).unwrap();
+    std::fs::write(root.join("input/Album/Song.mp3"), "mp3").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "extensionsToEncode": [],
+        "exportPlaylist": "./Lists/All.m3u8"
+    }));
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config.clone(), HashMap::new()).plan(&mut warnings).unwrap();
+    let executed = match Executor::new(&EventSink::new()).execute(&plan, &mut warnings) {
+        Ok(executed) => executed,
+        Err(interrupted) => panic!("{}", interrupted.error),
+    };
+    let contents = plan.exported_playlist(&executed).unwrap();
+    std::fs::create_dir_all(root.join("output/Lists")).unwrap();
+    std::fs::write(root.join("output/Lists/All.m3u8"), &contents).unwrap();
+
+    // The next run neither deletes the playlist as an output without a source nor reports it
+    let plan = SyncPlanner::new(config, plan.final_encoded(&executed)).plan(&mut warnings).unwrap();
+    assert_eq!("#EXTM3U\n../Album/Song.mp3\n", contents);
+    assert!(plan.to_delete.is_empty());
+    assert!(plan.verify(false, false).unwrap().categories.is_empty());
+}
+
+#[test]
+fn applied_plan() {
+    let root = TestDir::new("applied_plan");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    std::fs::write(root.join("input/cover.jpg"), "cover").unwrap();
+    std::fs::write(root.join("output/Removed.ogg"), "encoded").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy()
+    }));
+    let new_plan = |config: &Config| {
+        SyncPlanner::new(config.clone(), HashMap::new()).plan(&mut Warnings::new()).unwrap()
+    };
+    new_plan(&config).export().save(root.join("plan.json")).unwrap();
+    let exported_plan = ExportedPlan::load(root.join("plan.json")).unwrap();
+
+    // The plan runs as exported while the directories are unchanged
+    let mut plan = new_plan(&config);
+    plan.use_exported(&exported_plan).unwrap();
+    let mut warnings = Warnings::new();
+    if let Err(interrupted) = Executor::new(&EventSink::new()).execute(&plan, &mut warnings) {
+        panic!("{}", interrupted.error);
+    }
+    assert_eq!(vec!["cover.jpg"], walk_files(&root.join("output")));
+
+    // It's refused once a file was added, removed or renamed, for other directories and without a listing checksum
+    let apply = |config: &Config, exported_plan: &ExportedPlan| {
+        new_plan(config).use_exported(exported_plan).unwrap_err().to_string()
+    };
+    assert_eq!("the input or output directory changed since the plan was exported", apply(&config, &exported_plan));
+    let mut other_output = config.clone();
+    other_output.output_directory = RclonePath::Local(root.join("input").to_string_lossy().to_string());
+    assert_eq!("the plan was exported for other directories", apply(&other_output, &exported_plan));
+    let mut report = exported_plan.clone();
+    report.listing_checksum = None;
+    assert_eq!("the plan has no listing checksum, it's a report or from an older version", apply(&config, &report));
+}
+
+#[test]
+fn edited_plan() {
+    let root = TestDir::new("edited_plan");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    for file in ["A.flac", "B.flac", "cover.jpg"] {
+        std::fs::write(input_directory.join(file), file).unwrap();
+    }
+    std::fs::write(output_directory.join("Removed.ogg"), "encoded").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": ""
+    })).unwrap();
+    let new_plan = || SyncPlanner::new(config.clone(), HashMap::new()).plan(&mut Warnings::new()).unwrap();
+    let exported_plan = new_plan().export();
+    let edited = |edit: &dyn Fn(&mut Vec<PlannedAction>)| {
+        let mut edited_plan = exported_plan.clone();
+        edit(&mut edited_plan.actions);
+        let mut plan = new_plan();
+        plan.use_exported(&edited_plan).map(|_| plan).map_err(|error| error.to_string())
+    };
+    let is_a = |action: &&mut PlannedAction| action.source.as_deref() == Some("A.flac");
+
+    // Removed actions are skipped
+    let plan = edited(&|actions| actions.retain(|action| action.source.as_deref() != Some("B.flac"))).unwrap();
+    assert_eq!(
+        vec!["A.flac", "cover.jpg"].into_iter().map(String::from).collect::<HashSet<String>>(),
+        plan.to_process
+    );
+    assert_eq!(1, plan.to_delete.len());
+
+    let errors = [
+        edited(&|actions| actions.iter_mut().find(is_a).unwrap().destination = Some(String::from("Other.ogg"))),
+        edited(&|actions| actions.iter_mut().find(is_a).unwrap().kind = ActionKind::Copy),
+        edited(&|actions| actions.iter_mut().find(is_a).unwrap().source = Some(String::from("Missing.flac"))),
+        edited(&|actions| {
+            let action = actions.iter_mut().find(|action| action.kind == ActionKind::Delete).unwrap();
+            action.destination = Some(String::from("Missing.ogg"));
+        }),
+        edited(&|actions| {
+            let action = actions.iter_mut().find(is_a).unwrap().clone();
+            actions.push(action);
+        }),
+    ];
+    assert_eq!(
+        vec![
+            "A.flac is named differently with the current config",
+            "A.flac is encoded with the current config",
+            "Missing.flac is not in the input",
+            "Missing.ogg is not in the output",
+            "A.ogg is written by multiple actions",
+        ],
+        errors.iter().map(|error| error.as_ref().err().unwrap().as_str()).collect::<Vec<&str>>()
+    );
+
+    // The edited file is removed once the plan is applied, and kept with the edits if it can't be read or applied
+    #[cfg(unix)]
+    {
+        let kept_file = |error: Box<dyn std::error::Error>| {
+            let error = error.to_string();
+            let (_, path) = error.split_once(", the edited plan is kept in ").unwrap();
+            let contents = std::fs::read_to_string(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            contents
+        };
+        let mut edited_paths = Vec::new();
+        let actions = exported_plan.edit_with("true", |edited_plan| Ok(edited_plan.actions.len())).unwrap();
+        assert_eq!(exported_plan.actions.len(), actions);
+        let error = exported_plan
+            .edit_with("sed -i s/A.flac/Missing.flac/", |edited_plan| {
+                let mut plan = new_plan();
+                plan.use_exported(edited_plan)
+            })
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Missing.flac is not in the input"));
+        assert!(kept_file(error).contains("Missing.flac"));
+        let error = exported_plan.edit_with("sed -i s/actions/broken/", |_| Ok(())).unwrap_err();
+        assert!(kept_file(error).contains("broken"));
+        // Every edit gets a file of its own
+        for _ in 0..2 {
+            let error = exported_plan.edit_with("true", |_| Err::<(), _>(Box::from("Rejected"))).unwrap_err();
+            let error = error.to_string();
+            edited_paths.push(error.split_once(" kept in ").unwrap().1.to_string());
+        }
+        assert_ne!(edited_paths[0], edited_paths[1]);
+        for path in edited_paths {
+            std::fs::remove_file(path).unwrap();
+        }
+        // A failed editor leaves nothing behind
+        assert!(exported_plan.edit_with("false", |_| Ok(())).unwrap_err().to_string().contains("exited with"));
+    }
+}
+
+#[test]
+fn exported_script() {
+    assert_eq!("Artist/Song.flac", shell_quote("Artist/Song.flac"));
+    assert_eq!("'It'\\''s $HOME.flac'", shell_quote("It's $HOME.flac"));
+    assert_eq!("''", shell_quote(""));
+
+    let root = TestDir::new("exported_script");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(input_directory.join("Album")).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    std::fs::write(input_directory.join("Album/It's.flac"), "flac").unwrap();
+    std::fs::write(input_directory.join("Album/cover.jpg"), "cover").unwrap();
+    std::fs::write(output_directory.join("Removed.ogg"), "encoded").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K",
+        "copyCovers": true
+    })).unwrap();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config, HashMap::new()).plan(&mut warnings).unwrap();
+    let script = plan.export_script();
+
+    let input = input_directory.to_string_lossy();
+    let output = output_directory.to_string_lossy();
+    assert!(script.starts_with("#!/bin/sh\n"));
+    let commands = script.lines().filter(|line| !line.is_empty() && !line.starts_with('#')).collect::<Vec<&str>>();
+    assert_eq!(
+        vec![
+            String::from("set -e"),
+            format!("mkdir -p {}", output),
+            format!("mkdir -p {}/Album", output),
+            format!(
+                "ffmpeg -nostdin -y -i '{0}/Album/It'\\''s.flac' -c:a libopus -b:a 128K '{1}/Album/It'\\''s.ffmusicsync-part.ogg'",
+                input, output
+            ),
+            format!("mv '{0}/Album/It'\\''s.ffmusicsync-part.ogg' '{0}/Album/It'\\''s.ogg'", output),
+            format!("cp {0}/Album/cover.jpg {1}/Album/cover.ffmusicsync-part.jpg", input, output),
+            format!("mv {0}/Album/cover.ffmusicsync-part.jpg {0}/Album/cover.jpg", output),
+            format!("rm {}/Removed.ogg", output),
+        ],
+        commands
+    );
+    assert!(script.contains("# ffmusicsync copies the cover of Album/It's.flac to Album/It's.ogg here"));
+
+    // A line break in a file name can't end a comment and run the rest as a command
+    let mut script = ShellScript::new(&RclonePath::Local(String::from("in")), &RclonePath::Local(String::from("out")));
+    script.comment("Album/Song\nrm -rf ~.flac");
+    script.begin_unless_exists(&RclonePath::Local(String::from("out/Song.ogg")));
+    script.command(&["mv", "out/Song.part.ogg", "out/Song.ogg"]);
+    script.end_block();
+    assert!(script.render().ends_with(
+        "set -e\n# Album/Song rm -rf ~.flac\nif [ ! -e out/Song.ogg ]; then\n  mv out/Song.part.ogg out/Song.ogg\nfi\n"
+    ));
+}
+
+#[test]
+fn encode_command_lines() {
+    let config = |extra: serde_json::Value| {
+        let mut config = serde_json::json!({
+            "inputDirectory": "Music",
+            "outputDirectory": "Phone",
+            "extensionsToEncode": ["flac"],
+            "encodedExtension": "ogg",
+            "ffmpegParams": "-c:a libopus -b:a 128K"
+        });
+        config.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value::<Config>(config).unwrap()
+    };
+    assert_eq!(vec!["ffmpeg"], encode_command_line(&config(serde_json::json!({}))));
+    assert_eq!(
+        vec!["nice", "-n", "19", "ffmpeg", "-hwaccel", "auto"],
+        encode_command_line(&config(serde_json::json!({ "ffmpegPre": "-hwaccel  auto", "nice": 19 })))
+    );
+    assert!(lint_config(&config(serde_json::json!({ "nice": 25 }))).iter().any(|issue| issue.message.contains("nice 25")));
+
+    // ffprobe is taken from the directory of a configured ffmpeg
+    assert_eq!("ffprobe", sibling_program("ffmpeg", "ffprobe"));
+    assert_eq!(
+        std::path::Path::new("/opt/ffmpeg/bin").join("ffprobe").to_string_lossy(),
+        sibling_program("/opt/ffmpeg/bin/ffmpeg", "ffprobe")
+    );
+    assert_eq!(std::path::Path::new("tools").join("ffprobe.exe").to_string_lossy(), sibling_program("tools/ffmpeg.exe", "ffprobe"));
+
+    // Each config runs its own ffmpeg, a config without ffmpegPath doesn't pick up another one's
+    let configured = test_config(serde_json::json!({ "ffmpegPath": "/opt/ffmpeg/bin/ffmpeg" }));
+    assert_eq!("/opt/ffmpeg/bin/ffmpeg", ffmpeg_path(&configured));
+    assert_eq!(sibling_program("/opt/ffmpeg/bin/ffmpeg", "ffprobe"), ffprobe_path(&configured));
+    assert_eq!("ffmpeg", ffmpeg_path(&test_config(serde_json::json!({}))));
+
+    // Every rclone command gets the flags, each entry being one argument
+    set_rclone_flags(vec![String::from("--bwlimit"), String::from("5M")]);
+    assert_eq!(vec!["rclone", "--bwlimit", "5M"], rclone_command_line());
+    set_rclone_flags(Vec::new());
+    assert!(lint_config(&config(serde_json::json!({ "rcloneFlags": ["--bwlimit 5M"] })))
+        .iter()
+        .any(|issue| issue.message.contains("rcloneFlags")));
+}
+
+#[test]
+fn shallow_verify() {
+    let root = TestDir::new("shallow_verify");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    let trash_directory = root.join("trash");
+    std::fs::create_dir_all(input_directory.join("Album")).unwrap();
+    std::fs::create_dir_all(output_directory.join("Album")).unwrap();
+    for file in ["Album/Intact.flac", "Album/Truncated.flac", "Album/Edited.flac"] {
+        std::fs::write(input_directory.join(file), file).unwrap();
+    }
+    std::fs::write(input_directory.join("Album/cover.jpg"), "cover").unwrap();
+    for file in ["Album/Intact.ogg", "Album/Truncated.ogg", "Album/Edited.ogg"] {
+        std::fs::write(output_directory.join(file), "encoded").unwrap();
+    }
+    std::fs::write(output_directory.join("Album/cover.jpg"), "broken").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "trashDirectory": trash_directory.to_string_lossy(),
+        "maxDeleteCount": 3
+    }));
+    let encoded = ["Intact", "Truncated", "Edited"]
+        .iter()
+        .map(|name| (format!("Album/{}.flac", name), format!("Album/{}.ogg", name)))
+        .chain([("Album/cover.jpg".to_string(), "Album/cover.jpg".to_string())])
+        .collect::<HashMap<String, String>>();
+    let output_info = vec![
+        ("Album/Intact.ogg".to_string(), OutputInfo { size: 7, duration: Some(180.0) }),
+        // Only the recorded duration tells a broken file from one whose tags were edited
+        ("Album/Truncated.ogg".to_string(), OutputInfo { size: 4096, duration: Some(180.0) }),
+        ("Album/Edited.ogg".to_string(), OutputInfo { size: 4096, duration: None }),
+    ].into_iter().collect::<HashMap<String, OutputInfo>>();
+    let mut warnings = Warnings::new();
+    let mut plan = SyncPlanner::new(config, encoded)
+        .with_output_info(output_info)
+        .plan(&mut warnings)
+        .unwrap();
+    let shallow = plan.verify(true, false).unwrap();
+    let full = plan.verify(false, false).unwrap();
+    // Requeued outputs are deleted like by a sync
+    plan.requeue(&full.broken);
+    let within_delete_limit = plan.exceeded_delete_limit().is_none();
+    let executed = match Executor::new(&EventSink::new()).execute(&plan, &mut warnings) {
+        Ok(executed) => executed,
+        Err(interrupted) => panic!("{}", interrupted.error),
+    };
+    let requeued = plan.requeued_encoded(&executed);
+    let remaining_output = ["Intact", "Truncated", "Edited"]
+        .iter()
+        .map(|name| output_directory.join(format!("Album/{}.ogg", name)).exists())
+        .collect::<Vec<bool>>();
+    // Below the directory of the trash run
+    let trashed = walk_files(&trash_directory)
+        .iter()
+        .filter_map(|file| file.split_once('/').map(|(_, file)| file.to_string()))
+        .collect::<Vec<String>>();
+
+    let files = |report: &HealthReport, name: &str| {
+        report.categories
+            .iter()
+            .find(|category| category.name.starts_with(name))
+            .map(|category| category.files.clone())
+            .unwrap_or_default()
+    };
+    assert_eq!(vec!["Album/cover.jpg".to_string()], files(&shallow, "Copies differing"));
+    assert_eq!(vec!["Album/Truncated.ogg".to_string()], files(&shallow, "Outputs with a different duration"));
+    assert_eq!(vec!["Album/Edited.ogg".to_string()], files(&shallow, "Outputs with a different size"));
+    // The full verify reads the duration of every output, none of these can be read
+    assert_eq!(vec!["Album/cover.jpg".to_string()], files(&full, "Copies differing"));
+    assert_eq!(
+        vec!["Album/Intact.ogg".to_string(), "Album/Truncated.ogg".to_string()],
+        files(&full, "Outputs with a different duration")
+    );
+    assert!(files(&full, "Outputs with a different size").is_empty());
+    // Requeued outputs are deleted and left out of the state, so the next sync processes them again
+    assert_eq!(
+        vec!["Album/Intact.ogg", "Album/Truncated.ogg", "Album/cover.jpg"],
+        full.broken.iter().map(String::as_str).collect::<Vec<&str>>()
+    );
+    assert!(within_delete_limit);
+    assert_eq!(vec![false, false, true], remaining_output);
+    assert_eq!(vec!["Album/Intact.ogg", "Album/Truncated.ogg", "Album/cover.jpg"], trashed);
+    assert_eq!(
+        vec![("Album/Edited.flac".to_string(), "Album/Edited.ogg".to_string())],
+        requeued.into_iter().collect::<Vec<(String, String)>>()
+    );
+}
+
+#[test]
+fn verify_decode_without_ffmpeg() {
+    let root = TestDir::new("verify_decode_without_ffmpeg");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    std::fs::write(root.join("input/Song.flac"), "flac").unwrap();
+    std::fs::write(root.join("output/Song.ogg"), "encoded").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "ffmpegPath": root.join("missing-ffmpeg").to_string_lossy()
+    }));
+    let encoded = [(String::from("Song.flac"), String::from("Song.ogg"))].into_iter().collect();
+    let plan = SyncPlanner::new(config, encoded).plan(&mut Warnings::new()).unwrap();
+
+    // A missing ffmpeg says nothing about the outputs, they mustn't be reported as undecodable and requeued
+    assert!(plan.verify(false, true).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn probe_encodes() {
+    use std::os::unix::fs::PermissionsExt;
+    let root = TestDir::new("probe_encodes");
+    for file in ["input/A/1.flac", "input/A/2.flac", "input/A/cover.jpg", "input/Live/1.flac"] {
+        std::fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+        std::fs::write(root.join(file), "flac").unwrap();
+    }
+    // Logs its arguments, refuses unknown encoders like ffmpeg and writes the output given last otherwise
+    let ffmpeg = root.join("ffmpeg");
+    let script = format!(
+        "#!/bin/sh\necho \"$@\" >> '{}'\ncase \"$*\" in *bogus*) echo \"Unknown encoder 'bogus'\" >&2; exit 1;; esac\n\
+        for last; do :; done\necho encoded > \"$last\"\n",
+        root.join("calls.log").display()
+    );
+    std::fs::write(&ffmpeg, script).unwrap();
+    std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "ffmpegParams": "-c:a libopus",
+        "ffmpegPath": ffmpeg.to_string_lossy()
+    }));
+    let new_plan = || SyncPlanner::new(config.clone(), HashMap::new()).plan(&mut Warnings::new()).unwrap();
+
+    // Sources sharing their params are probed once, copies not at all, and one second is encoded
+    assert_eq!(Ok(()), new_plan().probe_encodes());
+    let calls = std::fs::read_to_string(root.join("calls.log")).unwrap();
+    assert_eq!(1, calls.lines().count());
+    assert!(calls.contains("A/1.flac -t 1 -c:a libopus "));
+    assert!(!std::env::temp_dir().join(format!("ffmusicsync-probe-{}.ogg", std::process::id())).exists());
+    std::fs::remove_file(root.join("calls.log")).unwrap();
+    // The first params ffmpeg refuses stop the run with its error
+    std::fs::write(root.join("input/Live/.ffmusicsync.json"), r#"{"ffmpegParams": "-c:a bogus"}"#).unwrap();
+    assert_eq!(
+        Err((String::from("-c:a bogus"), String::from("Unknown encoder 'bogus'"))),
+        new_plan().probe_encodes()
+    );
+    assert_eq!(1, std::fs::read_to_string(root.join("calls.log")).unwrap().lines().count());
+}
+
+#[test]
+fn extension_changes() {
+    // Only the codec matters, the quality isn't compared
+    assert!(can_change_extension("Song.ogg", "opus", Some("-c:a libopus -b:a 96K"), "-c:a opus -b:a 128K"));
+    assert!(!can_change_extension("Song.ogg", "opus", Some("-c:a libvorbis"), "-c:a libopus"));
+    // Without recorded params, only containers holding one codec tell it
+    assert!(!can_change_extension("Song.ogg", "opus", None, "-c:a libopus"));
+    assert!(can_change_extension("Song.opus", "ogg", None, "-c:a libopus"));
+    assert!(can_change_extension("Song.m4a", "m4b", None, "-c:a libmp3lame"));
+    assert!(!can_change_extension("Song.mp3", "ogg", None, "-c:a libopus"));
+    assert!(!can_change_extension("Song.xyz", "ogg", None, "-c:a libopus"));
+
+    let root = TestDir::new("extension_changes");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    for (input_file, output_file) in [("Opus.flac", "Opus.ogg"), ("Vorbis.flac", "Vorbis.ogg")] {
+        std::fs::write(input_directory.join(input_file), input_file).unwrap();
+        std::fs::write(output_directory.join(output_file), output_file).unwrap();
+    }
+    let encoded = vec![
+        ("Opus.flac".to_string(), "Opus.ogg".to_string()),
+        ("Vorbis.flac".to_string(), "Vorbis.ogg".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    let params = vec![
+        ("Opus.flac".to_string(), "-c:a libopus -b:a 128K".to_string()),
+        ("Vorbis.flac".to_string(), "-c:a libvorbis".to_string()),
+    ].into_iter().collect::<HashMap<String, String>>();
+    let plan = |remux: bool| {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "inputDirectory": input_directory.to_string_lossy(),
+            "outputDirectory": output_directory.to_string_lossy(),
+            "extensionsToEncode": ["flac"],
+            "encodedExtension": "opus",
+            "ffmpegParams": "-c:a libopus -b:a 128K",
+            "remuxOnExtensionChange": remux
+        })).unwrap();
+        let mut warnings = Warnings::new();
+        SyncPlanner::new(config, encoded.clone()).with_params(params.clone()).plan(&mut warnings).unwrap()
+    };
+    let remuxed = plan(true);
+    let reported = plan(false);
+
+    assert_eq!(Some(&"Opus.opus".to_string()), remuxed.to_rename.get("Opus.ogg"));
+    assert_eq!(ActionReason::ExtensionChanged, remuxed.rename_reason("Opus.ogg", "Opus.opus"));
+    assert_eq!(vec!["Vorbis.flac".to_string()].into_iter().collect::<HashSet<String>>(), remuxed.to_process);
+    assert!(remuxed.remuxable.is_empty());
+    // Without the option, the outputs are encoded again and only reported
+    assert!(reported.to_rename.is_empty());
+    assert_eq!(2, reported.to_process.len());
+    assert_eq!(vec!["Opus.flac".to_string()].into_iter().collect::<HashSet<String>>(), reported.remuxable);
+}
+
+#[test]
+fn fair_scheduling() {
+    let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+    assert_eq!(
+        names(&["N1", "N2", "B1", "N3", "N4", "B2", "B3", "B4"]),
+        interleave(names(&["N1", "N2", "N3", "N4"]), names(&["B1", "B2", "B3", "B4"]), 2)
+    );
+    assert_eq!(names(&["N1", "B1", "N2", "N3"]), interleave(names(&["N1", "N2", "N3"]), names(&["B1"]), 1));
+
+    let root = TestDir::new("fair_scheduling");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    let now = std::time::SystemTime::now();
+    for (index, input_file) in ["Old 1.flac", "Old 2.flac", "Old 3.flac", "New A.flac", "New B.flac"].iter().enumerate() {
+        let file = std::fs::File::create(input_directory.join(input_file)).unwrap();
+        file.set_modified(now - Duration::from_secs(60 * (5 - index as u64))).unwrap();
+    }
+    // The outputs of the old sources are missing, so they're encoded again
+    let encoded = (1..=3)
+        .map(|number| (format!("Old {}.flac", number), format!("Old {}.ogg", number)))
+        .collect::<HashMap<String, String>>();
+    let plan = |new_sources_per_cycle: Option<usize>| {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "inputDirectory": input_directory.to_string_lossy(),
+            "outputDirectory": output_directory.to_string_lossy(),
+            "extensionsToEncode": ["flac"],
+            "encodedExtension": "ogg",
+            "ffmpegParams": "-c:a libopus -b:a 128K",
+            "newSourcesPerCycle": new_sources_per_cycle
+        })).unwrap();
+        let mut warnings = Warnings::new();
+        SyncPlanner::new(config, encoded.clone()).plan(&mut warnings).unwrap().process_order()
+    };
+    let by_name = plan(None);
+    let interleaved = plan(Some(1));
+
+    assert_eq!(names(&["New A.flac", "New B.flac", "Old 1.flac", "Old 2.flac", "Old 3.flac"]), by_name);
+    // New sources come newest first
+    assert_eq!(names(&["New B.flac", "Old 1.flac", "New A.flac", "Old 2.flac", "Old 3.flac"]), interleaved);
+}
+
+#[test]
+fn stream_remuxes() {
+    assert_eq!(Some(String::from("-vn -c:a copy")), remux_params("opus", "opus", "-c:a libopus -b:a 128K -vn"));
+    assert_eq!(Some(String::from("-vn -c:a copy -f ipod")), remux_params("aac", "m4a", "-c:a aac -b:a 256K -f ipod"));
+    assert_eq!(None, remux_params("vorbis", "opus", "-c:a libopus -b:a 128K"));
+    // Vorbis fits ogg but not opus files
+    assert_eq!(None, remux_params("vorbis", "opus", "-c:a libvorbis"));
+    // Filters change the audio, it has to be encoded
+    assert_eq!(None, remux_params("opus", "ogg", "-c:a libopus -af atempo=1.25"));
+    assert_eq!(None, remux_params("mp3", "mp3", "-c:a libmp3lame -ac 1"));
+    // Without an encoder the codec of the output isn't known
+    assert_eq!(None, remux_params("opus", "ogg", "-b:a 128K"));
+}
+
+#[test]
+fn lossy_transcodes() {
+    assert!(is_lossy_source("Album/Song.MP3"));
+    assert!(!is_lossy_source("Album/Song.m4a"));
+    assert!(encodes_lossy("ogg", "-c:a libopus -b:a 128K"));
+    assert!(!encodes_lossy("m4a", "-c:a alac"));
+    assert!(!encodes_lossy("wav", "-c:a pcm_s16le"));
+    assert!(encodes_lossy("m4a", "-vn"));
+    assert!(!encodes_lossy("flac", ""));
+
+    let root = TestDir::new("lossy_transcodes");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::create_dir_all(&output_directory).unwrap();
+    for file in ["Lossless.flac", "Lossy.mp3", "New.opus"] {
+        std::fs::write(input_directory.join(file), file).unwrap();
+    }
+    std::fs::write(output_directory.join("Lossy.ogg"), "encoded").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "extensionsToEncode": ["flac", "mp3", "opus"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K",
+        "skipLossyTranscode": true
+    })).unwrap();
+    let encoded = vec![("Lossy.mp3".to_string(), "Lossy.ogg".to_string())].into_iter().collect();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config, encoded).plan(&mut warnings).unwrap();
+
+    assert_eq!("Lossless.ogg", plan.namer().output_file_name("Lossless.flac"));
+    assert_eq!("Lossy.mp3", plan.namer().output_file_name("Lossy.mp3"));
+    assert_eq!("New.opus", plan.namer().output_file_name("New.opus"));
+    // The output encoded before the option was set is replaced by a copy
+    assert_eq!(3, plan.to_process.len());
+    assert!(plan.to_rename.is_empty());
+    assert_eq!(vec!["Lossy.ogg".to_string()].into_iter().collect::<HashSet<String>>(), plan.to_delete);
+    let kinds = plan.export().actions.into_iter().map(|action| action.kind).collect::<Vec<ActionKind>>();
+    assert_eq!(vec![ActionKind::Delete, ActionKind::Encode, ActionKind::Copy, ActionKind::Copy], kinds);
+}
+
+#[test]
+fn low_bitrate_copies() {
+    // One second of silence as PCM WAV, its bitrate is the sample rate times the bits per sample
+    let wav = |sample_rate: u32, bits_per_sample: u16| {
+        let byte_rate = sample_rate * bits_per_sample as u32 / 8;
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + byte_rate).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(sample_rate.to_le_bytes());
+        wav.extend(byte_rate.to_le_bytes());
+        wav.extend((bits_per_sample / 8).to_le_bytes());
+        wav.extend(bits_per_sample.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(byte_rate.to_le_bytes());
+        wav.extend(vec![0; byte_rate as usize]);
+        wav
+    };
+    let root = TestDir::new("low_bitrate_copies");
+    let input_directory = root.join("input");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::write(input_directory.join("Voice.wav"), wav(8000, 8)).unwrap();
+    std::fs::write(input_directory.join("Music.wav"), wav(44100, 16)).unwrap();
+    std::fs::write(input_directory.join("Broken.wav"), "not audio").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "extensionsToEncode": ["wav"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K",
+        "copyIfBitrateBelow": 128
+    })).unwrap();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config.clone(), HashMap::new()).plan(&mut warnings).unwrap();
+
+    // 64 kbps is copied, 705.6 kbps is encoded
+    assert_eq!("Voice.wav", plan.namer().output_file_name("Voice.wav"));
+    assert_eq!("Music.ogg", plan.namer().output_file_name("Music.wav"));
+    // Sources whose bitrate can't be read are encoded
+    assert_eq!("Broken.ogg", plan.namer().output_file_name("Broken.wav"));
+    assert_eq!(1, warnings.len());
+
+    // Synced sources aren't read again, they keep being copied or encoded like their output was
+    let encoded = HashMap::from([
+        (String::from("Voice.wav"), String::from("Voice.ogg")),
+        (String::from("Broken.wav"), String::from("Broken.wav")),
+    ]);
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config, encoded).plan(&mut warnings).unwrap();
+    assert_eq!("Voice.ogg", plan.namer().output_file_name("Voice.wav"));
+    assert_eq!("Broken.wav", plan.namer().output_file_name("Broken.wav"));
+    assert_eq!("Music.ogg", plan.namer().output_file_name("Music.wav"));
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn params_reconciliation() {
+    assert_eq!(
+        Ok((String::from("ogg"), String::from("-c:a libopus -f ogg"), Vec::new())),
+        reconcile_params("ogg", "-c:a libopus -f ogg")
+    );
+    let (extension, params, corrections) = reconcile_params(".opus", "-f mp3 -c:a libopus -b:a 128K").unwrap();
+    assert_eq!("opus", extension);
+    assert_eq!("-c:a libopus -b:a 128K", params);
+    assert_eq!(2, corrections.len());
+    assert!(reconcile_params("mp3", "-c:a libopus").is_err());
+    // Unknown containers can't be checked
+    assert_eq!(
+        Ok((String::from("xyz"), String::from("-f mp3"), Vec::new())),
+        reconcile_params("xyz", "-f mp3")
+    );
+
+    let mut config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "Music",
+        "outputDirectory": "Encoded",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "m4a",
+        "ffmpegParams": "-c:a aac -f adts",
+        "encodeRules": [{ "extensions": ["wav"], "encodedExtension": "flac", "ffmpegParams": "-c:a flac" }]
+    }"#).unwrap();
+    assert_eq!(
+        vec![("ffmpegParams", String::from("removed -f adts, which doesn't write .m4a files"))],
+        config.reconcile_params().unwrap()
+    );
+    assert_eq!("-c:a aac", config.ffmpeg_params);
+    let messages = lint_config(&serde_json::from_str(r#"{
+        "inputDirectory": "Music",
+        "outputDirectory": "Encoded",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": ".m4a",
+        "ffmpegParams": "-c:a aac -f adts"
+    }"#).unwrap()).into_iter().map(|issue| issue.message).collect::<Vec<String>>();
+    assert_eq!(
+        vec![
+            "The encoded extension .m4a of ffmpegParams starts with a dot",
+            "ffmpegParams forces the adts format, which doesn't write .m4a files",
+        ],
+        messages
+    );
+}
+
+#[test]
+fn doctor_checks() {
+    let ffmpeg_version = "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers\nbuilt with gcc 13\n";
+    assert_eq!("6.1.1-3ubuntu5", parse_version(ffmpeg_version));
+    assert_eq!("v1.65.0", parse_version("rclone v1.65.0\n- os/version: ubuntu 24.04\n"));
+    assert_eq!("unknown", parse_version(""));
+
+    let encoders = "Encoders:\n V..... = Video\n A..... = Audio\n ------\n A....D aac                  AAC (Advanced Audio Coding)\n A....D libopus              libopus Opus (codec opus)\n";
+    assert_eq!(
+        vec!["aac", "libopus"].into_iter().map(String::from).collect::<BTreeSet<String>>(),
+        parse_encoders(encoders)
+    );
+    assert_eq!(
+        vec!["Phone", "My Drive"].into_iter().map(String::from).collect::<BTreeSet<String>>(),
+        parse_remotes("Phone:\nMy Drive:\n\n")
+    );
+
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": "Storage:FLAC",
+        "outputDirectory": "Music",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K",
+        "encodeRules": [{ "extensions": ["wav"], "encodedExtension": "mp3", "ffmpegParams": "-c:a libmp3lame -q:a 0" }],
+        "outputs": [
+            { "name": "phone", "outputDirectory": "Phone:Music" },
+            { "name": "car", "outputDirectory": "Car", "ffmpegParams": "-c:a aac -b:a 256K", "encodedExtension": "m4a" }
+        ]
+    })).unwrap();
+    assert_eq!(
+        vec!["aac", "libmp3lame", "libopus"].into_iter().map(String::from).collect::<BTreeSet<String>>(),
+        required_encoders(&config)
+    );
+    assert_eq!(
+        vec!["Phone", "Storage"].into_iter().map(String::from).collect::<BTreeSet<String>>(),
+        required_remotes(&config)
+    );
+}
+
+#[test]
+fn thermal_throttling() {
+    assert_eq!(Some(48.5), parse_temperature("48500\n"));
+    assert_eq!(None, parse_temperature("unknown"));
+
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": "Music",
+        "outputDirectory": "Phone",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K",
+        "throttle": { "maxTemperature": 75, "pauseOnBattery": true }
+    })).unwrap();
+    let throttle = config.throttle.as_ref().unwrap();
+    assert_eq!(30, throttle.check_seconds);
+    assert_eq!(Some(65.0), throttle.resume_temperature());
+
+    assert_eq!(None, pause_reason(throttle, Some(70.0), Some(false), false));
+    assert_eq!(
+        Some(String::from("CPU at 80 °C, waiting for 65 °C")),
+        pause_reason(throttle, Some(80.0), Some(false), false)
+    );
+    // Paused encodes wait for the resume temperature, not just below the maximum
+    assert!(pause_reason(throttle, Some(70.0), Some(false), true).is_some());
+    assert_eq!(None, pause_reason(throttle, Some(65.0), Some(false), true));
+    assert_eq!(Some(String::from("running on battery")), pause_reason(throttle, Some(40.0), Some(true), false));
+    // Machines without sensors or power supplies are never paused
+    assert_eq!(None, pause_reason(throttle, None, None, false));
+
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": "Music",
+        "outputDirectory": "Phone",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus -b:a 128K",
+        "throttle": { "maxTemperature": 70, "resumeTemperature": 75 }
+    })).unwrap();
+    assert!(lint_config(&config).iter().any(|issue| issue.message.contains("resumeTemperature")));
+}
+
+#[test]
+fn support_bundle() {
+    let header = tar_header("config.json", 1000, 0).unwrap();
+    assert_eq!(b"config.json\0", &header[..12]);
+    assert_eq!(b"00000001750\0", &header[124..136]);
+    assert_eq!(b"ustar\0", &header[257..263]);
+    // The checksum is the sum of all bytes with the checksum field counted as spaces
+    let checksum = u32::from_str_radix(std::str::from_utf8(&header[148..154]).unwrap(), 8).unwrap();
+    let expected = header[..148].iter().chain(header[156..].iter()).map(|byte| *byte as u32).sum::<u32>() + 8 * 32;
+    assert_eq!(expected, checksum);
+    assert!(tar_header(&"a".repeat(101), 0, 0).is_err());
+
+    let mut bundle = SupportBundle::new();
+    bundle.add("version.txt", "ffmusicsync");
+    let mut archive = Vec::new();
+    bundle.write_tar(&mut archive).unwrap();
+    assert_eq!(512 + 512 + 1024, archive.len());
+    assert_eq!(b"ffmusicsync", &archive[512..523]);
+
+    let mut config = serde_json::json!({
+        "inputDirectory": "Music",
+        "mqtt": { "broker": "192.168.1.2:1883", "username": "ffmusicsync", "password": "hunter2" },
+        "outputs": [{ "name": "phone", "apiKey": "abc" }],
+        "exportPlaylist": null
+    });
+    redact_config(&mut config);
+    assert_eq!("192.168.1.2:1883", config["mqtt"]["broker"]);
+    assert_eq!("<redacted>", config["mqtt"]["username"]);
+    assert_eq!("<redacted>", config["mqtt"]["password"]);
+    assert_eq!("<redacted>", config["outputs"][0]["apiKey"]);
+    let mut flags = serde_json::json!({
+        "rcloneFlags": ["--sftp-pass=hunter2", "--s3-access-key-id", "AKIA", "--transfers", "8", "--fast-list"]
+    });
+    redact_config(&mut flags);
+    assert_eq!(
+        serde_json::json!([
+            "--sftp-pass=<redacted>",
+            "--s3-access-key-id",
+            "<redacted>",
+            "--transfers",
+            "8",
+            "--fast-list"
+        ]),
+        flags["rcloneFlags"]
+    );
+    assert_eq!(
+        vec!["inputDirectory", "mqtt", "mqtt.broker", "mqtt.password", "mqtt.username", "outputs"],
+        config_features(&config)
+    );
+
+    let mut telemetry = Telemetry::default();
+    let mut warnings = Warnings::new();
+    warnings.push(WarningKind::EncodeFailed, String::from("Album/Song.flac"), None);
+    for _ in 0..25 {
+        telemetry.record_run("sync", &[String::from("mqtt")], 10, &warnings, 1, None);
+    }
+    telemetry.record_run("status", &[], 1, &Warnings::new(), 0, Some("rclone"));
+    assert_eq!(26, telemetry.runs);
+    assert_eq!(Some(&25), telemetry.commands.get("sync"));
+    assert_eq!(Some(&25), telemetry.warnings.get("EncodeFailed"));
+    assert_eq!(Some(&1), telemetry.errors.get("rclone"));
+    assert_eq!(20, telemetry.recent_runs.len());
+    assert_eq!(Some(String::from("rclone")), telemetry.recent_runs.last().unwrap().error);
+    // No paths are kept
+    assert!(!serde_json::to_string(&telemetry).unwrap().contains("Album"));
+
+    let mut failed = FailedFiles::default();
+    failed.files.insert(String::from("Album/Song.flac"), String::from("Album/Song.ogg"));
+    let redacted = serde_json::to_string(&redact_failed_files(&failed)).unwrap();
+    assert!(!redacted.contains("Album") && redacted.contains(".flac"));
+
+    let dir = TestDir::new("support_bundle");
+    let log = dir.join("ffmusicsync.log");
+    let lines = (0..1000).map(|line| format!("Line {}\nEncoding Album/Song {}.flac\n", line, line)).collect::<String>();
+    std::fs::write(&log, lines).unwrap();
+    let tail = log_tail(&log.to_string_lossy(), false).unwrap();
+    assert_eq!(500, tail.lines().count());
+    assert!(tail.ends_with("Encoding Album/Song 999.flac\n"));
+    let tail = log_tail(&log.to_string_lossy(), true).unwrap();
+    assert!(tail.lines().all(|line| line.starts_with("Line ")));
+    assert!(tail.ends_with("Line 999\n"));
+    assert_eq!("io NotFound", error_category(&std::io::Error::from(std::io::ErrorKind::NotFound)));
+}
+
+#[test]
+fn redacted_paths() {
+    let song = redact_path("Artist/Album/01 Song.flac");
+    let parts = song.split('/').collect::<Vec<&str>>();
+    assert_eq!(3, parts.len());
+    assert!(parts[2].ends_with(".flac"));
+    assert!(!song.contains("Artist") && !song.contains("Song"));
+    // The names are hashed with a key of the run, not looked up by their plain hash
+    assert!(!song.starts_with(&blake3::hash(b"Artist").to_hex()[..8]));
+    // The same names get the same hashes, so files of an album still share their directory
+    assert_eq!(parts[..2], redact_path("Artist/Album/02 Other.flac").split('/').collect::<Vec<&str>>()[..2]);
+    assert_ne!(parts[2], redact_path("Artist/Album/02 Other.flac").split('/').nth(2).unwrap());
+
+    let remote = redact_path("Phone:Music/Song.ogg");
+    assert!(remote.starts_with("remote-") && !remote.contains("Phone"));
+    assert!(remote.ends_with(".ogg"));
+    assert!(redact_path("C:\\Music\\Song.mp3").starts_with("C:\\"));
+    assert!(redact_path("/home/user/Music").starts_with('/'));
+
+    let mut plan = ExportedPlan::new(String::from("/home/user/Music"), String::from("Phone:Music"));
+    plan.actions.push(PlannedAction {
+        kind: ActionKind::Encode,
+        source: Some(String::from("Artist/Song.flac")),
+        destination: Some(String::from("Artist/Song.ogg")),
+        new_destination: None,
+        reason: ActionReason::New,
+        size: Some(1000),
+        status: None,
+        slow_seconds: None,
+    });
+    let redacted = plan.redacted();
+    let json = serde_json::to_string(&redacted).unwrap();
+    assert!(!json.contains("user") && !json.contains("Artist") && !json.contains("Phone"));
+    assert_eq!(Some(1000), redacted.actions[0].size);
+    assert_eq!(redacted.actions[0].source, Some(redact_path("Artist/Song.flac")));
+}
+
+#[test]
+fn init_config() {
+    let answers = InitAnswers {
+        input_directory: String::from("C:\\Music"),
+        output_directory: String::from("Phone:Music"),
+        temp_directory: Some(String::from("temp")),
+        extensions_to_encode: vec![String::from("flac"), String::from("wav")],
+        preset: 2,
+        remove_brackets: [true, false, false, true],
+        copy_covers: false,
+    };
+    let contents = answers.render();
+    let config: Config = serde_json::from_reader(json_comments::StripComments::new(contents.as_bytes())).unwrap();
+
+    // Drive letters aren't remotes
+    assert!(!config.input_directory.is_remote());
+    assert_eq!("C:\\Music", config.input_directory.to_string());
+    assert_eq!("Phone:Music", config.output_directory.to_string());
+    assert_eq!(Some(String::from("temp")), config.temp_directory);
+    assert_eq!(vec![String::from("flac"), String::from("wav")], config.extensions_to_encode);
+    assert_eq!("mp3", config.encoded_extension);
+    assert_eq!("-c:a libmp3lame -q:a 0 -vn", config.ffmpeg_params);
+    assert_eq!(Some(false), config.copy_covers);
+    assert_eq!(Some(true), config.remove_round_brackets);
+    assert_eq!(Some(false), config.remove_square_brackets);
+    assert_eq!(Some(true), config.remove_angle_brackets);
+    assert!(lint_config(&config).iter().all(|issue| issue.severity != Severity::High));
+}
+
+#[test]
+fn translations() {
+    // Source of every message, plural variants continue on the indented lines below the key
+    let messages = |source: &str| {
+        let mut messages = BTreeMap::<String, String>::new();
+        let mut key = None;
+        for line in source.lines() {
+            if line.starts_with([' ', '}']) {
+                if let Some(key) = &key {
+                    messages.get_mut(key).unwrap().push_str(line);
+                }
+            } else if let Some((name, message)) = line.split_once(" = ").filter(|_| !line.starts_with('#')) {
+                messages.insert(name.to_string(), message.to_string());
+                key = Some(name.to_string());
+            } else {
+                key = None;
+            }
+        }
+        messages
+    };
+    let placeholders = |message: &str| {
+        message
+            .split("{ $")
+            .skip(1)
+            .filter_map(|part| part.split_once([' ', '}']).map(|(name, _)| name.to_string()))
+            .collect::<BTreeSet<String>>()
+    };
+    let english = messages(LOCALES[0].1);
+    assert!(english.contains_key("plan-summary"));
+    // Every translation has to load and have all messages with the same placeholders
+    for (language, source) in &LOCALES {
+        let bundle = load_bundle(language, source).unwrap();
+        let catalog = messages(source);
+        assert_eq!(
+            english.keys().collect::<BTreeSet<_>>(),
+            catalog.keys().collect::<BTreeSet<_>>(),
+            "{}",
+            language
+        );
+        for (key, message) in &english {
+            assert_eq!(placeholders(message), placeholders(&catalog[key]), "{} {}", language, key);
+            assert!(format_message(&bundle, key, None).is_some(), "{} {}", language, key);
+        }
+    }
+
+    let bundle = load_bundle(
+        "en",
+        concat!(
+            "# Comment\n\n",
+            "greeting = Hello { $name }, { $count ->\n    [one] one new song\n   *[other] { $count } new songs\n}\n",
+        ),
+    )
+    .unwrap();
+    let greeting = |count: usize| {
+        let mut arguments = FluentArgs::new();
+        arguments.set("name", "Alice");
+        arguments.set("count", count);
+        format_message(&bundle, "greeting", Some(&arguments)).unwrap()
+    };
+    assert_eq!("Hello Alice, one new song", greeting(1));
+    assert_eq!("Hello Alice, 3 new songs", greeting(3));
+    // Counts are grouped like the rest of the output
+    assert_eq!("Hello Alice, 1,234 new songs", greeting(1234));
+    assert_eq!(None, format_message(&bundle, "farewell", None));
+    assert!(load_bundle("en", "greeting = { $count ->\n}\n").is_err());
+}
+
+#[test]
+fn scheduled_tasks() {
+    assert_eq!(Ok(Schedule::Every(90)), Schedule::new(Some(Duration::from_secs(5400)), None, false));
+    assert_eq!(Ok(Schedule::Daily(String::from("03:00"))), Schedule::new(None, Some("03:00"), false));
+    assert_eq!(Ok(Schedule::Logon), Schedule::new(None, None, true));
+    assert!(Schedule::new(Some(Duration::from_secs(90)), None, false).is_err());
+    assert!(Schedule::new(None, Some("3:00"), false).is_err());
+    assert!(Schedule::new(None, Some("24:00"), false).is_err());
+    assert!(Schedule::new(None, None, false).is_err());
+    assert!(Schedule::new(Some(Duration::from_secs(3600)), Some("03:00"), false).is_err());
+
+    assert_eq!("config.json", cmd_quote("config.json"));
+    assert_eq!("\"C:\\My Music\\config.json\"", cmd_quote("C:\\My Music\\config.json"));
+    assert_eq!("\"Rock & Roll 100%%\"", cmd_quote("Rock & Roll 100%"));
+
+    let script = task_script(
+        "C:\\Sync",
+        "C:\\Program Files\\ffmusicsync.exe",
+        &[String::from("--config"), String::from("config.json"), String::from("--yes")],
+        "ffmusicsync.log",
+    );
+    let lines = script.split("\r\n").collect::<Vec<&str>>();
+    // Switched to UTF-8 before any path is read
+    assert_eq!("chcp 65001 > nul", lines[1]);
+    assert_eq!("cd /d C:\\Sync", lines[3]);
+    assert_eq!(
+        "\"C:\\Program Files\\ffmusicsync.exe\" --config config.json --yes >> ffmusicsync.log 2>&1",
+        lines[5]
+    );
+
+    let xml = task_xml(&Schedule::Every(90), "PC\\User", "C:\\Sync & Co\\ffmusicsync-task.cmd", "C:\\Sync & Co");
+    assert!(xml.contains("<Interval>PT90M</Interval>"));
+    assert!(xml.contains("<Command>C:\\Sync &amp; Co\\ffmusicsync-task.cmd</Command>"));
+    assert!(xml.contains("<WorkingDirectory>C:\\Sync &amp; Co</WorkingDirectory>"));
+    assert!(xml.contains("<ExecutionTimeLimit>PT0S</ExecutionTimeLimit>"));
+    assert!(task_xml(&Schedule::Logon, "PC\\User", "task.cmd", "C:\\Sync").contains("<UserId>PC\\User</UserId>"));
+    assert_eq!(vec![0xFF, 0xFE, b'<', 0], encode_utf16("<"));
+}
+
+#[test]
+fn remote_listings() {
+    assert_eq!(Some(1648816200), parse_rfc3339("2022-04-01T12:30:00Z"));
+    assert_eq!(Some(1648816200), parse_rfc3339("2022-04-01T14:30:00.123456789+02:00"));
+    assert_eq!(Some(1648816200), parse_rfc3339("2022-04-01T07:30:00-05:00"));
+    assert_eq!(None, parse_rfc3339("2022-04-01 12:30:00"));
+
+    let output = br#"[
+        {"Path":"Album/Song.flac","Name":"Song.flac","Size":1024,"MimeType":"audio/flac","ModTime":"2022-04-01T12:30:00Z","IsDir":false,"Hashes":{"crc32":"1a2b3c4d","sha1":"da39a3ee"}},
+        {"Path":"Document","Name":"Document","Size":-1,"ModTime":"2022-04-01T12:30:00.5Z","IsDir":false}
+    ]"#;
+    assert_eq!(
+        vec![
+            RemoteEntry {
+                path: String::from("Album/Song.flac"),
+                size: Some(1024),
+                mtime: Some(1648816200),
+                hash: Some(String::from("sha1:da39a3ee")),
+            },
+            RemoteEntry {
+                path: String::from("Document"),
+                size: None,
+                mtime: Some(1648816200),
+                hash: None,
+            },
+        ],
+        parse_lsjson(output).unwrap()
+    );
+    // Output which isn't a listing is an error, not an empty directory
+    assert!(parse_lsjson(b"").is_err());
+    assert!(parse_lsjson(b"Failed to lsjson: directory not found").is_err());
+
+    let root = TestDir::new("remote_listings");
+    std::fs::create_dir_all(root.join("Album")).unwrap();
+    std::fs::write(root.join("Album/Song.flac"), "flac").unwrap();
+    let entries = list_entries(&RclonePath::Local(root.to_string_lossy().to_string()), true).unwrap();
+    assert_eq!(1, entries.len());
+    assert_eq!(std::path::Path::new("Album").join("Song.flac").to_string_lossy(), entries[0].path);
+    assert_eq!(Some(4), entries[0].size);
+    assert!(entries[0].mtime.is_some());
+    assert_eq!(None, entries[0].hash);
+    // Like with rclone, a directory which doesn't exist yet is empty
+    let missing = RclonePath::Local(root.join("Missing").to_string_lossy().to_string());
+    assert!(list_entries(&missing, false).unwrap().is_empty());
+}
+
+#[test]
+fn batched_file_operations() {
+    assert_eq!(("Artist/Album", "Song.ogg"), split_directory("Artist/Album/Song.ogg"));
+    assert_eq!(("", "Song.ogg"), split_directory("Song.ogg"));
+
+    let root = TestDir::new("batched_file_operations");
+    let directory = |name: &str| RclonePath::Local(root.join(name).to_string_lossy().to_string());
+    std::fs::create_dir_all(root.join("output/Album")).unwrap();
+    for file in ["Album/One.ogg", "Album/Two.ogg", "Removed.ogg"] {
+        std::fs::write(root.join("output").join(file), file).unwrap();
+    }
+    let files = [String::from("Album/One.ogg"), String::from("Album/Two.ogg")];
+    move_files(&directory("output"), &directory("trash/1650000000"), &files).unwrap();
+    remove_files(&directory("output"), &[String::from("Removed.ogg")]).unwrap();
+    let moved = std::fs::read_to_string(root.join("trash/1650000000/Album/Two.ogg"));
+    let removed = root.join("output/Removed.ogg").exists();
+    let left = std::fs::read_dir(root.join("output/Album")).unwrap().count();
+    assert_eq!("Album/Two.ogg", moved.unwrap());
+    assert!(!removed);
+    assert_eq!(0, left);
+}
+
+#[test]
+fn input_snapshots() {
+    let datasets = "tank\t/tank\ntank/music\t/tank/music\ntank/legacy\tlegacy\n";
+    assert_eq!(
+        Some((String::from("tank/music"), std::path::PathBuf::from("/tank/music"))),
+        zfs_dataset(datasets, std::path::Path::new("/tank/music/FLAC"))
+    );
+    assert_eq!(Some(String::from("tank")), zfs_dataset(datasets, std::path::Path::new("/tank/photos")).map(|(name, _)| name));
+    assert_eq!(None, zfs_dataset(datasets, std::path::Path::new("/home/user")));
+    assert_eq!(
+        std::path::PathBuf::from("/tank/music/.zfs/snapshot/ffmusicsync-snapshot-1-2/FLAC"),
+        zfs_snapshot_path(std::path::Path::new("/tank/music"), std::path::Path::new("/tank/music/FLAC"), "ffmusicsync-snapshot-1-2")
+    );
+    // Concurrent runs get their own snapshots
+    assert!(snapshot_name().starts_with(&format!("ffmusicsync-snapshot-{}-", std::process::id())));
+    // Never inside the input, which would list the snapshot as part of it
+    assert_eq!(
+        std::path::PathBuf::from("/data/.ffmusicsync-snapshot-1-2"),
+        btrfs_snapshot_path(std::path::Path::new("/data"), std::path::Path::new("/data/Music"), "ffmusicsync-snapshot-1-2")
+    );
+    assert_eq!(
+        std::path::PathBuf::from("/.ffmusicsync-snapshot-1-2"),
+        btrfs_snapshot_path(std::path::Path::new("/music"), std::path::Path::new("/music"), "ffmusicsync-snapshot-1-2")
+    );
+
+    assert_eq!(
+        Some((String::from("{0A1B}"), String::from(r"\\?\GLOBALROOT\Device\HarddiskVolumeShadowCopy3"))),
+        parse_shadow_copy("{0A1B}|\\\\?\\GLOBALROOT\\Device\\HarddiskVolumeShadowCopy3\r\n")
+    );
+    assert_eq!(None, parse_shadow_copy("|"));
+
+    // Plans and the manifest keep the input directory of the config while the snapshot is read
+    let mut config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": "Music",
+        "outputDirectory": "Phone",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus",
+        "inputSnapshot": "zfs"
+    })).unwrap();
+    config.snapshot_of = Some(config.input_directory.clone());
+    config.input_directory = RclonePath::Local(String::from("/tank/.zfs/snapshot/ffmusicsync-snapshot/Music"));
+    assert_eq!("Music", config.source_directory().to_string());
+    assert!(lint_config(&serde_json::from_value(serde_json::json!({
+        "inputDirectory": "Storage:Music",
+        "outputDirectory": "Phone",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus",
+        "inputSnapshot": "btrfs"
+    })).unwrap()).iter().any(|issue| issue.message.contains("inputSnapshot")));
+}
+
+#[test]
+fn content_store() {
+    assert_eq!("objects/ab/abcdef.ogg", object_name("blake3:ABCDEF", "Album/Song.ogg"));
+    assert_eq!("objects/12/1234", object_name("md5:1234", "Album/README"));
+
+    let root = TestDir::new("content_store");
+    let output_directory = RclonePath::Local(root.join("output").to_string_lossy().to_string());
+    let store = RclonePath::Local(root.join("store").to_string_lossy().to_string());
+    std::fs::create_dir_all(root.join("output/Album")).unwrap();
+    std::fs::create_dir_all(root.join("output/Compilation")).unwrap();
+    std::fs::write(root.join("output/Album/Song.ogg"), "song").unwrap();
+    std::fs::write(root.join("output/Compilation/Song.ogg"), "song").unwrap();
+    std::fs::write(root.join("output/Album/Other.ogg"), "other").unwrap();
+    let mut output_files = BTreeSet::from([
+        String::from("Album/Song.ogg"),
+        String::from("Compilation/Song.ogg"),
+        String::from("Album/Other.ogg"),
+    ]);
+    // Identical files share one object
+    let update = update_store(&store, &output_directory, &output_files, &ExecutedActions::default()).unwrap();
+    assert_eq!((2, 0), (update.uploaded, update.removed));
+    let index = StoreIndex::load(&store).unwrap();
+    assert_eq!(index.files["Album/Song.ogg"], index.files["Compilation/Song.ogg"]);
+    assert_eq!(2, index.objects().len());
+
+    // Renames keep their object without hashing or uploading anything, removed files free theirs
+    std::fs::rename(root.join("output/Album/Other.ogg"), root.join("output/Album/Renamed.ogg")).unwrap();
+    std::fs::remove_file(root.join("output/Compilation/Song.ogg")).unwrap();
+    output_files = BTreeSet::from([String::from("Album/Song.ogg"), String::from("Album/Renamed.ogg")]);
+    let mut executed = ExecutedActions::default();
+    executed.renamed.insert(String::from("Album/Other.ogg"), String::from("Album/Renamed.ogg"));
+    executed.deleted.insert(String::from("Compilation/Song.ogg"));
+    let update = update_store(&store, &output_directory, &output_files, &executed).unwrap();
+    assert_eq!((0, 0), (update.uploaded, update.removed));
+    assert!(verify_store(&store, Some(&output_directory)).unwrap().is_empty());
+
+    // Refreshed tags leave the object stale until the hash taken by the executor replaces it
+    std::fs::write(root.join("output/Album/Renamed.ogg"), "refreshed").unwrap();
+    let stale = verify_store(&store, Some(&output_directory)).unwrap();
+    assert_eq!(vec![String::from("Album/Renamed.ogg")], stale.into_iter().map(|(file, _)| file).collect::<Vec<String>>());
+    let mut executed = ExecutedActions::default();
+    executed.content_hashes.insert(String::from("Album/Renamed.ogg"), hash_local_file(root.join("output/Album/Renamed.ogg")).unwrap());
+    let update = update_store(&store, &output_directory, &output_files, &executed).unwrap();
+    assert_eq!((1, 1), (update.uploaded, update.removed));
+    assert!(verify_store(&store, Some(&output_directory)).unwrap().is_empty());
+
+    // A re-encoded file gets a new object and the old one is removed
+    std::fs::write(root.join("output/Album/Song.ogg"), "re-encoded").unwrap();
+    let mut executed = ExecutedActions::default();
+    executed.processed.insert(String::from("Album/Song.flac"), String::from("Album/Song.ogg"));
+    let update = update_store(&store, &output_directory, &output_files, &executed).unwrap();
+    assert_eq!((1, 1), (update.uploaded, update.removed));
+    let index = StoreIndex::load(&store).unwrap();
+    let playlist = std::fs::read_to_string(root.join("store/index.m3u8")).unwrap();
+
+    // Damaged objects are found by verify
+    std::fs::write(root.join("store").join(&index.files["Album/Renamed.ogg"].object), "damaged!").unwrap();
+    let problems = verify_store(&store, None).unwrap();
+    assert_eq!(10, index.files["Album/Song.ogg"].size);
+    assert!(playlist.contains(&format!("#EXTINF:-1,Album/Song.ogg\n{}\n", index.files["Album/Song.ogg"].object)));
+    assert_eq!(vec![String::from("Album/Renamed.ogg")], problems.into_iter().map(|(file, _)| file).collect::<Vec<String>>());
+}
+
+#[test]
+fn rclone_remote_control() {
+    assert_eq!("", base64(b""));
+    assert_eq!("Zg==", base64(b"f"));
+    assert_eq!("Zm8=", base64(b"fo"));
+    assert_eq!("Zm9v", base64(b"foo"));
+    assert_eq!("QWxhZGRpbjpvcGVuIHNlc2FtZQ==", base64(b"Aladdin:open sesame"));
+
+    assert_eq!(
+        (String::from("gdrive:"), String::from("Music/Song.ogg")),
+        file_parameters(&RclonePath::Remote(String::from("gdrive"), String::from("Music/Song.ogg")))
+    );
+    assert_eq!(
+        (String::from("temp"), String::from("Song.ogg")),
+        file_parameters(&RclonePath::Local(String::from("temp/Song.ogg")))
+    );
+    assert_eq!((String::from("."), String::from("Song.ogg")), file_parameters(&RclonePath::Local(String::from("Song.ogg"))));
+
+    let error = parse_response(b"HTTP/1.0 404 Not Found\r\nContent-Type: application/json\r\n\r\n{\"error\": \"object not found\", \"status\": 404}")
+        .unwrap_err();
+    assert_eq!(std::io::ErrorKind::NotFound, error.kind());
+    assert_eq!("rclone: object not found", error.to_string());
+    assert!(parse_response(b"HTTP/1.0 500 Internal Server Error\r\n\r\n{}").is_err());
+    assert!(parse_response(b"garbage").is_err());
+
+    // A fake rcd answering one request with what it received
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        use std::io::{Read, Write};
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !String::from_utf8_lossy(&request).ends_with('}') {
+            let read = stream.read(&mut buffer).unwrap();
+            request.extend(&buffer[..read]);
+        }
+        let request = String::from_utf8(request).unwrap();
+        let body = serde_json::json!({ "request": request });
+        write!(stream, "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{}", body).unwrap();
+    });
+    let response = post(address, "Basic dXNlcjpwYXNz", "operations/deletefile", &serde_json::json!({ "fs": "gdrive:", "remote": "Song.ogg" }))
+        .unwrap();
+    server.join().unwrap();
+    let request = response["request"].as_str().unwrap();
+    assert!(request.starts_with("POST /operations/deletefile HTTP/1.0\r\n"));
+    assert!(request.contains("\r\nAuthorization: Basic dXNlcjpwYXNz\r\n"));
+    assert!(request.contains("\r\nContent-Type: application/json\r\n"));
+    assert!(request.ends_with("\r\n\r\n{\"fs\":\"gdrive:\",\"remote\":\"Song.ogg\"}"));
+}
+
+#[test]
+fn state_archives() {
+    let files = vec![
+        (String::from("empty.json"), Vec::new()),
+        (String::from("encoded.phone.json"), vec![b'x'; 1000]),
+    ];
+    let mut tar = Vec::new();
+    write_tar(&files, &mut tar).unwrap();
+    assert_eq!(files, read_tar(tar.as_slice()).unwrap());
+    assert!(read_tar(&tar[..700]).is_err());
+    // A header claiming a huge file fails instead of allocating its size
+    let mut huge = tar_header("huge.bin", 1 << 40, 0).unwrap().to_vec();
+    huge.extend([0; 512]);
+    assert!(read_tar(huge.as_slice()).is_err());
+
+    let root = TestDir::new("state_archives");
+    let mut archive = StateArchive::new();
+    archive.add("config.json", b"{}".to_vec());
+    archive.add("encoded.json", b"{\"Song.flac\": \"Song.ogg\"}".to_vec());
+    let mut read = Vec::new();
+    for name in ["state.tar", "state.tar.zst"] {
+        let path = root.join(name).to_string_lossy().to_string();
+        archive.write(&path).unwrap();
+        read.push(StateArchive::read(&path).unwrap().files);
+    }
+    // Archives of newer versions may mean something else by their files
+    let info = ArchiveInfo {
+        version: 2,
+        exported_by: String::from("9.0.0"),
+        exported: 0,
+    };
+    let newer = root.join("newer.tar").to_string_lossy().to_string();
+    let mut newer_tar = Vec::new();
+    write_tar(&[(INFO_FILE.to_string(), serde_json::to_vec(&info).unwrap())], &mut newer_tar).unwrap();
+    std::fs::write(&newer, newer_tar).unwrap();
+    let newer_error = StateArchive::read(&newer).unwrap_err();
+    let not_an_archive = root.join("other.tar").to_string_lossy().to_string();
+    std::fs::write(&not_an_archive, &tar).unwrap();
+    let not_an_archive = StateArchive::read(&not_an_archive);
+
+    assert_eq!(vec![archive.files.clone(), archive.files.clone()], read);
+    assert_eq!(Some(b"{}".as_slice()), archive.get("config.json"));
+    assert!(newer_error.to_string().contains("9.0.0"));
+    assert!(not_an_archive.is_err());
+}
+
+#[test]
+fn server_side_copies() {
+    let remote_types = parse_remote_types("Drive:       drive\nOtherDrive:  drive\nPhone:       sftp\n\n");
+    assert_eq!(3, remote_types.len());
+    assert_eq!("drive", remote_types["OtherDrive"]);
+    assert_eq!("sftp", remote_types["Phone"]);
+
+    // Copies within one remote are done by the provider without the flag, local files are never
+    let drive = RclonePath::Remote(String::from("Drive"), String::from("FLAC"));
+    assert!(!server_side_across_configs(&drive, &drive.with_path(String::from("Encoded"))));
+    assert!(!server_side_across_configs(&drive, &RclonePath::Local(String::from("Encoded"))));
+
+    // Only a refused server-side copy is transferred again, other failures are reported
+    let error = |message: &str| std::io::Error::other(String::from(message));
+    assert!(is_server_side_unsupported(&error("rclone exit status: 1: server side copy not supported")));
+    assert!(is_server_side_unsupported(&error("rclone: can't copy object - incompatible remotes")));
+    assert!(!is_server_side_unsupported(&error("rclone exit status: 3: directory not found")));
+    assert!(!is_server_side_unsupported(&error("rclone: googleapi: Error 403: storageQuotaExceeded")));
+}
+
+#[test]
+fn parallel_copies() {
+    let root = TestDir::new("parallel_copies");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    let mut copies = Vec::new();
+    for index in 0..10 {
+        std::fs::write(root.join(format!("input/{} (Live).mp3", index)), index.to_string()).unwrap();
+        copies.push((format!("{} (Live).mp3", index), format!("{}.mp3", index)));
+    }
+    copies.push((String::from("Missing.mp3"), String::from("Missing.mp3")));
+    let input = RclonePath::Local(root.join("input").to_string_lossy().to_string());
+    let output = RclonePath::Local(root.join("output").to_string_lossy().to_string());
+    let results = copy_in_parallel(&input, &output, &copies, 3, None);
+    let copied = (0..10)
+        .map(|index| std::fs::read_to_string(root.join(format!("output/{}.mp3", index))).unwrap())
+        .collect::<Vec<String>>();
+
+    // Every copy has its own result, a failed one doesn't stop the others
+    assert_eq!(11, results.len());
+    assert!(results[..10].iter().all(|result| matches!(result, Some(Ok(())))));
+    assert!(matches!(results[10], Some(Err(_))));
+    assert_eq!((0..10).map(|index| index.to_string()).collect::<Vec<String>>(), copied);
+
+    // Nothing is started after the deadline, the copies are left for later
+    std::fs::remove_dir_all(root.join("output")).unwrap();
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    let results = copy_in_parallel(&input, &output, &copies, 3, Some(std::time::Instant::now()));
+    assert!(results.iter().all(Option::is_none));
+    assert!(walk_files(&root.join("output")).is_empty());
+}
+
+#[test]
+fn pipelined_transfers() {
+    let root = TestDir::new("pipelined_transfers");
+    for directory in ["input/Album", "output/Album", "temp/Album"] {
+        std::fs::create_dir_all(root.join(directory)).unwrap();
+    }
+    for name in ["01.flac", "02.flac", "03.flac"] {
+        std::fs::write(root.join("input/Album").join(name), name).unwrap();
+    }
+    // Left in the temp directory by an interrupted run
+    std::fs::write(root.join("temp/Album/02.flac"), "02.flac").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.join("input").to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+    }));
+    let temp_directory = root.join("temp").to_string_lossy().to_string();
+    let sources = ["Album/01.flac", "Album/02.flac", "Album/03.flac"].map(String::from).to_vec();
+    let leftovers = HashSet::from([String::from("Album/02.flac")]);
+    let stop = std::sync::atomic::AtomicBool::new(false);
+
+    // The sources arrive in the order they're encoded in, the leftover isn't copied again
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+    std::fs::remove_file(root.join("input/Album/02.flac")).unwrap();
+    let downloaded = std::thread::scope(|scope| {
+        scope.spawn(|| download_sources(&config, &temp_directory, sources.clone(), &leftovers, &stop, sender));
+        receiver
+            .iter()
+            .map(|(input_file_name, path)| (input_file_name, std::fs::read_to_string(path.unwrap()).unwrap()))
+            .collect::<Vec<(String, String)>>()
+    });
+    assert_eq!(
+        vec![
+            (String::from("Album/01.flac"), String::from("01.flac")),
+            (String::from("Album/02.flac"), String::from("02.flac")),
+            (String::from("Album/03.flac"), String::from("03.flac")),
+        ],
+        downloaded
+    );
+
+    // Nothing is copied once the encoder stops
+    std::fs::remove_dir_all(root.join("temp/Album")).unwrap();
+    std::fs::create_dir_all(root.join("temp/Album")).unwrap();
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+    stop.store(true, std::sync::atomic::Ordering::SeqCst);
+    download_sources(&config, &temp_directory, sources, &HashSet::new(), &stop, sender);
+    assert!(receiver.iter().next().is_none());
+    assert!(walk_files(&root.join("temp")).is_empty());
+
+    // The outputs are moved to the output directory, every upload reports its result
+    std::fs::write(root.join("temp/Album/01.ogg"), "01.ogg").unwrap();
+    std::fs::write(root.join("temp/Album/03.ogg"), "03.ogg").unwrap();
+    let (sender, uploads) = std::sync::mpsc::channel();
+    let (results, uploaded) = std::sync::mpsc::channel();
+    for name in ["01", "02", "03"] {
+        sender.send((format!("Album/{}.flac", name), format!("Album/{}.ogg", name))).unwrap();
+    }
+    drop(sender);
+    upload_outputs(&config, &temp_directory, uploads, results);
+    let uploaded = uploaded
+        .iter()
+        .map(|(input_file_name, output_file_name, result)| (input_file_name, output_file_name, result.is_ok()))
+        .collect::<Vec<(String, String, bool)>>();
+    assert_eq!(
+        vec![
+            (String::from("Album/01.flac"), String::from("Album/01.ogg"), true),
+            (String::from("Album/02.flac"), String::from("Album/02.ogg"), false),
+            (String::from("Album/03.flac"), String::from("Album/03.ogg"), true),
+        ],
+        uploaded
+    );
+    assert_eq!(vec![String::from("Album/01.ogg"), String::from("Album/03.ogg")], walk_files(&root.join("output")));
+    assert!(walk_files(&root.join("temp")).is_empty());
+}
+
+#[test]
+fn rclone_filters() {
+    let filter = RcloneFilter::parse(
+        "filters.txt",
+        "# Backup rules\n\
+         - /Podcasts/\n\
+         + /Music/Live/*.{flac,mp3}\n\
+         - /Music/Live/**\n\
+         ; Scans are kept with the albums\n\
+         - *.tmp\n\
+         + Sc?ns/\n\
+         - [Ss]crap*\n\
+         + /Music/**\n\
+         - *\n",
+    )
+    .unwrap();
+    assert!(filter.includes("Music/Album/01 - Song.flac"));
+    assert!(filter.includes("Music/Live/Concert.mp3"));
+    assert!(!filter.includes("Music/Live/Concert.wav"));
+    assert!(!filter.includes("Music/Live/2020/Concert.flac"));
+    assert!(!filter.includes("Podcasts/Episode.mp3"));
+    // Unanchored patterns match the end of the path in every directory
+    assert!(!filter.includes("Music/Album/Song.flac.tmp"));
+    assert!(!filter.includes("Music/Album/scraps.txt"));
+    assert!(!filter.includes("Music/Album/Scrap.txt"));
+    assert!(filter.includes(r"Music\Album\Song.flac"));
+    // Including a directory doesn't include its files by itself
+    assert!(!filter.includes("Scans/Cover.jpg"));
+    assert!(!filter.includes("Audiobooks/Book.m4b"));
+    assert_eq!("filters.txt", filter.path());
+
+    // "!" clears the rules before it and paths matching no rule are included
+    let filter = RcloneFilter::parse("filters.txt", "- *\n!\n- Demos/\n").unwrap();
+    assert!(filter.includes("Album/Song.flac"));
+    assert!(!filter.includes("Artist/Demos/Song.flac"));
+    assert!(!filter.includes("Demos/Song.flac"));
+
+    assert!(RcloneFilter::parse("filters.txt", "*.flac\n").is_err());
+    assert!(RcloneFilter::parse("filters.txt", "+ {a,{b,c}}\n").is_err());
+    assert!(RcloneFilter::parse("filters.txt", "- [abc\n").is_err());
+    assert!(RcloneFilter::parse("filters.txt", "- ***\n").is_err());
+
+    let root = TestDir::new("rclone_filters");
+    std::fs::create_dir_all(root.join("Music/Album")).unwrap();
+    std::fs::create_dir_all(root.join("Podcasts")).unwrap();
+    std::fs::write(root.join("Music/Album/Song.flac"), "song").unwrap();
+    std::fs::write(root.join("Music/Album/Song.flac.tmp"), "song").unwrap();
+    std::fs::write(root.join("Podcasts/Episode.mp3"), "episode").unwrap();
+    let filter = RcloneFilter::parse("filters.txt", "- /Podcasts/\n- *.tmp\n").unwrap();
+    let mut listed = list_filtered_entries(&RclonePath::Local(root.to_string_lossy().to_string()), false, Some(&filter))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.path.replace('\\', "/"))
+        .collect::<Vec<String>>();
+    listed.sort();
+    assert_eq!(vec![String::from("Music/Album/Song.flac")], listed);
+
+    // The config files of the input are listed whatever the filter file says, in front of its rules for rclone
+    std::fs::write(root.join("filters.txt"), "+ /Music/**\n- *\n").unwrap();
+    std::fs::write(root.join(".ffmusicsync.json"), "{}").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "rcloneFilterFile": root.join("filters.txt").to_string_lossy()
+    }));
+    let filter = config.rclone_filter().unwrap().unwrap();
+    assert_eq!(&[String::from("+ .ffmusicsync.json")], filter.extra_rules());
+    let input = list_input(&config, Some(&filter), None, &mut Warnings::new()).unwrap();
+    assert!(input.contains(".ffmusicsync.json"));
+    assert!(input.contains("Music/Album/Song.flac"));
+    assert!(!input.contains("Podcasts/Episode.mp3"));
+
+    // A filter file which can't be read stops the sync instead of syncing every file
+    let config = test_config(serde_json::json!({
+        "inputDirectory": root.to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "rcloneFilterFile": root.join("missing.txt").to_string_lossy()
+    }));
+    assert!(SyncPlanner::new(config, HashMap::new()).plan(&mut Warnings::new()).is_err());
+}
+
+#[test]
+fn partial_outputs() {
+    // The extension stays last, ffmpeg picks the container by it
+    assert_eq!(
+        std::path::Path::new("Album/Song.ffmusicsync-part.ogg"),
+        partial_path(std::path::Path::new("Album/Song.ogg"))
+    );
+    assert_eq!(
+        std::path::Path::new("Album/Song.ffmusicsync-part.m4a"),
+        partial_path(std::path::Path::new("Album/Song.m4a"))
+    );
+    assert_eq!(std::path::Path::new("Album/Song.ffmusicsync-part"), partial_path(std::path::Path::new("Album/Song")));
+    // The partial name of an output is never the output of another source, like Song.part.flac would be with .part
+    assert_ne!(
+        partial_path(std::path::Path::new("Album/Song.ogg")),
+        std::path::Path::new("Album/Song.part.ogg")
+    );
+
+    // Local copies are written to a partial name too, nothing is left behind under it
+    let root = TestDir::new("partial_outputs");
+    std::fs::write(root.join("cover.jpg"), "cover").unwrap();
+    let from = RclonePath::Local(root.join("cover.jpg").to_string_lossy().to_string());
+    let to = RclonePath::Local(root.join("copied.jpg").to_string_lossy().to_string());
+    crate::fs_wrapper::copy(&from, &to).unwrap();
+    copy_verified(&from, &to, 0).unwrap();
+    assert_eq!(vec![String::from("copied.jpg"), String::from("cover.jpg")], walk_files(&root));
+    // A partial copy in the input is skipped like a partial download
+    let input_directory = root.join("input");
+    std::fs::create_dir_all(&input_directory).unwrap();
+    std::fs::write(input_directory.join("Song.ffmusicsync-part.flac"), "flac").unwrap();
+    std::fs::write(input_directory.join("Song.flac"), "flac").unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": root.join("output").to_string_lossy(),
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "ogg",
+        "ffmpegParams": "-c:a libopus"
+    })).unwrap();
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config, HashMap::new()).plan(&mut warnings).unwrap();
+    assert_eq!(vec![&String::from("Song.flac")], plan.to_process.iter().collect::<Vec<&String>>());
+}
+
+#[test]
+fn verified_copies() {
+    let root = TestDir::new("verified_copies");
+    let local = |file: &str| RclonePath::Local(root.join(file).to_string_lossy().to_string());
+    std::fs::write(root.join("Song.ogg"), "ogg contents").unwrap();
+    std::fs::write(root.join("Copy.ogg"), "stale").unwrap();
+
+    // The copy replaces what was there and is read back until it matches the source
+    copy_verified(&local("Song.ogg"), &local("Copy.ogg"), 3).unwrap();
+    assert_eq!("ogg contents", std::fs::read_to_string(root.join("Copy.ogg")).unwrap());
+    assert!(files_match(&local("Song.ogg"), &local("Copy.ogg")).unwrap());
+    // Local files are compared byte by byte, not only by their size
+    std::fs::write(root.join("Copy.ogg"), "ogg_contents").unwrap();
+    assert!(!files_match(&local("Song.ogg"), &local("Copy.ogg")).unwrap());
+    assert!(!files_match(&local("Song.ogg"), &local("Missing.ogg")).unwrap());
+    // A source which can't be read fails the copy instead of being retried
+    assert!(copy_verified(&local("Missing.ogg"), &local("Copy.ogg"), 3).is_err());
+}
+
+#[test]
+fn slow_file_budgets() {
+    let slow_file_budget: SlowFileBudget =
+        serde_json::from_str(r#"{"baseSeconds": 30, "secondsPerMb": 1, "secondsPerMinute": 10}"#).unwrap();
+    // 30 + 25 MB + 4 minutes
+    assert_eq!(Duration::from_secs(95), slow_file_budget.budget(25_000_000, Some(Duration::from_secs(240))));
+    assert_eq!(Duration::from_secs(55), slow_file_budget.budget(25_000_000, None));
+    let slow_file_budget: SlowFileBudget = serde_json::from_str(r#"{"secondsPerMinute": 2.5}"#).unwrap();
+    assert_eq!(Duration::from_secs(5), slow_file_budget.budget(1_000_000, Some(Duration::from_secs(120))));
+
+    let mut exported_plan = ExportedPlan::new(String::from("Music"), String::from("Phone:Music"));
+    for source in ["Slow.flac", "Fast.flac"] {
+        exported_plan.actions.push(PlannedAction {
+            kind: ActionKind::Encode,
+            source: Some(source.to_string()),
+            destination: Some(source.replace("flac", "ogg")),
+            new_destination: None,
+            reason: ActionReason::New,
+            size: None,
+            status: None,
+            slow_seconds: None,
+        });
+    }
+    exported_plan.mark_slow(&vec![(String::from("Slow.flac"), 300)].into_iter().collect());
+    let exported = serde_json::to_string(&exported_plan).unwrap();
+    assert!(exported.contains(r#""source":"Slow.flac","destination":"Slow.ogg","newDestination":null,"reason":"new","size":null,"status":null,"slowSeconds":300}"#));
+    assert!(exported.contains(r#""source":"Fast.flac","destination":"Fast.ogg","newDestination":null,"reason":"new","size":null,"status":null}"#));
+}
+
+#[test]
+fn encode_verification() {
+    // A second of leeway for short files, a percent for long ones
+    assert!(durations_match(Duration::from_secs(180), Duration::from_millis(180_900)));
+    assert!(!durations_match(Duration::from_secs(180), Duration::from_secs(120)));
+    assert!(durations_match(Duration::from_secs(3600), Duration::from_secs(3630)));
+    assert!(!durations_match(Duration::from_secs(3600), Duration::from_secs(3540)));
+
+    assert!(!changes_length("-c:a libopus -b:a 128k"));
+    assert!(changes_length(&with_speed("-c:a libopus", 1.5)));
+    assert!(changes_length(&with_audio_filter("-c:a libopus", "silenceremove=start_periods=1")));
+    assert!(changes_length("-c:a libopus -t 30"));
+}
+
+#[test]
+fn path_display() {
+    let remote = RclonePath::Remote(String::from("Phone"), String::from("Music/"));
+    let local = RclonePath::Local(String::from("/home/user/Music"));
+    assert_eq!("Phone:Music/Song.ogg", qualified(&remote, "Song.ogg"));
+    assert_eq!("Phone:Song.ogg", qualified(&RclonePath::Remote(String::from("Phone"), String::new()), "Song.ogg"));
+    assert_eq!("/home/user/Music/Song.flac", qualified(&local, "Song.flac"));
+    assert_eq!("/Song.flac", qualified(&RclonePath::Local(String::from("/")), "Song.flac"));
+    assert_eq!("Song.flac", qualified(&RclonePath::Local(String::new()), "Song.flac"));
+    // Relative unless --absolute-paths is given
+    assert_eq!("Song (Live).flac -> Song.ogg (Phone:Music/)", transfer(&local, "Song (Live).flac", &remote, "Song.ogg"));
+}
+
+#[test]
+fn untagged_refreshes() {
+    let root = TestDir::new("untagged_refreshes");
+    std::fs::write(root.join("Source.wav"), silent_wav(1)).unwrap();
+    std::fs::write(root.join("Output.wav"), silent_wav(1)).unwrap();
+
+    // Files without tags are treated like files with empty tags
+    assert!(!refresh_tags(root.join("Source.wav"), root.join("Output.wav")).unwrap());
+    assert!(!refresh_pictures(root.join("Source.wav"), root.join("Output.wav")).unwrap());
+    copy_pictures(root.join("Source.wav"), root.join("Output.wav")).unwrap();
+    assert_eq!(silent_wav(1), std::fs::read(root.join("Output.wav")).unwrap());
+}
+
+#[test]
+fn tags_refresh() {
+    let root = TestDir::new("tags_refresh");
+    let mut source_tag = lofty::Tag::new(lofty::TagType::VorbisComments);
+    source_tag.insert_text(lofty::ItemKey::TrackTitle, String::from("New Title"));
+    source_tag.insert_text(lofty::ItemKey::TrackArtist, String::from("Artist"));
+    std::fs::write(root.join("Source.flac"), empty_flac()).unwrap();
+    lofty::TagExt::save_to_path(&source_tag, root.join("Source.flac")).unwrap();
+    let mut output_tag = lofty::Tag::new(lofty::TagType::VorbisComments);
+    output_tag.insert_text(lofty::ItemKey::TrackTitle, String::from("Old Title"));
+    output_tag.insert_text(lofty::ItemKey::Comment, String::from("Only in the output"));
+    output_tag.push_picture(lofty::Picture::new_unchecked(
+        lofty::PictureType::CoverFront,
+        lofty::MimeType::Png,
+        None,
+        b"png".to_vec(),
+    ));
+    std::fs::write(root.join("Output.flac"), empty_flac()).unwrap();
+    lofty::TagExt::save_to_path(&output_tag, root.join("Output.flac")).unwrap();
+
+    // The items of the output are replaced by the ones of the source, its pictures are kept
+    assert!(refresh_tags(root.join("Source.flac"), root.join("Output.flac")).unwrap());
+    let tagged_file = lofty::Probe::open(root.join("Output.flac")).unwrap().guess_file_type().unwrap();
+    let tagged_file = tagged_file.read(false).unwrap();
+    let tag = tagged_file.primary_tag().unwrap();
+    assert_eq!(Some("New Title"), tag.get_string(&lofty::ItemKey::TrackTitle));
+    assert_eq!(Some("Artist"), tag.get_string(&lofty::ItemKey::TrackArtist));
+    assert_eq!(None, tag.get_string(&lofty::ItemKey::Comment));
+    assert_eq!(1, tag.picture_count());
+    // Outputs whose tags already match aren't written again
+    let modified = std::fs::read(root.join("Output.flac")).unwrap();
+    assert!(!refresh_tags(root.join("Source.flac"), root.join("Output.flac")).unwrap());
+    assert_eq!(modified, std::fs::read(root.join("Output.flac")).unwrap());
+}
+
+#[test]
+fn encode_durations() {
+    let root = TestDir::new("encode_durations");
+    std::fs::write(root.join("Song.wav"), silent_wav(3)).unwrap();
+    std::fs::write(root.join("Broken.wav"), "not audio").unwrap();
+
+    // The properties are read along with the tags, the progress bar of the encode is sized by them
+    assert_eq!(Some(Duration::from_secs(3)), read_duration(root.join("Song.wav")));
+    assert_eq!(None, read_duration(root.join("Broken.wav")));
+    assert_eq!(None, read_duration(root.join("Missing.wav")));
+}
+
+#[test]
+fn temp_leftovers() {
+    let wav = silent_wav;
+    let root = TestDir::new("temp_leftovers");
+    std::fs::write(root.join("Source.wav"), wav(10)).unwrap();
+    std::fs::write(root.join("Finished.ogg"), wav(10)).unwrap();
+    std::fs::write(root.join("Truncated.ogg"), wav(4)).unwrap();
+    std::fs::write(root.join("Empty.ogg"), "").unwrap();
+    std::fs::write(root.join("Broken.ogg"), "not audio").unwrap();
+    let complete = |output: &str, ffmpeg_params: &str| {
+        is_complete_encode(&root.join("Source.wav"), &root.join(output), ffmpeg_params)
+    };
+    let results = [
+        complete("Finished.ogg", "-c:a libopus"),
+        complete("Truncated.ogg", "-c:a libopus"),
+        // Sped up audio is shorter on purpose
+        complete("Truncated.ogg", &with_speed("-c:a libopus", 2.5)),
+        complete("Empty.ogg", "-c:a libopus"),
+        complete("Broken.ogg", "-c:a libopus"),
+        complete("Missing.ogg", "-c:a libopus"),
+    ];
+
+    assert_eq!([true, false, true, false, false, false], results);
+    // Any non-empty file when there's no length to compare to
+    assert!(complete("Broken.ogg", &with_speed("-c:a libopus", 2.5)));
+    assert!(!complete("Empty.ogg", &with_speed("-c:a libopus", 2.5)));
+}
+
+#[test]
+fn reused_leftovers() {
+    let root = TestDir::new("reused_leftovers");
+    std::fs::create_dir_all(root.join("input")).unwrap();
+    std::fs::create_dir_all(root.join("temp")).unwrap();
+    let mut leftovers = Leftovers::default();
+    for name in ["Listed", "OtherParams", "Unlisted", "Truncated", "Changed"] {
+        std::fs::write(root.join(format!("input/{}.wav", name)), silent_wav(10)).unwrap();
+        std::fs::write(root.join(format!("temp/{}.ogg", name)), silent_wav(10)).unwrap();
+        if name != "Unlisted" {
+            leftovers.encodes.insert(
+                format!("{}.wav", name),
+                LeftoverEncode {
+                    output_file_name: format!("{}.ogg", name),
+                    ffmpeg_params: String::from(if name == "OtherParams" { "-c:a libvorbis" } else { "-c:a libopus" }),
+                    size: std::fs::metadata(root.join(format!("temp/{}.ogg", name))).unwrap().len(),
+                    source_hash: Some(hash_local_file(root.join(format!("input/{}.wav", name))).unwrap()),
+                },
+            );
+        }
+    }
+    leftovers.save(&root.join("temp").to_string_lossy()).unwrap();
+    std::fs::write(root.join("temp/Truncated.ogg"), silent_wav(4)).unwrap();
+    std::fs::write(root.join("input/Changed.wav"), silent_wav(9)).unwrap();
+    let leftover_encodes = |detect_changes: bool| {
+        let config = test_config(serde_json::json!({
+            "inputDirectory": root.join("input").to_string_lossy(),
+            "outputDirectory": "Phone:Music",
+            "tempDirectory": root.join("temp").to_string_lossy(),
+            "extensionsToEncode": ["wav"],
+            "ffmpegParams": "-c:a libopus",
+            "detectChanges": detect_changes
+        }));
+        // Retrying failed files, so the remote output isn't listed
+        let failures = FailedFiles {
+            override_files: BTreeSet::new(),
+            files: ["Listed", "OtherParams", "Unlisted", "Truncated", "Changed"]
+                .map(|name| (format!("{}.wav", name), format!("{}.ogg", name)))
+                .into_iter()
+                .collect(),
+        };
+        let mut warnings = Warnings::new();
+        let mut plan = SyncPlanner::new(config, HashMap::new())
+            .with_failures(failures)
+            .retry_failed(true)
+            .plan(&mut warnings)
+            .unwrap();
+        // Only looked for right before the plan is run
+        assert!(plan.leftover_encodes.is_empty());
+        plan.find_leftovers();
+        plan.leftover_encodes.into_iter().collect::<BTreeSet<String>>()
+    };
+
+    // Only listed outputs of the current params are reused, with detectChanges only if the source is unchanged
+    assert_eq!(
+        ["Changed.wav", "Listed.wav"].map(String::from).into_iter().collect::<BTreeSet<String>>(),
+        leftover_encodes(false)
+    );
+    assert_eq!(["Listed.wav"].map(String::from).into_iter().collect::<BTreeSet<String>>(), leftover_encodes(true));
+}
+
+#[test]
+fn reencode_loop_detection() {
+    let remote = |remote: &str, path: &str| RclonePath::Remote(remote.to_string(), path.to_string());
+    assert!(is_inside(&remote("Drive", "Music/Encoded/"), &remote("Drive", "Music")));
+    assert!(is_inside(&remote("Drive", "Music"), &remote("Drive", "Music/")));
+    // The root of a remote contains everything on it
+    assert!(is_inside(&remote("Drive", "Encoded"), &remote("Drive", "")));
+    assert!(!is_inside(&remote("Drive", "Music Encoded"), &remote("Drive", "Music")));
+    assert!(!is_inside(&remote("Phone", "Music/Encoded"), &remote("Drive", "Music")));
+    assert!(!is_inside(&remote("Drive", "Music"), &RclonePath::Local(String::from("Music"))));
+
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "Drive:Music",
+        "outputDirectory": "Drive:Music/Encoded",
+        "extensionsToEncode": ["flac", "opus"],
+        "encodedExtension": "opus",
+        "ffmpegParams": "-c:a libopus"
+    }"#).unwrap();
+    assert_eq!(
+        vec!["encodedExtension opus is listed in extensionsToEncode", "outputDirectory is inside inputDirectory"],
+        reencode_loops(&config)
+    );
+    let config: Config = serde_json::from_str(r#"{
+        "inputDirectory": "Drive:Music",
+        "outputDirectory": "Phone:Music",
+        "extensionsToEncode": ["flac"],
+        "encodedExtension": "opus",
+        "ffmpegParams": "-c:a libopus"
+    }"#).unwrap();
+    assert!(reencode_loops(&config).is_empty());
+
+    // Per-directory config files can start a loop for their directory only
+    let root = TestDir::new("reencode_loops");
+    std::fs::create_dir_all(root.join("Lossy")).unwrap();
+    std::fs::create_dir_all(root.join("Lossless")).unwrap();
+    std::fs::write(root.join("Lossy/.ffmusicsync.json"), r#"{"extensionsToEncode": ["flac", "mp3"], "encodedExtension": "mp3"}"#).unwrap();
+    std::fs::write(root.join("Lossless/.ffmusicsync.json"), r#"{"encodedExtension": "mp3"}"#).unwrap();
+    let input = vec!["Lossy/.ffmusicsync.json", "Lossless/.ffmusicsync.json"]
+        .into_iter()
+        .map(String::from)
+        .collect::<HashSet<String>>();
+    let overrides = Overrides::load(&RclonePath::Local(root.to_string_lossy().to_string()), &input).unwrap();
+    assert_eq!(
+        vec![(String::from("Lossy"), String::from("mp3"))],
+        overrides.reencoding_directories(&config)
+    );
+}
+
+#[test]
+fn watch_polls() {
+    let sizes = |files: &[(&str, u64)]| {
+        files.iter().map(|(file, size)| (file.to_string(), *size)).collect::<HashMap<String, u64>>()
+    };
+    let start = std::time::Instant::now();
+    let seconds = |seconds: u64| start + Duration::from_secs(seconds);
+    // Files present when the watch starts are settled, the first sync checks them like a single run
+    let mut watcher = InputWatcher::new(Duration::from_secs(10), sizes(&[("Album/01.flac", 100)]));
+    assert!(watcher.unsettled(seconds(0)).is_empty());
+    watcher.observe(sizes(&[("Album/01.flac", 100)]), seconds(5));
+    assert!(!watcher.is_sync_due(seconds(0), seconds(5)));
+
+    // A track being ripped is unsettled until its size stays the same for the settle time
+    watcher.observe(sizes(&[("Album/01.flac", 100), ("Album/02.flac", 10)]), seconds(20));
+    watcher.observe(sizes(&[("Album/01.flac", 100), ("Album/02.flac", 50)]), seconds(25));
+    assert_eq!(HashSet::from([String::from("Album/02.flac")]), watcher.unsettled(seconds(30)));
+    assert!(!watcher.is_sync_due(seconds(0), seconds(30)));
+    watcher.observe(sizes(&[("Album/01.flac", 100), ("Album/02.flac", 50)]), seconds(35));
+    assert!(watcher.unsettled(seconds(35)).is_empty());
+    assert!(watcher.is_sync_due(seconds(0), seconds(35)));
+    // A sync started after it settled has already taken it
+    assert!(!watcher.is_sync_due(seconds(36), seconds(40)));
+    // A sync which skipped it while it was written syncs it again once it settled
+    assert!(watcher.is_sync_due(seconds(30), seconds(40)));
+    // Deleted sources are changes as well, their outputs are removed by the next sync
+    watcher.observe(sizes(&[("Album/02.flac", 50)]), seconds(50));
+    assert!(watcher.is_sync_due(seconds(36), seconds(60)));
+
+    // Without settleSeconds every change is due at the next poll
+    let mut watcher = InputWatcher::new(Duration::ZERO, sizes(&[]));
+    watcher.observe(sizes(&[("Song.flac", 10)]), seconds(1));
+    assert!(watcher.unsettled(seconds(1)).is_empty());
+    assert!(watcher.is_sync_due(seconds(0), seconds(1)));
+
+    // The listing takes the files seen by the watch instead of waiting, and holds back their whole directory
+    let root = TestDir::new("watch_polls");
+    std::fs::create_dir_all(root.join("Album")).unwrap();
+    std::fs::create_dir_all(root.join("Single")).unwrap();
+    for file in ["Album/01.flac", "Album/02.flac", "Single/Song.flac"] {
+        std::fs::write(root.join(file), "flac").unwrap();
+    }
+    let mut config = test_config(serde_json::json!({
+        "inputDirectory": root.to_string_lossy(),
+        "settleSeconds": 3600,
+    }));
+    let unsettled = HashSet::from([String::from("Album/02.flac")]);
+    let mut warnings = Warnings::new();
+    let input = list_input(&config, None, Some(&unsettled), &mut warnings).unwrap();
+    assert_eq!(
+        BTreeSet::from(["Album/01.flac", "Single/Song.flac"]),
+        input.iter().map(String::as_str).collect::<BTreeSet<&str>>()
+    );
+    config.settle_directories = Some(true);
+    let input = list_input(&config, None, Some(&unsettled), &mut warnings).unwrap();
+    assert_eq!(vec![String::from("Single/Song.flac")], input.into_iter().collect::<Vec<String>>());
+}
+
+#[test]
+fn album_completion() {
+    let summary = "[Parsed_ebur128_0 @ 0x5581] t: 12.3   M: -20.1 S: -21.0     I: -22.4 LUFS\n\
+        [Parsed_ebur128_0 @ 0x5581] Summary:\n\
+        \n\
+          Integrated loudness:\n\
+            I:         -14.0 LUFS\n\
+            Threshold: -24.3 LUFS\n\
+        \n\
+          True peak:\n\
+            Peak:        -6.0 dBFS\n";
+    let loudness = parse_ebur128_summary(summary).unwrap();
+    assert_eq!(AlbumLoudness { integrated: -14.0, true_peak: -6.0 }, loudness);
+    assert_eq!(-4.0, loudness.gain());
+    assert!((loudness.peak() - 0.501187).abs() < 0.000001);
+    // Only the summary counts, the running values before it don't
+    assert_eq!(None, parse_ebur128_summary("[Parsed_ebur128_0 @ 0x5581] t: 12.3 I: -22.4 LUFS"));
+    // Silence has no peak
+    let silence = "Summary:\n  Integrated loudness:\n    I: -70.0 LUFS\n  True peak:\n    Peak: -inf dBFS\n";
+    assert_eq!(0.0, parse_ebur128_summary(silence).unwrap().peak());
+
+    // Vorbis comments store the album gain, ID3v2 tags of WAV files can't yet
+    let root = TestDir::new("album_completion");
+    std::fs::write(root.join("Track.flac"), empty_flac()).unwrap();
+    std::fs::write(root.join("Track.wav"), silent_wav(1)).unwrap();
+    assert!(write_album_gain(&root.join("Track.flac"), &loudness).unwrap());
+    let tagged_file = lofty::Probe::open(root.join("Track.flac")).unwrap().guess_file_type().unwrap();
+    let tagged_file = tagged_file.read(false).unwrap();
+    let tag = tagged_file.primary_tag().unwrap();
+    assert_eq!(
+        Some("-4.00 dB"),
+        tag.get_string(&lofty::ItemKey::Unknown(String::from("REPLAYGAIN_ALBUM_GAIN")))
+    );
+    assert_eq!(
+        Some("0.501187"),
+        tag.get_string(&lofty::ItemKey::Unknown(String::from("REPLAYGAIN_ALBUM_PEAK")))
+    );
+    assert!(!write_album_gain(&root.join("Track.wav"), &loudness).unwrap());
+
+    // The cover of the first source with one is exported, only to folders without an image
+    std::fs::create_dir_all(root.join("output")).unwrap();
+    let mut tag = lofty::Tag::new(lofty::TagType::VorbisComments);
+    tag.push_picture(lofty::Picture::new_unchecked(
+        lofty::PictureType::CoverFront,
+        lofty::MimeType::Png,
+        None,
+        b"png".to_vec(),
+    ));
+    std::fs::write(root.join("Cover.flac"), empty_flac()).unwrap();
+    lofty::TagExt::save_to_path(&tag, root.join("Cover.flac")).unwrap();
+    let sources = vec![root.join("Track.flac"), root.join("Cover.flac")];
+    assert_eq!(Some("cover.png"), export_folder_cover(&sources, &root.join("output")).unwrap());
+    assert_eq!(b"png".to_vec(), std::fs::read(root.join("output/cover.png")).unwrap());
+    assert_eq!(None, export_folder_cover(&sources, &root.join("output")).unwrap());
+
+    // Only albums whose tracks were all synced by the run are completed, audiobooks aren't albums
+    let input_directory = root.join("input");
+    let output_directory = root.join("synced");
+    for file in [
+        "Album/01.flac", "Album/02.flac", "Album/cover.jpg", "Broken/01.flac", "Broken/02.flac", "Books/Book.flac",
+        "Old/01.flac",
+    ] {
+        std::fs::create_dir_all(input_directory.join(file).parent().unwrap()).unwrap();
+        std::fs::write(input_directory.join(file), "flac").unwrap();
+    }
+    std::fs::create_dir_all(output_directory.join("Old")).unwrap();
+    std::fs::write(output_directory.join("Old/01.ogg"), "ogg").unwrap();
+    std::fs::write(output_directory.join("Old/cover.jpg"), "jpg").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+        "audiobooks": { "globs": ["Books/**"] },
+        "exportFolderCovers": true,
+    }));
+    let encoded = HashMap::from([(String::from("Old/01.flac"), String::from("Old/01.ogg"))]);
+    let mut warnings = Warnings::new();
+    let plan = SyncPlanner::new(config, encoded).plan(&mut warnings).unwrap();
+    // The exported cover of an album isn't deleted for lacking a source
+    assert!(plan.to_delete.is_empty());
+    let processed = |files: &[(&str, &str)]| {
+        files
+            .iter()
+            .map(|(input_file_name, output_file_name)| (input_file_name.to_string(), output_file_name.to_string()))
+            .collect::<BTreeMap<String, String>>()
+    };
+    let executed = ExecutedActions {
+        processed: processed(&[
+            ("Album/01.flac", "Album/01.ogg"),
+            ("Album/02.flac", "Album/02.ogg"),
+            ("Album/cover.jpg", "Album/cover.jpg"),
+            ("Broken/01.flac", "Broken/01.ogg"),
+            ("Books/Book.flac", "Books/Book.m4b"),
+        ]),
+        failed: processed(&[("Broken/02.flac", "Broken/02.ogg")]),
+        ..Default::default()
+    };
+    let albums = plan.completed_albums(&executed);
+    assert_eq!(vec![&String::from("Album")], albums.keys().collect::<Vec<&String>>());
+    assert_eq!(processed(&[("Album/01.flac", "Album/01.ogg"), ("Album/02.flac", "Album/02.ogg")]), albums["Album"]);
+}
+
+#[test]
+fn pending_sync() {
+    let root = TestDir::new("pending_sync");
+    let input_directory = root.join("input");
+    let output_directory = root.join("output");
+    std::fs::create_dir_all(input_directory.join("Album")).unwrap();
+    std::fs::create_dir_all(output_directory.join("Album")).unwrap();
+    std::fs::write(input_directory.join("Album/01.flac"), "flac").unwrap();
+    std::fs::write(output_directory.join("Album/01.ogg"), "ogg").unwrap();
+    let config = test_config(serde_json::json!({
+        "inputDirectory": input_directory.to_string_lossy(),
+        "outputDirectory": output_directory.to_string_lossy(),
+    }));
+    let plan = |encoded: &[(&str, &str)]| {
+        let encoded = encoded
+            .iter()
+            .map(|(input_file_name, output_file_name)| (input_file_name.to_string(), output_file_name.to_string()))
+            .collect::<HashMap<String, String>>();
+        SyncPlanner::new(config.clone(), encoded).plan(&mut Warnings::new()).unwrap()
+    };
+    // An output in sync exits --dry-run and status with 0
+    assert!(!plan(&[("Album/01.flac", "Album/01.ogg")]).is_pending());
+    // New sources and orphaned outputs are left for a sync, so they exit with 5
+    std::fs::write(input_directory.join("Album/02.flac"), "flac").unwrap();
+    assert!(plan(&[("Album/01.flac", "Album/01.ogg")]).is_pending());
+    std::fs::remove_file(input_directory.join("Album/02.flac")).unwrap();
+    std::fs::write(output_directory.join("Album/03.ogg"), "ogg").unwrap();
+    let orphaned = plan(&[("Album/01.flac", "Album/01.ogg")]);
+    assert!(orphaned.to_process.is_empty());
+    assert!(orphaned.is_pending());
+}
+
+#[test]
+fn partial_files() {
+    let root = TestDir::new("partial_files");
+    let path = root.join("Song.ffmusicsync-part.ogg");
+    // Files are tracked while they're written, so a second Ctrl+C can remove them before exiting
+    let partial_file = PartialFile::track(&path);
+    let other = PartialFile::track(&root.join("Other.ffmusicsync-part.ogg"));
+    assert!(interrupt::partial_files().contains(&path));
+    drop(partial_file);
+    assert!(!interrupt::partial_files().contains(&path));
+    assert!(interrupt::partial_files().contains(&root.join("Other.ffmusicsync-part.ogg")));
+    drop(other);
+    assert!(!interrupt::partial_files().contains(&root.join("Other.ffmusicsync-part.ogg")));
+
+    // Local copies are only tracked until they're complete
+    std::fs::write(root.join("Song.ogg"), "ogg").unwrap();
+    copy_verified(
+        &RclonePath::Local(root.join("Song.ogg").to_string_lossy().to_string()),
+        &RclonePath::Local(root.join("Copy.ogg").to_string_lossy().to_string()),
+        0,
+    )
+    .unwrap();
+    assert!(!interrupt::partial_files().iter().any(|path| path.starts_with(&*root)));
+}
+
+/// Publishes the events emitted by `emit` to a fake broker, returning the connect packet and the published messages
+#[cfg(test)]
+fn mqtt_published(emit: impl FnOnce(&EventSink)) -> (Vec<u8>, Vec<(String, String)>) {
+    use std::io::Read;
+    let broker = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let config: MqttConfig = serde_json::from_value(serde_json::json!({
+        "broker": broker.local_addr().unwrap().to_string(),
+        "username": "user",
+        "password": "secret",
+        "topic": "home/music"
+    }))
+    .unwrap();
+    let received = std::thread::spawn(move || {
+        let (mut stream, _) = broker.accept().unwrap();
+        let mut connect = vec![0u8; 2];
+        stream.read_exact(&mut connect).unwrap();
+        connect.resize(2 + connect[1] as usize, 0);
+        stream.read_exact(&mut connect[2..]).unwrap();
+        std::io::Write::write_all(&mut stream, &[0x20, 2, 0, 0]).unwrap();
+        let mut packets = Vec::new();
+        stream.read_to_end(&mut packets).unwrap();
+        (connect, packets)
+    });
+    let mut events = EventSink::new();
+    events.connect_mqtt(&config).unwrap();
+    emit(&events);
+    drop(events);
+    let (connect, packets) = received.join().unwrap();
+
+    let mut published = Vec::new();
+    let mut packets = packets.as_slice();
+    while let [header, rest @ ..] = packets {
+        let (mut length, mut multiplier, mut read) = (0, 1, 0);
+        loop {
+            length += (rest[read] & 0x7f) as usize * multiplier;
+            multiplier *= 128;
+            read += 1;
+            if rest[read - 1] & 0x80 == 0 {
+                break;
+            }
+        }
+        let body = &rest[read..read + length];
+        packets = &rest[read + length..];
+        if *header == 0xE0 {
+            continue;
+        }
+        // Every message is retained, so Home Assistant shows the last state after restarting
+        assert_eq!(0x31, *header);
+        let topic_length = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let topic = String::from_utf8(body[2..2 + topic_length].to_vec()).unwrap();
+        published.push((topic, String::from_utf8(body[2 + topic_length..].to_vec()).unwrap()));
+    }
+    (connect, published)
+}
+
+#[test]
+fn mqtt_state() {
+    let (connect, published) = mqtt_published(|events| {
+        events.emit(Event::Plan { to_process: 4, to_rename: 0, to_delete: 0 });
+        events.emit(Event::Progress { action: "encode", file: "Song.flac", current: 1, total: 4 });
+        events.emit(Event::Completed { warnings: 2, failed: 0, exit_code: None, error: None });
+    });
+
+    assert_eq!(0x10, connect[0]);
+    // The broker reports the run offline if it goes away without closing the connection
+    assert!(connect.windows(27).any(|window| window == b"\0\x10home/music/state\0\x07offline"));
+    assert_eq!("homeassistant/sensor/home_music/state/config", published[0].0);
+    let discovery = serde_json::from_str::<serde_json::Value>(&published[0].1).unwrap();
+    assert_eq!("home/music/state", discovery["state_topic"]);
+    assert_eq!(
+        vec![
+            ("home/music/state", "running"),
+            ("home/music/progress", "0"),
+            ("home/music/progress", "25"),
+            ("home/music/state", "idle"),
+            ("home/music/progress", "100"),
+            ("home/music/last_result", "ok with 2 warnings"),
+            ("home/music/state", "offline"),
+        ],
+        published[3..].iter().map(|(topic, payload)| (topic.as_str(), payload.as_str())).collect::<Vec<(&str, &str)>>()
+    );
+}
+
+#[test]
+fn mqtt_failed_run() {
+    let (_, published) = mqtt_published(|events| {
+        events.emit(Event::Completed { warnings: 0, failed: 3, exit_code: None, error: None });
+        events.emit(Event::Completed { warnings: 0, failed: 0, exit_code: Some(2), error: None });
+        events.emit(Event::Completed { warnings: 0, failed: 1, exit_code: None, error: Some("rclone failed") });
+        events.emit(Event::Completed {
+            warnings: 1,
+            failed: 0,
+            exit_code: Some(interrupt::INTERRUPTED_EXIT_CODE),
+            error: None,
+        });
+    });
+
+    // Failed encodes, refused plans and errors aren't reported as a completed sync
+    let last_results = published
+        .iter()
+        .filter(|(topic, _)| topic == "home/music/last_result")
+        .map(|(_, payload)| payload.as_str())
+        .collect::<Vec<&str>>();
+    assert_eq!(
+        vec!["failed for 3 files", "failed with exit code 2", "failed: rclone failed", "interrupted"],
+        last_results
+    );
+}
+
+#[test]
+fn server_api() {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let state = std::sync::Arc::new(std::sync::Mutex::new(ServerState::default()));
+    let mut next_job_id = 1;
+    let mut request = |request_line: &str| {
+        let mut client = std::net::TcpStream::connect(address).unwrap();
+        write!(client, "{}\r\nHost: localhost\r\n\r\n", request_line).unwrap();
+        handle_connection(listener.accept().unwrap().0, &state, &mut next_job_id).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), serde_json::from_str::<serde_json::Value>(body).unwrap())
+    };
+
+    // Syncs are only queued by the request, the monitor thread of serve starts them
+    let (status, job) = request("POST /sync HTTP/1.1");
+    assert_eq!("HTTP/1.1 202 Accepted", status);
+    assert_eq!(serde_json::json!({ "id": 1, "priority": "normal" }), job);
+    assert_eq!(2, request("POST /sync HTTP/1.1").1["id"]);
+    let (status, body) = request("GET /status HTTP/1.1");
+    assert_eq!("HTTP/1.1 200 OK", status);
+    assert_eq!("idle", body["state"]);
+    assert_eq!(2, body["queue"].as_array().unwrap().len());
+    let (_, report) = request("GET /report HTTP/1.1");
+    assert_eq!(serde_json::json!({ "running": false, "exitCode": null, "output": "" }), report);
+    assert_eq!("HTTP/1.1 409 Conflict", request("POST /cancel HTTP/1.1").0);
+    assert_eq!("HTTP/1.1 404 Not Found", request("GET /sync HTTP/1.1").0);
+}
+
+#[test]
+fn server_queue() {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let state = std::sync::Arc::new(std::sync::Mutex::new(ServerState::default()));
+    let mut next_job_id = 1;
+    for request_line in [
+        "POST /sync?priority=low HTTP/1.1",
+        "POST /sync?priority=high HTTP/1.1",
+        "POST /sync HTTP/1.1",
+        "POST /sync?priority=high HTTP/1.1",
+        "POST /sync?priority=urgent HTTP/1.1",
+    ] {
+        let mut client = std::net::TcpStream::connect(address).unwrap();
+        write!(client, "{}\r\n\r\n", request_line).unwrap();
+        handle_connection(listener.accept().unwrap().0, &state, &mut next_job_id).unwrap();
+        client.read_to_end(&mut Vec::new()).unwrap();
+    }
+
+    // Higher priorities run first, the oldest job of a priority before the newer ones. Unknown priorities aren't queued
+    let mut state = state.lock().unwrap();
+    let order = std::iter::from_fn(|| state.next_job()).map(|job| job.id).collect::<Vec<u64>>();
+    assert_eq!(vec![2, 4, 3, 1], order);
+}
+
+#[cfg(unix)]
+#[test]
+fn events_socket() {
+    use std::io::BufRead;
+    let root = TestDir::new("events_socket");
+    let path = root.join("events.sock");
+    // A socket left over from a crashed run is replaced
+    std::fs::write(&path, "").unwrap();
+    let mut events = EventSink::new();
+    events.bind_socket(path.clone()).unwrap();
+    let client = std::os::unix::net::UnixStream::connect(&path).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    let mut client = std::io::BufReader::new(client);
+
+    // Clients are accepted in the background, events sent before that aren't received
+    let mut line = String::new();
+    for _ in 0..50 {
+        events.emit(Event::Plan { to_process: 3, to_rename: 1, to_delete: 2 });
+        if client.read_line(&mut line).is_ok() {
+            break;
+        }
+    }
+    assert_eq!(r#"{"event":"plan","toProcess":3,"toRename":1,"toDelete":2}"#, line.trim_end());
+    line.clear();
+    events.emit(Event::Progress { action: "encode", file: "Song.flac", current: 1, total: 3 });
+    client.read_line(&mut line).unwrap();
+    // Events sent while the client was connecting may come first
+    while line.contains(r#""plan""#) {
+        line.clear();
+        client.read_line(&mut line).unwrap();
+    }
+    assert_eq!(
+        r#"{"event":"progress","action":"encode","file":"Song.flac","current":1,"total":3}"#,
+        line.trim_end()
+    );
+    drop(events);
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn purge_status() {
+    use std::os::unix::process::ExitStatusExt;
+    let exited = |code: i32| std::process::ExitStatus::from_raw(code << 8);
+    assert!(check_purge_status(exited(0)).is_ok());
+    // The trash of a run which is already gone is no failure, anything else keeps the run from going on
+    assert!(check_purge_status(exited(3)).is_ok());
+    assert!(check_purge_status(exited(1)).is_err());
+    assert!(check_purge_status(exited(7)).is_err());
+}
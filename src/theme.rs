@@ -0,0 +1,82 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use console::Style;
+
+use crate::health::Severity;
+
+/// Colors used for the console output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// Red for errors, green for success
+    Default,
+    /// Doesn't rely on telling red and green apart, magenta for errors and cyan for success
+    Colorblind,
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(theme: &str) -> Result<Self, Self::Err> {
+        match theme {
+            "default" => Ok(Theme::Default),
+            "colorblind" => Ok(Theme::Colorblind),
+            _ => Err(format!("unknown theme {}, expected default or colorblind", theme)),
+        }
+    }
+}
+
+static COLORBLIND: AtomicBool = AtomicBool::new(false);
+
+/// Sets the theme of all styles returned from now on
+pub fn set_theme(theme: Theme) {
+    COLORBLIND.store(theme == Theme::Colorblind, Ordering::Relaxed);
+}
+
+fn theme() -> Theme {
+    if COLORBLIND.load(Ordering::Relaxed) {
+        Theme::Colorblind
+    } else {
+        Theme::Default
+    }
+}
+
+/// Whether colors should be disabled by the [NO_COLOR](https://no-color.org/) environment variable
+pub fn no_color_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|no_color| !no_color.is_empty())
+}
+
+/// Errors and aborted runs
+pub fn error() -> Style {
+    match theme() {
+        Theme::Default => Style::new().bold().red(),
+        Theme::Colorblind => Style::new().bold().magenta(),
+    }
+}
+
+/// Finished steps and the plan summary
+pub fn success() -> Style {
+    match theme() {
+        Theme::Default => Style::new().bold().green(),
+        Theme::Colorblind => Style::new().bold().cyan(),
+    }
+}
+
+/// Problems which didn't stop the run
+pub fn warning() -> Style {
+    Style::new().bold().yellow()
+}
+
+/// Headings without a meaning of their own
+pub fn heading() -> Style {
+    Style::new().bold()
+}
+
+/// Style of a problem found by the health check or the config linter
+pub fn severity(severity: Severity) -> Style {
+    match severity {
+        Severity::High => error(),
+        Severity::Medium => warning(),
+        Severity::Low => heading(),
+    }
+}
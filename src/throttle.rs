@@ -0,0 +1,81 @@
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Throttle;
+use crate::interrupt;
+
+/// Highest temperature of the thermal zones in °C, None if none of them can be read
+pub fn read_cpu_temperature() -> Option<f64> {
+    fs::read_dir("/sys/class/thermal")
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("thermal_zone"))
+        .filter_map(|entry| fs::read_to_string(entry.path().join("temp")).ok())
+        .filter_map(|temp| parse_temperature(&temp))
+        .reduce(f64::max)
+}
+
+/// Parses a thermal zone temperature, given in millidegrees like "48500"
+pub fn parse_temperature(temp: &str) -> Option<f64> {
+    temp.trim().parse::<i64>().ok().map(|millidegrees| millidegrees as f64 / 1000.0)
+}
+
+/// Whether the machine runs on battery, None if it has no mains power supply to check
+pub fn on_battery() -> Option<bool> {
+    let mut mains_online = None;
+    for entry in fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let read = |file: &str| fs::read_to_string(entry.path().join(file)).ok().map(|value| value.trim().to_string());
+        if read("type").as_deref() == Some("Mains") {
+            let online = read("online").as_deref() == Some("1");
+            mains_online = Some(mains_online.unwrap_or(false) || online);
+        }
+    }
+    mains_online.map(|online| !online)
+}
+
+/// Why encodes have to wait, None if they can run
+///
+/// `paused` is whether they're already waiting, they wait for the resume temperature then instead of the maximum
+pub fn pause_reason(throttle: &Throttle, temperature: Option<f64>, on_battery: Option<bool>, paused: bool) -> Option<String> {
+    if throttle.pause_on_battery && on_battery == Some(true) {
+        return Some(String::from("running on battery"));
+    }
+    let temperature = temperature?;
+    let resume_temperature = throttle.resume_temperature()?;
+    let too_hot = if paused {
+        temperature > resume_temperature
+    } else {
+        throttle.max_temperature.is_some_and(|max_temperature| temperature >= max_temperature)
+    };
+    if too_hot {
+        Some(format!("CPU at {:.0} °C, waiting for {:.0} °C", temperature, resume_temperature))
+    } else {
+        None
+    }
+}
+
+/// Waits until encodes can run, printing why they're paused
+///
+/// Returns false if the deadline passed or Ctrl+C was pressed while waiting
+pub fn wait_until_allowed(throttle: &Throttle, deadline: Option<Instant>, print: impl Fn(String)) -> bool {
+    let mut paused = false;
+    loop {
+        let reason = pause_reason(throttle, read_cpu_temperature(), on_battery(), paused);
+        let Some(reason) = reason else {
+            if paused {
+                print(String::from("Resuming encodes"));
+            }
+            return true;
+        };
+        if !paused {
+            print(format!("Pausing encodes, {}", reason));
+            paused = true;
+        }
+        if interrupt::is_interrupted() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return false;
+        }
+        thread::sleep(Duration::from_secs(throttle.check_seconds.max(1)));
+    }
+}
+
@@ -0,0 +1,130 @@
+use crate::redact;
+use crate::theme;
+
+/// Categories of recoverable problems found during a run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    MissingExtension,
+    IncompleteFile,
+    UnreadableDirectory,
+    SuspiciousName,
+    MissingTrackNumber,
+    UnknownBitrate,
+    ParamsCorrected,
+    EncodeFailed,
+    SlowFile,
+    RemuxFailed,
+    CoverCopyFailed,
+    TagCopyFailed,
+    OutputBusy,
+    MirrorFailed,
+    ContentStoreFailed,
+    StatusPublishFailed,
+    AlbumGainFailed,
+    FolderCoverFailed,
+}
+
+impl WarningKind {
+    pub fn description(&self) -> String {
+        let key = match self {
+            Self::MissingExtension => "warning-missing-extension",
+            Self::IncompleteFile => "warning-incomplete-file",
+            Self::UnreadableDirectory => "warning-unreadable-directory",
+            Self::SuspiciousName => "warning-suspicious-name",
+            Self::MissingTrackNumber => "warning-missing-track-number",
+            Self::UnknownBitrate => "warning-unknown-bitrate",
+            Self::ParamsCorrected => "warning-params-corrected",
+            Self::EncodeFailed => "warning-encode-failed",
+            Self::SlowFile => "warning-slow-file",
+            Self::RemuxFailed => "warning-remux-failed",
+            Self::CoverCopyFailed => "warning-cover-copy-failed",
+            Self::TagCopyFailed => "warning-tag-copy-failed",
+            Self::MirrorFailed => "warning-mirror-failed",
+            Self::ContentStoreFailed => "warning-content-store-failed",
+            Self::OutputBusy => "warning-output-busy",
+            Self::StatusPublishFailed => "warning-status-publish-failed",
+            Self::AlbumGainFailed => "warning-album-gain-failed",
+            Self::FolderCoverFailed => "warning-folder-cover-failed",
+        };
+        crate::tr!(key)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub path: String,
+    pub message: Option<String>,
+}
+
+/// Collects warnings during the run so they can be printed grouped at the end instead of
+/// getting lost in the ffmpeg output
+#[derive(Default, Debug)]
+pub struct Warnings {
+    warnings: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, kind: WarningKind, path: String, message: Option<String>) {
+        self.warnings.push(
+            Warning {
+                kind,
+                path,
+                message,
+            }
+        );
+    }
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.warnings.len()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.warnings.iter()
+    }
+    /// Prints all warnings to stderr, grouped by their kind
+    pub fn print(&self) {
+        if self.is_empty() {
+            return;
+        }
+        eprintln!(
+            "{}",
+            theme::warning().apply_to(crate::tr!("warnings-heading", count = self.len()))
+        );
+        let mut warnings = self.warnings.clone();
+        warnings.sort_by(|a, b| (a.kind, &a.path).cmp(&(b.kind, &b.path)));
+        let mut current_kind = None;
+        for warning in warnings {
+            if current_kind != Some(warning.kind) {
+                current_kind = Some(warning.kind);
+                eprintln!("{}:", warning.kind.description());
+            }
+            let path = redact::path(&warning.path);
+            match warning.message.as_deref().and_then(redact::message) {
+                Some(message) => eprintln!(" - {}: {}", path, message),
+                None => eprintln!(" - {}", path),
+            }
+        }
+    }
+}
+
+/// Returns why a file name might cause problems on the target, if it's suspicious
+pub fn check_file_name(file_name: &str) -> Option<String> {
+    let name = file_name.rsplit('/').next().unwrap_or(file_name);
+    let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name);
+    if stem.trim().is_empty() {
+        Some(String::from("the name is empty"))
+    } else if stem.trim() != stem {
+        Some(String::from("the name starts or ends with whitespace"))
+    } else {
+        name.chars()
+            .find(|character| "\\:*?\"<>|".contains(*character))
+            .map(|character| {
+                format!("the name contains '{}', which is not allowed on FAT and NTFS", character)
+            })
+    }
+}
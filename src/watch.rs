@@ -0,0 +1,74 @@
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::fs_wrapper;
+use crate::fs_wrapper::RclonePath;
+use crate::interrupt;
+
+/// Polls the input directory between syncs of --watch and tells when the changes are done being written
+///
+/// A file is settled once its size stayed the same for settleSeconds, so unlike a single run the watch doesn't have
+/// to wait before every sync - the polls in between already show which files are still being written
+pub struct InputWatcher {
+    settle_time: Duration,
+    sizes: HashMap<String, u64>,
+    /// When the size of a file was last seen changing, files present since the first poll aren't listed
+    changed: HashMap<String, Instant>,
+}
+
+impl InputWatcher {
+    /// Starts watching with the sizes of the first poll, the files in it count as settled
+    pub fn new(settle_time: Duration, sizes: HashMap<String, u64>) -> Self {
+        Self {
+            settle_time,
+            sizes,
+            changed: HashMap::new(),
+        }
+    }
+    /// Records the sizes of a poll taken at `now`, files which appeared, disappeared or changed their size are
+    /// changed at that time
+    pub fn observe(&mut self, sizes: HashMap<String, u64>, now: Instant) {
+        for file in self.sizes.keys().chain(sizes.keys()) {
+            if self.sizes.get(file) != sizes.get(file) {
+                self.changed.insert(file.clone(), now);
+            }
+        }
+        self.sizes = sizes;
+    }
+    /// Files whose size changed within the settle time, they're skipped by the sync
+    pub fn unsettled(&self, now: Instant) -> HashSet<String> {
+        self.changed
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) < self.settle_time)
+            .map(|(file, _)| file.clone())
+            .collect()
+    }
+    /// Whether a change settled since the sync started at `last_sync`, either a change made after it or one the sync
+    /// skipped as it was still being written
+    pub fn is_sync_due(&self, last_sync: Instant, now: Instant) -> bool {
+        self.changed
+            .values()
+            .any(|changed| *changed + self.settle_time > last_sync && *changed + self.settle_time <= now)
+    }
+    /// Polls the directory every `interval` until a sync is due, returns false if Ctrl+C was pressed while waiting
+    pub fn wait_for_changes(&mut self, directory: &RclonePath, interval: Duration, last_sync: Instant) -> bool {
+        loop {
+            let waited = Instant::now();
+            while waited.elapsed() < interval {
+                if interrupt::is_interrupted() {
+                    return false;
+                }
+                thread::sleep(Duration::from_millis(200).min(interval));
+            }
+            let now = Instant::now();
+            // A failed poll is taken again after the next interval, the sizes of the last one still count
+            if let Ok(sizes) = fs_wrapper::list_file_sizes(directory) {
+                self.observe(sizes, now);
+            }
+            if self.is_sync_due(last_sync, now) {
+                return true;
+            }
+        }
+    }
+}